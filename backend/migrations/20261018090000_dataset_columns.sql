@@ -0,0 +1,31 @@
+-- Step 1: Create column_data_type enum
+CREATE TYPE column_data_type AS ENUM (
+   'integer',
+   'float',
+   'boolean',
+   'date',
+   'datetime',
+   'string',
+   'empty'
+);
+
+-- Step 2: Create dataset_columns table with the profile inferred at upload time
+CREATE TABLE dataset_columns (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   position INT NOT NULL,
+   name TEXT NOT NULL,
+   data_type column_data_type NOT NULL,
+   null_rate DOUBLE PRECISION NOT NULL,
+   distinct_count BIGINT NOT NULL,
+   min_value TEXT NULL,
+   max_value TEXT NULL,
+   sample_values TEXT[] NOT NULL DEFAULT '{}',
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_dataset_columns_agent_position UNIQUE (agent_id, position)
+);
+
+-- Step 3: Add indexes for performance
+-- Fast lookup of all columns of an agent dataset
+CREATE INDEX idx_dataset_columns_agent_id ON dataset_columns (agent_id);
@@ -0,0 +1,21 @@
+-- Step 1: Create guardrail enums
+CREATE TYPE guardrail_event_kind AS ENUM ('row_leak', 'pii_leak');
+
+CREATE TYPE guardrail_action AS ENUM ('redacted', 'refused');
+
+-- Step 2: Create guardrail_events table recording responses filtered by the output guardrail
+CREATE TABLE guardrail_events (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   kind guardrail_event_kind NOT NULL,
+   action guardrail_action NOT NULL,
+   leaked_rows INT NOT NULL DEFAULT 0,
+   pii_matches INT NOT NULL DEFAULT 0,
+   prompt TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+-- Step 3: Add indexes for performance
+-- Fast lookup of the events of an agent, most recent first
+CREATE INDEX idx_guardrail_events_agent_created_at ON guardrail_events (agent_id, created_at DESC);
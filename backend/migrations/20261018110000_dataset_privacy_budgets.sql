@@ -0,0 +1,14 @@
+-- Create dataset_privacy_budgets table for datasets opted in to differential privacy
+CREATE TABLE dataset_privacy_budgets (
+   agent_id BIGINT PRIMARY KEY,
+   epsilon_total DOUBLE PRECISION NOT NULL CHECK (epsilon_total > 0),
+   epsilon_per_query DOUBLE PRECISION NOT NULL CHECK (epsilon_per_query > 0),
+   epsilon_spent DOUBLE PRECISION NOT NULL DEFAULT 0,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT chk_epsilon_spent CHECK (epsilon_spent <= epsilon_total)
+);
+
+CREATE TRIGGER trg_dataset_privacy_budgets_updated_at BEFORE
+UPDATE ON dataset_privacy_budgets FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
@@ -0,0 +1,11 @@
+-- Create dataset_privacy_bounds table for the clamp bounds owners declare on the numeric columns of datasets in privacy mode
+CREATE TABLE dataset_privacy_bounds (
+   agent_id BIGINT NOT NULL,
+   column_name TEXT NOT NULL,
+   lower_bound DOUBLE PRECISION NOT NULL,
+   upper_bound DOUBLE PRECISION NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   PRIMARY KEY (agent_id, column_name),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES dataset_privacy_budgets (agent_id) ON DELETE CASCADE,
+   CONSTRAINT chk_bounds CHECK (lower_bound < upper_bound)
+);
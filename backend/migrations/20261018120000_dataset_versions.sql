@@ -0,0 +1,21 @@
+-- Step 1: Track the dataset version currently answering queries
+ALTER TABLE agents ADD COLUMN current_version INT NOT NULL DEFAULT 1;
+
+-- Step 2: Create dataset_versions table with every file published for an agent
+CREATE TABLE dataset_versions (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   version INT NOT NULL,
+   dataset_path TEXT NOT NULL,
+   dataset_size DOUBLE PRECISION NOT NULL,
+   row_count BIGINT NULL,
+   mode VARCHAR(50) NOT NULL CHECK (mode IN ('initial', 'append', 'replace')),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_dataset_versions_agent_version UNIQUE (agent_id, version)
+);
+
+-- Step 3: Register the existing datasets as their first version
+INSERT INTO dataset_versions (agent_id, version, dataset_path, dataset_size, mode, created_at)
+SELECT id, 1, dataset_path, dataset_size, 'initial', created_at
+FROM agents;
@@ -0,0 +1,10 @@
+-- Step 1: Create auth_nonces table with the nonces of the signed requests, each can be used once per address
+CREATE TABLE auth_nonces (
+   address VARCHAR(255) NOT NULL,
+   nonce VARCHAR(128) NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   PRIMARY KEY (address, nonce)
+);
+
+-- Nonces older than the signature max age are pruned
+CREATE INDEX idx_auth_nonces_created_at ON auth_nonces (created_at);
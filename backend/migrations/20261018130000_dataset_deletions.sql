@@ -0,0 +1,22 @@
+-- Step 1: Allow agents to be tombstoned once their dataset is deleted
+ALTER TABLE agents DROP CONSTRAINT agents_status_check;
+
+ALTER TABLE agents ADD CONSTRAINT agents_status_check CHECK (status IN ('active', 'paused', 'archived', 'deleted'));
+
+ALTER TABLE agents ADD COLUMN deleted_at TIMESTAMPTZ NULL;
+
+-- Step 2: Create dataset_deletions table recording every dataset withdrawal or erasure
+CREATE TABLE dataset_deletions (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   requested_by VARCHAR(255) NOT NULL,
+   reason VARCHAR(50) NOT NULL CHECK (reason IN ('owner_withdrawal', 'erasure_request')),
+   note TEXT NULL,
+   removed_files TEXT[] NOT NULL DEFAULT '{}',
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+-- Step 3: Add indexes for performance
+-- Fast lookup of the deletion record of an agent
+CREATE INDEX idx_dataset_deletions_agent_id ON dataset_deletions (agent_id);
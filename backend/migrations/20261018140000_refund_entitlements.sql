@@ -0,0 +1,21 @@
+-- Step 1: Create refund_entitlements table recording agents that failed to answer after being paid
+CREATE TABLE refund_entitlements (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   tx_hash VARCHAR(255) NOT NULL,
+   amount DOUBLE PRECISION NOT NULL,
+   reason VARCHAR(50) NOT NULL CHECK (reason IN ('failed', 'timed_out')),
+   error TEXT NULL,
+   status VARCHAR(50) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'refunded', 'credited')),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_refund_entitlements_tx_agent UNIQUE (tx_hash, agent_id)
+);
+
+CREATE TRIGGER trg_refund_entitlements_updated_at BEFORE
+UPDATE ON refund_entitlements FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Add indexes for performance
+-- Fast lookup of the entitlements of a payment
+CREATE INDEX idx_refund_entitlements_tx_hash ON refund_entitlements (tx_hash);
@@ -0,0 +1,32 @@
+-- Step 1: Create conversations table grouping the questions a user asked in a chat session
+CREATE TABLE conversations (
+   id BIGSERIAL PRIMARY KEY,
+   user_address VARCHAR(255) NOT NULL,
+   title TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+CREATE TRIGGER trg_conversations_updated_at BEFORE
+UPDATE ON conversations FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Create messages table with the user questions and the answers of each agent
+CREATE TABLE messages (
+   id BIGSERIAL PRIMARY KEY,
+   conversation_id BIGINT NOT NULL,
+   role VARCHAR(50) NOT NULL CHECK (role IN ('user', 'assistant')),
+   agent_id BIGINT NULL,
+   content TEXT NOT NULL,
+   dataset_version INT NULL,
+   tx_hash VARCHAR(255) NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_conversation FOREIGN KEY (conversation_id) REFERENCES conversations (id) ON DELETE CASCADE,
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+-- Step 3: Add indexes for performance
+-- Fast lookup of the conversations of a user, most recent first
+CREATE INDEX idx_conversations_user_address_updated_at ON conversations (user_address, updated_at DESC);
+
+-- Fast lookup of the messages of a conversation in order
+CREATE INDEX idx_messages_conversation_id ON messages (conversation_id, id);
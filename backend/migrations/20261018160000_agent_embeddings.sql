@@ -0,0 +1,14 @@
+-- Step 1: Create agent_embeddings table with the embedding of each agent name, description, category and dataset columns
+CREATE TABLE agent_embeddings (
+   agent_id BIGINT PRIMARY KEY,
+   model VARCHAR(100) NOT NULL,
+   -- Text the embedding was computed from, compared to detect stale embeddings
+   source_text TEXT NOT NULL,
+   embedding DOUBLE PRECISION[] NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER trg_agent_embeddings_updated_at BEFORE
+UPDATE ON agent_embeddings FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
@@ -0,0 +1,36 @@
+-- Step 1: Pause the agents whose price can't be stored as a positive number of base units
+-- (zero, negative, NaN, infinite, below one base unit or beyond BIGINT) and set it to one base unit,
+-- their owner reprices them before resuming them
+DO $$
+DECLARE
+   invalid_agent RECORD;
+BEGIN
+   FOR invalid_agent IN
+      SELECT id, price, status
+      FROM agents
+      WHERE NOT (ROUND(price * 100000000) >= 1 AND price * 100000000 < 9223372036854775807)
+      ORDER BY id
+   LOOP
+      UPDATE agents
+      SET price = 0.00000001,
+         status = CASE WHEN status = 'active' THEN 'paused' ELSE status END
+      WHERE id = invalid_agent.id;
+
+      RAISE NOTICE 'Agent % had the invalid price %, set to one base unit with status % (was %)',
+         invalid_agent.id,
+         invalid_agent.price,
+         CASE WHEN invalid_agent.status = 'active' THEN 'paused' ELSE invalid_agent.status END,
+         invalid_agent.status;
+   END LOOP;
+END $$;
+
+-- Step 2: Store agent prices as integer base units (8 decimals, the units paid on-chain)
+ALTER TABLE agents
+ALTER COLUMN price TYPE BIGINT USING ROUND(price * 100000000)::BIGINT;
+
+ALTER TABLE agents
+ADD CONSTRAINT agents_price_check CHECK (price > 0);
+
+-- Step 3: Store refund amounts as integer base units
+ALTER TABLE refund_entitlements
+ALTER COLUMN amount TYPE BIGINT USING ROUND(amount * 100000000)::BIGINT;
@@ -0,0 +1,120 @@
+-- Step 1: Create ledger_accounts table, balance is the sum of the account entries kept up to date with them
+CREATE TABLE ledger_accounts (
+   id BIGSERIAL PRIMARY KEY,
+   kind VARCHAR(50) NOT NULL CHECK (
+      kind IN (
+         'user_credit',
+         'agent_revenue',
+         'settlements_pending',
+         'onchain_deposits',
+         'onchain_settlements',
+         'platform_funding'
+      )
+   ),
+   -- User address for user_credit, agent id for agent_revenue, empty for the system accounts
+   owner_key VARCHAR(255) NOT NULL DEFAULT '',
+   balance BIGINT NOT NULL DEFAULT 0,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT uq_ledger_accounts_kind_owner_key UNIQUE (kind, owner_key),
+   -- Users can't spend more credits than they deposited, checked in the same transaction as the debit
+   CONSTRAINT ledger_accounts_user_credit_check CHECK (
+      kind <> 'user_credit'
+      OR balance >= 0
+   )
+);
+
+CREATE TRIGGER trg_ledger_accounts_updated_at BEFORE
+UPDATE ON ledger_accounts FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Create ledger_transactions table, each one groups entries summing to zero
+CREATE TABLE ledger_transactions (
+   id BIGSERIAL PRIMARY KEY,
+   kind VARCHAR(50) NOT NULL CHECK (
+      kind IN ('deposit', 'answer_debit', 'refund', 'settlement_accrual', 'settlement')
+   ),
+   -- On-chain event or answer the transaction comes from, posting it twice is refused
+   reference VARCHAR(255) NOT NULL UNIQUE,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+-- Step 3: Create ledger_entries table
+CREATE TABLE ledger_entries (
+   id BIGSERIAL PRIMARY KEY,
+   transaction_id BIGINT NOT NULL,
+   account_id BIGINT NOT NULL,
+   amount BIGINT NOT NULL CHECK (amount <> 0),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_transaction FOREIGN KEY (transaction_id) REFERENCES ledger_transactions (id) ON DELETE CASCADE,
+   CONSTRAINT fk_account FOREIGN KEY (account_id) REFERENCES ledger_accounts (id) ON DELETE CASCADE
+);
+
+-- Step 4: Refuse unbalanced transactions when the database transaction commits
+CREATE OR REPLACE FUNCTION check_ledger_transaction_balanced () RETURNS TRIGGER AS $$
+BEGIN
+   IF (
+      SELECT COALESCE(SUM(amount), 0)
+      FROM ledger_entries
+      WHERE transaction_id = NEW.transaction_id
+   ) <> 0 THEN
+      RAISE EXCEPTION 'Ledger transaction % is not balanced', NEW.transaction_id;
+   END IF;
+
+   RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER trg_ledger_entries_balanced
+AFTER INSERT ON ledger_entries DEFERRABLE INITIALLY DEFERRED FOR EACH ROW
+EXECUTE FUNCTION check_ledger_transaction_balanced ();
+
+-- Step 5: Create credit_events table with the indexed CreditsDeposited and CreditsSettled events
+CREATE TABLE credit_events (
+   id BIGSERIAL PRIMARY KEY,
+   kind VARCHAR(50) NOT NULL CHECK (kind IN ('deposit', 'settlement')),
+   tx_hash VARCHAR(255) NOT NULL,
+   log_index BIGINT NOT NULL,
+   block_number BIGINT NOT NULL,
+   -- Depositor of a deposit
+   user_address VARCHAR(255) NULL,
+   -- Dataset NFT of a settlement
+   token_id BIGINT NULL,
+   amount BIGINT NOT NULL,
+   -- Ledger transaction booking the event, NULL when it could not be matched
+   ledger_transaction_id BIGINT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT uq_credit_events_tx_hash_log_index UNIQUE (tx_hash, log_index),
+   CONSTRAINT fk_ledger_transaction FOREIGN KEY (ledger_transaction_id) REFERENCES ledger_transactions (id)
+);
+
+-- Step 6: Create credit_settlements table with the revenue to settle on-chain with settleCredits
+CREATE TABLE credit_settlements (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   token_id BIGINT NOT NULL,
+   amount BIGINT NOT NULL CHECK (amount > 0),
+   status VARCHAR(50) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'settled')),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   settled_at TIMESTAMPTZ NULL,
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+-- Step 7: Create fetcher_cursors table so event fetchers resume where they stopped
+CREATE TABLE fetcher_cursors (
+   name VARCHAR(100) PRIMARY KEY,
+   last_block BIGINT NOT NULL,
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+CREATE TRIGGER trg_fetcher_cursors_updated_at BEFORE
+UPDATE ON fetcher_cursors FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 8: Add indexes for performance
+-- Fast lookup of the entries of an account, most recent first
+CREATE INDEX idx_ledger_entries_account_id ON ledger_entries (account_id, id DESC);
+
+-- Fast lookup of the entries of a transaction
+CREATE INDEX idx_ledger_entries_transaction_id ON ledger_entries (transaction_id);
+
+-- Fast lookup of the pending settlements of a token
+CREATE INDEX idx_credit_settlements_token_id_status ON credit_settlements (token_id, status);
@@ -0,0 +1,89 @@
+-- Step 1: Create agent_pricing_plans table, agents without a plan are priced per query at agents.price
+CREATE TABLE agent_pricing_plans (
+   agent_id BIGINT PRIMARY KEY,
+   model VARCHAR(50) NOT NULL DEFAULT 'per_query' CHECK (model IN ('per_query', 'subscription', 'volume_tiers')),
+   -- Price of one subscription period, in 8 decimals base units
+   subscription_price BIGINT NULL CHECK (subscription_price > 0),
+   subscription_period_days INTEGER NULL CHECK (subscription_period_days > 0),
+   -- Queries each user can make for free before paying, whatever the model
+   free_sample_queries INTEGER NOT NULL DEFAULT 0 CHECK (free_sample_queries >= 0),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT agent_pricing_plans_subscription_check CHECK (
+      model <> 'subscription'
+      OR (
+         subscription_price IS NOT NULL
+         AND subscription_period_days IS NOT NULL
+      )
+   )
+);
+
+CREATE TRIGGER trg_agent_pricing_plans_updated_at BEFORE
+UPDATE ON agent_pricing_plans FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Create agent_price_tiers table with the volume tiers of the agents
+CREATE TABLE agent_price_tiers (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   -- Queries the user made to the agent in the tier window from which the tier price applies
+   min_queries INTEGER NOT NULL CHECK (min_queries > 0),
+   price BIGINT NOT NULL CHECK (price > 0),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_agent_price_tiers_agent_min_queries UNIQUE (agent_id, min_queries)
+);
+
+-- Step 3: Create agent_subscriptions table
+CREATE TABLE agent_subscriptions (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   user_address VARCHAR(255) NOT NULL,
+   -- Credits debit paying the subscription
+   payment_reference VARCHAR(255) NOT NULL UNIQUE,
+   amount BIGINT NOT NULL,
+   starts_at TIMESTAMPTZ NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT agent_subscriptions_period_check CHECK (expires_at > starts_at)
+);
+
+-- Step 4: Create agent_usage table with every paid, free sample or subscription query
+CREATE TABLE agent_usage (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   user_address VARCHAR(255) NOT NULL,
+   -- Tx hash or credits debit reference of the question
+   payment_reference VARCHAR(255) NOT NULL,
+   basis VARCHAR(50) NOT NULL CHECK (basis IN ('per_query', 'volume_tier', 'free_sample', 'subscription')),
+   amount BIGINT NOT NULL CHECK (amount >= 0),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_agent_usage_payment_agent UNIQUE (payment_reference, agent_id)
+);
+
+-- Step 5: Allow subscriptions paid with credits in the ledger
+ALTER TABLE ledger_transactions
+DROP CONSTRAINT ledger_transactions_kind_check;
+
+ALTER TABLE ledger_transactions
+ADD CONSTRAINT ledger_transactions_kind_check CHECK (
+   kind IN (
+      'deposit',
+      'answer_debit',
+      'refund',
+      'settlement_accrual',
+      'settlement',
+      'subscription'
+   )
+);
+
+-- Step 6: Add indexes for performance
+-- Fast lookup of the tiers of an agent
+CREATE INDEX idx_agent_price_tiers_agent_id ON agent_price_tiers (agent_id, min_queries);
+
+-- Fast lookup of the active subscriptions of a user
+CREATE INDEX idx_agent_subscriptions_user_agent ON agent_subscriptions (user_address, agent_id, expires_at DESC);
+
+-- Fast count of the queries of a user to an agent
+CREATE INDEX idx_agent_usage_user_agent ON agent_usage (user_address, agent_id, created_at);
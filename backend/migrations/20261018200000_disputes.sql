@@ -0,0 +1,75 @@
+-- Step 1: Create agent_answers table keeping the prompt and answer of every paid agent as dispute evidence
+CREATE TABLE agent_answers (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   -- Tx hash or credits debit reference of the question
+   payment_reference VARCHAR(255) NOT NULL,
+   prompt TEXT NOT NULL,
+   response TEXT NOT NULL,
+   status VARCHAR(50) NOT NULL CHECK (status IN ('success', 'failed', 'timed_out')),
+   error TEXT NULL,
+   dataset_version INT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_agent_answers_payment_agent UNIQUE (payment_reference, agent_id)
+);
+
+-- Step 2: Let refunds be owed for disputes and executed on-chain by the refund signer
+ALTER TABLE refund_entitlements
+DROP CONSTRAINT refund_entitlements_reason_check;
+
+ALTER TABLE refund_entitlements
+ADD CONSTRAINT refund_entitlements_reason_check CHECK (reason IN ('failed', 'timed_out', 'disputed'));
+
+ALTER TABLE refund_entitlements
+DROP CONSTRAINT refund_entitlements_status_check;
+
+-- processing while the refund transaction is sent, failed when it could not be confirmed and needs a manual check
+ALTER TABLE refund_entitlements
+ADD CONSTRAINT refund_entitlements_status_check CHECK (
+   status IN ('pending', 'processing', 'refunded', 'credited', 'failed')
+);
+
+-- Transaction sending the refund back to the payer
+ALTER TABLE refund_entitlements
+ADD COLUMN refund_tx_hash VARCHAR(255) NULL;
+
+-- Step 3: Create disputes table with the disputes users raise on the answers they paid for
+CREATE TABLE disputes (
+   id BIGSERIAL PRIMARY KEY,
+   answer_id BIGINT NOT NULL UNIQUE,
+   agent_id BIGINT NOT NULL,
+   user_address VARCHAR(255) NOT NULL,
+   category VARCHAR(50) NOT NULL CHECK (
+      category IN ('technical_failure', 'incorrect_answer', 'incomplete_answer', 'other')
+   ),
+   description TEXT NOT NULL,
+   status VARCHAR(50) NOT NULL DEFAULT 'open' CHECK (
+      status IN ('open', 'owner_responded', 'refunded', 'rejected')
+   ),
+   owner_response TEXT NULL,
+   owner_responded_at TIMESTAMPTZ NULL,
+   resolution_note TEXT NULL,
+   -- Agent owner accepting the refund or dispute arbiter
+   resolved_by VARCHAR(255) NULL,
+   resolved_at TIMESTAMPTZ NULL,
+   refund_entitlement_id BIGINT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_answer FOREIGN KEY (answer_id) REFERENCES agent_answers (id) ON DELETE CASCADE,
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT fk_refund_entitlement FOREIGN KEY (refund_entitlement_id) REFERENCES refund_entitlements (id) ON DELETE SET NULL
+);
+
+CREATE TRIGGER trg_disputes_updated_at BEFORE
+UPDATE ON disputes FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 4: Add indexes for performance
+-- Fast lookup of the disputes raised by a user
+CREATE INDEX idx_disputes_user_address ON disputes (user_address, created_at DESC);
+
+-- Fast lookup of the disputes on an agent
+CREATE INDEX idx_disputes_agent_id ON disputes (agent_id, created_at DESC);
+
+-- Fast lookup of the refunds left to execute
+CREATE INDEX idx_refund_entitlements_status ON refund_entitlements (status);
@@ -0,0 +1,46 @@
+-- Step 1: Create queries table logging every paid question: who asked, what it cost and how it went
+CREATE TABLE queries (
+   id BIGSERIAL PRIMARY KEY,
+   requester_address VARCHAR(255) NOT NULL,
+   agent_ids BIGINT[] NOT NULL,
+   -- Keccak-256 of the prompt, the text itself is only kept when the requester opts in
+   prompt_hash VARCHAR(64) NOT NULL,
+   prompt TEXT NULL,
+   -- Tx hash or credits debit reference
+   payment_reference VARCHAR(255) NOT NULL UNIQUE,
+   -- Total charged to the requester, in 8 decimals base units
+   amount BIGINT NOT NULL CHECK (amount >= 0),
+   outcome VARCHAR(50) NOT NULL CHECK (outcome IN ('success', 'partial', 'failed')),
+   latency_ms BIGINT NOT NULL,
+   streamed BOOLEAN NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+-- Step 2: Create query_agents table with the outcome of each agent of a query
+CREATE TABLE query_agents (
+   id BIGSERIAL PRIMARY KEY,
+   query_id BIGINT NOT NULL,
+   agent_id BIGINT NOT NULL,
+   status VARCHAR(50) NOT NULL CHECK (status IN ('success', 'failed', 'timed_out')),
+   -- Keccak-256 of the answer, missing when the agent failed
+   response_hash VARCHAR(64) NULL,
+   latency_ms BIGINT NOT NULL,
+   -- Token usage, when the model reported it
+   input_tokens BIGINT NULL,
+   output_tokens BIGINT NULL,
+   total_tokens BIGINT NULL,
+   model VARCHAR(255) NOT NULL,
+   dataset_version INT NOT NULL,
+   amount BIGINT NOT NULL CHECK (amount >= 0),
+   error TEXT NULL,
+   CONSTRAINT fk_query FOREIGN KEY (query_id) REFERENCES queries (id) ON DELETE CASCADE,
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_query_agents_query_agent UNIQUE (query_id, agent_id)
+);
+
+-- Step 3: Add indexes for performance
+-- Fast lookup of the queries of a requester, most recent first
+CREATE INDEX idx_queries_requester_address ON queries (requester_address, id DESC);
+
+-- Fast lookup of the queries to an agent, most recent first
+CREATE INDEX idx_query_agents_agent_id ON query_agents (agent_id, query_id DESC);
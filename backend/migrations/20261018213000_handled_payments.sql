@@ -0,0 +1,6 @@
+-- Create handled_payments table claiming each payment tx once, so it can't be reused after a restart or by concurrent requests
+CREATE TABLE handled_payments (
+   tx_hash VARCHAR(66) PRIMARY KEY,
+   payer_address VARCHAR(255) NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
@@ -0,0 +1,44 @@
+-- Step 1: Daily usage and revenue of each agent, refunded answers are deducted from the revenue
+CREATE MATERIALIZED VIEW stats_agent_daily_usage AS
+SELECT
+   date_trunc('day', u.created_at) AS day,
+   u.agent_id,
+   COUNT(*) AS queries,
+   COUNT(*) FILTER (WHERE u.amount > 0) AS paid_queries,
+   SUM(u.amount)::BIGINT AS revenue,
+   COALESCE(SUM(r.amount) FILTER (WHERE r.status IN ('refunded', 'credited')), 0)::BIGINT AS refunded
+FROM agent_usage u
+LEFT JOIN refund_entitlements r ON r.tx_hash = u.payment_reference AND r.agent_id = u.agent_id
+GROUP BY 1, 2;
+
+-- Unique index required to refresh the view concurrently
+CREATE UNIQUE INDEX uq_stats_agent_daily_usage_day_agent ON stats_agent_daily_usage (day, agent_id);
+
+-- Step 2: Daily answer latency of each agent, from the queries log
+CREATE MATERIALIZED VIEW stats_agent_daily_latency AS
+SELECT
+   date_trunc('day', q.created_at) AS day,
+   a.agent_id,
+   COUNT(*) AS answers,
+   SUM(a.latency_ms)::BIGINT AS total_latency_ms
+FROM query_agents a
+JOIN queries q ON q.id = a.query_id
+WHERE a.status = 'success'
+GROUP BY 1, 2;
+
+CREATE UNIQUE INDEX uq_stats_agent_daily_latency_day_agent ON stats_agent_daily_latency (day, agent_id);
+
+-- Step 3: Users who asked at least one question each day
+CREATE MATERIALIZED VIEW stats_daily_buyers AS
+SELECT DISTINCT
+   date_trunc('day', created_at) AS day,
+   user_address
+FROM agent_usage;
+
+CREATE UNIQUE INDEX uq_stats_daily_buyers_day_user ON stats_daily_buyers (day, user_address);
+
+-- Step 4: Create stats_refreshes table with the last refresh of the statistics views
+CREATE TABLE stats_refreshes (
+   name VARCHAR(255) PRIMARY KEY,
+   refreshed_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
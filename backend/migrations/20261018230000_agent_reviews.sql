@@ -0,0 +1,24 @@
+-- Step 1: Create agent_reviews table, one review per paying address and agent
+CREATE TABLE agent_reviews (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   reviewer_address VARCHAR(255) NOT NULL,
+   rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+   comment TEXT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_agent_reviews_agent_reviewer UNIQUE (agent_id, reviewer_address)
+);
+
+CREATE TRIGGER trg_agent_reviews_updated_at BEFORE
+UPDATE ON agent_reviews FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Create agent_ratings view aggregating the reviews of each agent
+CREATE VIEW agent_ratings AS
+SELECT
+   agent_id,
+   COUNT(*) AS review_count,
+   AVG(rating)::DOUBLE PRECISION AS average_rating
+FROM agent_reviews
+GROUP BY agent_id;
@@ -0,0 +1,57 @@
+-- Step 1: Create evaluation_cases table with the questions an agent should answer and their expected answer
+CREATE TABLE evaluation_cases (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   question TEXT NOT NULL,
+   expected_answer TEXT NOT NULL,
+   -- How the agent answer is compared with the expected one
+   match_kind VARCHAR(50) NOT NULL CHECK (match_kind IN ('exact', 'numeric', 'llm_judge')),
+   -- Relative tolerance of numeric matches
+   tolerance DOUBLE PRECISION NULL CHECK (tolerance >= 0),
+   created_by VARCHAR(255) NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_evaluation_cases_agent ON evaluation_cases (agent_id);
+
+-- Step 2: Create evaluation_runs table with the score of an agent per dataset version and model
+CREATE TABLE evaluation_runs (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   dataset_version INTEGER NOT NULL,
+   -- Model answering the questions, 'mock' for canned answers
+   model VARCHAR(255) NOT NULL,
+   status VARCHAR(50) NOT NULL DEFAULT 'running' CHECK (status IN ('running', 'completed', 'failed')),
+   total_cases INTEGER NOT NULL,
+   passed_cases INTEGER NOT NULL DEFAULT 0,
+   -- Average score of the cases, from 0 to 1
+   score DOUBLE PRECISION NULL,
+   error TEXT NULL,
+   -- Address that started the run, missing for runs started from the command line
+   started_by VARCHAR(255) NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   completed_at TIMESTAMPTZ NULL,
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_evaluation_runs_agent ON evaluation_runs (agent_id, dataset_version, model);
+
+-- Step 3: Create evaluation_results table with the answer and score of each case of a run
+CREATE TABLE evaluation_results (
+   id BIGSERIAL PRIMARY KEY,
+   run_id BIGINT NOT NULL,
+   case_id BIGINT NOT NULL,
+   -- Answer after the output guardrail, missing when the agent failed
+   answer TEXT NULL,
+   passed BOOLEAN NOT NULL,
+   score DOUBLE PRECISION NOT NULL,
+   -- Why the judge model accepted or rejected the answer of llm_judge cases
+   judge_reasoning TEXT NULL,
+   error TEXT NULL,
+   latency_ms BIGINT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_run FOREIGN KEY (run_id) REFERENCES evaluation_runs (id) ON DELETE CASCADE,
+   CONSTRAINT fk_case FOREIGN KEY (case_id) REFERENCES evaluation_cases (id) ON DELETE CASCADE,
+   CONSTRAINT uq_evaluation_results_run_case UNIQUE (run_id, case_id)
+);
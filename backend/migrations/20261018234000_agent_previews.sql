@@ -0,0 +1,28 @@
+-- Step 1: Create agent_preview_settings table with what free preview questions are answered from, schema when missing
+CREATE TABLE agent_preview_settings (
+   agent_id BIGINT PRIMARY KEY,
+   mode VARCHAR(50) NOT NULL CHECK (mode IN ('disabled', 'schema', 'sample_rows')),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER trg_agent_preview_settings_updated_at BEFORE
+UPDATE ON agent_preview_settings FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
+
+-- Step 2: Create preview_queries table with every free preview question, counted against the daily limits
+CREATE TABLE preview_queries (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   ip_address VARCHAR(64) NOT NULL,
+   -- Signer of the question, missing for anonymous previews
+   requester_address VARCHAR(255) NULL,
+   -- Keccak-256 of the prompt, the text itself is never kept
+   prompt_hash VARCHAR(64) NOT NULL,
+   mode VARCHAR(50) NOT NULL CHECK (mode IN ('schema', 'sample_rows')),
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_preview_queries_ip ON preview_queries (ip_address, created_at);
+
+CREATE INDEX idx_preview_queries_requester ON preview_queries (requester_address, created_at);
@@ -0,0 +1,294 @@
+use actix_web::{HttpResponse, post, web};
+use tracing::{error, warn};
+
+use crate::{
+    database,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    types::{
+        AggregateFunction, AggregateQueryRequest, AggregateQueryResponse, ColumnDataType,
+        ErrorResponse,
+    },
+};
+
+/*
+Endpoint that computes count/sum/mean queries directly on the agent dataset.
+Datasets in privacy mode get calibrated Laplace noise and each query consumes epsilon from their budget.
+*/
+#[utoipa::path(
+    post,
+    path = "/chat/agents/{id}/aggregate",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = AggregateQueryRequest,
+        content_type = "application/json",
+        description = "Aggregate function, column, filters and tx hash to verify payment. Free samples and subscriptions require the payer signature headers."
+    ),
+    responses(
+        (status = 200, description = "Aggregate computed successfully", body = AggregateQueryResponse),
+        (status = 400, description = "Bad request - invalid query", body = ErrorResponse),
+        (status = 402, description = "Payment verification failed", body = ErrorResponse),
+        (status = 403, description = "Privacy budget exhausted", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/chat/agents/{id}/aggregate")]
+async fn aggregate_query_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: Option<SignedAddress>,
+    body: web::Json<AggregateQueryRequest>,
+) -> HttpResponse {
+    let agent_id = path.into_inner();
+    let query = body.into_inner();
+
+    if query.tx_hash.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "No tx hash specified".to_string(),
+            error_code: Some("NO_TX_HASH_SPECIFIED".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) if agent.status == "active" => agent,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not running", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let privacy_budget = match database::get_privacy_budget_by_agent_id(db, agent_id).await {
+        Ok(budget) => budget,
+        Err(e) => {
+            error!("Failed to get privacy budget: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get privacy budget from database".to_string(),
+                error_code: Some("PRIVACY_BUDGET_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Some(budget) = &privacy_budget
+        && budget.epsilon_spent + budget.epsilon_per_query > budget.epsilon_total
+    {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: format!("Privacy budget of agent {} is exhausted", agent_id),
+            error_code: Some("PRIVACY_BUDGET_EXHAUSTED".to_string()),
+        });
+    }
+
+    // Sums are computed on values clamped to the bounds the owner declared in privacy mode, the profiled ones otherwise
+    let mut bounds: Option<(f64, f64)> = None;
+
+    if let Some(column_name) = query
+        .column
+        .as_ref()
+        .filter(|_| query.function != AggregateFunction::Count)
+    {
+        if privacy_budget.is_some() {
+            bounds = match database::get_privacy_bounds(db, agent_id, column_name).await {
+                Ok(Some(bounds)) => Some(bounds),
+                Ok(None) => {
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: format!("No privacy bounds declared for column {}", column_name),
+                        error_code: Some("COLUMN_BOUNDS_NOT_DECLARED".to_string()),
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to get privacy bounds: {}", e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get privacy bounds from database".to_string(),
+                        error_code: Some("PRIVACY_BOUNDS_FETCH_FAILED".to_string()),
+                    });
+                }
+            };
+        } else {
+            let columns = match database::get_dataset_columns_by_agent_id(db, agent_id).await {
+                Ok(columns) => columns,
+                Err(e) => {
+                    error!("Failed to get dataset columns: {}", e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get dataset columns from database".to_string(),
+                        error_code: Some("DATASET_COLUMNS_FETCH_FAILED".to_string()),
+                    });
+                }
+            };
+
+            bounds = columns
+                .iter()
+                .find(|column| &column.name == column_name)
+                .filter(|column| {
+                    matches!(
+                        column.data_type,
+                        ColumnDataType::Integer | ColumnDataType::Float
+                    )
+                })
+                .and_then(|column| {
+                    let lower = column.min_value.as_ref()?.parse::<f64>().ok()?;
+                    let upper = column.max_value.as_ref()?.parse::<f64>().ok()?;
+                    Some((lower, upper))
+                });
+        }
+    }
+
+    // Run the query before payment so invalid queries are not charged
+    let (result, dataset_version) = {
+        let tee_agent = match app_state
+            .agent_cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+        {
+            Ok(agent) => agent,
+            Err(e) => {
+                error!("Failed to load agent {}: {}", agent_id, e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: format!("Failed to load agent with id {}", agent_id),
+                    error_code: Some("AGENT_LOAD_FAILED".to_string()),
+                });
+            }
+        };
+
+        match helpers::aggregate::run_aggregate_query(&tee_agent.dataset, &query, bounds) {
+            Ok(result) => (result, tee_agent.version),
+            Err(e) => {
+                warn!("Aggregate query failed: {}", e);
+                return HttpResponse::BadRequest().json(ErrorResponse {
+                    success: false,
+                    message: format!("Invalid aggregate query: {}", e),
+                    error_code: Some("INVALID_AGGREGATE_QUERY".to_string()),
+                });
+            }
+        }
+    };
+
+    // Reserve the epsilon before payment so a paid query can't find the budget exhausted
+    let budget = match &privacy_budget {
+        Some(budget) => {
+            match database::consume_privacy_budget(db, agent_id, budget.epsilon_per_query).await {
+                Ok(Some(budget)) => Some(budget),
+                Ok(None) => {
+                    return HttpResponse::Forbidden().json(ErrorResponse {
+                        success: false,
+                        message: format!("Privacy budget of agent {} is exhausted", agent_id),
+                        error_code: Some("PRIVACY_BUDGET_EXHAUSTED".to_string()),
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to consume privacy budget: {}", e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to consume privacy budget".to_string(),
+                        error_code: Some("PRIVACY_BUDGET_UPDATE_FAILED".to_string()),
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Verify payment using tx hash
+    let payment = helpers::agents::verif_selected_agents_payment(
+        &app_state,
+        &vec![agent_id],
+        &query.tx_hash,
+        None,
+        auth.as_ref(),
+    )
+    .await;
+
+    if !matches!(payment, Ok(Some(_))) {
+        // Nothing was released, the reserved epsilon goes back to the budget
+        if let Some(budget) = &budget
+            && let Err(e) =
+                database::release_privacy_budget(db, agent_id, budget.epsilon_per_query).await
+        {
+            error!(
+                "Failed to release privacy budget of agent {}: {}",
+                agent_id, e
+            );
+        }
+
+        if let Err(e) = &payment
+            && e.is::<helpers::agents::InvalidPayment>()
+        {
+            error!("Invalid payment {}: {}", query.tx_hash, e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: e.to_string(),
+                error_code: Some("INVALID_PAYMENT".to_string()),
+            });
+        }
+
+        if let Err(e) = payment {
+            error!("Failed to verify payment: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to verify payment".to_string(),
+                error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
+            });
+        }
+
+        return HttpResponse::PaymentRequired().json(ErrorResponse {
+            success: false,
+            message: "Payment verification failed".to_string(),
+            error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
+        });
+    }
+
+    let Some(budget) = budget else {
+        return HttpResponse::Ok().json(AggregateQueryResponse {
+            success: true,
+            agent_id,
+            function: query.function,
+            column: query.column,
+            value: result.value(query.function),
+            dataset_version,
+            noisy: false,
+            epsilon_spent: None,
+            epsilon_remaining: None,
+        });
+    };
+
+    let value = helpers::privacy::privatize_aggregate(
+        &result,
+        query.function,
+        budget.epsilon_per_query,
+        bounds,
+    );
+
+    HttpResponse::Ok().json(AggregateQueryResponse {
+        success: true,
+        agent_id,
+        function: query.function,
+        column: query.column,
+        value,
+        dataset_version,
+        noisy: true,
+        epsilon_spent: Some(budget.epsilon_per_query),
+        epsilon_remaining: Some(budget.epsilon_total - budget.epsilon_spent),
+    })
+}
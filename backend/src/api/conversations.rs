@@ -0,0 +1,136 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    database,
+    helpers::auth::SignedAddress,
+    state::AppState,
+    types::{ConversationResponse, ConversationsResponse, ErrorResponse},
+};
+
+/*
+Endpoint that lists the conversations of a user, most recently updated first.
+Follow-up questions are paid like any other question, one payment per message.
+*/
+#[utoipa::path(
+    get,
+    path = "/users/{address}/conversations",
+    params(
+        ("address" = String, Path, description = "User address")
+    ),
+    responses(
+        (status = 200, description = "Conversations fetched successfully", body = ConversationsResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the signer address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/users/{address}/conversations")]
+async fn get_conversations_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let address = path.into_inner();
+
+    if !auth.matches(&address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the owner of the address can list its conversations".to_string(),
+            error_code: Some("NOT_ADDRESS_OWNER".to_string()),
+        });
+    }
+
+    let conversations =
+        match database::get_conversations_by_user_address(&app_state.db, &address).await {
+            Ok(conversations) => conversations,
+            Err(e) => {
+                error!("Failed to get conversations: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get conversations from database".to_string(),
+                    error_code: Some("CONVERSATIONS_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    HttpResponse::Ok().json(ConversationsResponse {
+        success: true,
+        conversations,
+    })
+}
+
+/*
+Endpoint that returns a conversation with all its messages, to resume it by sending its id with the next question.
+*/
+#[utoipa::path(
+    get,
+    path = "/conversations/{id}",
+    params(
+        ("id" = i64, Path, description = "Conversation id")
+    ),
+    responses(
+        (status = 200, description = "Conversation fetched successfully", body = ConversationResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the conversation owner", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/conversations/{id}")]
+async fn get_conversation_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let conversation_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let conversation = match database::get_conversation_by_id(db, conversation_id).await {
+        Ok(Some(conversation)) => conversation,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Conversation with id {} not found", conversation_id),
+                error_code: Some("CONVERSATION_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get conversation: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get conversation from database".to_string(),
+                error_code: Some("CONVERSATION_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&conversation.user_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the conversation owner can read it".to_string(),
+            error_code: Some("NOT_CONVERSATION_OWNER".to_string()),
+        });
+    }
+
+    let messages = match database::get_messages_by_conversation_id(db, conversation_id).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Failed to get messages: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get messages from database".to_string(),
+                error_code: Some("MESSAGES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(ConversationResponse {
+        success: true,
+        conversation,
+        messages,
+    })
+}
@@ -0,0 +1,184 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    config::CREDIT_BALANCE_ENTRIES_LIMIT,
+    database,
+    helpers::auth::SignedAddress,
+    state::AppState,
+    types::{
+        CreditBalanceResponse, CreditSettlementsParams, CreditSettlementsResponse, ErrorResponse,
+        LedgerAccountKind, LedgerReconciliationResponse,
+    },
+};
+
+/*
+Endpoint that returns the prepaid credits of a user with their latest movements.
+Credits are deposited on-chain with depositCredits and spent by answers sent with pay_with_credits.
+*/
+#[utoipa::path(
+    get,
+    path = "/users/{address}/credits",
+    params(
+        ("address" = String, Path, description = "User address")
+    ),
+    responses(
+        (status = 200, description = "Credits fetched successfully", body = CreditBalanceResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the signer address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/users/{address}/credits")]
+async fn get_credits_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let address = path.into_inner();
+
+    if !auth.matches(&address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the owner of the address can see its credits".to_string(),
+            error_code: Some("NOT_ADDRESS_OWNER".to_string()),
+        });
+    }
+
+    // Credit accounts are keyed by the checksummed address
+    let owner_key = auth.address.to_string();
+
+    let balance = match database::get_ledger_account_balance(
+        &app_state.db,
+        LedgerAccountKind::UserCredit,
+        &owner_key,
+    )
+    .await
+    {
+        Ok(balance) => balance,
+        Err(e) => {
+            error!("Failed to get credits balance: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get credits from database".to_string(),
+                error_code: Some("CREDITS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let entries = match database::get_ledger_entries_by_account(
+        &app_state.db,
+        LedgerAccountKind::UserCredit,
+        &owner_key,
+        CREDIT_BALANCE_ENTRIES_LIMIT,
+    )
+    .await
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to get credits entries: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get credits from database".to_string(),
+                error_code: Some("CREDITS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(CreditBalanceResponse {
+        success: true,
+        address: owner_key,
+        balance,
+        entries,
+    })
+}
+
+/*
+Endpoint that lists the agents revenue from credits to settle on-chain.
+Pending settlements are paid by the contract owner with settleCredits(tokenIds, amounts),
+they are marked settled once the CreditsSettled events are indexed.
+*/
+#[utoipa::path(
+    get,
+    path = "/ledger/settlements",
+    params(
+        ("status" = Option<String>, Query, description = "pending or settled, all settlements when missing")
+    ),
+    responses(
+        (status = 200, description = "Settlements fetched successfully", body = CreditSettlementsResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Credits"
+)]
+#[get("/ledger/settlements")]
+async fn get_credit_settlements_service(
+    app_state: web::Data<AppState>,
+    query: web::Query<CreditSettlementsParams>,
+) -> impl Responder {
+    let status = query.status.as_deref();
+
+    if let Some(status) = status
+        && status != "pending"
+        && status != "settled"
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Invalid settlement status: {}", status),
+            error_code: Some("INVALID_SETTLEMENT_STATUS".to_string()),
+        });
+    }
+
+    match database::get_credit_settlements(&app_state.db, status).await {
+        Ok(settlements) => HttpResponse::Ok().json(CreditSettlementsResponse {
+            success: true,
+            settlements,
+        }),
+        Err(e) => {
+            error!("Failed to get credit settlements: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get credit settlements from database".to_string(),
+                error_code: Some("SETTLEMENTS_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint that checks the ledger: every transaction balances, every account balance matches its entries,
+and the credited deposits and booked settlements match the indexed on-chain events.
+*/
+#[utoipa::path(
+    get,
+    path = "/ledger/reconciliation",
+    responses(
+        (status = 200, description = "Reconciliation computed successfully", body = LedgerReconciliationResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Credits"
+)]
+#[get("/ledger/reconciliation")]
+async fn get_ledger_reconciliation_service(app_state: web::Data<AppState>) -> impl Responder {
+    match database::get_ledger_reconciliation(&app_state.db).await {
+        Ok(reconciliation) => {
+            if !reconciliation.reconciled {
+                tracing::warn!("Ledger is not reconciled: {:?}", reconciliation);
+            }
+
+            HttpResponse::Ok().json(LedgerReconciliationResponse {
+                success: true,
+                reconciliation,
+            })
+        }
+        Err(e) => {
+            error!("Failed to reconcile ledger: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to reconcile ledger".to_string(),
+                error_code: Some("RECONCILIATION_FAILED".to_string()),
+            })
+        }
+    }
+}
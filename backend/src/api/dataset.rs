@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use actix_multipart::Multipart;
 use actix_web::{HttpResponse, Responder, post, web};
@@ -8,13 +8,14 @@ use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    config::UPLOAD_DIR,
+    config::{DEFAULT_DP_EPSILON_PER_QUERY, UPLOAD_DIR},
     database,
-    helpers::{self, agents::init_ai_agent_with_dataset},
+    helpers::{self, agents::init_ai_agent_with_dataset, pii::PiiPolicies},
     state::AppState,
     types::{
-        AgentCategory, DatasetDetailsGenerateRequest, DatasetDetailsGenerateResponse,
-        DatasetMetadata, DatasetUploadRequest, DatasetUploadResponse, ErrorResponse, UserDb,
+        AgentCategory, ColumnDataType, DatasetDetailsGenerateRequest,
+        DatasetDetailsGenerateResponse, DatasetMetadata, DatasetUploadRequest,
+        DatasetUploadResponse, DatasetVersionMode, ErrorResponse, PiiPolicy, Price, UserDb,
     },
 };
 
@@ -131,6 +132,19 @@ async fn generate_dataset_details_service(
         }
     };
 
+    // Mask PII before sending the dataset to the model
+    let file_bytes = match helpers::pii::scan_and_redact_csv(&file_bytes, &PiiPolicies::default()) {
+        Ok(sanitized) => sanitized.data,
+        Err(e) => {
+            warn!("PII scan failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to scan CSV for PII: {}", e),
+                error_code: Some("PII_SCAN_FAILED".to_string()),
+            });
+        }
+    };
+
     // Convert the Csv to plain Text
     let csv_text = match helpers::csv::csv_bytes_to_string(&file_bytes).await {
         Ok(text) => text,
@@ -173,7 +187,7 @@ async fn generate_dataset_details_service(
     request_body(
         content = DatasetUploadRequest,
         content_type = "multipart/form-data",
-        description = "Upload your dataset with metadata. Send the CSV file as 'file' and individual metadata fields: user_address, dataset_price, description, and name."
+        description = "Upload your dataset with metadata. Send the CSV file as 'file' and individual metadata fields: user_address, dataset_price, description, name, category and optionally pii_policy, dp_epsilon_budget, dp_epsilon_per_query and dp_column_bounds (like amount=0:1000,age=0:120)."
     ),
     responses(
         (status = 200, description = "Dataset uploaded successfully", body = DatasetUploadResponse),
@@ -202,10 +216,14 @@ pub async fn upload_dataset_service(
 
     let mut file_data: Option<(String, Vec<u8>, u64)> = None; // (filename, data, size)
     let mut user_address: Option<String> = None;
-    let mut dataset_price: Option<f64> = None;
+    let mut dataset_price: Option<Price> = None;
     let mut description: Option<String> = None;
     let mut name: Option<String> = None;
     let mut category: Option<AgentCategory> = None;
+    let mut pii_policies = PiiPolicies::default();
+    let mut dp_epsilon_budget: Option<f64> = None;
+    let mut dp_epsilon_per_query: Option<f64> = None;
+    let mut dp_column_bounds: HashMap<String, (f64, f64)> = HashMap::new();
 
     while let Some(mut field) = payload.try_next().await.unwrap_or(None) {
         let field_name = field.name().unwrap_or("").to_string();
@@ -264,12 +282,12 @@ pub async fn upload_dataset_service(
                     field_bytes.extend_from_slice(&chunk);
                 }
                 let price_str = String::from_utf8_lossy(&field_bytes);
-                dataset_price = match price_str.parse::<f64>() {
-                    Ok(price) => Some(price),
-                    Err(_) => {
+                dataset_price = match price_str.parse::<Price>() {
+                    Ok(price) if price > Price::ZERO => Some(price),
+                    _ => {
                         return HttpResponse::BadRequest().json(ErrorResponse {
                             success: false,
-                            message: "Invalid dataset_price. Must be a number (1 or 2)".to_string(),
+                            message: "Invalid dataset_price. Must be a positive number with up to 8 decimals (1 or 2.5)".to_string(),
                             error_code: Some("INVALID_DATASET_PRICE_FORMAT".to_string()),
                         });
                     }
@@ -308,6 +326,65 @@ pub async fn upload_dataset_service(
                     }
                 };
             }
+            "pii_policy" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                pii_policies = match PiiPolicies::parse(&String::from_utf8_lossy(&field_bytes)) {
+                    Ok(policies) => policies,
+                    Err(e) => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!("Invalid pii_policy: {}", e),
+                            error_code: Some("INVALID_PII_POLICY".to_string()),
+                        });
+                    }
+                };
+            }
+            "dp_epsilon_budget" | "dp_epsilon_per_query" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                let epsilon = match String::from_utf8_lossy(&field_bytes).trim().parse::<f64>() {
+                    Ok(epsilon) if epsilon > 0.0 => epsilon,
+                    _ => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!("Invalid {}. Must be a positive number", field_name),
+                            error_code: Some("INVALID_DP_EPSILON".to_string()),
+                        });
+                    }
+                };
+
+                if field_name == "dp_epsilon_budget" {
+                    dp_epsilon_budget = Some(epsilon);
+                } else {
+                    dp_epsilon_per_query = Some(epsilon);
+                }
+            }
+            "dp_column_bounds" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                dp_column_bounds = match helpers::privacy::parse_column_bounds(
+                    &String::from_utf8_lossy(&field_bytes),
+                ) {
+                    Ok(bounds) => bounds,
+                    Err(e) => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!("Invalid dp_column_bounds: {}", e),
+                            error_code: Some("INVALID_DP_COLUMN_BOUNDS".to_string()),
+                        });
+                    }
+                };
+            }
             _ => {
                 // Skip unknown fields
                 while let Some(_chunk) = field.try_next().await.unwrap_or(None) {
@@ -385,6 +462,16 @@ pub async fn upload_dataset_service(
         }
     };
 
+    if let (Some(budget), Some(per_query)) = (dp_epsilon_budget, dp_epsilon_per_query)
+        && per_query > budget
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "dp_epsilon_per_query can't be greater than dp_epsilon_budget".to_string(),
+            error_code: Some("INVALID_DP_EPSILON".to_string()),
+        });
+    }
+
     // Create metadata object
     let metadata = DatasetMetadata {
         user_address: user_address.clone(),
@@ -407,6 +494,82 @@ pub async fn upload_dataset_service(
         }
     };
 
+    // Detect PII and apply the owner policies before the data reaches the agent
+    let sanitized = match helpers::pii::scan_and_redact_csv(&file_bytes, &pii_policies) {
+        Ok(sanitized) => sanitized,
+        Err(e) => {
+            warn!("PII scan failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to scan CSV for PII: {}", e),
+                error_code: Some("PII_SCAN_FAILED".to_string()),
+            });
+        }
+    };
+
+    if sanitized.report.rejected {
+        let rejected_columns = sanitized
+            .report
+            .findings
+            .iter()
+            .filter(|finding| finding.action == PiiPolicy::Reject)
+            .map(|finding| format!("{} ({})", finding.column, finding.kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Dataset rejected, PII found in: {}", rejected_columns),
+            error_code: Some("PII_DETECTED".to_string()),
+        });
+    }
+
+    let pii_report = sanitized.report;
+    let file_bytes = sanitized.data;
+    // Size of the stored dataset once sanitized
+    let dataset_size = file_bytes.len() as u64;
+
+    // Infer the columns profile of the dataset
+    let columns = match helpers::csv::profile_csv_columns(&file_bytes) {
+        Ok(columns) => columns,
+        Err(e) => {
+            warn!("CSV profiling failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to profile CSV columns: {}", e),
+                error_code: Some("CSV_PROFILING_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Bounds are only declared on numeric columns of datasets in privacy mode
+    if !dp_column_bounds.is_empty() && dp_epsilon_budget.is_none() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "dp_column_bounds requires dp_epsilon_budget".to_string(),
+            error_code: Some("INVALID_DP_COLUMN_BOUNDS".to_string()),
+        });
+    }
+
+    if let Some(column_name) = dp_column_bounds.keys().find(|column_name| {
+        !columns.iter().any(|column| {
+            &column.name == *column_name
+                && matches!(
+                    column.data_type,
+                    ColumnDataType::Integer | ColumnDataType::Float
+                )
+        })
+    }) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "dp_column_bounds column {} is not a numeric column",
+                column_name
+            ),
+            error_code: Some("INVALID_DP_COLUMN_BOUNDS".to_string()),
+        });
+    }
+
     // Generate unique file ID and save file
     let file_id = Uuid::new_v4().to_string();
     let file_extension = Path::new(&filename)
@@ -520,7 +683,7 @@ pub async fn upload_dataset_service(
         user.id,
         &dataset_path,
         &category,
-        file_size as f64,
+        dataset_size as f64,
     )
     .await
     {
@@ -538,6 +701,77 @@ pub async fn upload_dataset_service(
         }
     };
 
+    // Save the columns profile of the dataset
+    if let Err(e) = database::insert_dataset_columns(&mut tx, agent_db.id, &columns).await {
+        error!("Failed to insert dataset columns: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to insert dataset columns".to_string(),
+            error_code: Some("DATASET_COLUMNS_INSERT_FAILED".to_string()),
+        });
+    }
+
+    // Register the uploaded file as the first version of the dataset
+    if let Err(e) = database::insert_dataset_version(
+        &mut tx,
+        agent_db.id,
+        agent_db.current_version,
+        &agent_db.dataset_path,
+        agent_db.dataset_size,
+        row_count as i64,
+        DatasetVersionMode::Initial,
+    )
+    .await
+    {
+        error!("Failed to insert dataset version: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to insert dataset version".to_string(),
+            error_code: Some("DATASET_VERSION_INSERT_FAILED".to_string()),
+        });
+    }
+
+    // Opt the dataset in to differential privacy when the owner set a budget
+    if let Some(epsilon_total) = dp_epsilon_budget {
+        let epsilon_per_query = dp_epsilon_per_query.unwrap_or(DEFAULT_DP_EPSILON_PER_QUERY);
+
+        if let Err(e) =
+            database::insert_privacy_budget(&mut tx, agent_db.id, epsilon_total, epsilon_per_query)
+                .await
+        {
+            error!("Failed to insert privacy budget: {}", e);
+
+            tx.rollback().await.ok(); // Rollback transaction on error
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to insert privacy budget".to_string(),
+                error_code: Some("PRIVACY_BUDGET_INSERT_FAILED".to_string()),
+            });
+        }
+
+        if !dp_column_bounds.is_empty()
+            && let Err(e) =
+                database::insert_privacy_bounds(&mut tx, agent_db.id, &dp_column_bounds).await
+        {
+            error!("Failed to insert privacy bounds: {}", e);
+
+            tx.rollback().await.ok(); // Rollback transaction on error
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to insert privacy bounds".to_string(),
+                error_code: Some("PRIVACY_BOUNDS_INSERT_FAILED".to_string()),
+            });
+        }
+    }
+
     // Implement training new ai agent using rag with gemini using rig-core
     if let Err(e) = init_ai_agent_with_dataset(&user, &agent_db, &filepath, &app_state).await {
         error!("Failed to initialize AI agent with dataset: {}", e);
@@ -558,6 +792,10 @@ pub async fn upload_dataset_service(
         });
     }
 
+    // Searches and routing compare the prompt with this embedding
+    helpers::embeddings::refresh_agent_embedding(&app_state.db, &app_state.embedder, &agent_db)
+        .await;
+
     HttpResponse::Ok().json(DatasetUploadResponse {
         success: true,
         message: "Dataset uploaded and AI agent initialized successfully".to_string(),
@@ -567,5 +805,7 @@ pub async fn upload_dataset_service(
         row_count: Some(row_count),
         metadata: Some(metadata),
         dataset_id: agent_db.id,
+        columns,
+        pii_report,
     })
 }
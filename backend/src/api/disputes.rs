@@ -0,0 +1,833 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+use chrono::{Duration, Utc};
+use tracing::{error, info};
+
+use crate::{
+    config::{APP_CONFIG, DISPUTE_WINDOW_DAYS, MAX_DISPUTE_TEXT_CHARS},
+    database,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    types::{
+        CreateDisputeRequest, DisputeDb, DisputeOwnerResponseRequest, DisputeResponse,
+        DisputeStatus, DisputesResponse, ErrorResponse, Price, RefundEntitlementDb,
+        ResolveDisputeRequest,
+    },
+};
+
+/*
+Endpoint for users to dispute an answer they paid for, within DISPUTE_WINDOW_DAYS of it.
+The stored prompt and answer are the evidence, the agent owner can then accept the refund or contest it.
+Agents that failed are refunded automatically and can't be disputed.
+*/
+#[utoipa::path(
+    post,
+    path = "/disputes",
+    request_body(
+        content = CreateDisputeRequest,
+        content_type = "application/json",
+        description = "Disputed answer and why. Requires the payer signature headers."
+    ),
+    responses(
+        (status = 200, description = "Dispute opened successfully", body = DisputeResponse),
+        (status = 400, description = "Bad request - invalid parameters or dispute window closed", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the payer of the answer", body = ErrorResponse),
+        (status = 404, description = "Answer not found", body = ErrorResponse),
+        (status = 409, description = "Answer already refunded or disputed", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Disputes"
+)]
+#[post("/disputes")]
+async fn create_dispute_service(
+    app_state: web::Data<AppState>,
+    auth: SignedAddress,
+    body: web::Json<CreateDisputeRequest>,
+) -> impl Responder {
+    let description = body.description.trim();
+
+    if description.is_empty() || description.chars().count() > MAX_DISPUTE_TEXT_CHARS {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Dispute description must be between 1 and {} characters",
+                MAX_DISPUTE_TEXT_CHARS
+            ),
+            error_code: Some("INVALID_DISPUTE_DESCRIPTION".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let answer = match database::get_agent_answer(db, &body.payment_reference, body.agent_id).await
+    {
+        Ok(Some(answer)) => answer,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "No answer of agent {} paid by {}",
+                    body.agent_id, body.payment_reference
+                ),
+                error_code: Some("ANSWER_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get answer: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get answer from database".to_string(),
+                error_code: Some("ANSWER_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let payer =
+        match database::get_agent_usage_payer(db, &answer.payment_reference, answer.agent_id).await
+        {
+            Ok(payer) => payer,
+            Err(e) => {
+                error!("Failed to get answer payer: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get answer payer from database".to_string(),
+                    error_code: Some("ANSWER_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    let amount = match payer {
+        Some((payer, amount)) if auth.matches(&payer) => amount,
+        _ => {
+            return HttpResponse::Forbidden().json(ErrorResponse {
+                success: false,
+                message: "Only the payer of the answer can dispute it".to_string(),
+                error_code: Some("NOT_ANSWER_PAYER".to_string()),
+            });
+        }
+    };
+
+    if amount == Price::ZERO {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "The answer was free and has nothing to refund".to_string(),
+            error_code: Some("NOTHING_TO_REFUND".to_string()),
+        });
+    }
+
+    if answer.created_at + Duration::days(DISPUTE_WINDOW_DAYS) < Utc::now() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Answers can only be disputed within {} days",
+                DISPUTE_WINDOW_DAYS
+            ),
+            error_code: Some("DISPUTE_WINDOW_CLOSED".to_string()),
+        });
+    }
+
+    match database::get_refund_entitlement(db, &answer.payment_reference, answer.agent_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Conflict().json(ErrorResponse {
+                success: false,
+                message: "The answer is already refunded".to_string(),
+                error_code: Some("ALREADY_REFUNDED".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get refund entitlement: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get refunds from database".to_string(),
+                error_code: Some("REFUND_FETCH_FAILED".to_string()),
+            });
+        }
+    }
+
+    let dispute = match database::insert_dispute(
+        db,
+        &answer,
+        &auth.address.to_string(),
+        body.category,
+        description,
+    )
+    .await
+    {
+        Ok(dispute) => dispute,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return HttpResponse::Conflict().json(ErrorResponse {
+                success: false,
+                message: "The answer is already disputed".to_string(),
+                error_code: Some("DISPUTE_ALREADY_EXISTS".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to open dispute: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to open dispute".to_string(),
+                error_code: Some("DISPUTE_CREATE_FAILED".to_string()),
+            });
+        }
+    };
+
+    info!(
+        "Dispute {} opened by {} on agent {} for {}",
+        dispute.id, dispute.user_address, dispute.agent_id, answer.payment_reference
+    );
+
+    HttpResponse::Ok().json(DisputeResponse {
+        success: true,
+        dispute,
+        evidence: answer,
+        refund_entitlement: None,
+    })
+}
+
+/*
+Endpoint that returns a dispute with its evidence and refund.
+Only the payer, the agent owner and the dispute arbiter can see it.
+*/
+#[utoipa::path(
+    get,
+    path = "/disputes/{id}",
+    params(
+        ("id" = i64, Path, description = "Dispute id")
+    ),
+    responses(
+        (status = 200, description = "Dispute fetched successfully", body = DisputeResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not a party of the dispute", body = ErrorResponse),
+        (status = 404, description = "Dispute not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Disputes"
+)]
+#[get("/disputes/{id}")]
+async fn get_dispute_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let dispute = match fetch_dispute(&app_state, path.into_inner()).await {
+        Ok(dispute) => dispute,
+        Err(response) => return response,
+    };
+
+    let owner_address = match fetch_owner_address(&app_state, dispute.agent_id).await {
+        Ok(owner_address) => owner_address,
+        Err(response) => return response,
+    };
+
+    if !auth.matches(&dispute.user_address) && !auth.matches(&owner_address) && !is_arbiter(&auth) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the parties of the dispute can see it".to_string(),
+            error_code: Some("NOT_DISPUTE_PARTY".to_string()),
+        });
+    }
+
+    let refund_entitlement = match dispute.refund_entitlement_id {
+        Some(id) => match database::get_refund_entitlement_by_id(&app_state.db, id).await {
+            Ok(refund_entitlement) => Some(refund_entitlement),
+            Err(e) => {
+                error!("Failed to get refund entitlement: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get refunds from database".to_string(),
+                    error_code: Some("REFUND_FETCH_FAILED".to_string()),
+                });
+            }
+        },
+        None => None,
+    };
+
+    dispute_response(&app_state, dispute, refund_entitlement).await
+}
+
+/*
+Endpoint that lists the disputes raised by an address and the disputes on the agents it owns, most recent first
+*/
+#[utoipa::path(
+    get,
+    path = "/users/{address}/disputes",
+    params(
+        ("address" = String, Path, description = "User address")
+    ),
+    responses(
+        (status = 200, description = "Disputes fetched successfully", body = DisputesResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the signer address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Disputes"
+)]
+#[get("/users/{address}/disputes")]
+async fn get_user_disputes_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let address = path.into_inner();
+
+    if !auth.matches(&address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the owner of the address can see its disputes".to_string(),
+            error_code: Some("NOT_ADDRESS_OWNER".to_string()),
+        });
+    }
+
+    match database::get_disputes_by_address(&app_state.db, &address).await {
+        Ok(disputes) => HttpResponse::Ok().json(DisputesResponse {
+            success: true,
+            disputes,
+        }),
+        Err(e) => {
+            error!("Failed to get disputes: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get disputes from database".to_string(),
+                error_code: Some("DISPUTES_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint for the agent owner to respond to an open dispute.
+Accepting the refund resolves the dispute and refunds the user, otherwise the dispute waits for the arbiter.
+*/
+#[utoipa::path(
+    post,
+    path = "/disputes/{id}/response",
+    params(
+        ("id" = i64, Path, description = "Dispute id")
+    ),
+    request_body(
+        content = DisputeOwnerResponseRequest,
+        content_type = "application/json",
+        description = "Response of the owner. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Response recorded successfully", body = DisputeResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Dispute not found", body = ErrorResponse),
+        (status = 409, description = "Dispute no longer open", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Disputes"
+)]
+#[post("/disputes/{id}/response")]
+async fn respond_dispute_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<DisputeOwnerResponseRequest>,
+) -> impl Responder {
+    let owner_response = body.response.trim();
+
+    if owner_response.is_empty() || owner_response.chars().count() > MAX_DISPUTE_TEXT_CHARS {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Response must be between 1 and {} characters",
+                MAX_DISPUTE_TEXT_CHARS
+            ),
+            error_code: Some("INVALID_DISPUTE_RESPONSE".to_string()),
+        });
+    }
+
+    let dispute = match fetch_dispute(&app_state, path.into_inner()).await {
+        Ok(dispute) => dispute,
+        Err(response) => return response,
+    };
+
+    let owner_address = match fetch_owner_address(&app_state, dispute.agent_id).await {
+        Ok(owner_address) => owner_address,
+        Err(response) => return response,
+    };
+
+    if !auth.matches(&owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can respond to the dispute".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    if body.accept_refund {
+        return match resolve(
+            &app_state,
+            &dispute,
+            true,
+            &auth.address.to_string(),
+            Some(owner_response),
+            None,
+        )
+        .await
+        {
+            Ok((dispute, refund_entitlement)) => {
+                dispute_response(&app_state, dispute, refund_entitlement).await
+            }
+            Err(response) => response,
+        };
+    }
+
+    match database::update_dispute_owner_response(&app_state.db, dispute.id, owner_response).await {
+        Ok(Some(dispute)) => dispute_response(&app_state, dispute, None).await,
+        Ok(None) => HttpResponse::Conflict().json(ErrorResponse {
+            success: false,
+            message: format!("Dispute {} is no longer open", dispute.id),
+            error_code: Some("DISPUTE_NOT_OPEN".to_string()),
+        }),
+        Err(e) => {
+            error!("Failed to record dispute response: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record dispute response".to_string(),
+                error_code: Some("DISPUTE_UPDATE_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint for the dispute arbiter (DISPUTE_ARBITER_ADDRESS) to resolve a dispute, refunding the user or rejecting it
+*/
+#[utoipa::path(
+    post,
+    path = "/disputes/{id}/resolve",
+    params(
+        ("id" = i64, Path, description = "Dispute id")
+    ),
+    request_body(
+        content = ResolveDisputeRequest,
+        content_type = "application/json",
+        description = "Resolution of the dispute. Requires the arbiter signature headers."
+    ),
+    responses(
+        (status = 200, description = "Dispute resolved successfully", body = DisputeResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the dispute arbiter", body = ErrorResponse),
+        (status = 404, description = "Dispute not found", body = ErrorResponse),
+        (status = 409, description = "Dispute already resolved", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Disputes"
+)]
+#[post("/disputes/{id}/resolve")]
+async fn resolve_dispute_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<ResolveDisputeRequest>,
+) -> impl Responder {
+    if !is_arbiter(&auth) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the dispute arbiter can resolve disputes".to_string(),
+            error_code: Some("NOT_DISPUTE_ARBITER".to_string()),
+        });
+    }
+
+    let note = body.note.as_deref().map(str::trim);
+
+    if note.is_some_and(|note| note.chars().count() > MAX_DISPUTE_TEXT_CHARS) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Resolution note must be at most {} characters",
+                MAX_DISPUTE_TEXT_CHARS
+            ),
+            error_code: Some("INVALID_RESOLUTION_NOTE".to_string()),
+        });
+    }
+
+    let dispute = match fetch_dispute(&app_state, path.into_inner()).await {
+        Ok(dispute) => dispute,
+        Err(response) => return response,
+    };
+
+    match resolve(
+        &app_state,
+        &dispute,
+        body.refund,
+        &auth.address.to_string(),
+        None,
+        note,
+    )
+    .await
+    {
+        Ok((dispute, refund_entitlement)) => {
+            dispute_response(&app_state, dispute, refund_entitlement).await
+        }
+        Err(response) => response,
+    }
+}
+
+fn is_arbiter(auth: &SignedAddress) -> bool {
+    APP_CONFIG
+        .dispute_arbiter_address
+        .as_deref()
+        .is_some_and(|arbiter| auth.matches(arbiter))
+}
+
+async fn fetch_dispute(
+    app_state: &web::Data<AppState>,
+    dispute_id: i64,
+) -> Result<DisputeDb, HttpResponse> {
+    match database::get_dispute_by_id(&app_state.db, dispute_id).await {
+        Ok(dispute) => Ok(dispute),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json(ErrorResponse {
+            success: false,
+            message: format!("Dispute with id {} not found", dispute_id),
+            error_code: Some("DISPUTE_NOT_FOUND".to_string()),
+        })),
+        Err(e) => {
+            error!("Failed to get dispute: {}", e);
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get dispute from database".to_string(),
+                error_code: Some("DISPUTE_FETCH_FAILED".to_string()),
+            }))
+        }
+    }
+}
+
+async fn fetch_owner_address(
+    app_state: &web::Data<AppState>,
+    agent_id: i64,
+) -> Result<String, HttpResponse> {
+    match database::get_agent_by_id(&app_state.db, agent_id).await {
+        Ok(agent_db) => Ok(agent_db.owner_address),
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            }))
+        }
+    }
+}
+
+/// Closes a dispute, refunding the price charged for the disputed answer when `refund` is set.
+/// The refund is executed right away, it is left to the refund executor when that fails.
+async fn resolve(
+    app_state: &web::Data<AppState>,
+    dispute: &DisputeDb,
+    refund: bool,
+    resolved_by: &str,
+    owner_response: Option<&str>,
+    resolution_note: Option<&str>,
+) -> Result<(DisputeDb, Option<RefundEntitlementDb>), HttpResponse> {
+    let db = &app_state.db;
+
+    let answer = match database::get_agent_answer_by_id(db, dispute.answer_id).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            error!("Failed to get disputed answer: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get answer from database".to_string(),
+                error_code: Some("ANSWER_FETCH_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let amount =
+        match database::get_agent_usage_payer(db, &answer.payment_reference, answer.agent_id).await
+        {
+            Ok(Some((_, amount))) => amount,
+            Ok(None) => Price::ZERO,
+            Err(e) => {
+                error!("Failed to get answer payer: {}", e);
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get answer payer from database".to_string(),
+                    error_code: Some("ANSWER_FETCH_FAILED".to_string()),
+                }));
+            }
+        };
+
+    let status = if refund {
+        DisputeStatus::Refunded
+    } else {
+        DisputeStatus::Rejected
+    };
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let mut dispute = match database::resolve_dispute(
+        &mut tx,
+        dispute.id,
+        status,
+        resolved_by,
+        owner_response,
+        resolution_note,
+    )
+    .await
+    {
+        Ok(Some(dispute)) => dispute,
+        Ok(None) => {
+            return Err(HttpResponse::Conflict().json(ErrorResponse {
+                success: false,
+                message: format!("Dispute {} is already resolved", dispute.id),
+                error_code: Some("DISPUTE_ALREADY_RESOLVED".to_string()),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to resolve dispute: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to resolve dispute".to_string(),
+                error_code: Some("DISPUTE_UPDATE_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let refund_entitlement = if refund && amount != Price::ZERO {
+        match database::insert_dispute_refund_entitlement(
+            &mut tx,
+            &dispute,
+            &answer.payment_reference,
+            amount,
+        )
+        .await
+        {
+            Ok(refund_entitlement) => {
+                dispute.refund_entitlement_id = Some(refund_entitlement.id);
+                Some(refund_entitlement)
+            }
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(HttpResponse::Conflict().json(ErrorResponse {
+                    success: false,
+                    message: "The answer is already refunded".to_string(),
+                    error_code: Some("ALREADY_REFUNDED".to_string()),
+                }));
+            }
+            Err(e) => {
+                error!("Failed to record dispute refund: {}", e);
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to record dispute refund".to_string(),
+                    error_code: Some("REFUND_CREATE_FAILED".to_string()),
+                }));
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        }));
+    }
+
+    info!(
+        "Dispute {} resolved as {} by {}",
+        dispute.id, dispute.status, resolved_by
+    );
+
+    let refund_entitlement = match refund_entitlement {
+        Some(refund_entitlement) => {
+            match helpers::refunds::execute_refund(app_state, &refund_entitlement).await {
+                Ok(executed) => Some(executed),
+                Err(e) => {
+                    error!(
+                        "Failed to execute refund {} of dispute {}: {:?}",
+                        refund_entitlement.id, dispute.id, e
+                    );
+                    Some(refund_entitlement)
+                }
+            }
+        }
+        None => None,
+    };
+
+    Ok((dispute, refund_entitlement))
+}
+
+/// Response with the dispute and its evidence
+async fn dispute_response(
+    app_state: &web::Data<AppState>,
+    dispute: DisputeDb,
+    refund_entitlement: Option<RefundEntitlementDb>,
+) -> HttpResponse {
+    match database::get_agent_answer_by_id(&app_state.db, dispute.answer_id).await {
+        Ok(evidence) => HttpResponse::Ok().json(DisputeResponse {
+            success: true,
+            dispute,
+            evidence,
+            refund_entitlement,
+        }),
+        Err(e) => {
+            error!("Failed to get disputed answer: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get answer from database".to_string(),
+                error_code: Some("ANSWER_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::helpers::auth::{hash_signed_body, signed_test_request};
+
+    /// Agent of `owner` with a successful answer paid 100 units by `payer` under each of `payment_references`
+    async fn insert_paid_answers(
+        db: &sqlx::Pool<sqlx::Postgres>,
+        owner: &PrivateKeySigner,
+        payer: &PrivateKeySigner,
+        payment_references: &[&str],
+    ) -> i64 {
+        let agent_id: i64 = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(owner.address().to_string())
+        .fetch_one(db)
+        .await
+        .unwrap();
+
+        sqlx::query(
+            r#"
+            WITH answers AS (
+                INSERT INTO agent_answers (agent_id, payment_reference, prompt, response, status, dataset_version)
+                SELECT $1, reference, 'Sales in Paris?', '10', 'success', 1 FROM unnest($3::VARCHAR[]) AS reference
+                RETURNING payment_reference
+            )
+            INSERT INTO agent_usage (agent_id, user_address, payment_reference, basis, amount)
+            SELECT $1, $2, payment_reference, 'per_query', 100 FROM answers
+            "#,
+        )
+        .bind(agent_id)
+        .bind(payer.address().to_string())
+        .bind(payment_references)
+        .execute(db)
+        .await
+        .unwrap();
+
+        agent_id
+    }
+
+    #[sqlx::test]
+    async fn disputes_move_from_open_to_contested_or_refunded(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let payer = PrivateKeySigner::random();
+        let stranger = PrivateKeySigner::random();
+        let agent_id =
+            insert_paid_answers(&db, &owner, &payer, &["0xcontested", "0xaccepted"]).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(create_dispute_service)
+                .service(respond_dispute_service)
+                .service(resolve_dispute_service),
+        )
+        .await;
+
+        let open = |signer: &PrivateKeySigner, payment_reference: &str| {
+            let body = serde_json::json!({
+                "payment_reference": payment_reference,
+                "agent_id": agent_id,
+                "category": "incorrect_answer",
+                "description": "Paris sold 12, not 10",
+            })
+            .to_string();
+            signed_test_request(signer, Method::POST, "/disputes", &body).to_request()
+        };
+        let respond = |signer: &PrivateKeySigner, dispute_id: i64, accept_refund: bool| {
+            let body = serde_json::json!({
+                "response": "The dataset says 10",
+                "accept_refund": accept_refund,
+            })
+            .to_string();
+            let uri = format!("/disputes/{}/response", dispute_id);
+            signed_test_request(signer, Method::POST, &uri, &body).to_request()
+        };
+
+        let response = test::call_service(&app, open(&stranger, "0xcontested")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let contested: serde_json::Value =
+            test::call_and_read_body_json(&app, open(&payer, "0xcontested")).await;
+        assert_eq!(contested["dispute"]["status"], "open");
+        assert_eq!(contested["evidence"]["response"], "10");
+        let contested_id = contested["dispute"]["id"].as_i64().unwrap();
+
+        let response = test::call_service(&app, open(&payer, "0xcontested")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = test::call_service(&app, respond(&payer, contested_id, true)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let contested: serde_json::Value =
+            test::call_and_read_body_json(&app, respond(&owner, contested_id, false)).await;
+        assert_eq!(contested["dispute"]["status"], "owner_responded");
+        assert!(contested["refund_entitlement"].is_null());
+
+        // A contested dispute waits for the arbiter
+        let response = test::call_service(&app, respond(&owner, contested_id, false)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let uri = format!("/disputes/{}/resolve", contested_id);
+        let response = test::call_service(
+            &app,
+            signed_test_request(&owner, Method::POST, &uri, r#"{"refund":false}"#).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let accepted: serde_json::Value =
+            test::call_and_read_body_json(&app, open(&payer, "0xaccepted")).await;
+        let accepted_id = accepted["dispute"]["id"].as_i64().unwrap();
+
+        let accepted: serde_json::Value =
+            test::call_and_read_body_json(&app, respond(&owner, accepted_id, true)).await;
+        assert_eq!(accepted["dispute"]["status"], "refunded");
+        assert_eq!(accepted["refund_entitlement"]["amount"], "0.000001");
+        assert_eq!(accepted["refund_entitlement"]["tx_hash"], "0xaccepted");
+
+        let response = test::call_service(&app, respond(&owner, accepted_id, true)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,467 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+use tracing::error;
+
+use crate::{
+    config::{APP_CONFIG, EVALUATION_RUNS_LIMIT, MAX_EVALUATION_CASES, MAX_EVALUATION_TEXT_CHARS},
+    database,
+    helpers::{
+        auth::SignedAddress,
+        evaluation::{self, EvaluationModel, extract_first_number},
+    },
+    state::AppState,
+    types::{
+        AgentDb, CreateEvaluationCasesRequest, ErrorResponse, EvaluationCasesResponse,
+        EvaluationMatchKind, EvaluationRunResponse, EvaluationRunsResponse, NewEvaluationCase,
+    },
+};
+
+/*
+Endpoint for the agent owner or the platform admin to add questions with their expected answer to an agent.
+They are answered by the agent on each evaluation run and scored with their match kind.
+*/
+#[utoipa::path(
+    post,
+    path = "/agents/{id}/evaluation-cases",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = CreateEvaluationCasesRequest,
+        content_type = "application/json",
+        description = "Questions, expected answers and how to match them. Requires the owner or platform admin signature headers."
+    ),
+    responses(
+        (status = 200, description = "Evaluation cases added successfully", body = EvaluationCasesResponse),
+        (status = 400, description = "Bad request - invalid cases or too many cases", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner or platform admin", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Evaluations"
+)]
+#[post("/agents/{id}/evaluation-cases")]
+async fn create_evaluation_cases_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<CreateEvaluationCasesRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let cases: Vec<NewEvaluationCase> = body
+        .into_inner()
+        .cases
+        .into_iter()
+        .map(|case| NewEvaluationCase {
+            question: case.question.trim().to_string(),
+            expected_answer: case.expected_answer.trim().to_string(),
+            ..case
+        })
+        .collect();
+
+    if cases.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "At least one evaluation case is required".to_string(),
+            error_code: Some("INVALID_EVALUATION_CASE".to_string()),
+        });
+    }
+
+    if let Some(message) = cases.iter().find_map(invalid_case_message) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message,
+            error_code: Some("INVALID_EVALUATION_CASE".to_string()),
+        });
+    }
+
+    if let Err(response) = fetch_evaluated_agent(&app_state, agent_id, &auth).await {
+        return response;
+    }
+
+    let db = &app_state.db;
+
+    let existing_cases = match database::get_evaluation_cases(db, agent_id).await {
+        Ok(existing_cases) => existing_cases,
+        Err(e) => {
+            error!("Failed to get evaluation cases: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation cases from database".to_string(),
+                error_code: Some("EVALUATION_CASES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if (existing_cases.len() + cases.len()) as i64 > MAX_EVALUATION_CASES {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "An agent can have at most {} evaluation cases, it already has {}",
+                MAX_EVALUATION_CASES,
+                existing_cases.len()
+            ),
+            error_code: Some("TOO_MANY_EVALUATION_CASES".to_string()),
+        });
+    }
+
+    match database::insert_evaluation_cases(db, agent_id, &auth.address.to_string(), &cases).await {
+        Ok(cases) => HttpResponse::Ok().json(EvaluationCasesResponse {
+            success: true,
+            cases,
+        }),
+        Err(e) => {
+            error!("Failed to insert evaluation cases: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to save evaluation cases in database".to_string(),
+                error_code: Some("EVALUATION_CASES_SAVE_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint for the agent owner or the platform admin to list the evaluation cases of an agent.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/evaluation-cases",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Evaluation cases fetched successfully", body = EvaluationCasesResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner or platform admin", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Evaluations"
+)]
+#[get("/agents/{id}/evaluation-cases")]
+async fn get_evaluation_cases_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    if let Err(response) = fetch_evaluated_agent(&app_state, agent_id, &auth).await {
+        return response;
+    }
+
+    match database::get_evaluation_cases(&app_state.db, agent_id).await {
+        Ok(cases) => HttpResponse::Ok().json(EvaluationCasesResponse {
+            success: true,
+            cases,
+        }),
+        Err(e) => {
+            error!("Failed to get evaluation cases: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation cases from database".to_string(),
+                error_code: Some("EVALUATION_CASES_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint for the agent owner or the platform admin to evaluate the agent on its current dataset version.
+The run is returned right away with the running status, the cases are answered and scored in the background.
+*/
+#[utoipa::path(
+    post,
+    path = "/agents/{id}/evaluations",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Evaluation run started", body = EvaluationRunResponse),
+        (status = 400, description = "Agent has no evaluation cases", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner or platform admin", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Evaluations"
+)]
+#[post("/agents/{id}/evaluations")]
+async fn start_evaluation_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let agent_db = match fetch_evaluated_agent(&app_state, agent_id, &auth).await {
+        Ok(agent_db) => agent_db,
+        Err(response) => return response,
+    };
+
+    let db = &app_state.db;
+
+    let cases = match database::get_evaluation_cases(db, agent_id).await {
+        Ok(cases) if cases.is_empty() => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Agent {} has no evaluation cases", agent_id),
+                error_code: Some("NO_EVALUATION_CASES".to_string()),
+            });
+        }
+        Ok(cases) => cases,
+        Err(e) => {
+            error!("Failed to get evaluation cases: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation cases from database".to_string(),
+                error_code: Some("EVALUATION_CASES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let model = match EvaluationModel::agent(&app_state, &agent_db).await {
+        Ok(model) => model,
+        Err(e) => {
+            error!("Failed to load agent {}: {:?}", agent_id, e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to load agent".to_string(),
+                error_code: Some("AGENT_LOAD_FAILED".to_string()),
+            });
+        }
+    };
+
+    let run = match database::insert_evaluation_run(
+        db,
+        agent_id,
+        model.dataset_version(&agent_db),
+        model.name(),
+        cases.len() as i32,
+        Some(&auth.address.to_string()),
+    )
+    .await
+    {
+        Ok(run) => run,
+        Err(e) => {
+            error!("Failed to insert evaluation run: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to save evaluation run in database".to_string(),
+                error_code: Some("EVALUATION_RUN_SAVE_FAILED".to_string()),
+            });
+        }
+    };
+
+    let response = EvaluationRunResponse {
+        success: true,
+        run: run.clone(),
+        results: Vec::new(),
+    };
+
+    // Answering every case takes longer than a request, the run is polled with its id
+    actix_web::rt::spawn(async move {
+        if let Err(e) =
+            evaluation::execute_evaluation_run(&app_state.db, &run, &cases, &model).await
+        {
+            error!("Evaluation run {} failed: {:?}", run.id, e);
+        }
+    });
+
+    HttpResponse::Ok().json(response)
+}
+
+/*
+Endpoint for the agent owner or the platform admin to list the latest evaluation runs of an agent,
+with their score per dataset version and model.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/evaluations",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Evaluation runs fetched successfully", body = EvaluationRunsResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner or platform admin", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Evaluations"
+)]
+#[get("/agents/{id}/evaluations")]
+async fn get_evaluation_runs_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    if let Err(response) = fetch_evaluated_agent(&app_state, agent_id, &auth).await {
+        return response;
+    }
+
+    match database::get_evaluation_runs(&app_state.db, agent_id, EVALUATION_RUNS_LIMIT).await {
+        Ok(runs) => HttpResponse::Ok().json(EvaluationRunsResponse {
+            success: true,
+            runs,
+        }),
+        Err(e) => {
+            error!("Failed to get evaluation runs: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation runs from database".to_string(),
+                error_code: Some("EVALUATION_RUNS_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint for the agent owner or the platform admin to get an evaluation run with the answer and score of each case.
+*/
+#[utoipa::path(
+    get,
+    path = "/evaluations/{id}",
+    params(
+        ("id" = i64, Path, description = "Evaluation run id")
+    ),
+    responses(
+        (status = 200, description = "Evaluation run fetched successfully", body = EvaluationRunResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner or platform admin", body = ErrorResponse),
+        (status = 404, description = "Evaluation run not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Evaluations"
+)]
+#[get("/evaluations/{id}")]
+async fn get_evaluation_run_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let run_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let run = match database::get_evaluation_run(db, run_id).await {
+        Ok(run) => run,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Evaluation run with id {} not found", run_id),
+                error_code: Some("EVALUATION_RUN_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get evaluation run: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation run from database".to_string(),
+                error_code: Some("EVALUATION_RUN_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(response) = fetch_evaluated_agent(&app_state, run.agent_id, &auth).await {
+        return response;
+    }
+
+    match database::get_evaluation_results(db, run_id).await {
+        Ok(results) => HttpResponse::Ok().json(EvaluationRunResponse {
+            success: true,
+            run,
+            results,
+        }),
+        Err(e) => {
+            error!("Failed to get evaluation results: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get evaluation results from database".to_string(),
+                error_code: Some("EVALUATION_RUN_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/// Why a case can't be evaluated, if it can't
+fn invalid_case_message(case: &NewEvaluationCase) -> Option<String> {
+    let too_long = |text: &str| text.chars().count() > MAX_EVALUATION_TEXT_CHARS;
+
+    if case.question.is_empty() || case.expected_answer.is_empty() {
+        return Some("Question and expected answer can't be empty".to_string());
+    }
+
+    if too_long(&case.question) || too_long(&case.expected_answer) {
+        return Some(format!(
+            "Question and expected answer must be at most {} characters",
+            MAX_EVALUATION_TEXT_CHARS
+        ));
+    }
+
+    match (case.match_kind, case.tolerance) {
+        (EvaluationMatchKind::Numeric, _)
+            if extract_first_number(&case.expected_answer).is_none() =>
+        {
+            Some(format!(
+                "Expected answer of a numeric case must contain a number: {}",
+                case.expected_answer
+            ))
+        }
+        (EvaluationMatchKind::Numeric, Some(tolerance))
+            if !tolerance.is_finite() || tolerance < 0.0 =>
+        {
+            Some("Tolerance must be a positive number".to_string())
+        }
+        (EvaluationMatchKind::Exact | EvaluationMatchKind::LlmJudge, Some(_)) => {
+            Some("Tolerance only applies to numeric cases".to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Agent whose evaluations `auth` can manage, as its owner or as the platform admin
+async fn fetch_evaluated_agent(
+    app_state: &web::Data<AppState>,
+    agent_id: i64,
+    auth: &SignedAddress,
+) -> Result<AgentDb, HttpResponse> {
+    let agent_db = match database::get_agent_by_id(&app_state.db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let is_platform_admin = APP_CONFIG
+        .platform_admin_address
+        .as_deref()
+        .is_some_and(|admin| auth.matches(admin));
+
+    if !auth.matches(&agent_db.owner_address) && !is_platform_admin {
+        return Err(HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner or the platform admin can manage its evaluations"
+                .to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        }));
+    }
+
+    Ok(agent_db)
+}
@@ -0,0 +1,730 @@
+use std::path::Path;
+
+use actix_web::{HttpResponse, Responder, delete, patch, web};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{ERASED_CONTENT_PLACEHOLDER, UPLOAD_DIR},
+    database,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    types::{
+        DeleteAgentRequest, DeleteAgentResponse, DeletionReason, ErrorResponse, Price,
+        UpdateAgentRequest, UpdateAgentResponse,
+    },
+};
+
+/*
+Endpoint for owners to pause, archive, reprice or rename their agent.
+Paused and archived agents are evicted from the agent cache and can't be routed to or paid for anymore.
+*/
+#[utoipa::path(
+    patch,
+    path = "/agents/{id}",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = UpdateAgentRequest,
+        content_type = "application/json",
+        description = "Fields to update. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Agent updated successfully", body = UpdateAgentResponse),
+        (status = 400, description = "Bad request - invalid fields", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 410, description = "Agent deleted", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[patch("/agents/{id}")]
+async fn update_agent_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<UpdateAgentRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let status = body.status.as_deref().map(str::trim);
+    let name = body.name.as_deref().map(str::trim);
+    let description = body.description.as_deref().map(str::trim);
+
+    if let Some(status) = status
+        && !["active", "paused", "archived"].contains(&status)
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Invalid status. Must be 'active', 'paused' or 'archived'".to_string(),
+            error_code: Some("INVALID_STATUS".to_string()),
+        });
+    }
+
+    if let Some(price) = body.price
+        && !(Price::from_whole(1)..=Price::from_whole(50000000)).contains(&price)
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Invalid price. Must be between 1 and 50000000".to_string(),
+            error_code: Some("INVALID_PRICE".to_string()),
+        });
+    }
+
+    if name.is_some_and(str::is_empty) || description.is_some_and(str::is_empty) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Name and description can't be empty".to_string(),
+            error_code: Some("INVALID_AGENT_DETAILS".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can update it".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    if agent_db.status == "deleted" {
+        return HttpResponse::Gone().json(ErrorResponse {
+            success: false,
+            message: format!("Agent with id {} has been deleted", agent_id),
+            error_code: Some("AGENT_DELETED".to_string()),
+        });
+    }
+
+    if agent_db.status == "archived" && status.is_some_and(|status| status != "archived") {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Archived agents can't be reactivated".to_string(),
+            error_code: Some("AGENT_ARCHIVED".to_string()),
+        });
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(e) =
+        database::update_agent_details(&mut tx, agent_id, status, body.price, name, description)
+            .await
+    {
+        error!("Failed to update agent: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to update agent".to_string(),
+            error_code: Some("AGENT_UPDATE_FAILED".to_string()),
+        });
+    }
+
+    let updated_agent = match database::get_agent_by_id_optional(&mut tx, agent_id).await {
+        Ok(Some(agent)) => agent,
+        Ok(None) | Err(_) => {
+            tx.rollback().await.ok(); // Rollback transaction on error
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get updated agent".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    // The name and description are part of the agent preamble, so the cached agent is rebuilt on its next use
+    app_state.agent_cache.invalidate(agent_id);
+
+    // They are also part of its embedding
+    helpers::embeddings::refresh_agent_embedding(
+        &app_state.db,
+        &app_state.embedder,
+        &updated_agent,
+    )
+    .await;
+
+    info!(
+        "Agent {} updated by its owner (status: {})",
+        agent_id, updated_agent.status
+    );
+
+    HttpResponse::Ok().json(UpdateAgentResponse {
+        success: true,
+        message: "Agent updated successfully".to_string(),
+        agent: updated_agent,
+    })
+}
+
+/*
+Endpoint for owners to withdraw their dataset or process an erasure request.
+The dataset files of every version are removed and the agent is evicted from the cache, but the agent row is only tombstoned
+so the payments made to it stay resolvable. Everything derived from the dataset is erased in the same transaction:
+its description, profile, answers and the questions they answer, guardrail events, embeddings, evaluations and previews. Each deletion is recorded in dataset_deletions.
+Files are removed once the deletion is committed, deleting the agent again removes the files left by a failure.
+*/
+#[utoipa::path(
+    delete,
+    path = "/agents/{id}",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = Option<DeleteAgentRequest>,
+        content_type = "application/json",
+        description = "Reason of the deletion. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Dataset deleted successfully", body = DeleteAgentResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 410, description = "Agent already deleted", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[delete("/agents/{id}")]
+async fn delete_agent_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: Option<web::Json<DeleteAgentRequest>>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let request = body.map(|body| body.into_inner());
+    let reason = request
+        .as_ref()
+        .and_then(|request| request.reason)
+        .unwrap_or(DeletionReason::OwnerWithdrawal);
+    let note = request
+        .as_ref()
+        .and_then(|request| request.note.as_deref())
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can delete its dataset".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    let versions = match database::get_dataset_versions_by_agent_id(db, agent_id).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            error!("Failed to get dataset versions: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get dataset versions from database".to_string(),
+                error_code: Some("DATASET_VERSIONS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let mut dataset_paths: Vec<String> = versions
+        .into_iter()
+        .map(|version| version.dataset_path)
+        .collect();
+    dataset_paths.push(agent_db.dataset_path.clone());
+    dataset_paths.sort();
+    dataset_paths.dedup();
+
+    if agent_db.status == "deleted" {
+        if let Err(response) = remove_dataset_files(&dataset_paths).await {
+            return response;
+        }
+
+        return HttpResponse::Gone().json(ErrorResponse {
+            success: false,
+            message: format!("Agent with id {} has already been deleted", agent_id),
+            error_code: Some("AGENT_DELETED".to_string()),
+        });
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(e) =
+        database::mark_agent_deleted(&mut tx, agent_id, ERASED_CONTENT_PLACEHOLDER).await
+    {
+        error!("Failed to tombstone agent: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to delete agent".to_string(),
+            error_code: Some("AGENT_DELETE_FAILED".to_string()),
+        });
+    }
+
+    // The profile keeps sample values of the dataset, so it goes with it
+    if let Err(e) = database::delete_dataset_columns(&mut tx, agent_id).await {
+        error!("Failed to delete dataset columns: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to delete dataset columns".to_string(),
+            error_code: Some("DATASET_COLUMNS_DELETE_FAILED".to_string()),
+        });
+    }
+
+    if let Err(e) =
+        database::erase_agent_derived_data(&mut tx, agent_id, ERASED_CONTENT_PLACEHOLDER).await
+    {
+        error!("Failed to erase data derived from the dataset: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to erase data derived from the dataset".to_string(),
+            error_code: Some("DERIVED_DATA_ERASE_FAILED".to_string()),
+        });
+    }
+
+    let deletion = match database::insert_dataset_deletion(
+        &mut tx,
+        agent_id,
+        &auth.address.to_string(),
+        reason,
+        note,
+        &dataset_paths,
+    )
+    .await
+    {
+        Ok(deletion) => deletion,
+        Err(e) => {
+            error!("Failed to insert dataset deletion: {}", e);
+
+            tx.rollback().await.ok(); // Rollback transaction on error
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record dataset deletion".to_string(),
+                error_code: Some("DATASET_DELETION_INSERT_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    app_state.agent_cache.invalidate(agent_id);
+
+    // Files only go once the deletion is committed, a failed commit never leaves an active agent without data
+    if let Err(response) = remove_dataset_files(&deletion.removed_files).await {
+        return response;
+    }
+
+    info!(
+        "Dataset of agent {} deleted ({}), {} file(s) removed",
+        agent_id,
+        deletion.reason,
+        deletion.removed_files.len()
+    );
+
+    HttpResponse::Ok().json(DeleteAgentResponse {
+        success: true,
+        message: "Dataset deleted successfully".to_string(),
+        deletion,
+    })
+}
+
+/// Removes the dataset files from the upload directory, files already removed are skipped
+async fn remove_dataset_files(dataset_paths: &[String]) -> Result<(), HttpResponse> {
+    for dataset_path in dataset_paths {
+        match tokio::fs::remove_file(Path::new(UPLOAD_DIR).join(dataset_path)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Dataset file {} already removed", dataset_path);
+            }
+            Err(e) => {
+                error!("Failed to remove dataset file {}: {}", dataset_path, e);
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Dataset deleted but its files could not all be removed, delete the agent again to remove them".to_string(),
+                    error_code: Some("DATASET_FILE_DELETE_FAILED".to_string()),
+                }));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::helpers::auth::{hash_signed_body, signed_test_request};
+
+    async fn insert_test_agent(db: &sqlx::Pool<sqlx::Postgres>, owner: &PrivateKeySigner) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100000000, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(owner.address().to_string())
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn owners_update_their_agents_within_the_allowed_values(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(update_agent_service),
+        )
+        .await;
+        let uri = format!("/agents/{}", agent_id);
+
+        let patch = |signer: &PrivateKeySigner, body: &str| {
+            signed_test_request(signer, Method::PATCH, &uri, body).to_request()
+        };
+
+        let response = test::call_service(&app, patch(&owner, r#"{"status":"deleted"}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        for price in ["0.5", "50000001"] {
+            let body = format!(r#"{{"price":"{}"}}"#, price);
+            let response = test::call_service(&app, patch(&owner, &body)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = test::call_service(&app, patch(&owner, r#"{"name":"  "}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let stranger = PrivateKeySigner::random();
+        let response = test::call_service(&app, patch(&stranger, r#"{"status":"paused"}"#)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            patch(
+                &owner,
+                r#"{"status":"paused","price":"2.5","name":" Retail sales "}"#,
+            ),
+        )
+        .await;
+        assert_eq!(response["agent"]["status"], "paused");
+        assert_eq!(response["agent"]["price"], "2.5");
+        assert_eq!(response["agent"]["name"], "Retail sales");
+
+        let response = test::call_service(&app, patch(&owner, r#"{"status":"archived"}"#)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Archiving is final
+        let response = test::call_service(&app, patch(&owner, r#"{"status":"active"}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn deleting_an_agent_tombstones_it_and_erases_its_dataset(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let dataset_path = format!("agent-delete-{}.csv", uuid::Uuid::new_v4());
+        sqlx::query("UPDATE agents SET dataset_path = $2 WHERE id = $1")
+            .bind(agent_id)
+            .bind(&dataset_path)
+            .execute(&db)
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(UPLOAD_DIR).await.unwrap();
+        tokio::fs::write(
+            Path::new(UPLOAD_DIR).join(&dataset_path),
+            "city,sales\nParis,10\n",
+        )
+        .await
+        .unwrap();
+
+        sqlx::query(
+            r#"
+            INSERT INTO dataset_columns (agent_id, position, name, data_type, null_rate, distinct_count, sample_values)
+            VALUES ($1, 0, 'city', 'string', 0.0, 1, ARRAY['Paris'])
+            "#,
+        )
+        .bind(agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO guardrail_events (agent_id, kind, action, prompt) VALUES ($1, 'row_leak', 'redacted', 'Sales in Paris?')",
+        )
+        .bind(agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(update_agent_service)
+                .service(delete_agent_service),
+        )
+        .await;
+        let uri = format!("/agents/{}", agent_id);
+
+        let stranger = PrivateKeySigner::random();
+        let response = test::call_service(
+            &app,
+            signed_test_request(&stranger, Method::DELETE, &uri, "").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            signed_test_request(
+                &owner,
+                Method::DELETE,
+                &uri,
+                r#"{"reason":"erasure_request","note":"GDPR request"}"#,
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(response["deletion"]["reason"], "erasure_request");
+        assert_eq!(
+            response["deletion"]["removed_files"][0],
+            dataset_path.as_str()
+        );
+
+        assert!(!Path::new(UPLOAD_DIR).join(&dataset_path).exists());
+
+        // The row stays so payments to the agent stay resolvable
+        let status: String = sqlx::query_scalar("SELECT status FROM agents WHERE id = $1")
+            .bind(agent_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(status, "deleted");
+
+        let derived: i64 = sqlx::query_scalar(
+            r#"
+            SELECT (SELECT COUNT(*) FROM dataset_columns WHERE agent_id = $1)
+                + (SELECT COUNT(*) FROM guardrail_events WHERE agent_id = $1)
+            "#,
+        )
+        .bind(agent_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(derived, 0);
+
+        let response = test::call_service(
+            &app,
+            signed_test_request(&owner, Method::DELETE, &uri, "").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        let response = test::call_service(
+            &app,
+            signed_test_request(&owner, Method::PATCH, &uri, r#"{"status":"active"}"#).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[sqlx::test]
+    async fn deleting_an_agent_erases_its_description_and_the_questions_it_answered(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+        let other_agent_id = insert_test_agent(&db, &PrivateKeySigner::random()).await;
+
+        // First turn asked the deleted agent, the second one only the other agent
+        let conversation_id: i64 = sqlx::query_scalar(
+            "INSERT INTO conversations (user_address, title) VALUES ('0xuser', 'Sales in Paris?') RETURNING id",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO messages (conversation_id, role, agent_id, content, tx_hash)
+            VALUES
+                ($1, 'user', NULL, 'Sales in Paris?', '0xpaid'),
+                ($1, 'assistant', $2, 'Paris sold 10', NULL),
+                ($1, 'user', NULL, 'Sales in Lyon?', '0xother')
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO agent_answers (agent_id, payment_reference, prompt, response, status)
+            VALUES ($1, '0xpaid', 'Sales in Paris?', 'Paris sold 10', 'success'),
+                ($2, '0xother', 'Sales in Lyon?', 'Lyon sold 5', 'success')
+            "#,
+        )
+        .bind(agent_id)
+        .bind(other_agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(delete_agent_service),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            signed_test_request(&owner, Method::DELETE, &format!("/agents/{}", agent_id), "")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let descriptions: Vec<String> =
+            sqlx::query_scalar("SELECT description FROM agents WHERE id IN ($1, $2) ORDER BY id")
+                .bind(agent_id)
+                .bind(other_agent_id)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            descriptions,
+            vec![ERASED_CONTENT_PLACEHOLDER, "Sales per city"]
+        );
+
+        let title: String = sqlx::query_scalar("SELECT title FROM conversations WHERE id = $1")
+            .bind(conversation_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(title, ERASED_CONTENT_PLACEHOLDER);
+
+        let messages: Vec<String> = sqlx::query_scalar(
+            "SELECT content FROM messages WHERE conversation_id = $1 ORDER BY id",
+        )
+        .bind(conversation_id)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                ERASED_CONTENT_PLACEHOLDER,
+                ERASED_CONTENT_PLACEHOLDER,
+                "Sales in Lyon?"
+            ]
+        );
+    }
+}
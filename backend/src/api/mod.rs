@@ -1,24 +1,48 @@
+pub mod aggregate;
+pub mod conversations;
+pub mod credits;
 pub mod dataset;
+pub mod disputes;
+pub mod evaluations;
+pub mod lifecycle;
+pub mod preview;
+pub mod pricing;
 pub mod profile;
+pub mod queries;
+pub mod quote;
+pub mod reviews;
+pub mod search;
+pub mod stats;
+pub mod stream;
+pub mod versions;
 
 use crate::{
-    config::{MAX_ALLOWED_SELECTED_AGENTS, ROUTER_AGENT_MODEL},
-    database, helpers,
+    config::{
+        AGENT_RESPONSE_TIMEOUT_SECS, CONVERSATION_TITLE_MAX_CHARS, INIT_AGENT_MODEL,
+        MAX_ALLOWED_SELECTED_AGENTS, ROUTER_CANDIDATES_TOP_K,
+    },
+    database,
+    helpers::{self, auth::SignedAddress},
     state::AppState,
     tee,
     types::{
-        AgentCategory, AgentDb, AgentQueryParams, AgentQueryResult, AgentResponse,
-        DatasetStatsResponse, ErrorResponse, GetAgentsForPromptRequest, GetAgentsForPromptResponse,
-        GetResponseFromAgentsRequest, GetResponseFromAgentsResponse,
+        AgentCacheMetricsResponse, AgentCategory, AgentCharge, AgentDb, AgentDetailsResponse,
+        AgentQueryParams, AgentQueryResult, AgentResponse, AgentResponseStatus, AgentTokenUsage,
+        ConversationDb, DatasetStatsResponse, ErrorResponse, GetAgentsForPromptRequest,
+        GetAgentsForPromptResponse, GetResponseFromAgentsRequest, GetResponseFromAgentsResponse,
+        MessageDb, NewQuery, NewQueryAgent, Price, RefundEntitlementDb, RefundReason,
+        SynthesizedAnswer,
     },
 };
-use actix_web::{HttpResponse, Responder, get, post, web};
-use rig::{
-    client::ProviderClient,
-    completion::Prompt,
-    providers::gemini::{self},
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
 };
-use tracing::{debug, error, info, warn};
+
+use actix_web::{HttpResponse, Responder, get, post, web};
+use futures_util::future::join_all;
+use tokio::time::error::Elapsed;
+use tracing::{error, info, warn};
 
 #[utoipa::path(
         responses(
@@ -42,14 +66,28 @@ async fn get_health_service() -> impl Responder {
     HttpResponse::Ok().body("ok")
 }
 
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Agent cache metrics", body = AgentCacheMetricsResponse),
+    ),
+    tag = "Health"
+)]
+#[get("/metrics/agent-cache")]
+async fn get_agent_cache_metrics_service(app_state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(AgentCacheMetricsResponse {
+        success: true,
+        metrics: app_state.agent_cache.metrics(),
+    })
+}
+
 #[utoipa::path(
     get,
     path = "/agents",
     params(
-        ("search" = Option<String>, Query, description = "Search agents by name (case-insensitive partial match)"),
+        ("search" = Option<String>, Query, description = "Search agents by name (case-insensitive partial match), see /agents/search to search by meaning"),
         ("category" = Option<AgentCategory>, Query, description = "Filter agents by category"),
         ("status" = Option<String>, Query, description = "Filter agents by status"),
-        ("sort_by" = Option<String>, Query, description = "Sort field: price, created_at, updated_at, name"),
+        ("sort_by" = Option<String>, Query, description = "Sort field: price, created_at, updated_at, name, rating, review_count"),
         ("sort_order" = Option<String>, Query, description = "Sort order: asc or desc (default: asc)")
     ),
     responses(
@@ -68,7 +106,14 @@ async fn get_all_agents_service(
 
     // Validate sort_by field
     let sort_by = query.sort_by.as_deref().unwrap_or("created_at");
-    let valid_sort_fields = ["price", "created_at", "updated_at", "name"];
+    let valid_sort_fields = [
+        "price",
+        "created_at",
+        "updated_at",
+        "name",
+        "rating",
+        "review_count",
+    ];
     if !valid_sort_fields.contains(&sort_by) {
         return HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
@@ -111,9 +156,13 @@ async fn get_all_agents_service(
         g.updated_at,
         g.nft_id,
         g.nft_tx, 
-        u.address
+        g.current_version,
+        u.address,
+        COALESCE(r.review_count, 0) AS review_count,
+        r.average_rating
      FROM agents g
-     JOIN users u ON g.owner_id = u.id WHERE 1=1"#,
+     JOIN users u ON g.owner_id = u.id
+     LEFT JOIN agent_ratings r ON r.agent_id = g.id WHERE 1=1"#,
     );
 
     let mut param_count = 0;
@@ -132,16 +181,24 @@ async fn get_all_agents_service(
         sql.push_str(&format!(" AND category::text = ${}", param_count));
     }
 
-    // Add status filter
-    if let Some(status) = &query.status {
-        if !status.trim().is_empty() {
+    // Add status filter, deleted agents are only listed when asked for explicitly
+    match &query.status {
+        Some(status) if !status.trim().is_empty() => {
             param_count += 1;
             sql.push_str(&format!(" AND status = ${}", param_count));
         }
+        _ => sql.push_str(" AND status <> 'deleted'"),
     }
 
-    // Add ORDER BY clause
-    sql.push_str(&format!(" ORDER BY {} {}", sort_by, sort_order));
+    // Add ORDER BY clause, agents without reviews come last when sorting by rating
+    let sort_column = match sort_by {
+        "rating" => "average_rating",
+        sort_by => sort_by,
+    };
+    sql.push_str(&format!(
+        " ORDER BY {} {} NULLS LAST",
+        sort_column, sort_order
+    ));
 
     // Execute the query
     let mut query_builder = sqlx::query_as::<_, AgentQueryResult>(&sql);
@@ -192,6 +249,9 @@ async fn get_all_agents_service(
             owner_address: result.address,
             nft_id: result.nft_id,
             nft_tx: result.nft_tx,
+            current_version: result.current_version,
+            review_count: result.review_count,
+            average_rating: result.average_rating,
         })
         .collect();
 
@@ -205,7 +265,7 @@ async fn get_all_agents_service(
         ("id" = i64, Path, description = "Agent id")
     ),
     responses(
-        (status = 200, description = "Agent fetched successfully", body = AgentDb),
+        (status = 200, description = "Agent fetched successfully", body = AgentDetailsResponse),
         (status = 404, description = "Agent not found", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
@@ -232,11 +292,59 @@ async fn get_agent_by_id_service(
         }
     };
 
-    HttpResponse::Ok().json(agent_db)
+    let mut columns = match database::get_dataset_columns_by_agent_id(db, agent_id).await {
+        Ok(columns) => columns,
+        Err(e) => {
+            error!("Failed to get dataset columns: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get dataset columns from database".to_string(),
+                error_code: Some("DATASET_COLUMNS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let privacy_budget = match database::get_privacy_budget_by_agent_id(db, agent_id).await {
+        Ok(budget) => budget,
+        Err(e) => {
+            error!("Failed to get privacy budget: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get privacy budget from database".to_string(),
+                error_code: Some("PRIVACY_BUDGET_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if privacy_budget.is_some() {
+        helpers::privacy::redact_column_profiles(&mut columns);
+    }
+
+    let pricing_plan =
+        match helpers::pricing::pricing_plans(db, std::slice::from_ref(&agent_db)).await {
+            Ok(mut plans) => plans.remove(0),
+            Err(e) => {
+                error!("Failed to get pricing plan: {:?}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get pricing plan from database".to_string(),
+                    error_code: Some("PRICING_PLAN_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    HttpResponse::Ok().json(AgentDetailsResponse {
+        agent: agent_db,
+        columns,
+        privacy_budget,
+        pricing_plan,
+    })
 }
 
 /*
 Endpoint that its job is to get all the agents from database and using gemini ai(rig-core) that will return the ids of the agents that have the response for the prompt.
+The router model answers through a typed tool call with a score and a justification per agent, and keyword retrieval ranks the agents when it fails.
+The best ranked agents fitting in the optional budget are recommended with their total cost.
 */
 #[utoipa::path(
     post,
@@ -247,7 +355,8 @@ Endpoint that its job is to get all the agents from database and using gemini ai
         description = "User prompt to get agents that can respond to it"
     ),
     responses(
-        (status = 200, description = "Agents fetched successfully", body = String),
+        (status = 200, description = "Agents fetched successfully", body = GetAgentsForPromptResponse),
+        (status = 400, description = "Bad request - invalid budget or agent count", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Agents"
@@ -259,32 +368,33 @@ async fn get_agents_for_prompt_service(
 ) -> HttpResponse {
     let user_prompt = body.prompt.trim();
 
+    if let Some(max_budget) = body.max_budget
+        && max_budget <= Price::ZERO
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "max_budget must be a positive number".to_string(),
+            error_code: Some("INVALID_MAX_BUDGET".to_string()),
+        });
+    }
+
+    let max_agents = body.max_agents.unwrap_or(MAX_ALLOWED_SELECTED_AGENTS);
+
+    if max_agents == 0 || max_agents > MAX_ALLOWED_SELECTED_AGENTS {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "max_agents must be between 1 and {}",
+                MAX_ALLOWED_SELECTED_AGENTS
+            ),
+            error_code: Some("INVALID_MAX_AGENTS".to_string()),
+        });
+    }
+
     // Get the List of agents from database
     let db = &app_state.db;
 
-    let agents = match sqlx::query_as!(
-        AgentDb,
-        r#"SELECT
-        g.id,
-        g.name,
-        g.description,
-        g.price,
-        g.owner_id,
-        g.dataset_path,
-        g.status,
-        g.category as "category: AgentCategory",
-        g.dataset_size,
-        g.created_at,
-        g.updated_at,
-        g.nft_id,
-        g.nft_tx, 
-        u.address as "owner_address: String"
-    FROM agents g
-    JOIN users u ON g.owner_id = u.id"#
-    )
-    .fetch_all(db)
-    .await
-    {
+    let agents = match database::get_active_agents(db).await {
         Ok(agents) => agents,
         Err(e) => {
             error!("Failed to get agents: {}", e);
@@ -296,75 +406,119 @@ async fn get_agents_for_prompt_service(
         }
     };
 
-    let agents_vec_str: String = agents
-        .iter()
-        .map(|agent| {
-            format!(
-                "{{\"id\":{},\"name\":\"{}\",\"description\":\"{}\", \"category\":\"{}\"}}",
-                agent.id,
-                agent.name,
-                agent.description,
-                agent.category.to_string()
-            )
-        })
-        .collect::<Vec<_>>()
-        .join(", ");
-
-    let model = gemini::Client::from_env();
-    let ai = model
-        .agent(ROUTER_AGENT_MODEL)
-        .preamble("You are an AI agent that your main and only task is to return the agents ids that can respond to the user question. You decide wether to return an agent id by using their available description, name and category. You' ll find this data in your context. Remeber to always only return the response as an array of agents id.If you can't find anyone just return an empty array. Exemple of response : [5, 9]. ")
-        .temperature(0.0)
-        .build();
-
-    let prompt = format!(
-        "User question: {}. Please return the agents ids that can respond to this question. This is all the agents: [{}]",
-        user_prompt, agents_vec_str
-    );
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
 
-    let response = match ai.prompt(prompt).await {
-        Ok(response) => response,
+    let mut agents_columns = match database::get_dataset_columns_by_agent_ids(db, &agent_ids).await
+    {
+        Ok(columns) => columns,
         Err(e) => {
-            error!("Failed to get AI response: {}", e);
+            error!("Failed to get dataset columns: {}", e);
             return HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Failed to get AI response: {}", e),
-                error_code: Some("AI_RESPONSE_FAILED".to_string()),
+                message: "Failed to get dataset columns from database".to_string(),
+                error_code: Some("DATASET_COLUMNS_FETCH_FAILED".to_string()),
             });
         }
     };
 
-    debug!("AI response: {}", response);
-
-    // remove any markdown
-    let formatted_response = response.replace("```", "");
-
-    debug!("Formatted AI response: {}", formatted_response);
-
-    let agents_id_vec = match serde_json::from_str::<Vec<i64>>(&formatted_response) {
-        Ok(vec) => vec,
+    let privacy_mode_agent_ids = match database::get_privacy_mode_agent_ids(db, &agent_ids).await {
+        Ok(ids) => ids,
         Err(e) => {
-            error!("Failed to parse AI response: {}", e);
+            error!("Failed to get privacy mode agents: {}", e);
             return HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Failed to parse AI response: {}", e),
-                error_code: Some("AI_RESPONSE_PARSE_FAILED".to_string()),
+                message: "Failed to get privacy budgets from database".to_string(),
+                error_code: Some("PRIVACY_BUDGET_FETCH_FAILED".to_string()),
             });
         }
     };
 
-    if agents_id_vec.is_empty() {
-        return HttpResponse::Ok().json(GetAgentsForPromptResponse { agents: Vec::new() });
+    // Only the schema of datasets in privacy mode is described to the router model
+    for agent_id in &privacy_mode_agent_ids {
+        if let Some(columns) = agents_columns.get_mut(agent_id) {
+            helpers::privacy::redact_column_profiles(columns);
+        }
     }
 
-    // Filter agents to only include those whose IDs are in agents_id_vec
-    let available_agents: Vec<AgentDb> = agents
-        .into_iter()
-        .filter(|agent| agents_id_vec.contains(&agent.id))
+    // Only the agents most similar to the prompt are described to the router model
+    let agents = if agents.len() > ROUTER_CANDIDATES_TOP_K {
+        match helpers::embeddings::rank_agents_by_similarity(
+            db,
+            &app_state.embedder,
+            user_prompt,
+            &agents,
+        )
+        .await
+        {
+            Ok(similarities) => similarities
+                .iter()
+                .take(ROUTER_CANDIDATES_TOP_K)
+                .filter_map(|(agent_id, _)| agents.iter().find(|agent| agent.id == *agent_id))
+                .cloned()
+                .collect(),
+            Err(e) => {
+                warn!(
+                    "Failed to rank agents by similarity, routing over all of them: {:?}",
+                    e
+                );
+                agents
+            }
+        }
+    } else {
+        agents
+    };
+
+    let routing =
+        helpers::router::route_prompt(&app_state.ai_model, user_prompt, &agents, &agents_columns)
+            .await;
+
+    info!(
+        "Routed prompt to agents {:?} ({:?}, rejected ids: {:?})",
+        routing
+            .rankings
+            .iter()
+            .map(|ranking| ranking.agent_id)
+            .collect::<Vec<_>>(),
+        routing.method,
+        routing.rejected_ids
+    );
+
+    // Return the agents in ranking order
+    let available_agents: Vec<AgentDb> = routing
+        .rankings
+        .iter()
+        .filter_map(|ranking| agents.iter().find(|agent| agent.id == ranking.agent_id))
+        .cloned()
         .collect();
 
+    let recommendation = match helpers::router::recommend_within_budget(
+        &routing.rankings,
+        &available_agents,
+        body.max_budget,
+        max_agents,
+    ) {
+        Ok(recommendation) => recommendation,
+        Err(e) => {
+            error!("Failed to compute the agents recommendation: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to compute the agents cost".to_string(),
+                error_code: Some("RECOMMENDATION_FAILED".to_string()),
+            });
+        }
+    };
+
+    info!(
+        "Recommended agents {:?} for {} (dropped for budget: {:?})",
+        recommendation.agent_ids, recommendation.total_cost, recommendation.dropped_for_budget
+    );
+
     HttpResponse::Ok().json(GetAgentsForPromptResponse {
         agents: available_agents,
+        rankings: routing.rankings,
+        routing_method: routing.method,
+        rejected_agent_ids: routing.rejected_ids,
+        recommendation,
     })
 }
 
@@ -377,10 +531,15 @@ Endpoint that will use specifid agents ids by user and will return the response
     request_body(
         content = GetResponseFromAgentsRequest,
         content_type = "application/json",
-        description = "User prompt and specified agents ids to get response from and tx hashes to verify payment."
+        description = "User prompt and specified agents ids to get response from and tx hashes to verify payment. Free samples, subscriptions and volume tiers require the payer signature headers."
     ),
     responses(
         (status = 200, description = "Agents responses fetched successfully", body = GetResponseFromAgentsResponse),
+        (status = 400, description = "Bad request - invalid payment transaction", body = ErrorResponse),
+        (status = 401, description = "Signature required to continue a conversation or pay with credits", body = ErrorResponse),
+        (status = 402, description = "Insufficient credits or payment verification failed", body = ErrorResponse),
+        (status = 403, description = "Not the conversation owner", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Agents"
@@ -388,117 +547,719 @@ Endpoint that will use specifid agents ids by user and will return the response
 #[post("/chat/agents/answer")]
 async fn get_response_from_agents_service(
     app_state: web::Data<AppState>,
+    auth: Option<SignedAddress>,
     body: web::Json<GetResponseFromAgentsRequest>,
 ) -> HttpResponse {
-    let agent_ids = &body.agent_ids;
     let prompt = &body.prompt;
-    let tx_hash = &body.tx_hash;
 
-    if tx_hash.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+    let conversation =
+        match check_conversation_access(&app_state, auth.as_ref(), body.conversation_id).await {
+            Ok(conversation) => conversation,
+            Err(response) => return response,
+        };
+
+    let paid = match verify_paid_agents(&app_state, auth.as_ref(), &body).await {
+        Ok(paid) => paid,
+        Err(response) => return response,
+    };
+    let selected_agents = &paid.agents;
+
+    let conversation = open_conversation(&app_state, auth.as_ref(), conversation, prompt).await;
+
+    let started = Instant::now();
+
+    // Prompt every agent concurrently, one failing or hanging agent doesn't affect the others
+    let outcomes = join_all(selected_agents.iter().map(|agent_db| async {
+        tee::call_tee_ai_agent(&app_state, agent_db.id, prompt).await;
+
+        let history = conversation
+            .as_ref()
+            .map(|(_, messages)| helpers::conversations::agent_chat_history(messages, agent_db.id))
+            .unwrap_or_default();
+
+        let agent_started = Instant::now();
+
+        let outcome = tokio::time::timeout(
+            Duration::from_secs(AGENT_RESPONSE_TIMEOUT_SECS),
+            helpers::agents::prompt_agent(&app_state, agent_db, prompt, history),
+        )
+        .await;
+
+        (outcome, agent_started.elapsed())
+    }))
+    .await;
+
+    let mut agent_responses = Vec::new();
+    let mut latencies = Vec::new();
+    let mut refund_entitlements = Vec::new();
+
+    for (agent_db, (outcome, latency)) in selected_agents.iter().zip(outcomes) {
+        latencies.push(latency);
+
+        let (status, error) = match agent_outcome(agent_db.id, outcome) {
+            Ok((response, dataset_version)) => {
+                agent_responses.push(AgentResponse {
+                    agent_id: agent_db.id,
+                    prompt: prompt.clone(),
+                    response,
+                    dataset_version,
+                    status: AgentResponseStatus::Success,
+                    error: None,
+                });
+                continue;
+            }
+            Err(failure) => failure,
+        };
+
+        let (agent_response, refund_entitlement) =
+            record_failed_agent(&app_state, agent_db, prompt, &paid, status, error).await;
+
+        agent_responses.push(agent_response);
+        refund_entitlements.extend(refund_entitlement);
+    }
+
+    let success = agent_responses
+        .iter()
+        .any(|response| response.status == AgentResponseStatus::Success);
+
+    let synthesis = if body.synthesize && success {
+        synthesize_agent_responses(&app_state, prompt, selected_agents, &agent_responses).await
+    } else {
+        None
+    };
+
+    record_agent_answers(&app_state, &paid, prompt, &agent_responses).await;
+
+    // The model doesn't report token usage outside of streaming
+    let runs: Vec<AgentRun> = agent_responses
+        .iter()
+        .zip(latencies)
+        .map(|(response, latency)| (response, latency, None))
+        .collect();
+
+    record_query(
+        &app_state,
+        &paid,
+        prompt,
+        body.store_prompt,
+        false,
+        started.elapsed(),
+        &runs,
+    )
+    .await;
+
+    let conversation_id = save_conversation_turn(
+        &app_state,
+        conversation,
+        prompt,
+        &paid.payment_reference,
+        &agent_responses,
+    )
+    .await;
+
+    HttpResponse::Ok().json(GetResponseFromAgentsResponse {
+        agent_responses,
+        success,
+        charges: paid.charges,
+        refund_entitlements,
+        synthesis,
+        conversation_id,
+    })
+}
+
+/// Checks that the signer of the request owns the conversation it continues, before any payment is consumed
+async fn check_conversation_access(
+    app_state: &web::Data<AppState>,
+    auth: Option<&SignedAddress>,
+    conversation_id: Option<i64>,
+) -> Result<Option<ConversationDb>, HttpResponse> {
+    let Some(conversation_id) = conversation_id else {
+        return Ok(None);
+    };
+
+    let Some(auth) = auth else {
+        return Err(HttpResponse::Unauthorized().json(ErrorResponse {
+            success: false,
+            message: "Signature headers are required to continue a conversation".to_string(),
+            error_code: Some("UNAUTHORIZED".to_string()),
+        }));
+    };
+
+    let conversation = match database::get_conversation_by_id(&app_state.db, conversation_id).await
+    {
+        Ok(Some(conversation)) => conversation,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Conversation with id {} not found", conversation_id),
+                error_code: Some("CONVERSATION_NOT_FOUND".to_string()),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to get conversation: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get conversation from database".to_string(),
+                error_code: Some("CONVERSATION_FETCH_FAILED".to_string()),
+            }));
+        }
+    };
+
+    if !auth.matches(&conversation.user_address) {
+        return Err(HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the conversation owner can continue it".to_string(),
+            error_code: Some("NOT_CONVERSATION_OWNER".to_string()),
+        }));
+    }
+
+    Ok(Some(conversation))
+}
+
+/// Returns the conversation to save the answers to with its previous messages,
+/// starting a new one for signed requests that don't continue any.
+/// Runs after the payment, so failures only skip the persistence of the conversation.
+async fn open_conversation(
+    app_state: &web::Data<AppState>,
+    auth: Option<&SignedAddress>,
+    conversation: Option<ConversationDb>,
+    prompt: &str,
+) -> Option<(ConversationDb, Vec<MessageDb>)> {
+    let conversation = match (conversation, auth) {
+        (Some(conversation), _) => conversation,
+        (None, Some(auth)) => {
+            let title: String = prompt.chars().take(CONVERSATION_TITLE_MAX_CHARS).collect();
+
+            match database::insert_conversation(&app_state.db, &auth.address.to_string(), &title)
+                .await
+            {
+                Ok(conversation) => conversation,
+                Err(e) => {
+                    error!("Failed to create conversation: {}", e);
+                    return None;
+                }
+            }
+        }
+        (None, None) => return None,
+    };
+
+    match database::get_messages_by_conversation_id(&app_state.db, conversation.id).await {
+        Ok(messages) => Some((conversation, messages)),
+        Err(e) => {
+            error!(
+                "Failed to get messages of conversation {}: {}",
+                conversation.id, e
+            );
+            None
+        }
+    }
+}
+
+/// Saves the question and the answers to the conversation, returning its id when saved
+async fn save_conversation_turn(
+    app_state: &web::Data<AppState>,
+    conversation: Option<(ConversationDb, Vec<MessageDb>)>,
+    prompt: &str,
+    tx_hash: &str,
+    agent_responses: &[AgentResponse],
+) -> Option<i64> {
+    let (conversation, _) = conversation?;
+
+    match helpers::conversations::save_conversation_turn(
+        &app_state.db,
+        conversation.id,
+        prompt,
+        tx_hash,
+        agent_responses,
+    )
+    .await
+    {
+        Ok(()) => Some(conversation.id),
+        Err(e) => {
+            error!(
+                "Failed to save messages of conversation {}: {:?}",
+                conversation.id, e
+            );
+            None
+        }
+    }
+}
+
+/// Agents of a verified answer request and how they were paid
+struct PaidAgents {
+    /// Selected agents in the requested order
+    agents: Vec<AgentDb>,
+    /// Sender of the payment transaction or signer paying with credits
+    payer: String,
+    /// Tx hash or credits debit reference
+    payment_reference: String,
+    /// What each agent was charged under its pricing plan
+    charges: Vec<AgentCharge>,
+}
+
+impl PaidAgents {
+    fn charged(&self, agent_id: i64) -> Price {
+        self.charges
+            .iter()
+            .find(|charge| charge.agent_id == agent_id)
+            .map(|charge| charge.amount)
+            .unwrap_or(Price::ZERO)
+    }
+}
+
+/// Validates an answer request and its payment, debiting the credits of the signer when it pays with credits.
+/// Returns the paid agents, or the error response to send back.
+async fn verify_paid_agents(
+    app_state: &web::Data<AppState>,
+    auth: Option<&SignedAddress>,
+    request: &GetResponseFromAgentsRequest,
+) -> Result<PaidAgents, HttpResponse> {
+    let agent_ids = &request.agent_ids;
+    let prompt = &request.prompt;
+    let tx_hash = &request.tx_hash;
+
+    if !request.pay_with_credits && tx_hash.is_empty() {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "No tx hash specified".to_string(),
             error_code: Some("NO_TX_HASH_SPECIFIED".to_string()),
-        });
+        }));
     }
 
     if prompt.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "No prompt specified".to_string(),
             error_code: Some("NO_PROMPT_SPECIFIED".to_string()),
-        });
+        }));
     }
 
     if agent_ids.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "No agents specified".to_string(),
             error_code: Some("NO_AGENTS_SPECIFIED".to_string()),
-        });
+        }));
     }
 
     if agent_ids.len() > MAX_ALLOWED_SELECTED_AGENTS {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "Too many agents specified".to_string(),
             error_code: Some("TOO_MANY_AGENTS_SPECIFIED".to_string()),
-        });
+        }));
     }
 
-    let mut agent_responses = Vec::new();
+    // Each agent is charged once, it must be prompted once
+    if agent_ids.iter().collect::<HashSet<_>>().len() != agent_ids.len() {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Agents can only be specified once".to_string(),
+            error_code: Some("DUPLICATE_AGENTS_SPECIFIED".to_string()),
+        }));
+    }
+
+    // Datasets in privacy mode only answer through the differentially private aggregate queries
+    let privacy_mode_ids =
+        match database::get_privacy_mode_agent_ids(&app_state.db, agent_ids).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                error!("Failed to get privacy mode agents: {}", e);
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get privacy budgets from database".to_string(),
+                    error_code: Some("PRIVACY_BUDGET_FETCH_FAILED".to_string()),
+                }));
+            }
+        };
+
+    if !privacy_mode_ids.is_empty() {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Agents {:?} are in privacy mode and only answer aggregate queries",
+                privacy_mode_ids
+            ),
+            error_code: Some("PRIVACY_MODE_AGGREGATE_ONLY".to_string()),
+        }));
+    }
+
+    if request.pay_with_credits {
+        return debit_credits_for_agents(app_state, auth, agent_ids).await;
+    }
 
     // Verify payment using tx hash
-    let pay_sucess = match helpers::agents::verif_selected_agents_payment(
-        &app_state, agent_ids, tx_hash,
+    let payment = match helpers::agents::verif_selected_agents_payment(
+        app_state,
+        agent_ids,
+        tx_hash,
+        request.quote.as_ref(),
+        auth,
     )
     .await
     {
-        Ok(success) => success,
+        Ok(Some(payment)) => payment,
+        Ok(None) => {
+            return Err(HttpResponse::PaymentRequired().json(ErrorResponse {
+                success: false,
+                message: "Payment verification failed".to_string(),
+                error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
+            }));
+        }
+        Err(e) if e.is::<helpers::agents::InvalidPayment>() => {
+            error!("Invalid payment {}: {}", tx_hash, e);
+            return Err(HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: e.to_string(),
+                error_code: Some("INVALID_PAYMENT".to_string()),
+            }));
+        }
         Err(e) => {
             error!("Failed to verify payment: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse {
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Failed to verify payment: {}", e),
+                message: "Failed to verify payment".to_string(),
                 error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
-            });
+            }));
         }
     };
 
-    if !pay_sucess {
-        return HttpResponse::InternalServerError().json(ErrorResponse {
-            success: false,
-            message: "Payment verification failed".to_string(),
-            error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
-        });
-    }
+    // Payment verification already checked that every agent exists and is active
+    let agents_db = match database::get_agents_by_ids(&app_state.db, agent_ids).await {
+        Ok(agents) => agents,
+        Err(e) => {
+            error!("Failed to get agents: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agents from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let mut selected_agents = Vec::new();
 
-    // Get response from each agent specified
     for agent_id in agent_ids {
-        tee::call_tee_ai_agent(&app_state, *agent_id, &prompt).await;
+        let Some(agent_db) = agents_db.iter().find(|agent| agent.id == *agent_id) else {
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not running", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            }));
+        };
 
-        let agent = app_state.tee_agents.get(agent_id);
+        selected_agents.push(agent_db.clone());
+    }
 
-        if agent.is_none() {
-            return HttpResponse::InternalServerError().json(ErrorResponse {
+    Ok(PaidAgents {
+        agents: selected_agents,
+        payer: payment.payer,
+        payment_reference: payment.tx_hash,
+        charges: payment.charges,
+    })
+}
+
+/// Pays the agents from the prepaid credits of the signer, atomically with the balance check
+async fn debit_credits_for_agents(
+    app_state: &web::Data<AppState>,
+    auth: Option<&SignedAddress>,
+    agent_ids: &[i64],
+) -> Result<PaidAgents, HttpResponse> {
+    let Some(auth) = auth else {
+        return Err(HttpResponse::Unauthorized().json(ErrorResponse {
+            success: false,
+            message: "Signature headers are required to pay with credits".to_string(),
+            error_code: Some("UNAUTHORIZED".to_string()),
+        }));
+    };
+
+    let agents_db = match database::get_agents_by_ids(&app_state.db, agent_ids).await {
+        Ok(agents) => agents,
+        Err(e) => {
+            error!("Failed to get agents: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Agent with id {} not running", agent_id),
+                message: "Failed to get agents from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let mut selected_agents = Vec::new();
+
+    for agent_id in agent_ids {
+        let Some(agent_db) = agents_db.iter().find(|agent| agent.id == *agent_id) else {
+            return Err(HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
                 error_code: Some("AGENT_NOT_FOUND".to_string()),
-            });
+            }));
+        };
+
+        if agent_db.status != "active" {
+            return Err(HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "Agent {} is {} and can't be paid for",
+                    agent_db.id, agent_db.status
+                ),
+                error_code: Some("AGENT_NOT_ACTIVE".to_string()),
+            }));
         }
 
-        let agent = agent.unwrap();
+        selected_agents.push(agent_db.clone());
+    }
 
-        tracing::debug!("Prompting agent with id {} with prompt {}", agent_id, prompt);
+    let payer = auth.address.to_string();
 
-        let response = match agent.prompt(prompt).await {
-            Ok(response) => response,
-            Err(e) => {
-                error!("Failed to get AI response: {}", e);
-                return HttpResponse::InternalServerError().json(ErrorResponse {
-                    success: false,
-                    message: format!(
-                        "Failed to get AI response from agent with id {} : {}",
-                        agent_id, e
-                    ),
-                    error_code: Some("AI_RESPONSE_FAILED".to_string()),
-                });
-            }
-        };
+    match debit_agent_charges(&app_state.db, &payer, &selected_agents).await {
+        Ok(Some((payment_reference, charges))) => Ok(PaidAgents {
+            agents: selected_agents,
+            payer,
+            payment_reference,
+            charges,
+        }),
+        Ok(None) => Err(HttpResponse::PaymentRequired().json(ErrorResponse {
+            success: false,
+            message: format!("Insufficient credits to pay for agents {:?}", agent_ids),
+            error_code: Some("INSUFFICIENT_CREDITS".to_string()),
+        })),
+        Err(e) => {
+            error!("Failed to debit credits of {}: {:?}", payer, e);
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to debit credits".to_string(),
+                error_code: Some("CREDIT_DEBIT_FAILED".to_string()),
+            }))
+        }
+    }
+}
 
-        let agent_response = AgentResponse {
-            agent_id: *agent_id,
-            prompt: prompt.clone(),
-            response,
-        };
+/// Charges the agents to the payer under their pricing plans, debits the credits and records the usage in one transaction.
+/// Returns the payment reference and the charges, or None when the credits don't cover them.
+async fn debit_agent_charges(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payer: &str,
+    agents: &[AgentDb],
+) -> color_eyre::Result<Option<(String, Vec<AgentCharge>)>> {
+    let mut tx = db.begin().await?;
 
-        agent_responses.push(agent_response);
+    let charges = helpers::pricing::claim_agent_charges(db, &mut tx, payer, agents).await?;
+
+    let Some(payment_reference) = helpers::credits::debit_answer(&mut tx, payer, &charges).await?
+    else {
+        return Ok(None);
+    };
+
+    database::insert_agent_usages(&mut tx, payer, &payment_reference, &charges).await?;
+
+    tx.commit().await?;
+
+    Ok(Some((payment_reference, charges)))
+}
+
+/// Flattens the result of a prompt run under AGENT_RESPONSE_TIMEOUT_SECS into the status and error of a failed agent
+fn agent_outcome<T>(
+    agent_id: i64,
+    outcome: Result<color_eyre::Result<T>, Elapsed>,
+) -> Result<T, (AgentResponseStatus, String)> {
+    match outcome {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => {
+            error!("Agent {} failed to answer: {:?}", agent_id, e);
+            Err((AgentResponseStatus::Failed, e.to_string()))
+        }
+        Err(_) => {
+            error!(
+                "Agent {} did not answer within {} seconds",
+                agent_id, AGENT_RESPONSE_TIMEOUT_SECS
+            );
+            Err((
+                AgentResponseStatus::TimedOut,
+                format!(
+                    "Agent did not answer within {} seconds",
+                    AGENT_RESPONSE_TIMEOUT_SECS
+                ),
+            ))
+        }
     }
+}
 
-    HttpResponse::Ok().json(GetResponseFromAgentsResponse {
+/// Merges the agents responses into one cited answer.
+/// Synthesis is best effort, the individual responses are returned even when it fails.
+async fn synthesize_agent_responses(
+    app_state: &web::Data<AppState>,
+    prompt: &str,
+    selected_agents: &[AgentDb],
+    agent_responses: &[AgentResponse],
+) -> Option<SynthesizedAnswer> {
+    let agents: Vec<(&AgentDb, &AgentResponse)> =
+        selected_agents.iter().zip(agent_responses).collect();
+
+    match tokio::time::timeout(
+        Duration::from_secs(AGENT_RESPONSE_TIMEOUT_SECS),
+        helpers::synthesis::synthesize_answer(&app_state.ai_model, prompt, &agents),
+    )
+    .await
+    {
+        Ok(Ok(synthesis)) => Some(synthesis),
+        Ok(Err(e)) => {
+            error!("Failed to synthesize agents responses: {:?}", e);
+            None
+        }
+        Err(_) => {
+            error!(
+                "Synthesis did not finish within {} seconds",
+                AGENT_RESPONSE_TIMEOUT_SECS
+            );
+            None
+        }
+    }
+}
+
+/// Response of an agent with the time it took and its token usage
+type AgentRun<'a> = (&'a AgentResponse, Duration, Option<AgentTokenUsage>);
+
+/// Logs the question in the queries audit log, with the prompt text only when the requester chose to store it.
+/// Runs after the agents answered, so failures are only logged.
+async fn record_query(
+    app_state: &web::Data<AppState>,
+    paid: &PaidAgents,
+    prompt: &str,
+    store_prompt: bool,
+    streamed: bool,
+    latency: Duration,
+    runs: &[AgentRun<'_>],
+) {
+    let statuses: Vec<AgentResponseStatus> = runs
+        .iter()
+        .map(|(response, _, _)| response.status)
+        .collect();
+
+    let query = NewQuery {
+        requester_address: paid.payer.clone(),
+        agent_ids: paid.agents.iter().map(|agent| agent.id).collect(),
+        prompt_hash: helpers::queries::hash_text(prompt),
+        prompt: store_prompt.then(|| prompt.to_string()),
+        payment_reference: paid.payment_reference.clone(),
+        amount: paid.charges.iter().map(|charge| charge.amount).sum(),
+        outcome: helpers::queries::query_outcome(&statuses),
+        latency_ms: latency.as_millis() as i64,
+        streamed,
+        agents: runs
+            .iter()
+            .map(|(response, latency, usage)| NewQueryAgent {
+                agent_id: response.agent_id,
+                status: response.status,
+                response_hash: (response.status == AgentResponseStatus::Success)
+                    .then(|| helpers::queries::hash_text(&response.response)),
+                latency_ms: latency.as_millis() as i64,
+                usage: usage.clone(),
+                model: INIT_AGENT_MODEL.to_string(),
+                dataset_version: response.dataset_version,
+                amount: paid.charged(response.agent_id),
+                error: response.error.clone(),
+            })
+            .collect(),
+    };
+
+    if let Err(e) = helpers::queries::log_query(&app_state.db, &query).await {
+        error!(
+            "Failed to log the query paid by {}: {:?}",
+            paid.payment_reference, e
+        );
+    }
+}
+
+/// Keeps the prompt and the answers of the paid agents as the evidence of disputes.
+/// Runs after the agents answered, so failures are only logged.
+async fn record_agent_answers(
+    app_state: &web::Data<AppState>,
+    paid: &PaidAgents,
+    prompt: &str,
+    agent_responses: &[AgentResponse],
+) {
+    if let Err(e) = database::insert_agent_answers(
+        &app_state.db,
+        &paid.payment_reference,
+        prompt,
         agent_responses,
-        success: true,
-    })
+    )
+    .await
+    {
+        error!(
+            "Failed to record the answers paid by {}: {}",
+            paid.payment_reference, e
+        );
+    }
+}
+
+/// Builds the response of an agent that failed after the payment and records the refund owed for it.
+/// Agents paid with credits are refunded to the credits right away, refunds of on-chain payments are sent
+/// by the refund executor. Agents charged nothing owe no refund.
+async fn record_failed_agent(
+    app_state: &web::Data<AppState>,
+    agent_db: &AgentDb,
+    prompt: &str,
+    paid: &PaidAgents,
+    status: AgentResponseStatus,
+    error: String,
+) -> (AgentResponse, Option<RefundEntitlementDb>) {
+    let payment_reference = &paid.payment_reference;
+    let charged = paid.charged(agent_db.id);
+
+    let agent_response = AgentResponse {
+        agent_id: agent_db.id,
+        prompt: prompt.to_string(),
+        response: String::new(),
+        dataset_version: agent_db.current_version,
+        status,
+        error: Some(error.clone()),
+    };
+
+    if charged == Price::ZERO {
+        return (agent_response, None);
+    }
+
+    let reason = match status {
+        AgentResponseStatus::TimedOut => RefundReason::TimedOut,
+        _ => RefundReason::Failed,
+    };
+
+    let mut refund_entitlement = match database::insert_refund_entitlement(
+        &app_state.db,
+        agent_db.id,
+        payment_reference,
+        charged,
+        reason,
+        Some(&error),
+    )
+    .await
+    {
+        Ok(entitlement) => Some(entitlement),
+        Err(e) => {
+            error!(
+                "Failed to record refund entitlement of agent {} for tx {}: {}",
+                agent_db.id, payment_reference, e
+            );
+            None
+        }
+    };
+
+    if let Some(entitlement) = refund_entitlement
+        .as_mut()
+        .filter(|_| helpers::credits::is_credit_payment(payment_reference))
+    {
+        match helpers::credits::refund_to_credits(&app_state.db, entitlement).await {
+            Ok(credited) => *entitlement = credited,
+            Err(e) => error!(
+                "Failed to refund agent {} to credits for {}: {:?}",
+                agent_db.id, payment_reference, e
+            ),
+        }
+    }
+
+    (agent_response, refund_entitlement)
 }
 
 #[utoipa::path(
@@ -519,9 +1280,10 @@ async fn get_datasets_stats_service(app_state: web::Data<AppState>) -> impl Resp
         r#"
         SELECT
             COUNT(*) as total_count,
-            COALESCE(SUM(price), 0.0) as total_price,
+            COALESCE(SUM(price), 0)::BIGINT as "total_price: Price",
             COALESCE(SUM(dataset_size), 0.0) as total_size
         FROM agents
+        WHERE status <> 'deleted'
         "#
     )
     .fetch_one(db)
@@ -541,7 +1303,217 @@ async fn get_datasets_stats_service(app_state: web::Data<AppState>) -> impl Resp
     HttpResponse::Ok().json(DatasetStatsResponse {
         success: true,
         total_count: stats.total_count.unwrap_or(0),
-        total_price: stats.total_price.unwrap_or(0.0),
+        total_price: stats.total_price.unwrap_or(Price::ZERO),
         total_size: stats.total_size.unwrap_or(0.0),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, test};
+
+    use super::*;
+    use crate::types::ChargeBasis;
+
+    #[sqlx::test]
+    async fn agent_details_hide_the_profile_values_of_privacy_mode_datasets(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let agent_ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, name, 'Salaries per employee', 1000, 'salaries.csv', 'Financial', 1.0
+            FROM owner, (VALUES ('Public salaries'), ('Private salaries')) AS agent(name)
+            RETURNING agents.id
+            "#,
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        sqlx::query(
+            r#"
+            INSERT INTO dataset_columns
+                (agent_id, position, name, data_type, null_rate, distinct_count, min_value, max_value, sample_values)
+            SELECT id, 0, 'salary', 'integer', 0.0, 3, '31000', '250000', ARRAY['31000', '250000']
+            FROM unnest($1::BIGINT[]) AS id
+            "#,
+        )
+        .bind(&agent_ids)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        sqlx::query(
+            "INSERT INTO dataset_privacy_budgets (agent_id, epsilon_total, epsilon_per_query) VALUES ($1, 10.0, 1.0)",
+        )
+        .bind(agent_ids[1])
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .service(get_agent_by_id_service),
+        )
+        .await;
+
+        let mut columns = Vec::new();
+        for agent_id in &agent_ids {
+            let response: serde_json::Value = test::call_and_read_body_json(
+                &app,
+                test::TestRequest::get()
+                    .uri(&format!("/agents/{}", agent_id))
+                    .to_request(),
+            )
+            .await;
+            columns.push(response["columns"][0].clone());
+        }
+
+        assert_eq!(columns[0]["min_value"], "31000");
+        assert_eq!(columns[0]["max_value"], "250000");
+        assert_eq!(columns[0]["sample_values"].as_array().unwrap().len(), 2);
+
+        // The schema of the dataset in privacy mode is still described
+        assert_eq!(columns[1]["name"], "salary");
+        assert_eq!(columns[1]["data_type"], "integer");
+        assert!(columns[1]["min_value"].is_null());
+        assert!(columns[1]["max_value"].is_null());
+        assert!(columns[1]["sample_values"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn agents_time_out_independently() {
+        async fn answer(delay_ms: u64) -> color_eyre::Result<&'static str> {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            Ok("answer")
+        }
+
+        async fn fail() -> color_eyre::Result<&'static str> {
+            Err(color_eyre::eyre::eyre!("model unavailable"))
+        }
+
+        let timeout = Duration::from_millis(100);
+        let started = Instant::now();
+
+        let (fast, hanging, failing) = tokio::join!(
+            tokio::time::timeout(timeout, answer(10)),
+            tokio::time::timeout(timeout, answer(10_000)),
+            tokio::time::timeout(timeout, fail()),
+        );
+
+        // The hanging agent only delays the answer up to its own timeout
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert_eq!(agent_outcome(1, fast).unwrap(), "answer");
+        assert_eq!(
+            agent_outcome(2, hanging).unwrap_err().0,
+            AgentResponseStatus::TimedOut
+        );
+        assert_eq!(
+            agent_outcome(3, failing).unwrap_err(),
+            (AgentResponseStatus::Failed, "model unavailable".to_string())
+        );
+    }
+
+    #[sqlx::test]
+    async fn failed_agents_are_owed_what_they_were_charged(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, name, 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0
+            FROM owner, (VALUES ('Paid'), ('Free sample')) AS agent(name)
+            RETURNING agents.id
+            "#,
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        let agents = database::get_agents_by_ids(&db, &agent_ids).await.unwrap();
+        let app_state = web::Data::new(AppState::for_tests(db));
+
+        let paid = PaidAgents {
+            agents: agents.clone(),
+            payer: "0x00000000000000000000000000000000000000a1".to_string(),
+            payment_reference: "0xpayment".to_string(),
+            charges: vec![
+                AgentCharge {
+                    agent_id: agent_ids[0],
+                    amount: Price::from_units(100),
+                    basis: ChargeBasis::PerQuery,
+                },
+                AgentCharge {
+                    agent_id: agent_ids[1],
+                    amount: Price::ZERO,
+                    basis: ChargeBasis::FreeSample,
+                },
+            ],
+        };
+
+        let (response, entitlement) = record_failed_agent(
+            &app_state,
+            &agents[0],
+            "Sales in Paris?",
+            &paid,
+            AgentResponseStatus::TimedOut,
+            "Agent did not answer".to_string(),
+        )
+        .await;
+
+        assert_eq!(response.status, AgentResponseStatus::TimedOut);
+        assert_eq!(response.error.as_deref(), Some("Agent did not answer"));
+
+        let entitlement = entitlement.unwrap();
+        assert_eq!(entitlement.tx_hash, "0xpayment");
+        assert_eq!(entitlement.amount, Price::from_units(100));
+        assert_eq!(entitlement.reason, "timed_out");
+        assert_eq!(entitlement.status, "pending");
+
+        // Nothing is owed for a free query
+        let (_, entitlement) = record_failed_agent(
+            &app_state,
+            &agents[1],
+            "Sales in Paris?",
+            &paid,
+            AgentResponseStatus::Failed,
+            "model unavailable".to_string(),
+        )
+        .await;
+        assert!(entitlement.is_none());
+    }
+
+    #[sqlx::test]
+    async fn duplicate_agents_are_rejected_before_any_payment(db: sqlx::Pool<sqlx::Postgres>) {
+        let app_state = web::Data::new(AppState::for_tests(db));
+
+        for pay_with_credits in [false, true] {
+            let request: GetResponseFromAgentsRequest = serde_json::from_value(serde_json::json!({
+                "agent_ids": [5, 5, 5],
+                "prompt": "Sales in Paris?",
+                "tx_hash": "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060",
+                "pay_with_credits": pay_with_credits,
+            }))
+            .unwrap();
+
+            let Err(response) = verify_paid_agents(&app_state, None, &request).await else {
+                panic!("duplicate agents were accepted");
+            };
+            assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+            let body = actix_web::body::to_bytes(response.into_body())
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error_code"], "DUPLICATE_AGENTS_SPECIFIED");
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::{net::SocketAddr, time::Duration};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post, put, web};
+use tracing::error;
+
+use crate::{
+    config::{
+        AGENT_RESPONSE_TIMEOUT_SECS, APP_CONFIG, PREVIEW_DAILY_LIMIT, PREVIEW_MAX_PROMPT_CHARS,
+    },
+    database,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    types::{
+        AgentDb, ErrorResponse, PreviewAnswerResponse, PreviewMode, PreviewQueryRequest,
+        PreviewSettingsResponse, UpdatePreviewSettingsRequest,
+    },
+};
+
+/*
+Endpoint that returns the preview mode of an agent: what its free preview questions are answered from.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/preview",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Preview settings fetched successfully", body = PreviewSettingsResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[get("/agents/{id}/preview")]
+async fn get_preview_settings_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    if let Err(response) = fetch_agent(&app_state, agent_id).await {
+        return response;
+    }
+
+    preview_settings_response(&app_state, agent_id).await
+}
+
+/*
+Endpoint for owners to choose what the free preview questions to their agent are answered from:
+the columns profile only (default), the first rows of the dataset, or no preview at all.
+Datasets in privacy mode never have a preview, whatever mode is saved.
+*/
+#[utoipa::path(
+    put,
+    path = "/agents/{id}/preview",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = UpdatePreviewSettingsRequest,
+        content_type = "application/json",
+        description = "Preview mode replacing the current one. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Preview settings updated successfully", body = PreviewSettingsResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[put("/agents/{id}/preview")]
+async fn update_preview_settings_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<UpdatePreviewSettingsRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let agent_db = match fetch_agent(&app_state, agent_id).await {
+        Ok(agent_db) => agent_db,
+        Err(response) => return response,
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can change its preview settings".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    if let Err(e) = database::upsert_preview_mode(&app_state.db, agent_id, body.mode).await {
+        error!("Failed to update preview mode: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to save preview settings in database".to_string(),
+            error_code: Some("PREVIEW_SETTINGS_SAVE_FAILED".to_string()),
+        });
+    }
+
+    preview_settings_response(&app_state, agent_id).await
+}
+
+/*
+Endpoint answering a free preview question to an agent, separate from the paid /chat/agents/answer.
+Each address and each IP gets PREVIEW_DAILY_LIMIT questions a day over all agents, answered from the
+columns profile or a few sample rows only, with a smaller output than paid answers.
+*/
+#[utoipa::path(
+    post,
+    path = "/chat/agents/{id}/preview",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = PreviewQueryRequest,
+        content_type = "application/json",
+        description = "Preview question. Signature headers are optional, signed questions also count against the signer's limit."
+    ),
+    responses(
+        (status = 200, description = "Preview answer", body = PreviewAnswerResponse),
+        (status = 400, description = "Bad request - invalid prompt or agent not active", body = ErrorResponse),
+        (status = 403, description = "Preview disabled by the agent owner or dataset in privacy mode", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 429, description = "Daily preview limit reached", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/chat/agents/{id}/preview")]
+async fn preview_agent_service(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<i64>,
+    auth: Option<SignedAddress>,
+    body: web::Json<PreviewQueryRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+    let prompt = body.prompt.trim();
+
+    if prompt.is_empty() || prompt.chars().count() > PREVIEW_MAX_PROMPT_CHARS {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Preview prompt must be between 1 and {} characters",
+                PREVIEW_MAX_PROMPT_CHARS
+            ),
+            error_code: Some("INVALID_PROMPT".to_string()),
+        });
+    }
+
+    let Some(ip_address) = client_ip(&req) else {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Client IP address is unknown".to_string(),
+            error_code: Some("UNKNOWN_CLIENT_IP".to_string()),
+        });
+    };
+
+    let agent_db = match fetch_agent(&app_state, agent_id).await {
+        Ok(agent_db) => agent_db,
+        Err(response) => return response,
+    };
+
+    if agent_db.status != "active" {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Agent {} is {} and can't be previewed",
+                agent_db.id, agent_db.status
+            ),
+            error_code: Some("AGENT_NOT_ACTIVE".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let mode = match helpers::preview::preview_mode(db, agent_id).await {
+        Ok(PreviewMode::Disabled) => {
+            return HttpResponse::Forbidden().json(ErrorResponse {
+                success: false,
+                message: format!("Agent {} has no preview", agent_id),
+                error_code: Some("PREVIEW_DISABLED".to_string()),
+            });
+        }
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("Failed to get preview mode: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get preview settings from database".to_string(),
+                error_code: Some("PREVIEW_SETTINGS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Previews are counted under the checksummed address
+    let requester_address = auth.map(|auth| auth.address.to_string());
+
+    let (preview_query_id, remaining_today) = match helpers::preview::reserve_preview_query(
+        db,
+        agent_id,
+        &ip_address,
+        requester_address.as_deref(),
+        prompt,
+        mode,
+    )
+    .await
+    {
+        Ok(Some(reservation)) => reservation,
+        Ok(None) => {
+            return HttpResponse::TooManyRequests().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "Daily limit of {} preview questions reached, paid questions are not limited",
+                    PREVIEW_DAILY_LIMIT
+                ),
+                error_code: Some("PREVIEW_LIMIT_REACHED".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to record preview query: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record preview question".to_string(),
+                error_code: Some("PREVIEW_RECORD_FAILED".to_string()),
+            });
+        }
+    };
+
+    let outcome = tokio::time::timeout(
+        Duration::from_secs(AGENT_RESPONSE_TIMEOUT_SECS),
+        helpers::preview::answer_preview(&app_state, &agent_db, mode, prompt),
+    )
+    .await;
+
+    let error = match outcome {
+        Ok(Ok((response, truncated))) => {
+            return HttpResponse::Ok().json(PreviewAnswerResponse {
+                success: true,
+                agent_id,
+                mode,
+                response,
+                truncated,
+                remaining_today,
+            });
+        }
+        Ok(Err(e)) => format!("{:?}", e),
+        Err(_) => "Agent timed out".to_string(),
+    };
+
+    error!("Preview of agent {} failed: {}", agent_id, error);
+
+    // Failed previews don't count against the daily limit
+    if let Err(e) = database::delete_preview_query(db, preview_query_id).await {
+        error!("Failed to release preview query: {}", e);
+    }
+
+    HttpResponse::InternalServerError().json(ErrorResponse {
+        success: false,
+        message: "Failed to get preview answer".to_string(),
+        error_code: Some("PREVIEW_FAILED".to_string()),
+    })
+}
+
+/// IP of the client, from the proxy headers when they are trusted
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    if APP_CONFIG.trust_proxy_headers {
+        // Falls back to the peer address, with its port, without proxy headers
+        return req.connection_info().realip_remote_addr().map(|ip| {
+            ip.parse::<SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|_| ip.to_string())
+        });
+    }
+
+    req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+async fn fetch_agent(
+    app_state: &web::Data<AppState>,
+    agent_id: i64,
+) -> Result<AgentDb, HttpResponse> {
+    match database::get_agent_by_id(&app_state.db, agent_id).await {
+        Ok(agent) => Ok(agent),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json(ErrorResponse {
+            success: false,
+            message: format!("Agent with id {} not found", agent_id),
+            error_code: Some("AGENT_NOT_FOUND".to_string()),
+        })),
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            }))
+        }
+    }
+}
+
+async fn preview_settings_response(app_state: &web::Data<AppState>, agent_id: i64) -> HttpResponse {
+    match helpers::preview::preview_mode(&app_state.db, agent_id).await {
+        Ok(mode) => HttpResponse::Ok().json(PreviewSettingsResponse {
+            success: true,
+            agent_id,
+            mode,
+            daily_limit: PREVIEW_DAILY_LIMIT,
+        }),
+        Err(e) => {
+            error!("Failed to get preview mode: {:?}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get preview settings from database".to_string(),
+                error_code: Some("PREVIEW_SETTINGS_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
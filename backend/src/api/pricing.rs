@@ -0,0 +1,494 @@
+use std::collections::HashSet;
+
+use actix_web::{HttpResponse, Responder, get, post, put, web};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    config::{MAX_FREE_SAMPLE_QUERIES, MAX_PRICE_TIERS, MAX_SUBSCRIPTION_PERIOD_DAYS},
+    database,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    types::{
+        AgentDb, AgentSubscriptionDb, ErrorResponse, LedgerTransactionKind, Price, PricingModel,
+        PricingPlanResponse, SubscribeResponse, UpdatePricingPlanRequest,
+    },
+};
+
+/*
+Endpoint that returns the pricing plan of an agent.
+Signed requests also get the charge of the signer's next query and their active subscription.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/pricing-plan",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Pricing plan fetched successfully", body = PricingPlanResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[get("/agents/{id}/pricing-plan")]
+async fn get_pricing_plan_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: Option<SignedAddress>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let agents = std::slice::from_ref(&agent_db);
+
+    let pricing_plan = match helpers::pricing::pricing_plans(db, agents).await {
+        Ok(mut plans) => plans.remove(0),
+        Err(e) => {
+            error!("Failed to get pricing plan: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get pricing plan from database".to_string(),
+                error_code: Some("PRICING_PLAN_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let (next_charge, subscription) = match auth {
+        Some(auth) => {
+            let user_address = auth.address.to_string();
+
+            let charge = helpers::pricing::agent_charges(db, Some(&user_address), agents).await;
+            let subscriptions =
+                database::get_active_subscriptions(db, &user_address, &[agent_id]).await;
+
+            match (charge, subscriptions) {
+                (Ok(mut charges), Ok(mut subscriptions)) => (
+                    charges.pop(),
+                    (!subscriptions.is_empty()).then(|| subscriptions.remove(0)),
+                ),
+                (Err(e), _) => {
+                    error!("Failed to compute charge of {}: {:?}", user_address, e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to compute charges".to_string(),
+                        error_code: Some("PRICING_FAILED".to_string()),
+                    });
+                }
+                (_, Err(e)) => {
+                    error!("Failed to get subscriptions of {}: {}", user_address, e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get subscriptions from database".to_string(),
+                        error_code: Some("SUBSCRIPTIONS_FETCH_FAILED".to_string()),
+                    });
+                }
+            }
+        }
+        None => (None, None),
+    };
+
+    HttpResponse::Ok().json(PricingPlanResponse {
+        success: true,
+        agent_id,
+        pricing_plan,
+        next_charge,
+        subscription,
+    })
+}
+
+/*
+Endpoint for owners to set the pricing plan of their agent: per query, subscription or volume tiers,
+with optional free sample queries. The per query price stays the agent price, updated with PATCH /agents/{id}.
+*/
+#[utoipa::path(
+    put,
+    path = "/agents/{id}/pricing-plan",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = UpdatePricingPlanRequest,
+        content_type = "application/json",
+        description = "Pricing plan replacing the current one. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Pricing plan updated successfully", body = PricingPlanResponse),
+        (status = 400, description = "Bad request - invalid pricing plan", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 410, description = "Agent deleted", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[put("/agents/{id}/pricing-plan")]
+async fn update_pricing_plan_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<UpdatePricingPlanRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    if let Err(message) = validate_pricing_plan(&body) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message,
+            error_code: Some("INVALID_PRICING_PLAN".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can update its pricing plan".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    if agent_db.status == "deleted" {
+        return HttpResponse::Gone().json(ErrorResponse {
+            success: false,
+            message: format!("Agent with id {} has been deleted", agent_id),
+            error_code: Some("AGENT_DELETED".to_string()),
+        });
+    }
+
+    let mut tiers = body.tiers.clone();
+    tiers.sort_by_key(|tier| tier.min_queries);
+
+    let is_subscription = body.model == PricingModel::Subscription;
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    let plan = match database::upsert_pricing_plan(
+        &mut tx,
+        agent_id,
+        body.model,
+        body.subscription_price.filter(|_| is_subscription),
+        body.subscription_period_days.filter(|_| is_subscription),
+        body.free_sample_queries,
+    )
+    .await
+    {
+        Ok(plan) => plan,
+        Err(e) => {
+            error!("Failed to save pricing plan: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to save pricing plan".to_string(),
+                error_code: Some("PRICING_PLAN_UPDATE_FAILED".to_string()),
+            });
+        }
+    };
+
+    let tiers = match database::replace_price_tiers(&mut tx, agent_id, &tiers).await {
+        Ok(tiers) => tiers,
+        Err(e) => {
+            error!("Failed to save price tiers: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to save pricing plan".to_string(),
+                error_code: Some("PRICING_PLAN_UPDATE_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    info!(
+        "Pricing plan of agent {} set to {}",
+        agent_id,
+        body.model.to_string()
+    );
+
+    HttpResponse::Ok().json(PricingPlanResponse {
+        success: true,
+        agent_id,
+        pricing_plan: helpers::pricing::pricing_plan(&agent_db, Some(&plan), &tiers),
+        next_charge: None,
+        subscription: None,
+    })
+}
+
+/*
+Endpoint that subscribes the signer to an agent with the subscription model, paid with their prepaid credits.
+A subscription bought before the current one expires starts when it expires.
+*/
+#[utoipa::path(
+    post,
+    path = "/agents/{id}/subscriptions",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Subscribed successfully", body = SubscribeResponse),
+        (status = 400, description = "Agent not active or without subscription", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 402, description = "Insufficient credits", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/agents/{id}/subscriptions")]
+async fn subscribe_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if agent_db.status != "active" {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Agent {} is {} and can't be subscribed to",
+                agent_db.id, agent_db.status
+            ),
+            error_code: Some("AGENT_NOT_ACTIVE".to_string()),
+        });
+    }
+
+    let plan = match helpers::pricing::pricing_plans(db, std::slice::from_ref(&agent_db)).await {
+        Ok(mut plans) => plans.remove(0),
+        Err(e) => {
+            error!("Failed to get pricing plan: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get pricing plan from database".to_string(),
+                error_code: Some("PRICING_PLAN_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let (PricingModel::Subscription, Some(price), Some(period_days)) = (
+        plan.model,
+        plan.subscription_price,
+        plan.subscription_period_days,
+    ) else {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Agent {} has no subscription plan", agent_id),
+            error_code: Some("NO_SUBSCRIPTION_PLAN".to_string()),
+        });
+    };
+
+    match subscribe_with_credits(&app_state, &agent_db, &auth, price, period_days).await {
+        Ok(Some(subscription)) => HttpResponse::Ok().json(SubscribeResponse {
+            success: true,
+            subscription,
+        }),
+        Ok(None) => HttpResponse::PaymentRequired().json(ErrorResponse {
+            success: false,
+            message: format!("Insufficient credits to subscribe to agent {}", agent_id),
+            error_code: Some("INSUFFICIENT_CREDITS".to_string()),
+        }),
+        Err(e) => {
+            error!("Failed to subscribe to agent {}: {:?}", agent_id, e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to subscribe".to_string(),
+                error_code: Some("SUBSCRIPTION_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/// Debits the subscription price and records the subscription in one transaction,
+/// returns None when the credits don't cover the price
+async fn subscribe_with_credits(
+    app_state: &web::Data<AppState>,
+    agent_db: &AgentDb,
+    auth: &SignedAddress,
+    price: Price,
+    period_days: i32,
+) -> color_eyre::Result<Option<AgentSubscriptionDb>> {
+    let user_address = auth.address.to_string();
+    let reference = format!("subscription:{}", Uuid::new_v4());
+
+    let mut tx = app_state.db.begin().await?;
+
+    if !helpers::credits::debit_credits(
+        &mut tx,
+        &user_address,
+        LedgerTransactionKind::Subscription,
+        &reference,
+        &[(agent_db.id, price)],
+    )
+    .await?
+    {
+        return Ok(None);
+    }
+
+    let subscription = database::insert_agent_subscription(
+        &mut tx,
+        agent_db.id,
+        &user_address,
+        &reference,
+        price,
+        period_days,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    info!(
+        "{} subscribed to agent {} until {}",
+        user_address, agent_db.id, subscription.expires_at
+    );
+
+    Ok(Some(subscription))
+}
+
+fn validate_pricing_plan(plan: &UpdatePricingPlanRequest) -> Result<(), String> {
+    let price_range = Price::from_units(1)..=Price::from_whole(50000000);
+
+    if !(0..=MAX_FREE_SAMPLE_QUERIES).contains(&plan.free_sample_queries) {
+        return Err(format!(
+            "Free sample queries must be between 0 and {}",
+            MAX_FREE_SAMPLE_QUERIES
+        ));
+    }
+
+    if plan.model == PricingModel::Subscription {
+        if !plan
+            .subscription_price
+            .is_some_and(|price| price_range.contains(&price))
+        {
+            return Err("Subscription price must be between 0.00000001 and 50000000".to_string());
+        }
+
+        if !plan
+            .subscription_period_days
+            .is_some_and(|days| (1..=MAX_SUBSCRIPTION_PERIOD_DAYS).contains(&days))
+        {
+            return Err(format!(
+                "Subscription period must be between 1 and {} days",
+                MAX_SUBSCRIPTION_PERIOD_DAYS
+            ));
+        }
+    }
+
+    if plan.model != PricingModel::VolumeTiers {
+        if !plan.tiers.is_empty() {
+            return Err("Tiers are only allowed with the volume_tiers model".to_string());
+        }
+
+        return Ok(());
+    }
+
+    if plan.tiers.is_empty() || plan.tiers.len() > MAX_PRICE_TIERS {
+        return Err(format!(
+            "Volume tiers pricing needs between 1 and {} tiers",
+            MAX_PRICE_TIERS
+        ));
+    }
+
+    if plan.tiers.iter().any(|tier| tier.min_queries < 1) {
+        return Err("Tiers min_queries must be at least 1".to_string());
+    }
+
+    if plan
+        .tiers
+        .iter()
+        .map(|tier| tier.min_queries)
+        .collect::<HashSet<_>>()
+        .len()
+        != plan.tiers.len()
+    {
+        return Err("Tiers min_queries must be unique".to_string());
+    }
+
+    if plan
+        .tiers
+        .iter()
+        .any(|tier| !price_range.contains(&tier.price))
+    {
+        return Err("Tiers price must be between 0.00000001 and 50000000".to_string());
+    }
+
+    Ok(())
+}
@@ -2,8 +2,9 @@ use actix_web::{HttpResponse, Responder, get, web};
 
 use crate::{
     database,
+    helpers::auth::SignedAddress,
     state::AppState,
-    types::{ErrorResponse, ProfileResponse},
+    types::{ErrorResponse, GuardrailEventsResponse, ProfileResponse},
 };
 
 #[utoipa::path(
@@ -46,3 +47,54 @@ async fn get_profile_service(
         agents: agents_db,
     })
 }
+
+#[utoipa::path(
+    get,
+    path = "/users/{address}/guardrail-events",
+    params(
+        ("address" = String, Path, description = "Dataset owner address")
+    ),
+    responses(
+        (status = 200, description = "Guardrail events of the owner agents retrieved successfully", body = GuardrailEventsResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the signer address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/users/{address}/guardrail-events")]
+async fn get_guardrail_events_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let owner_address = path.into_inner();
+
+    // Events contain the prompts of buyers, only the dataset owner can read them
+    if !auth.matches(&owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the owner of the address can see its guardrail events".to_string(),
+            error_code: Some("NOT_ADDRESS_OWNER".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let events = match database::get_guardrail_events_by_owner_address(db, &owner_address).await {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::error!("Failed to get guardrail events: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get guardrail events from database".to_string(),
+                error_code: Some("GUARDRAIL_EVENTS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(GuardrailEventsResponse {
+        success: true,
+        events,
+    })
+}
@@ -0,0 +1,337 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    config::{QUERY_LOG_DEFAULT_LIMIT, QUERY_LOG_MAX_LIMIT},
+    database,
+    helpers::auth::SignedAddress,
+    state::AppState,
+    types::{
+        AgentQueryLogResponse, ErrorResponse, QueryDb, QueryLogEntry, QueryLogParams,
+        QueryLogResponse,
+    },
+};
+
+/*
+Endpoint that returns the questions a user paid for, most recent first: agents asked, what was charged,
+how each agent answered and the hashes of the prompt and answers.
+*/
+#[utoipa::path(
+    get,
+    path = "/users/{address}/queries",
+    params(
+        ("address" = String, Path, description = "User address"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of queries returned (default: 50, max: 200)"),
+        ("before_id" = Option<i64>, Query, description = "Only queries older than this query id, to fetch the next page")
+    ),
+    responses(
+        (status = 200, description = "Queries fetched successfully", body = QueryLogResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the signer address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/users/{address}/queries")]
+async fn get_user_queries_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<QueryLogParams>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let address = path.into_inner();
+
+    if !auth.matches(&address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the owner of the address can see its queries".to_string(),
+            error_code: Some("NOT_ADDRESS_OWNER".to_string()),
+        });
+    }
+
+    let limit = match query_log_limit(&query) {
+        Ok(limit) => limit,
+        Err(response) => return response,
+    };
+
+    // Queries are logged under the checksummed address
+    let queries = match database::get_queries_by_requester(
+        &app_state.db,
+        &auth.address.to_string(),
+        query.before_id,
+        limit,
+    )
+    .await
+    {
+        Ok(queries) => queries,
+        Err(e) => {
+            error!("Failed to get queries: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get queries from database".to_string(),
+                error_code: Some("QUERIES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    query_log_response(&app_state, queries).await
+}
+
+/*
+Endpoint for owners to audit the questions asked to their agent, most recent first.
+Only the outcome of their agent is returned for each question, never the requester, the prompt or the other agents asked.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/queries",
+    params(
+        ("id" = i64, Path, description = "Agent id"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of queries returned (default: 50, max: 200)"),
+        ("before_id" = Option<i64>, Query, description = "Only queries older than this query id, to fetch the next page")
+    ),
+    responses(
+        (status = 200, description = "Queries fetched successfully", body = AgentQueryLogResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[get("/agents/{id}/queries")]
+async fn get_agent_queries_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<QueryLogParams>,
+    auth: SignedAddress,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let limit = match query_log_limit(&query) {
+        Ok(limit) => limit,
+        Err(response) => return response,
+    };
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can see its queries".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    let queries = match database::get_queries_by_agent(db, agent_id, query.before_id, limit).await {
+        Ok(queries) => queries,
+        Err(e) => {
+            error!("Failed to get queries: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get queries from database".to_string(),
+                error_code: Some("QUERIES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(AgentQueryLogResponse {
+        success: true,
+        queries,
+    })
+}
+
+fn query_log_limit(query: &QueryLogParams) -> Result<i64, HttpResponse> {
+    let limit = query.limit.unwrap_or(QUERY_LOG_DEFAULT_LIMIT);
+
+    if limit <= 0 || limit > QUERY_LOG_MAX_LIMIT {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("limit must be between 1 and {}", QUERY_LOG_MAX_LIMIT),
+            error_code: Some("INVALID_LIMIT".to_string()),
+        }));
+    }
+
+    Ok(limit)
+}
+
+/// Response with the queries and the outcome of their agents
+async fn query_log_response(
+    app_state: &web::Data<AppState>,
+    queries: Vec<QueryDb>,
+) -> HttpResponse {
+    let query_ids: Vec<i64> = queries.iter().map(|query| query.id).collect();
+
+    let query_agents = match database::get_query_agents(&app_state.db, &query_ids).await {
+        Ok(query_agents) => query_agents,
+        Err(e) => {
+            error!("Failed to get query agents: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get queries from database".to_string(),
+                error_code: Some("QUERIES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let queries = queries
+        .into_iter()
+        .map(|query| QueryLogEntry {
+            agents: query_agents
+                .iter()
+                .filter(|agent| agent.query_id == query.id)
+                .cloned()
+                .collect(),
+            query,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(QueryLogResponse {
+        success: true,
+        queries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::{
+        helpers::{
+            self,
+            auth::{hash_signed_body, signed_test_request},
+        },
+        types::{AgentResponseStatus, NewQuery, NewQueryAgent, Price, QueryOutcome},
+    };
+
+    async fn insert_agent(db: &sqlx::Pool<sqlx::Postgres>, owner: &PrivateKeySigner) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(owner.address().to_string())
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    fn query_agent(agent_id: i64) -> NewQueryAgent {
+        NewQueryAgent {
+            agent_id,
+            status: AgentResponseStatus::Success,
+            response_hash: Some(helpers::queries::hash_text("10")),
+            latency_ms: 120,
+            usage: None,
+            model: "model".to_string(),
+            dataset_version: 1,
+            amount: Price::from_units(100),
+            error: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn query_logs_are_only_shown_to_the_requester_and_the_agent_owners(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let requester = PrivateKeySigner::random();
+        let owner = PrivateKeySigner::random();
+        let other_owner = PrivateKeySigner::random();
+        let agent_id = insert_agent(&db, &owner).await;
+        let other_agent_id = insert_agent(&db, &other_owner).await;
+
+        helpers::queries::log_query(
+            &db,
+            &NewQuery {
+                requester_address: requester.address().to_string(),
+                agent_ids: vec![agent_id, other_agent_id],
+                prompt_hash: helpers::queries::hash_text("Sales in Paris?"),
+                prompt: Some("Sales in Paris?".to_string()),
+                payment_reference: "0xpayment".to_string(),
+                amount: Price::from_units(200),
+                outcome: QueryOutcome::Success,
+                latency_ms: 150,
+                streamed: false,
+                agents: vec![query_agent(agent_id), query_agent(other_agent_id)],
+            },
+        )
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(get_user_queries_service)
+                .service(get_agent_queries_service),
+        )
+        .await;
+
+        let get = |signer: &PrivateKeySigner, uri: &str| {
+            signed_test_request(signer, Method::GET, uri, "").to_request()
+        };
+
+        let user_uri = format!("/users/{}/queries", requester.address());
+        let response: serde_json::Value =
+            test::call_and_read_body_json(&app, get(&requester, &user_uri)).await;
+        assert_eq!(response["queries"][0]["prompt"], "Sales in Paris?");
+        assert_eq!(
+            response["queries"][0]["agents"].as_array().unwrap().len(),
+            2
+        );
+
+        let response = test::call_service(&app, get(&owner, &user_uri)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response =
+            test::call_service(&app, get(&requester, &format!("{}?limit=0", user_uri))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Owners only see the outcome of their own agent
+        let agent_uri = format!("/agents/{}/queries", agent_id);
+        let response: serde_json::Value =
+            test::call_and_read_body_json(&app, get(&owner, &agent_uri)).await;
+        let queries = response["queries"].as_array().unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0]["amount"], "0.000001");
+        assert!(queries[0].get("prompt").is_none());
+        assert!(queries[0].get("requester_address").is_none());
+
+        for signer in [&other_owner, &requester] {
+            let response = test::call_service(&app, get(signer, &agent_uri)).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::HashSet, str::FromStr};
+
+use actix_web::{HttpResponse, Responder, post, web};
+use alloy::primitives::Address;
+use tracing::error;
+
+use crate::{
+    config::MAX_ALLOWED_SELECTED_AGENTS,
+    database, helpers,
+    state::AppState,
+    types::{AgentDb, ErrorResponse, Price, QuoteRequest, QuoteResponse},
+};
+
+/*
+Endpoint that quotes the price of asking the specified agents and builds the payForMultipleDatasets transaction paying it.
+Each agent is charged under its pricing plan for the payer: free samples, subscription or volume tier.
+The signed quote is sent back with the tx hash to /chat/agents/answer, the payment is then checked against it exactly.
+*/
+#[utoipa::path(
+    post,
+    path = "/chat/quote",
+    request_body(
+        content = QuoteRequest,
+        content_type = "application/json",
+        description = "Agents to pay for and address that will pay"
+    ),
+    responses(
+        (status = 200, description = "Quote created successfully", body = QuoteResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/chat/quote")]
+async fn create_quote_service(
+    app_state: web::Data<AppState>,
+    body: web::Json<QuoteRequest>,
+) -> impl Responder {
+    let Ok(payer) = Address::from_str(&body.payer_address) else {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Invalid payer address: {}", body.payer_address),
+            error_code: Some("INVALID_PAYER_ADDRESS".to_string()),
+        });
+    };
+
+    let agent_ids = &body.agent_ids;
+
+    if agent_ids.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "No agents specified".to_string(),
+            error_code: Some("NO_AGENTS_SPECIFIED".to_string()),
+        });
+    }
+
+    if agent_ids.len() > MAX_ALLOWED_SELECTED_AGENTS {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Too many agents specified".to_string(),
+            error_code: Some("TOO_MANY_AGENTS_SPECIFIED".to_string()),
+        });
+    }
+
+    if agent_ids.iter().collect::<HashSet<_>>().len() != agent_ids.len() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Agents can only be specified once".to_string(),
+            error_code: Some("DUPLICATE_AGENTS_SPECIFIED".to_string()),
+        });
+    }
+
+    let agents_db = match database::get_agents_by_ids(&app_state.db, agent_ids).await {
+        Ok(agents) => agents,
+        Err(e) => {
+            error!("Failed to get agents: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agents from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Keep the order of the request
+    let mut agents: Vec<AgentDb> = Vec::new();
+
+    for agent_id in agent_ids {
+        let Some(agent) = agents_db.iter().find(|agent| agent.id == *agent_id) else {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        };
+
+        if agent.status != "active" {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "Agent {} is {} and can't be paid for",
+                    agent.id, agent.status
+                ),
+                error_code: Some("AGENT_NOT_ACTIVE".to_string()),
+            });
+        }
+
+        if agent.nft_id.is_none() {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Agent {} has no NFT yet and can't be paid for", agent.id),
+                error_code: Some("AGENT_NOT_MINTED".to_string()),
+            });
+        }
+
+        agents.push(agent.clone());
+    }
+
+    let charges =
+        match helpers::pricing::agent_charges(&app_state.db, Some(&payer.to_string()), &agents)
+            .await
+        {
+            Ok(charges) => charges,
+            Err(e) => {
+                error!("Failed to compute charges of {}: {:?}", payer, e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to compute charges".to_string(),
+                    error_code: Some("PRICING_FAILED".to_string()),
+                });
+            }
+        };
+
+    // Nothing to send on-chain, the answer is asked with pay_with_credits without spending any
+    if charges.iter().all(|charge| charge.amount == Price::ZERO) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Nothing to pay for these agents, ask them with pay_with_credits".to_string(),
+            error_code: Some("NO_PAYMENT_REQUIRED".to_string()),
+        });
+    }
+
+    let quote = match helpers::quote::build_quote(&app_state.quote_signer, payer, &agents, &charges)
+    {
+        Ok(quote) => quote,
+        Err(e) => {
+            error!("Failed to build quote: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to build quote".to_string(),
+                error_code: Some("QUOTE_FAILED".to_string()),
+            });
+        }
+    };
+
+    let tx_request = match helpers::quote::quote_tx_request(&quote) {
+        Ok(tx_request) => tx_request,
+        Err(e) => {
+            error!("Failed to build quote transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to build quote transaction".to_string(),
+                error_code: Some("QUOTE_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(QuoteResponse {
+        success: true,
+        quote,
+        signer_address: app_state.quote_signer.address().to_string(),
+        tx_request,
+        charges,
+    })
+}
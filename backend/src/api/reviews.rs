@@ -0,0 +1,311 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+use tracing::error;
+
+use crate::{
+    config::{
+        MAX_REVIEW_COMMENT_CHARS, MAX_REVIEW_RATING, MIN_REVIEW_RATING, REVIEWS_DEFAULT_LIMIT,
+        REVIEWS_MAX_LIMIT,
+    },
+    database,
+    helpers::auth::SignedAddress,
+    state::AppState,
+    types::{
+        AgentReviewsParams, AgentReviewsResponse, CreateReviewRequest, ErrorResponse,
+        ReviewResponse,
+    },
+};
+
+/*
+Endpoint for users to rate and comment an agent they paid a query to, free samples don't count.
+An address has one review per agent, posting again replaces it.
+*/
+#[utoipa::path(
+    post,
+    path = "/agents/{id}/reviews",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = CreateReviewRequest,
+        content_type = "application/json",
+        description = "Rating from 1 to 5 and optional comment. Requires the reviewer signature headers."
+    ),
+    responses(
+        (status = 200, description = "Review saved successfully", body = ReviewResponse),
+        (status = 400, description = "Bad request - invalid rating or comment", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "No paid query to the agent or agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/agents/{id}/reviews")]
+async fn create_review_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    body: web::Json<CreateReviewRequest>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    if !(MIN_REVIEW_RATING..=MAX_REVIEW_RATING).contains(&body.rating) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Rating must be between {} and {}",
+                MIN_REVIEW_RATING, MAX_REVIEW_RATING
+            ),
+            error_code: Some("INVALID_RATING".to_string()),
+        });
+    }
+
+    let comment = body
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|comment| !comment.is_empty());
+
+    if comment.is_some_and(|comment| comment.chars().count() > MAX_REVIEW_COMMENT_CHARS) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "Review comment must be at most {} characters",
+                MAX_REVIEW_COMMENT_CHARS
+            ),
+            error_code: Some("INVALID_REVIEW_COMMENT".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Owners can't review their own agent".to_string(),
+            error_code: Some("OWN_AGENT_REVIEW".to_string()),
+        });
+    }
+
+    // Reviews are stored under the checksummed address
+    let reviewer_address = auth.address.to_string();
+
+    match database::has_paid_agent_query(db, agent_id, &reviewer_address).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Forbidden().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "Only users who paid a query to agent {} can review it",
+                    agent_id
+                ),
+                error_code: Some("NO_PAID_QUERY".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to check paid queries: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to check paid queries in database".to_string(),
+                error_code: Some("USAGE_FETCH_FAILED".to_string()),
+            });
+        }
+    }
+
+    match database::upsert_agent_review(db, agent_id, &reviewer_address, body.rating, comment).await
+    {
+        Ok(review) => HttpResponse::Ok().json(ReviewResponse {
+            success: true,
+            review,
+        }),
+        Err(e) => {
+            error!("Failed to save review: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to save review in database".to_string(),
+                error_code: Some("REVIEW_SAVE_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Endpoint that returns the rating of an agent and its reviews, most recent first.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/reviews",
+    params(
+        ("id" = i64, Path, description = "Agent id"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of reviews returned (default: 50, max: 200)"),
+        ("before_id" = Option<i64>, Query, description = "Only reviews older than this review id, to fetch the next page")
+    ),
+    responses(
+        (status = 200, description = "Reviews fetched successfully", body = AgentReviewsResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[get("/agents/{id}/reviews")]
+async fn get_agent_reviews_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<AgentReviewsParams>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let limit = query.limit.unwrap_or(REVIEWS_DEFAULT_LIMIT);
+
+    if limit <= 0 || limit > REVIEWS_MAX_LIMIT {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("limit must be between 1 and {}", REVIEWS_MAX_LIMIT),
+            error_code: Some("INVALID_LIMIT".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    match database::get_agent_reviews(db, agent_id, query.before_id, limit).await {
+        Ok(reviews) => HttpResponse::Ok().json(AgentReviewsResponse {
+            success: true,
+            review_count: agent_db.review_count,
+            average_rating: agent_db.average_rating,
+            reviews,
+        }),
+        Err(e) => {
+            error!("Failed to get reviews: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get reviews from database".to_string(),
+                error_code: Some("REVIEWS_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::helpers::auth::{hash_signed_body, signed_test_request};
+
+    #[sqlx::test]
+    async fn only_paying_users_review_an_agent(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let buyer = PrivateKeySigner::random();
+        let sampler = PrivateKeySigner::random();
+
+        let agent_id: i64 = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(owner.address().to_string())
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        // Usage may be recorded in lowercase, the check ignores the case
+        sqlx::query(
+            r#"
+            INSERT INTO agent_usage (agent_id, user_address, payment_reference, basis, amount)
+            VALUES ($1, $2, '0xpaid', 'per_query', 100), ($1, $3, '0xsample', 'free_sample', 0)
+            "#,
+        )
+        .bind(agent_id)
+        .bind(buyer.address().to_string().to_lowercase())
+        .bind(sampler.address().to_string())
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(create_review_service),
+        )
+        .await;
+        let uri = format!("/agents/{}/reviews", agent_id);
+
+        let review = |signer: &PrivateKeySigner, body: &str| {
+            signed_test_request(signer, Method::POST, &uri, body).to_request()
+        };
+
+        for signer in [&owner, &sampler] {
+            let response = test::call_service(&app, review(signer, r#"{"rating":5}"#)).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let response = test::call_service(&app, review(&buyer, r#"{"rating":6}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response: serde_json::Value =
+            test::call_and_read_body_json(&app, review(&buyer, r#"{"rating":2,"comment":"  "}"#))
+                .await;
+        assert_eq!(response["review"]["rating"], 2);
+        assert!(response["review"]["comment"].is_null());
+
+        // Reviewing again replaces the review
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            review(&buyer, r#"{"rating":4,"comment":"Accurate"}"#),
+        )
+        .await;
+        assert_eq!(response["review"]["rating"], 4);
+        assert_eq!(response["review"]["comment"], "Accurate");
+    }
+}
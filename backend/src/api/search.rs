@@ -0,0 +1,105 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    config::{AGENT_SEARCH_DEFAULT_LIMIT, AGENT_SEARCH_MAX_LIMIT},
+    database, helpers,
+    state::AppState,
+    types::{AgentSearchParams, AgentSearchResponse, AgentSearchResult, ErrorResponse},
+};
+
+/*
+Endpoint that searches the active agents by meaning rather than by name,
+comparing the embedding of the query with the embeddings of the agents name, description, category and columns.
+*/
+#[utoipa::path(
+    get,
+    path = "/agents/search",
+    params(
+        ("q" = String, Query, description = "Free text describing the data looked for"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of agents returned (default: 10, max: 50)")
+    ),
+    responses(
+        (status = 200, description = "Agents found successfully", body = AgentSearchResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[get("/agents/search")]
+async fn search_agents_service(
+    app_state: web::Data<AppState>,
+    query: web::Query<AgentSearchParams>,
+) -> impl Responder {
+    let search = query.q.trim();
+
+    if search.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Search query cannot be empty".to_string(),
+            error_code: Some("EMPTY_SEARCH_QUERY".to_string()),
+        });
+    }
+
+    let limit = query.limit.unwrap_or(AGENT_SEARCH_DEFAULT_LIMIT);
+
+    if limit == 0 || limit > AGENT_SEARCH_MAX_LIMIT {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("limit must be between 1 and {}", AGENT_SEARCH_MAX_LIMIT),
+            error_code: Some("INVALID_LIMIT".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let agents = match database::get_active_agents(db).await {
+        Ok(agents) => agents,
+        Err(e) => {
+            error!("Failed to get agents: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agents from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let similarities = match helpers::embeddings::rank_agents_by_similarity(
+        db,
+        &app_state.embedder,
+        search,
+        &agents,
+    )
+    .await
+    {
+        Ok(similarities) => similarities,
+        Err(e) => {
+            error!("Failed to rank agents by similarity: {:?}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to search agents".to_string(),
+                error_code: Some("AGENT_SEARCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let results = similarities
+        .into_iter()
+        .take(limit)
+        .filter_map(|(agent_id, similarity)| {
+            agents
+                .iter()
+                .find(|agent| agent.id == agent_id)
+                .map(|agent| AgentSearchResult {
+                    agent: agent.clone(),
+                    similarity,
+                })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(AgentSearchResponse {
+        success: true,
+        results,
+    })
+}
@@ -0,0 +1,179 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use chrono::{Duration, Utc};
+use tracing::error;
+
+use crate::{
+    config::{
+        STATS_DEFAULT_RANGE_DAYS, STATS_MAX_RANGE_DAYS, STATS_TOP_DATASETS_DEFAULT_LIMIT,
+        STATS_TOP_DATASETS_MAX_LIMIT,
+    },
+    database,
+    state::AppState,
+    types::{ErrorResponse, MarketplaceStatsParams, MarketplaceStatsResponse},
+};
+
+const STATS_INTERVALS: [&str; 3] = ["day", "week", "month"];
+
+/*
+Endpoint that returns the marketplace statistics over a time range: totals, per-category breakdown,
+top datasets by queries and revenue, and the new datasets and active buyers per day, week or month.
+Usage figures come from aggregates refreshed every STATS_REFRESH_INTERVAL_SECS.
+*/
+#[utoipa::path(
+    get,
+    path = "/marketplace/stats",
+    params(
+        ("from" = Option<String>, Query, description = "Start of the time range, RFC 3339 (default: 30 days before `to`)"),
+        ("to" = Option<String>, Query, description = "End of the time range, RFC 3339 (default: now)"),
+        ("interval" = Option<String>, Query, description = "Bucket of the time series: day, week or month (default: day)"),
+        ("limit" = Option<i64>, Query, description = "Number of top datasets returned (default: 10, max: 50)")
+    ),
+    responses(
+        (status = 200, description = "Marketplace statistics retrieved successfully", body = MarketplaceStatsResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Data Management"
+)]
+#[get("/marketplace/stats")]
+async fn get_marketplace_stats_service(
+    app_state: web::Data<AppState>,
+    query: web::Query<MarketplaceStatsParams>,
+) -> impl Responder {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query
+        .from
+        .unwrap_or(to - Duration::days(STATS_DEFAULT_RANGE_DAYS));
+
+    if from >= to || to - from > Duration::days(STATS_MAX_RANGE_DAYS) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "from must be before to and the range at most {} days",
+                STATS_MAX_RANGE_DAYS
+            ),
+            error_code: Some("INVALID_TIME_RANGE".to_string()),
+        });
+    }
+
+    let interval = query.interval.as_deref().unwrap_or("day");
+
+    if !STATS_INTERVALS.contains(&interval) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Invalid interval: {}", interval),
+            error_code: Some("INVALID_INTERVAL".to_string()),
+        });
+    }
+
+    let limit = query.limit.unwrap_or(STATS_TOP_DATASETS_DEFAULT_LIMIT);
+
+    if limit <= 0 || limit > STATS_TOP_DATASETS_MAX_LIMIT {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!(
+                "limit must be between 1 and {}",
+                STATS_TOP_DATASETS_MAX_LIMIT
+            ),
+            error_code: Some("INVALID_LIMIT".to_string()),
+        });
+    }
+
+    let db = &app_state.db;
+
+    let stats = tokio::try_join!(
+        database::get_stats_refreshed_at(db),
+        database::get_marketplace_totals(db, from, to),
+        database::get_category_stats(db, from, to),
+        database::get_top_datasets(db, from, to, false, limit),
+        database::get_top_datasets(db, from, to, true, limit),
+        database::get_new_datasets_over_time(db, from, to, interval),
+        database::get_active_buyers_over_time(db, from, to, interval),
+    );
+
+    let (
+        refreshed_at,
+        totals,
+        categories,
+        top_by_queries,
+        top_by_revenue,
+        new_datasets,
+        active_buyers,
+    ) = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!("Failed to get marketplace statistics: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to retrieve marketplace statistics from database".to_string(),
+                error_code: Some("STATS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(MarketplaceStatsResponse {
+        success: true,
+        from,
+        to,
+        interval: interval.to_string(),
+        refreshed_at,
+        totals,
+        categories,
+        top_by_queries,
+        top_by_revenue,
+        new_datasets,
+        active_buyers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, http::StatusCode, test};
+    use chrono::DateTime;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn stats_ranges_are_validated(db: sqlx::Pool<sqlx::Postgres>) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .service(get_marketplace_stats_service),
+        )
+        .await;
+
+        for query in [
+            "from=2026-02-01T00:00:00Z&to=2026-01-01T00:00:00Z",
+            "from=2026-01-01T00:00:00Z&to=2026-01-01T00:00:00Z",
+            "from=2025-01-01T00:00:00Z&to=2026-01-03T00:00:00Z",
+            "interval=hour",
+            "limit=0",
+            "limit=51",
+            "from=yesterday",
+        ] {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(&format!("/marketplace/stats?{}", query))
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+        }
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/marketplace/stats?to=2026-03-01T00:00:00Z&interval=week")
+                .to_request(),
+        )
+        .await;
+
+        // The range defaults to the STATS_DEFAULT_RANGE_DAYS before `to`
+        let from: DateTime<Utc> = response["from"].as_str().unwrap().parse().unwrap();
+        let to: DateTime<Utc> = response["to"].as_str().unwrap().parse().unwrap();
+        assert_eq!(to - from, Duration::days(STATS_DEFAULT_RANGE_DAYS));
+        assert_eq!(response["interval"], "week");
+        assert_eq!(response["top_by_queries"].as_array().unwrap().len(), 0);
+    }
+}
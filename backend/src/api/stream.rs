@@ -0,0 +1,451 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    HttpResponse,
+    http::header,
+    post,
+    web::{self, Bytes},
+};
+use futures_util::{future::join_all, stream};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    api::{
+        AgentRun, agent_outcome, check_conversation_access, open_conversation,
+        record_agent_answers, record_failed_agent, record_query, save_conversation_turn,
+        synthesize_agent_responses, verify_paid_agents,
+    },
+    config::AGENT_RESPONSE_TIMEOUT_SECS,
+    helpers::{self, auth::SignedAddress},
+    state::AppState,
+    tee,
+    types::{
+        AgentResponse, AgentResponseStatus, AgentTokenUsage, AnswerStreamChunk, AnswerStreamDone,
+        ErrorResponse, GetResponseFromAgentsRequest,
+    },
+};
+
+/*
+Endpoint that streams the answers of the selected agents as Server-Sent Events.
+Events: `chunk` (AnswerStreamChunk) as the agents write, `agent_done` (AgentResponse) when an agent finishes
+and `done` (AnswerStreamDone) once all of them finished, with the merged answer when synthesis is requested.
+*/
+#[utoipa::path(
+    post,
+    path = "/chat/agents/answer/stream",
+    request_body(
+        content = GetResponseFromAgentsRequest,
+        content_type = "application/json",
+        description = "User prompt and specified agents ids to get response from and tx hashes to verify payment. Free samples, subscriptions and volume tiers require the payer signature headers."
+    ),
+    responses(
+        (status = 200, description = "Stream of agents answers", content_type = "text/event-stream", body = AnswerStreamDone),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Signature required to continue a conversation or pay with credits", body = ErrorResponse),
+        (status = 402, description = "Insufficient credits or payment verification failed", body = ErrorResponse),
+        (status = 403, description = "Not the conversation owner", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Agents"
+)]
+#[post("/chat/agents/answer/stream")]
+async fn stream_response_from_agents_service(
+    app_state: web::Data<AppState>,
+    auth: Option<SignedAddress>,
+    body: web::Json<GetResponseFromAgentsRequest>,
+) -> HttpResponse {
+    let conversation =
+        match check_conversation_access(&app_state, auth.as_ref(), body.conversation_id).await {
+            Ok(conversation) => conversation,
+            Err(response) => return response,
+        };
+
+    let paid = match verify_paid_agents(&app_state, auth.as_ref(), &body).await {
+        Ok(paid) => paid,
+        Err(response) => return response,
+    };
+
+    let GetResponseFromAgentsRequest {
+        prompt,
+        synthesize,
+        store_prompt,
+        ..
+    } = body.into_inner();
+
+    let conversation = open_conversation(&app_state, auth.as_ref(), conversation, &prompt).await;
+
+    let (sender, receiver) = mpsc::unbounded_channel::<Bytes>();
+
+    // Agents keep answering if the client disconnects, so their answers and refunds are still recorded
+    actix_web::rt::spawn(async move {
+        let started = Instant::now();
+
+        let outcomes = join_all(paid.agents.iter().map(|agent_db| {
+            let sender = sender.clone();
+            let (app_state, prompt, paid) = (&app_state, &prompt, &paid);
+
+            let history = conversation
+                .as_ref()
+                .map(|(_, messages)| {
+                    helpers::conversations::agent_chat_history(messages, agent_db.id)
+                })
+                .unwrap_or_default();
+
+            async move {
+                tee::call_tee_ai_agent(app_state, agent_db.id, prompt).await;
+
+                let agent_started = Instant::now();
+
+                let outcome = tokio::time::timeout(
+                    Duration::from_secs(AGENT_RESPONSE_TIMEOUT_SECS),
+                    helpers::agents::stream_agent_answer(
+                        app_state,
+                        agent_db,
+                        prompt,
+                        history,
+                        |text| {
+                            send_event(
+                                &sender,
+                                "chunk",
+                                &AnswerStreamChunk {
+                                    agent_id: agent_db.id,
+                                    text,
+                                },
+                            );
+                        },
+                    ),
+                )
+                .await;
+
+                let latency = agent_started.elapsed();
+
+                let (agent_response, refund_entitlement, usage) =
+                    match agent_outcome(agent_db.id, outcome) {
+                        Ok(answer) => (
+                            AgentResponse {
+                                agent_id: agent_db.id,
+                                prompt: prompt.clone(),
+                                response: answer.response,
+                                dataset_version: answer.dataset_version,
+                                status: AgentResponseStatus::Success,
+                                error: None,
+                            },
+                            None,
+                            answer.usage.map(|usage| AgentTokenUsage {
+                                agent_id: agent_db.id,
+                                input_tokens: usage.input_tokens,
+                                output_tokens: usage.output_tokens,
+                                total_tokens: usage.total_tokens,
+                            }),
+                        ),
+                        Err((status, error)) => {
+                            let (agent_response, refund_entitlement) = record_failed_agent(
+                                app_state, agent_db, prompt, paid, status, error,
+                            )
+                            .await;
+
+                            (agent_response, refund_entitlement, None)
+                        }
+                    };
+
+                send_event(&sender, "agent_done", &agent_response);
+
+                (agent_response, refund_entitlement, usage, latency)
+            }
+        }))
+        .await;
+
+        let mut done = AnswerStreamDone {
+            agent_responses: Vec::new(),
+            success: false,
+            charges: paid.charges.clone(),
+            refund_entitlements: Vec::new(),
+            usage: Vec::new(),
+            synthesis: None,
+            conversation_id: None,
+        };
+
+        let mut runs = Vec::new();
+
+        for (agent_response, refund_entitlement, usage, latency) in outcomes {
+            done.success |= agent_response.status == AgentResponseStatus::Success;
+            done.agent_responses.push(agent_response);
+            done.refund_entitlements.extend(refund_entitlement);
+            done.usage.extend(usage.clone());
+            runs.push((latency, usage));
+        }
+
+        if synthesize && done.success {
+            done.synthesis = synthesize_agent_responses(
+                &app_state,
+                &prompt,
+                &paid.agents,
+                &done.agent_responses,
+            )
+            .await;
+        }
+
+        record_agent_answers(&app_state, &paid, &prompt, &done.agent_responses).await;
+
+        let runs: Vec<AgentRun> = done
+            .agent_responses
+            .iter()
+            .zip(runs)
+            .map(|(response, (latency, usage))| (response, latency, usage))
+            .collect();
+
+        record_query(
+            &app_state,
+            &paid,
+            &prompt,
+            store_prompt,
+            true,
+            started.elapsed(),
+            &runs,
+        )
+        .await;
+
+        done.conversation_id = save_conversation_turn(
+            &app_state,
+            conversation,
+            &prompt,
+            &paid.payment_reference,
+            &done.agent_responses,
+        )
+        .await;
+
+        send_event(&sender, "done", &done);
+    });
+
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        receiver
+            .recv()
+            .await
+            .map(|event| (Ok::<_, actix_web::Error>(event), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+fn send_event(sender: &mpsc::UnboundedSender<Bytes>, event: &str, data: &impl Serialize) {
+    let data = match serde_json::to_string(data) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!("Failed to serialize {} event: {}", event, e);
+            return;
+        }
+    };
+
+    // The client may have disconnected, the agents still finish
+    sender
+        .send(Bytes::from(format!("event: {}\ndata: {}\n\n", event, data)))
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::{
+        helpers::{
+            auth::{hash_signed_body, signed_test_request},
+            credits::credit_deposit,
+        },
+        types::Price,
+    };
+
+    /// Active agent whose dataset file was never uploaded, so loading it fails
+    async fn insert_agent_without_dataset(db: &sqlx::Pool<sqlx::Postgres>, name: &str) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, $2, 'Sales per city', 100000000, 'missing-dataset.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(PrivateKeySigner::random().address().to_string())
+        .bind(name)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    /// Splits a Server-Sent Events body into its event names and json data
+    fn parse_events(body: &[u8]) -> Vec<(String, serde_json::Value)> {
+        std::str::from_utf8(body)
+            .unwrap()
+            .split_terminator("\n\n")
+            .map(|event| {
+                let (name, data) = event.split_once('\n').unwrap();
+                (
+                    name.strip_prefix("event: ").unwrap().to_string(),
+                    serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn events_are_framed_as_server_sent_events() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        send_event(
+            &sender,
+            "chunk",
+            &AnswerStreamChunk {
+                agent_id: 1,
+                text: "Paris: 10\n".to_string(),
+            },
+        );
+
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            "event: chunk\ndata: {\"agent_id\":1,\"text\":\"Paris: 10\\n\"}\n\n"
+        );
+
+        // A closed stream doesn't stop the agents
+        drop(receiver);
+        send_event(&sender, "done", &serde_json::json!({}));
+    }
+
+    #[sqlx::test]
+    async fn rejected_payments_get_a_json_error_before_the_stream_opens(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let agent_id = insert_agent_without_dataset(&db, "Sales").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(stream_response_from_agents_service),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/chat/agents/answer/stream")
+                .set_json(serde_json::json!({
+                    "agent_ids": [agent_id],
+                    "prompt": "Sales in Paris?",
+                }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error_code"], "NO_TX_HASH_SPECIFIED");
+
+        // Paying with credits the signer doesn't have
+        let body = serde_json::json!({
+            "agent_ids": [agent_id],
+            "prompt": "Sales in Paris?",
+            "pay_with_credits": true,
+        })
+        .to_string();
+        let response = test::call_service(
+            &app,
+            signed_test_request(
+                &PrivateKeySigner::random(),
+                Method::POST,
+                "/chat/agents/answer/stream",
+                &body,
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error_code"], "INSUFFICIENT_CREDITS");
+    }
+
+    #[sqlx::test]
+    async fn failed_agents_get_an_event_and_the_stream_goes_on(db: sqlx::Pool<sqlx::Postgres>) {
+        let payer = PrivateKeySigner::random();
+        credit_deposit(
+            &db,
+            "0xdeposit",
+            0,
+            10,
+            &payer.address().to_string(),
+            Price::from_units(1_000_000_000),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let agent_ids = [
+            insert_agent_without_dataset(&db, "Sales").await,
+            insert_agent_without_dataset(&db, "Stock").await,
+        ];
+
+        // The agents answer on a task of the actix runtime
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(AppState::for_tests(db)))
+                        .wrap(middleware::from_fn(hash_signed_body))
+                        .service(stream_response_from_agents_service),
+                )
+                .await;
+
+                let body = serde_json::json!({
+                    "agent_ids": agent_ids,
+                    "prompt": "Sales in Paris?",
+                    "pay_with_credits": true,
+                })
+                .to_string();
+                let response = test::call_service(
+                    &app,
+                    signed_test_request(&payer, Method::POST, "/chat/agents/answer/stream", &body)
+                        .to_request(),
+                )
+                .await;
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(
+                    response.headers().get(header::CONTENT_TYPE).unwrap(),
+                    "text/event-stream"
+                );
+
+                let events = parse_events(&test::read_body(response).await);
+                let (last, agents_done) = events.split_last().unwrap();
+
+                assert_eq!(agents_done.len(), 2);
+                for (name, data) in agents_done {
+                    assert_eq!(name, "agent_done");
+                    assert_eq!(data["status"], "failed");
+                    assert!(agent_ids.contains(&data["agent_id"].as_i64().unwrap()));
+                }
+
+                assert_eq!(last.0, "done");
+                assert_eq!(last.1["success"], false);
+                assert_eq!(last.1["agent_responses"].as_array().unwrap().len(), 2);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,893 @@
+use std::path::Path;
+
+use actix_multipart::Multipart;
+use actix_web::{HttpResponse, Responder, get, post, web};
+use futures_util::TryStreamExt;
+use tracing::{error, info, warn};
+
+use uuid::Uuid;
+
+use crate::{
+    config::UPLOAD_DIR,
+    database,
+    helpers::{self, auth::SignedAddress, pii::PiiPolicies},
+    state::AppState,
+    types::{
+        DatasetVersionMode, DatasetVersionUploadRequest, DatasetVersionUploadResponse,
+        DatasetVersionsResponse, ErrorResponse, PiiPolicy,
+    },
+};
+
+#[utoipa::path(
+    post,
+    path = "/agents/{id}/versions",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    request_body(
+        content = DatasetVersionUploadRequest,
+        content_type = "multipart/form-data",
+        description = "Publish a new version of the agent dataset. Send the CSV file as 'file', the mode ('append' or 'replace') and optionally pii_policy. Requires the owner signature headers."
+    ),
+    responses(
+        (status = 200, description = "Dataset version published successfully", body = DatasetVersionUploadResponse),
+        (status = 400, description = "Bad request - invalid file or format", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid signature", body = ErrorResponse),
+        (status = 403, description = "Not the agent owner", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 410, description = "Agent deleted", body = ErrorResponse),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Data Management"
+)]
+#[post("/agents/{id}/versions")]
+async fn upload_dataset_version_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+    auth: SignedAddress,
+    mut payload: Multipart,
+) -> impl Responder {
+    const MAX_FILE_SIZE: usize = 10 * 1024 * 1024; // 10MB
+
+    let agent_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    if !auth.matches(&agent_db.owner_address) {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the agent owner can publish a new dataset version".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    if agent_db.status == "deleted" {
+        return HttpResponse::Gone().json(ErrorResponse {
+            success: false,
+            message: format!("Agent with id {} has been deleted", agent_id),
+            error_code: Some("AGENT_DELETED".to_string()),
+        });
+    }
+
+    let mut file_data: Option<(String, Vec<u8>)> = None; // (filename, data)
+    let mut mode = DatasetVersionMode::Replace;
+    let mut pii_policies = PiiPolicies::default();
+
+    while let Some(mut field) = payload.try_next().await.unwrap_or(None) {
+        let field_name = field.name().unwrap_or("").to_string();
+
+        match field_name.as_str() {
+            "file" => {
+                let filename = field
+                    .content_disposition()
+                    .and_then(|cd| cd.get_filename().map(|s| s.to_string()));
+
+                if let Some(filename) = filename {
+                    // Validate file extension
+                    if !filename.to_lowercase().ends_with(".csv") {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: "Only CSV files are allowed".to_string(),
+                            error_code: Some("INVALID_FILE_TYPE".to_string()),
+                        });
+                    }
+
+                    let mut file_bytes = Vec::new();
+
+                    // Read file data
+                    while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                        // Check file size limit
+                        if file_bytes.len() + chunk.len() > MAX_FILE_SIZE {
+                            return HttpResponse::PayloadTooLarge().json(ErrorResponse {
+                                success: false,
+                                message: format!(
+                                    "File too large. Maximum size is {} MB",
+                                    MAX_FILE_SIZE / (1024 * 1024)
+                                ),
+                                error_code: Some("FILE_TOO_LARGE".to_string()),
+                            });
+                        }
+
+                        file_bytes.extend_from_slice(&chunk);
+                    }
+
+                    file_data = Some((filename, file_bytes));
+                }
+            }
+            "mode" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                mode = match DatasetVersionMode::from_string(
+                    String::from_utf8_lossy(&field_bytes).trim(),
+                ) {
+                    Some(DatasetVersionMode::Initial) | None => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: "Invalid mode. Must be 'append' or 'replace'".to_string(),
+                            error_code: Some("INVALID_VERSION_MODE".to_string()),
+                        });
+                    }
+                    Some(mode) => mode,
+                };
+            }
+            "pii_policy" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                pii_policies = match PiiPolicies::parse(&String::from_utf8_lossy(&field_bytes)) {
+                    Ok(policies) => policies,
+                    Err(e) => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!("Invalid pii_policy: {}", e),
+                            error_code: Some("INVALID_PII_POLICY".to_string()),
+                        });
+                    }
+                };
+            }
+            _ => {
+                // Skip unknown fields
+                while let Some(_chunk) = field.try_next().await.unwrap_or(None) {
+                    // Just consume the field
+                }
+            }
+        }
+    }
+
+    let (filename, file_bytes) = match file_data {
+        Some(data) => data,
+        None => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "No file found in the request".to_string(),
+                error_code: Some("NO_FILE_FOUND".to_string()),
+            });
+        }
+    };
+
+    // Tokens restart at 1 for every scan, appended rows would reuse the pseudonyms of different people
+    if mode == DatasetVersionMode::Append && pii_policies.uses(PiiPolicy::Tokenise) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "The tokenise PII policy can't be used to append rows, use replace mode or another policy"
+                .to_string(),
+            error_code: Some("INVALID_PII_POLICY".to_string()),
+        });
+    }
+
+    if let Err(e) = helpers::csv::validate_and_count_csv(&file_bytes) {
+        warn!("CSV validation failed: {}", e);
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Invalid CSV format: {}", e),
+            error_code: Some("INVALID_CSV_FORMAT".to_string()),
+        });
+    }
+
+    // Only the new rows are scanned, the current version is already sanitized
+    let sanitized = match helpers::pii::scan_and_redact_csv(&file_bytes, &pii_policies) {
+        Ok(sanitized) => sanitized,
+        Err(e) => {
+            warn!("PII scan failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to scan CSV for PII: {}", e),
+                error_code: Some("PII_SCAN_FAILED".to_string()),
+            });
+        }
+    };
+
+    if sanitized.report.rejected {
+        let rejected_columns = sanitized
+            .report
+            .findings
+            .iter()
+            .filter(|finding| finding.action == PiiPolicy::Reject)
+            .map(|finding| format!("{} ({})", finding.column, finding.kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("Dataset rejected, PII found in: {}", rejected_columns),
+            error_code: Some("PII_DETECTED".to_string()),
+        });
+    }
+
+    let pii_report = sanitized.report;
+
+    let dataset_bytes = match mode {
+        DatasetVersionMode::Append => {
+            let current_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
+
+            let current_bytes = match tokio::fs::read(&current_path).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Failed to read current dataset version: {}", e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to read current dataset version".to_string(),
+                        error_code: Some("FILE_READ_FAILED".to_string()),
+                    });
+                }
+            };
+
+            match helpers::csv::append_csv(&current_bytes, &sanitized.data) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("CSV append failed: {}", e);
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: format!("Failed to append CSV: {}", e),
+                        error_code: Some("CSV_APPEND_FAILED".to_string()),
+                    });
+                }
+            }
+        }
+        _ => sanitized.data,
+    };
+
+    let row_count = match helpers::csv::validate_and_count_csv(&dataset_bytes) {
+        Ok(count) => count,
+        Err(e) => {
+            warn!("CSV validation failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Invalid CSV format: {}", e),
+                error_code: Some("INVALID_CSV_FORMAT".to_string()),
+            });
+        }
+    };
+
+    let columns = match helpers::csv::profile_csv_columns(&dataset_bytes) {
+        Ok(columns) => columns,
+        Err(e) => {
+            warn!("CSV profiling failed: {}", e);
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to profile CSV columns: {}", e),
+                error_code: Some("CSV_PROFILING_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Save the new version next to the previous ones
+    let filename_without_extension = Path::new(&filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(&filename);
+
+    let unique_filename = format!("{}_{}.csv", Uuid::new_v4(), filename_without_extension);
+    let filepath = Path::new(UPLOAD_DIR).join(&unique_filename);
+
+    if let Err(e) = tokio::fs::write(&filepath, &dataset_bytes).await {
+        error!("Failed to write file: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to save file".to_string(),
+            error_code: Some("FILE_SAVE_FAILED".to_string()),
+        });
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            remove_unpublished_file(&filepath).await;
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    let new_version = agent_db.current_version + 1;
+    let dataset_size = dataset_bytes.len() as f64;
+
+    let version = match database::insert_dataset_version(
+        &mut tx,
+        agent_id,
+        new_version,
+        &unique_filename,
+        dataset_size,
+        row_count as i64,
+        mode,
+    )
+    .await
+    {
+        Ok(version) => version,
+        Err(e) => {
+            error!("Failed to insert dataset version: {}", e);
+
+            tx.rollback().await.ok(); // Rollback transaction on error
+            remove_unpublished_file(&filepath).await;
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to insert dataset version".to_string(),
+                error_code: Some("DATASET_VERSION_INSERT_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(e) = database::update_agent_current_version(
+        &mut tx,
+        agent_id,
+        new_version,
+        &unique_filename,
+        dataset_size,
+    )
+    .await
+    {
+        error!("Failed to update agent version: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+        remove_unpublished_file(&filepath).await;
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to update agent version".to_string(),
+            error_code: Some("AGENT_UPDATE_FAILED".to_string()),
+        });
+    }
+
+    // Replace the columns profile with the one of the new version
+    let columns_result = match database::delete_dataset_columns(&mut tx, agent_id).await {
+        Ok(_) => database::insert_dataset_columns(&mut tx, agent_id, &columns).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = columns_result {
+        error!("Failed to replace dataset columns: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+        remove_unpublished_file(&filepath).await;
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to replace dataset columns".to_string(),
+            error_code: Some("DATASET_COLUMNS_INSERT_FAILED".to_string()),
+        });
+    }
+
+    let updated_agent = match database::get_agent_by_id_optional(&mut tx, agent_id).await {
+        Ok(Some(agent)) => agent,
+        Ok(None) | Err(_) => {
+            tx.rollback().await.ok(); // Rollback transaction on error
+            remove_unpublished_file(&filepath).await;
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get updated agent".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Build the agent on the new version before committing so a failure keeps the current one
+    let tee_agent =
+        match helpers::agents::init_agent(&filepath, &app_state.ai_model, &updated_agent).await {
+            Ok(agent) => agent,
+            Err(e) => {
+                error!(
+                    "Failed to initialize AI agent with new dataset version: {}",
+                    e
+                );
+
+                tx.rollback().await.ok(); // Rollback transaction on error
+                remove_unpublished_file(&filepath).await;
+
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: format!("Failed to initialize AI agent with dataset: {}", e),
+                    error_code: Some("AGENT_INIT_FAILED".to_string()),
+                });
+            }
+        };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        remove_unpublished_file(&filepath).await;
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    // Hot swap the running agent, the previous version is dropped from the cache
+    app_state.agent_cache.insert(agent_id, tee_agent);
+
+    // The columns of the new version are part of the agent embedding
+    helpers::embeddings::refresh_agent_embedding(
+        &app_state.db,
+        &app_state.embedder,
+        &updated_agent,
+    )
+    .await;
+
+    info!(
+        "Agent {} now answers from dataset version {} ({} rows)",
+        agent_id, new_version, row_count
+    );
+
+    HttpResponse::Ok().json(DatasetVersionUploadResponse {
+        success: true,
+        message: "Dataset version published successfully".to_string(),
+        version,
+        columns,
+        pii_report,
+    })
+}
+
+/// Removes the file of a dataset version that failed to be published
+async fn remove_unpublished_file(filepath: &Path) {
+    if let Err(e) = tokio::fs::remove_file(filepath).await {
+        warn!(
+            "Failed to remove unpublished dataset file {}: {}",
+            filepath.display(),
+            e
+        );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/versions",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Dataset versions fetched successfully", body = DatasetVersionsResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Data Management"
+)]
+#[get("/agents/{id}/versions")]
+async fn get_dataset_versions_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get agent from database".to_string(),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let versions = match database::get_dataset_versions_by_agent_id(db, agent_id).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            error!("Failed to get dataset versions: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get dataset versions from database".to_string(),
+                error_code: Some("DATASET_VERSIONS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(DatasetVersionsResponse {
+        success: true,
+        current_version: agent_db.current_version,
+        versions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::{Method, StatusCode},
+        middleware, test,
+    };
+    use alloy::signers::local::PrivateKeySigner;
+
+    use super::*;
+    use crate::helpers::auth::{hash_signed_body, signed_test_request};
+
+    const BOUNDARY: &str = "dataset-version-boundary";
+
+    /// Agent of `owner` answering from a dataset of two cities
+    async fn insert_test_agent(db: &sqlx::Pool<sqlx::Postgres>, owner: &PrivateKeySigner) -> i64 {
+        let dataset_path = format!("agent-versions-{}.csv", Uuid::new_v4());
+        tokio::fs::create_dir_all(UPLOAD_DIR).await.unwrap();
+        tokio::fs::write(
+            Path::new(UPLOAD_DIR).join(&dataset_path),
+            "city,sales\nParis,10\nLyon,5\n",
+        )
+        .await
+        .unwrap();
+
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ($1)
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100000000, $2, 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(owner.address().to_string())
+        .bind(&dataset_path)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    /// Removes the dataset files of every version of the agent
+    async fn remove_test_datasets(db: &sqlx::Pool<sqlx::Postgres>, agent_id: i64) {
+        let dataset_paths: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT dataset_path FROM agents WHERE id = $1
+            UNION
+            SELECT dataset_path FROM dataset_versions WHERE agent_id = $1
+            "#,
+        )
+        .bind(agent_id)
+        .fetch_all(db)
+        .await
+        .unwrap();
+
+        for dataset_path in dataset_paths {
+            tokio::fs::remove_file(Path::new(UPLOAD_DIR).join(dataset_path))
+                .await
+                .ok();
+        }
+    }
+
+    fn upload_request(
+        signer: &PrivateKeySigner,
+        agent_id: i64,
+        mode: &str,
+        pii_policy: Option<&str>,
+        csv: &str,
+    ) -> test::TestRequest {
+        upload_file_request(signer, agent_id, "sales.csv", mode, pii_policy, csv)
+    }
+
+    fn upload_file_request(
+        signer: &PrivateKeySigner,
+        agent_id: i64,
+        filename: &str,
+        mode: &str,
+        pii_policy: Option<&str>,
+        csv: &str,
+    ) -> test::TestRequest {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"mode\"\r\n\r\n{mode}\r\n"
+        );
+        if let Some(pii_policy) = pii_policy {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"pii_policy\"\r\n\r\n{pii_policy}\r\n"
+            ));
+        }
+        body.push_str(&format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: text/csv\r\n\r\n{csv}\r\n--{BOUNDARY}--\r\n"
+        ));
+
+        signed_test_request(
+            signer,
+            Method::POST,
+            &format!("/agents/{}/versions", agent_id),
+            &body,
+        )
+        .insert_header((
+            "content-type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        ))
+    }
+
+    #[sqlx::test]
+    async fn appending_adds_rows_and_replacing_swaps_them(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(upload_dataset_version_service)
+                .service(get_dataset_versions_service),
+        )
+        .await;
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            upload_request(&owner, agent_id, "append", None, "city,sales\nNice,7\n").to_request(),
+        )
+        .await;
+        assert_eq!(response["version"]["version"], 2);
+        assert_eq!(response["version"]["mode"], "append");
+        assert_eq!(response["version"]["row_count"], 3);
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            upload_request(&owner, agent_id, "replace", None, "city,sales\nLille,3\n").to_request(),
+        )
+        .await;
+        assert_eq!(response["version"]["version"], 3);
+        assert_eq!(response["version"]["mode"], "replace");
+        assert_eq!(response["version"]["row_count"], 1);
+
+        // Appended rows must keep the columns of the current version
+        let response = test::call_service(
+            &app,
+            upload_request(&owner, agent_id, "append", None, "town,sales\nNice,7\n").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/agents/{}/versions", agent_id))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response["current_version"], 3);
+        assert_eq!(response["versions"].as_array().unwrap().len(), 2);
+
+        let dataset_path: String =
+            sqlx::query_scalar("SELECT dataset_path FROM agents WHERE id = $1")
+                .bind(agent_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        let dataset = tokio::fs::read_to_string(Path::new(UPLOAD_DIR).join(dataset_path))
+            .await
+            .unwrap();
+        assert_eq!(dataset, "city,sales\nLille,3\n");
+
+        remove_test_datasets(&db, agent_id).await;
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_publishes_versions_of_a_live_agent(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(upload_dataset_version_service),
+        )
+        .await;
+        let csv = "city,sales\nNice,7\n";
+
+        let stranger = PrivateKeySigner::random();
+        let response = test::call_service(
+            &app,
+            upload_request(&stranger, agent_id, "append", None, csv).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query("UPDATE agents SET status = 'deleted', deleted_at = NOW() WHERE id = $1")
+            .bind(agent_id)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let response = test::call_service(
+            &app,
+            upload_request(&owner, agent_id, "append", None, csv).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        let versions: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM dataset_versions WHERE agent_id = $1")
+                .bind(agent_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(versions, 0);
+
+        remove_test_datasets(&db, agent_id).await;
+    }
+
+    #[sqlx::test]
+    async fn tokenise_is_refused_when_appending(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(upload_dataset_version_service),
+        )
+        .await;
+        let csv = "city,sales,customer_name\nNice,7,Alice Martin\n";
+
+        let response = test::call_service(
+            &app,
+            upload_request(&owner, agent_id, "append", Some("tokenise"), csv).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(response["error_code"], "INVALID_PII_POLICY");
+
+        // Replacing the whole dataset scans every row with the same tokens
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            upload_request(&owner, agent_id, "replace", Some("tokenise"), csv).to_request(),
+        )
+        .await;
+        assert_eq!(response["version"]["version"], 2);
+        assert_eq!(response["pii_report"]["findings"][0]["action"], "tokenise");
+
+        remove_test_datasets(&db, agent_id).await;
+    }
+
+    #[sqlx::test]
+    async fn the_agent_answers_from_the_new_version_after_the_swap(db: sqlx::Pool<sqlx::Postgres>) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        let app_state = web::Data::new(AppState::for_tests(db.clone()));
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(upload_dataset_version_service),
+        )
+        .await;
+
+        let agent_db = database::get_agent_by_id(&db, agent_id).await.unwrap();
+        let running = app_state
+            .agent_cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+        assert_eq!(running.version, 1);
+        assert!(running.dataset.contains("Paris"));
+
+        let response = test::call_service(
+            &app,
+            upload_request(&owner, agent_id, "replace", None, "city,sales\nLille,3\n").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The next question is answered by the agent swapped in, without rebuilding it
+        let agent_db = database::get_agent_by_id(&db, agent_id).await.unwrap();
+        let running = app_state
+            .agent_cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+        assert_eq!(running.version, 2);
+        assert!(running.dataset.contains("Lille"));
+        assert!(!running.dataset.contains("Paris"));
+
+        let metrics = app_state.agent_cache.metrics();
+        assert_eq!((metrics.hits, metrics.misses), (1, 1));
+        assert_eq!(metrics.cached_agents, 1);
+
+        remove_test_datasets(&db, agent_id).await;
+    }
+
+    #[sqlx::test]
+    async fn failed_publications_keep_the_current_version_and_remove_the_new_file(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let owner = PrivateKeySigner::random();
+        let agent_id = insert_test_agent(&db, &owner).await;
+
+        // The version number the upload would take is already used, so its insert fails
+        sqlx::query(
+            r#"
+            INSERT INTO dataset_versions (agent_id, version, dataset_path, dataset_size, mode)
+            VALUES ($1, 2, 'taken.csv', 1.0, 'replace')
+            "#,
+        )
+        .bind(agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db.clone())))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(upload_dataset_version_service),
+        )
+        .await;
+
+        let filename = format!("{}.csv", Uuid::new_v4());
+        let response = test::call_service(
+            &app,
+            upload_file_request(
+                &owner,
+                agent_id,
+                &filename,
+                "replace",
+                None,
+                "city,sales\nLille,3\n",
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let agent_db = database::get_agent_by_id(&db, agent_id).await.unwrap();
+        assert_eq!(agent_db.current_version, 1);
+
+        let mut uploads = tokio::fs::read_dir(UPLOAD_DIR).await.unwrap();
+        while let Some(entry) = uploads.next_entry().await.unwrap() {
+            assert!(!entry.file_name().to_string_lossy().ends_with(&filename));
+        }
+
+        remove_test_datasets(&db, agent_id).await;
+    }
+}
@@ -1,9 +1,27 @@
 use once_cell::sync::Lazy;
 
+use crate::types::PiiPolicy;
+
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub database_url: String,
     pub port: u16,
+    /// Salt of the `hash` PII policy, which is refused when missing since unsalted hashes of emails or phones are reversible
+    pub pii_hash_salt: Option<String>,
+    /// "gemini" or "local", the local embedder is a deterministic fake for tests and offline use
+    pub embedding_provider: String,
+    /// Key signing the payment quotes, a random one is used when missing and quotes don't survive restarts
+    pub quote_signer_private_key: Option<String>,
+    /// Key sending the refunds of on-chain payments back to the payers, they are refunded as credits when missing
+    pub refund_signer_private_key: Option<String>,
+    /// Address whose credit deposits back the credits given as refunds of on-chain payments, instead of being credited to it
+    pub platform_funding_address: Option<String>,
+    /// Address allowed to resolve disputes the agent owner contested
+    pub dispute_arbiter_address: Option<String>,
+    /// Address allowed to manage the evaluations of every agent
+    pub platform_admin_address: Option<String>,
+    /// Take the client IP from the Forwarded and X-Forwarded-For headers, only safe behind a proxy setting them
+    pub trust_proxy_headers: bool,
 }
 
 impl AppConfig {
@@ -16,6 +34,19 @@ impl AppConfig {
                 .unwrap_or_else(|_| "8080".to_string())
                 .parse()
                 .expect("PORT must be a valid u16"),
+            pii_hash_salt: std::env::var("PII_HASH_SALT")
+                .ok()
+                .filter(|salt| !salt.is_empty()),
+            embedding_provider: std::env::var("EMBEDDING_PROVIDER")
+                .unwrap_or_else(|_| "gemini".to_string()),
+            quote_signer_private_key: std::env::var("QUOTE_SIGNER_PRIVATE_KEY").ok(),
+            refund_signer_private_key: std::env::var("REFUND_SIGNER_PRIVATE_KEY").ok(),
+            platform_funding_address: std::env::var("PLATFORM_FUNDING_ADDRESS").ok(),
+            dispute_arbiter_address: std::env::var("DISPUTE_ARBITER_ADDRESS").ok(),
+            platform_admin_address: std::env::var("PLATFORM_ADMIN_ADDRESS").ok(),
+            trust_proxy_headers: std::env::var("TRUST_PROXY_HEADERS")
+                .map(|value| value == "true")
+                .unwrap_or(false),
         }
     }
 }
@@ -24,10 +55,113 @@ pub const UPLOAD_DIR: &str = "./uploads";
 pub const INIT_AGENT_MODEL: &str = "gemini-flash-lite-latest";
 pub const ROUTER_AGENT_MODEL: &str = "gemini-flash-lite-latest";
 pub const DATASET_DETAILS_GEN_AGENT_MODEL: &str = "gemini-flash-lite-latest";
+pub const SYNTHESIS_AGENT_MODEL: &str = "gemini-flash-lite-latest";
 // pub const ENCLAVA_CONTRACT_ADDRESS: &str = "0x015C507e3E79D5049b003C3bE5b2E208A4Bb7e56";
 pub const ENCLAVA_CONTRACT_ADDRESS: &str = "0xc409D09C1B5bE78FFB344fBAa70901cAeB79458B";
 pub const MAX_ALLOWED_SELECTED_AGENTS: usize = 3;
+// Decimals of the amounts paid through the contract, prices are stored as integer base units of this precision
+pub const PAYMENT_TOKEN_DECIMALS: u8 = 8;
+// Policy applied to PII found in uploaded datasets when the owner does not specify one
+pub const DEFAULT_PII_POLICY: PiiPolicy = PiiPolicy::Mask;
+// Share of non-empty values that must look like PII for a whole column to be flagged
+pub const PII_COLUMN_MATCH_RATIO: f64 = 0.5;
+// Responses reproducing more dataset rows than this are refused instead of redacted
+pub const GUARDRAIL_MAX_LEAKED_ROWS: usize = 3;
+// Number of distinctive cells of a row that must appear in a response for the row to count as leaked
+pub const GUARDRAIL_MIN_ROW_CELLS_MATCH: usize = 3;
+// Characters of the previous lines a streamed line is checked with, for dataset rows split across lines
+pub const GUARDRAIL_STREAM_WINDOW_CHARS: usize = 2000;
+pub const GUARDRAIL_REFUSAL_MESSAGE: &str = "This answer was withheld because it reproduces raw rows of the dataset. Please ask for aggregated or summarized information instead.";
+// Epsilon consumed by each aggregate query when the owner enables privacy mode without setting it
+pub const DEFAULT_DP_EPSILON_PER_QUERY: f64 = 0.1;
+// Replaces the answers and prompts of an agent once its dataset is deleted
+pub const ERASED_CONTENT_PLACEHOLDER: &str = "[Removed with the dataset of this agent]";
+// Headers used by owners to authenticate with a signed message
+pub const AUTH_ADDRESS_HEADER: &str = "X-Enclava-Address";
+pub const AUTH_TIMESTAMP_HEADER: &str = "X-Enclava-Timestamp";
+pub const AUTH_SIGNATURE_HEADER: &str = "X-Enclava-Signature";
+pub const AUTH_NONCE_HEADER: &str = "X-Enclava-Nonce";
+pub const AUTH_SIGNATURE_MAX_AGE_SECS: i64 = 5 * 60;
+pub const AUTH_NONCE_MAX_CHARS: usize = 128;
+// Largest body hashed for a signed request, signed dataset uploads included
+pub const AUTH_MAX_SIGNED_BODY_BYTES: usize = 256 * 1024 * 1024;
+// Used nonces are kept until their signature expires, then pruned at this interval
+pub const AUTH_NONCE_PRUNE_INTERVAL_SECS: u64 = 10 * 60;
+
+// Agents kept in memory at once, the least recently used ones are rebuilt on demand
+pub const AGENT_CACHE_CAPACITY: usize = 32;
+// Total size of the datasets held by cached agents
+pub const AGENT_CACHE_MAX_DATASET_BYTES: usize = 256 * 1024 * 1024;
+// Time given to each selected agent to answer before it is reported as timed out
+pub const AGENT_RESPONSE_TIMEOUT_SECS: u64 = 30;
+// Previous questions and answers of a conversation given to the agents as chat history
+pub const CONVERSATION_MAX_HISTORY_TURNS: usize = 10;
+// Length of the first question kept as the conversation title
+pub const CONVERSATION_TITLE_MAX_CHARS: usize = 100;
+// Time given to the router model before agents are ranked by keyword retrieval
+pub const ROUTER_TIMEOUT_SECS: u64 = 20;
+// Minimum share of the weighted prompt keywords an agent must match to be returned by the retrieval fallback
+pub const ROUTER_FALLBACK_MIN_SCORE: f64 = 0.2;
+pub const EMBEDDING_MODEL: &str = "text-embedding-004";
+// Dimensions of the deterministic local embeddings
+pub const LOCAL_EMBEDDING_DIMENSIONS: usize = 256;
+// Embeddings that failed to be computed when their agent changed are retried at this interval
+pub const EMBEDDING_INDEX_INTERVAL_SECS: u64 = 5 * 60;
+// Agents most similar to the prompt that are described to the router model
+pub const ROUTER_CANDIDATES_TOP_K: usize = 20;
+pub const AGENT_SEARCH_DEFAULT_LIMIT: usize = 10;
+pub const AGENT_SEARCH_MAX_LIMIT: usize = 50;
+// Time a payment quote can be paid and used in
+pub const PAYMENT_QUOTE_TTL_SECS: i64 = 10 * 60;
 pub const HEDERA_TESTNET_RPC_URL: &str = "https://testnet.hashio.io/api";
+pub const HEDERA_TESTNET_CHAIN_ID: u64 = 296;
+// Cursor of the fetcher indexing the CreditsDeposited and CreditsSettled events
+pub const CREDITS_FETCHER_CURSOR: &str = "credits";
+// Blocks fetched per get_logs request when catching up, RPCs refuse wider ranges
+pub const CREDITS_FETCHER_BLOCK_RANGE: u64 = 1000;
+// Time between two accruals of the agents revenue paid with credits into pending settlements
+pub const CREDIT_SETTLEMENT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+// Revenue below this amount of base units waits for the next accrual
+pub const CREDIT_SETTLEMENT_MIN_UNITS: i64 = 100_000_000;
+// Latest movements returned with a credit balance
+pub const CREDIT_BALANCE_ENTRIES_LIMIT: i64 = 50;
+// Queries counted to pick the volume tier of the next query
+pub const VOLUME_TIER_WINDOW_DAYS: i64 = 30;
+pub const MAX_PRICE_TIERS: usize = 10;
+pub const MAX_FREE_SAMPLE_QUERIES: i32 = 100;
+pub const MAX_SUBSCRIPTION_PERIOD_DAYS: i32 = 365;
+// Time after an answer during which its payer can dispute it
+pub const DISPUTE_WINDOW_DAYS: i64 = 7;
+pub const MAX_DISPUTE_TEXT_CHARS: usize = 2000;
+// Time between two runs of the executor sending the pending refunds
+pub const REFUND_EXECUTOR_INTERVAL_SECS: u64 = 60;
+pub const QUERY_LOG_DEFAULT_LIMIT: i64 = 50;
+pub const QUERY_LOG_MAX_LIMIT: i64 = 200;
+// Time between two refreshes of the usage aggregates behind the marketplace statistics
+pub const STATS_REFRESH_INTERVAL_SECS: u64 = 5 * 60;
+pub const STATS_DEFAULT_RANGE_DAYS: i64 = 30;
+pub const STATS_MAX_RANGE_DAYS: i64 = 366;
+pub const STATS_TOP_DATASETS_DEFAULT_LIMIT: i64 = 10;
+pub const STATS_TOP_DATASETS_MAX_LIMIT: i64 = 50;
+pub const MIN_REVIEW_RATING: i16 = 1;
+pub const MAX_REVIEW_RATING: i16 = 5;
+pub const MAX_REVIEW_COMMENT_CHARS: usize = 2000;
+pub const REVIEWS_DEFAULT_LIMIT: i64 = 50;
+pub const REVIEWS_MAX_LIMIT: i64 = 200;
+// Model judging the answers of llm_judge evaluation cases
+pub const EVALUATION_JUDGE_MODEL: &str = "gemini-flash-lite-latest";
+pub const EVALUATION_DEFAULT_NUMERIC_TOLERANCE: f64 = 0.01;
+pub const MAX_EVALUATION_CASES: i64 = 100;
+pub const MAX_EVALUATION_TEXT_CHARS: usize = 2000;
+pub const EVALUATION_RUNS_LIMIT: i64 = 50;
+pub const PREVIEW_AGENT_MODEL: &str = "gemini-flash-lite-latest";
+// Free preview questions per address and per IP each day (UTC), all agents included
+pub const PREVIEW_DAILY_LIMIT: i64 = 5;
+// Dataset rows given to the preview agent of agents sharing sample rows
+pub const PREVIEW_SAMPLE_ROWS: usize = 20;
+pub const PREVIEW_MAX_PROMPT_CHARS: usize = 500;
+pub const PREVIEW_MAX_OUTPUT_TOKENS: u64 = 256;
+pub const PREVIEW_MAX_ANSWER_CHARS: usize = 1000;
 
 // Define a globally accessible static Config instance
 pub static APP_CONFIG: Lazy<AppConfig> = Lazy::new(AppConfig::load);
@@ -1,6 +1,20 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 
-use crate::types::{AgentCategory, AgentDb, UserDb};
+use crate::types::{
+    AgentAnswerDb, AgentCategory, AgentCharge, AgentDb, AgentEmbeddingDb, AgentQueryDb,
+    AgentResponse, AgentReviewDb, AgentSubscriptionDb, CategoryStats, ColumnDataType,
+    ConversationDb, CreditEventKind, CreditSettlementDb, DatasetColumn, DatasetDeletionDb,
+    DatasetStats, DatasetVersionDb, DatasetVersionMode, DeletionReason, DisputeCategory, DisputeDb,
+    DisputeStatus, EvaluationCaseDb, EvaluationResultDb, EvaluationRunDb, EvaluationRunStatus,
+    GuardrailAction, GuardrailEventDb, GuardrailEventKind, LedgerAccountKind, LedgerEntryDb,
+    LedgerReconciliation, LedgerTransactionKind, MarketplaceTotals, MessageDb, MessageRole,
+    NewEvaluationCase, NewEvaluationResult, NewQuery, NewQueryAgent, PreviewMode, Price, PriceTier,
+    PriceTierDb, PricingModel, PricingPlanDb, PrivacyBudgetDb, QueryAgentDb, QueryDb,
+    RefundEntitlementDb, RefundReason, TimeBucketCount, UserDb,
+};
 
 pub async fn insert_user(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
@@ -24,7 +38,7 @@ pub async fn insert_new_agent(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     name: &str,
     description: &str,
-    price: f64,
+    price: Price,
     owner_id: i64,
     dataset_path: &str,
     category: &AgentCategory,
@@ -35,9 +49,9 @@ pub async fn insert_new_agent(
         WITH inserted AS (
     INSERT INTO agents (name, description, price, owner_id, dataset_path, category, status, dataset_size)
     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-    RETURNING id, name, description, price, owner_id, dataset_path, category, dataset_size, status, created_at, updated_at, nft_id, nft_tx
+    RETURNING id, name, description, price, owner_id, dataset_path, category, dataset_size, status, created_at, updated_at, nft_id, nft_tx, current_version
 )
-SELECT i.*, u.address AS owner_address
+SELECT i.*, u.address AS owner_address, 0::BIGINT AS review_count, NULL::DOUBLE PRECISION AS average_rating
 FROM inserted i
 JOIN users u ON i.owner_id = u.id;
         "#,
@@ -86,7 +100,7 @@ pub async fn get_agent_by_id_optional(
         g.id,
         g.name,
         g.description,
-        g.price,
+        g.price as "price: Price",
         g.owner_id,
         g.dataset_path,
         g.status,
@@ -96,9 +110,13 @@ pub async fn get_agent_by_id_optional(
         g.updated_at, 
         g.nft_id,
         g.nft_tx,
-        u.address as "owner_address: String"
+        g.current_version,
+        u.address as "owner_address: String",
+        COALESCE(r.review_count, 0) as "review_count!",
+        r.average_rating as "average_rating?"
     FROM agents g
     JOIN users u ON g.owner_id = u.id
+    LEFT JOIN agent_ratings r ON r.agent_id = g.id
     WHERE g.id = $1
         "#,
         id
@@ -137,7 +155,7 @@ pub async fn update_agent_with_nft_details(
 
 pub async fn get_agents_by_ids(
     db: &sqlx::Pool<sqlx::Postgres>,
-    agent_ids: &Vec<i64>,
+    agent_ids: &[i64],
 ) -> Result<Vec<AgentDb>, sqlx::Error> {
     let agents = sqlx::query_as!(
         AgentDb,
@@ -146,7 +164,7 @@ pub async fn get_agents_by_ids(
         g.id,
         g.name,
         g.description,
-        g.price,
+        g.price as "price: Price",
         g.owner_id,
         g.dataset_path,
         g.status,
@@ -156,9 +174,13 @@ pub async fn get_agents_by_ids(
         g.updated_at, 
         g.nft_id,
         g.nft_tx,
-        u.address as "owner_address: String"
+        g.current_version,
+        u.address as "owner_address: String",
+        COALESCE(r.review_count, 0) as "review_count!",
+        r.average_rating as "average_rating?"
     FROM agents g
     JOIN users u ON g.owner_id = u.id
+    LEFT JOIN agent_ratings r ON r.agent_id = g.id
     WHERE g.id = ANY($1)
         "#,
         agent_ids
@@ -169,6 +191,43 @@ pub async fn get_agents_by_ids(
     Ok(agents)
 }
 
+// Get the agents that can be asked questions
+pub async fn get_active_agents(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<AgentDb>, sqlx::Error> {
+    let agents = sqlx::query_as!(
+        AgentDb,
+        r#"
+        SELECT
+        g.id,
+        g.name,
+        g.description,
+        g.price as "price: Price",
+        g.owner_id,
+        g.dataset_path,
+        g.status,
+        g.category as "category: AgentCategory",
+        g.dataset_size,
+        g.created_at,
+        g.updated_at,
+        g.nft_id,
+        g.nft_tx,
+        g.current_version,
+        u.address as "owner_address: String",
+        COALESCE(r.review_count, 0) as "review_count!",
+        r.average_rating as "average_rating?"
+    FROM agents g
+    JOIN users u ON g.owner_id = u.id
+    LEFT JOIN agent_ratings r ON r.agent_id = g.id
+    WHERE g.status = 'active'
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(agents)
+}
+
 // Get Agents by user address
 pub async fn get_agents_by_user_address(
     db: &sqlx::Pool<sqlx::Postgres>,
@@ -181,7 +240,7 @@ pub async fn get_agents_by_user_address(
         g.id,
         g.name,
         g.description,
-        g.price,
+        g.price as "price: Price",
         g.owner_id,
         g.dataset_path,
         g.status,
@@ -191,9 +250,13 @@ pub async fn get_agents_by_user_address(
         g.updated_at, 
         g.nft_id,
         g.nft_tx,
-        u.address as "owner_address: String"
+        g.current_version,
+        u.address as "owner_address: String",
+        COALESCE(r.review_count, 0) as "review_count!",
+        r.average_rating as "average_rating?"
     FROM agents g
     JOIN users u ON g.owner_id = u.id
+    LEFT JOIN agent_ratings r ON r.agent_id = g.id
     WHERE u.address = $1
         "#,
         user_address
@@ -215,7 +278,7 @@ pub async fn get_agent_by_id(
         g.id,
         g.name,
         g.description,
-        g.price,
+        g.price as "price: Price",
         g.owner_id,
         g.dataset_path,
         g.status,
@@ -225,9 +288,13 @@ pub async fn get_agent_by_id(
         g.updated_at, 
         g.nft_id,
         g.nft_tx,
-        u.address as "owner_address: String"
+        g.current_version,
+        u.address as "owner_address: String",
+        COALESCE(r.review_count, 0) as "review_count!",
+        r.average_rating as "average_rating?"
     FROM agents g
     JOIN users u ON g.owner_id = u.id
+    LEFT JOIN agent_ratings r ON r.agent_id = g.id
     WHERE g.id = $1
         "#,
         id
@@ -237,3 +304,2927 @@ pub async fn get_agent_by_id(
 
     Ok(agent)
 }
+
+pub async fn insert_dataset_columns(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    columns: &[DatasetColumn],
+) -> Result<(), sqlx::Error> {
+    for column in columns {
+        sqlx::query(
+            r#"
+            INSERT INTO dataset_columns (agent_id, position, name, data_type, null_rate, distinct_count, min_value, max_value, sample_values)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(agent_id)
+        .bind(column.position)
+        .bind(&column.name)
+        .bind(column.data_type)
+        .bind(column.null_rate)
+        .bind(column.distinct_count)
+        .bind(&column.min_value)
+        .bind(&column.max_value)
+        .bind(&column.sample_values)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_dataset_columns_by_agent_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Vec<DatasetColumn>, sqlx::Error> {
+    let columns = sqlx::query_as!(
+        DatasetColumn,
+        r#"
+        SELECT
+        position,
+        name,
+        data_type as "data_type: ColumnDataType",
+        null_rate,
+        distinct_count,
+        min_value,
+        max_value,
+        sample_values
+    FROM dataset_columns
+    WHERE agent_id = $1
+    ORDER BY position
+        "#,
+        agent_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(columns)
+}
+
+// Get the columns of several agents grouped by agent id
+pub async fn get_dataset_columns_by_agent_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &Vec<i64>,
+) -> Result<HashMap<i64, Vec<DatasetColumn>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+        agent_id,
+        position,
+        name,
+        data_type as "data_type: ColumnDataType",
+        null_rate,
+        distinct_count,
+        min_value,
+        max_value,
+        sample_values
+    FROM dataset_columns
+    WHERE agent_id = ANY($1)
+    ORDER BY agent_id, position
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut columns_by_agent: HashMap<i64, Vec<DatasetColumn>> = HashMap::new();
+
+    for row in rows {
+        columns_by_agent
+            .entry(row.agent_id)
+            .or_default()
+            .push(DatasetColumn {
+                position: row.position,
+                name: row.name,
+                data_type: row.data_type,
+                null_rate: row.null_rate,
+                distinct_count: row.distinct_count,
+                min_value: row.min_value,
+                max_value: row.max_value,
+                sample_values: row.sample_values,
+            });
+    }
+
+    Ok(columns_by_agent)
+}
+
+pub async fn insert_guardrail_event(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    kind: GuardrailEventKind,
+    action: GuardrailAction,
+    leaked_rows: i32,
+    pii_matches: i32,
+    prompt: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO guardrail_events (agent_id, kind, action, leaked_rows, pii_matches, prompt)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(agent_id)
+    .bind(kind)
+    .bind(action)
+    .bind(leaked_rows)
+    .bind(pii_matches)
+    .bind(prompt)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Get the guardrail events of all the agents owned by a user address
+pub async fn get_guardrail_events_by_owner_address(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    owner_address: &str,
+) -> Result<Vec<GuardrailEventDb>, sqlx::Error> {
+    let events = sqlx::query_as!(
+        GuardrailEventDb,
+        r#"
+        SELECT
+        e.id,
+        e.agent_id,
+        g.name as agent_name,
+        e.kind as "kind: GuardrailEventKind",
+        e.action as "action: GuardrailAction",
+        e.leaked_rows,
+        e.pii_matches,
+        e.prompt,
+        e.created_at
+    FROM guardrail_events e
+    JOIN agents g ON e.agent_id = g.id
+    JOIN users u ON g.owner_id = u.id
+    WHERE u.address = $1
+    ORDER BY e.created_at DESC
+        "#,
+        owner_address
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(events)
+}
+
+pub async fn insert_privacy_budget(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    epsilon_total: f64,
+    epsilon_per_query: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO dataset_privacy_budgets (agent_id, epsilon_total, epsilon_per_query)
+        VALUES ($1, $2, $3)
+        "#,
+        agent_id,
+        epsilon_total,
+        epsilon_per_query
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_privacy_budget_by_agent_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Option<PrivacyBudgetDb>, sqlx::Error> {
+    let budget = sqlx::query_as!(
+        PrivacyBudgetDb,
+        r#"
+        SELECT agent_id, epsilon_total, epsilon_per_query, epsilon_spent, created_at, updated_at
+        FROM dataset_privacy_budgets
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(budget)
+}
+
+// Get the ids of the agents that are in privacy mode among the given ones
+pub async fn get_privacy_mode_agent_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &Vec<i64>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT agent_id
+        FROM dataset_privacy_budgets
+        WHERE agent_id = ANY($1)
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(ids)
+}
+
+/// Atomically consumes epsilon from the budget of an agent.
+/// Returns None when the remaining budget is not enough.
+pub async fn consume_privacy_budget(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    epsilon: f64,
+) -> Result<Option<PrivacyBudgetDb>, sqlx::Error> {
+    let budget = sqlx::query_as!(
+        PrivacyBudgetDb,
+        r#"
+        UPDATE dataset_privacy_budgets
+        SET epsilon_spent = epsilon_spent + $2
+        WHERE agent_id = $1 AND epsilon_spent + $2 <= epsilon_total
+        RETURNING agent_id, epsilon_total, epsilon_per_query, epsilon_spent, created_at, updated_at
+        "#,
+        agent_id,
+        epsilon
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(budget)
+}
+
+/// Releases epsilon reserved for a query that was never answered
+pub async fn release_privacy_budget(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    epsilon: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE dataset_privacy_budgets
+        SET epsilon_spent = GREATEST(epsilon_spent - $2, 0)
+        WHERE agent_id = $1
+        "#,
+        agent_id,
+        epsilon
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_privacy_bounds(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    bounds: &HashMap<String, (f64, f64)>,
+) -> Result<(), sqlx::Error> {
+    let column_names: Vec<String> = bounds.keys().cloned().collect();
+    let lower_bounds: Vec<f64> = column_names.iter().map(|name| bounds[name].0).collect();
+    let upper_bounds: Vec<f64> = column_names.iter().map(|name| bounds[name].1).collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO dataset_privacy_bounds (agent_id, column_name, lower_bound, upper_bound)
+        SELECT $1, * FROM UNNEST($2::TEXT[], $3::DOUBLE PRECISION[], $4::DOUBLE PRECISION[])
+        "#,
+        agent_id,
+        &column_names,
+        &lower_bounds,
+        &upper_bounds
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Get the clamp bounds the owner declared for a column of a dataset in privacy mode
+pub async fn get_privacy_bounds(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    column_name: &str,
+) -> Result<Option<(f64, f64)>, sqlx::Error> {
+    let bounds = sqlx::query!(
+        r#"
+        SELECT lower_bound, upper_bound
+        FROM dataset_privacy_bounds
+        WHERE agent_id = $1 AND column_name = $2
+        "#,
+        agent_id,
+        column_name
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(bounds.map(|bounds| (bounds.lower_bound, bounds.upper_bound)))
+}
+
+pub async fn insert_dataset_version(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    version: i32,
+    dataset_path: &str,
+    dataset_size: f64,
+    row_count: i64,
+    mode: DatasetVersionMode,
+) -> Result<DatasetVersionDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        DatasetVersionDb,
+        r#"
+        INSERT INTO dataset_versions (agent_id, version, dataset_path, dataset_size, row_count, mode)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, agent_id, version, dataset_path, dataset_size, row_count, mode, created_at
+        "#,
+        agent_id,
+        version,
+        dataset_path,
+        dataset_size,
+        row_count,
+        mode.to_string()
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn get_dataset_versions_by_agent_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Vec<DatasetVersionDb>, sqlx::Error> {
+    let versions = sqlx::query_as!(
+        DatasetVersionDb,
+        r#"
+        SELECT id, agent_id, version, dataset_path, dataset_size, row_count, mode, created_at
+        FROM dataset_versions
+        WHERE agent_id = $1
+        ORDER BY version DESC
+        "#,
+        agent_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(versions)
+}
+
+// Point the agent to a new dataset version
+pub async fn update_agent_current_version(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    version: i32,
+    dataset_path: &str,
+    dataset_size: f64,
+) -> Result<(), sqlx::Error> {
+    let update_result = sqlx::query!(
+        r#"
+        UPDATE agents
+        SET current_version = $1, dataset_path = $2, dataset_size = $3
+        WHERE id = $4
+        "#,
+        version,
+        dataset_path,
+        dataset_size,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if update_result.rows_affected() != 1 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn delete_dataset_columns(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM dataset_columns
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Erase what was derived from the dataset of a deleted agent: answers and the prompts that produced them
+// are replaced by `placeholder`, guardrail events, embeddings, evaluations and previews are deleted
+pub async fn erase_agent_derived_data(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    placeholder: &str,
+) -> Result<(), sqlx::Error> {
+    // Questions the agent was asked in conversations, found by their payment, and the titles taken from the first ones
+    sqlx::query!(
+        r#"
+        UPDATE conversations SET title = $2
+        WHERE id IN (
+            SELECT m.conversation_id
+            FROM messages m
+            JOIN agent_answers a ON a.payment_reference = m.tx_hash AND a.agent_id = $1
+            WHERE m.role = 'user'
+                AND NOT EXISTS (
+                    SELECT 1 FROM messages earlier
+                    WHERE earlier.conversation_id = m.conversation_id
+                        AND earlier.role = 'user'
+                        AND earlier.id < m.id
+                )
+        )
+        "#,
+        agent_id,
+        placeholder
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE messages SET content = $2
+        WHERE role = 'user'
+            AND tx_hash IN (SELECT payment_reference FROM agent_answers WHERE agent_id = $1)
+        "#,
+        agent_id,
+        placeholder
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE messages SET content = $2
+        WHERE agent_id = $1
+        "#,
+        agent_id,
+        placeholder
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE agent_answers SET prompt = $2, response = $2, error = NULL
+        WHERE agent_id = $1
+        "#,
+        agent_id,
+        placeholder
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE queries SET prompt = NULL
+        WHERE $1 = ANY(agent_ids) AND prompt IS NOT NULL
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM guardrail_events
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM agent_embeddings
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // Results go with their runs and cases
+    sqlx::query!(
+        r#"
+        DELETE FROM evaluation_runs
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM evaluation_cases
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM preview_queries
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM agent_preview_settings
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Update the owner editable fields of an agent, None fields are left unchanged
+pub async fn update_agent_details(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    status: Option<&str>,
+    price: Option<Price>,
+    name: Option<&str>,
+    description: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let update_result = sqlx::query!(
+        r#"
+        UPDATE agents
+        SET status = COALESCE($1, status),
+            price = COALESCE($2, price),
+            name = COALESCE($3, name),
+            description = COALESCE($4, description)
+        WHERE id = $5
+        "#,
+        status,
+        price.map(|price| price.units()),
+        name,
+        description,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if update_result.rows_affected() != 1 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    Ok(())
+}
+
+// Tombstone the agent, the row is kept so payments made to it can still be resolved
+// Tombstone the agent, its description was generated from the dataset so it is replaced by `placeholder`
+pub async fn mark_agent_deleted(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    placeholder: &str,
+) -> Result<(), sqlx::Error> {
+    let update_result = sqlx::query!(
+        r#"
+        UPDATE agents
+        SET status = 'deleted', deleted_at = NOW(), description = $2
+        WHERE id = $1 AND status <> 'deleted'
+        "#,
+        agent_id,
+        placeholder
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if update_result.rows_affected() != 1 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn insert_dataset_deletion(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    requested_by: &str,
+    reason: DeletionReason,
+    note: Option<&str>,
+    removed_files: &[String],
+) -> Result<DatasetDeletionDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        DatasetDeletionDb,
+        r#"
+        INSERT INTO dataset_deletions (agent_id, requested_by, reason, note, removed_files)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, agent_id, requested_by, reason, note, removed_files, created_at
+        "#,
+        agent_id,
+        requested_by,
+        reason.to_string(),
+        note,
+        removed_files
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(record)
+}
+
+// Record that the payer of tx_hash is owed the price of an agent that failed to answer
+pub async fn insert_refund_entitlement(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    tx_hash: &str,
+    amount: Price,
+    reason: RefundReason,
+    error: Option<&str>,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        INSERT INTO refund_entitlements (agent_id, tx_hash, amount, reason, error)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        agent_id,
+        tx_hash,
+        amount.units(),
+        reason.to_string(),
+        error
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn insert_conversation(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    user_address: &str,
+    title: &str,
+) -> Result<ConversationDb, sqlx::Error> {
+    let conversation = sqlx::query_as!(
+        ConversationDb,
+        r#"
+        INSERT INTO conversations (user_address, title)
+        VALUES ($1, $2)
+        RETURNING id, user_address, title, created_at, updated_at
+        "#,
+        user_address,
+        title
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(conversation)
+}
+
+pub async fn get_conversation_by_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    id: i64,
+) -> Result<Option<ConversationDb>, sqlx::Error> {
+    let conversation = sqlx::query_as!(
+        ConversationDb,
+        r#"
+        SELECT id, user_address, title, created_at, updated_at
+        FROM conversations
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(conversation)
+}
+
+pub async fn get_conversations_by_user_address(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    user_address: &str,
+) -> Result<Vec<ConversationDb>, sqlx::Error> {
+    let conversations = sqlx::query_as!(
+        ConversationDb,
+        r#"
+        SELECT id, user_address, title, created_at, updated_at
+        FROM conversations
+        WHERE LOWER(user_address) = LOWER($1)
+        ORDER BY updated_at DESC
+        "#,
+        user_address
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(conversations)
+}
+
+pub async fn get_messages_by_conversation_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    conversation_id: i64,
+) -> Result<Vec<MessageDb>, sqlx::Error> {
+    let messages = sqlx::query_as!(
+        MessageDb,
+        r#"
+        SELECT id, conversation_id, role, agent_id, content, dataset_version, tx_hash, created_at
+        FROM messages
+        WHERE conversation_id = $1
+        ORDER BY id
+        "#,
+        conversation_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(messages)
+}
+
+pub async fn insert_message(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    conversation_id: i64,
+    role: MessageRole,
+    agent_id: Option<i64>,
+    content: &str,
+    dataset_version: Option<i32>,
+    tx_hash: Option<&str>,
+) -> Result<MessageDb, sqlx::Error> {
+    let message = sqlx::query_as!(
+        MessageDb,
+        r#"
+        INSERT INTO messages (conversation_id, role, agent_id, content, dataset_version, tx_hash)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, conversation_id, role, agent_id, content, dataset_version, tx_hash, created_at
+        "#,
+        conversation_id,
+        role.to_string(),
+        agent_id,
+        content,
+        dataset_version,
+        tx_hash
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(message)
+}
+
+// Move the conversation to the top of its owner list
+pub async fn touch_conversation(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    conversation_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE conversations
+        SET updated_at = NOW()
+        WHERE id = $1
+        "#,
+        conversation_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_agent_embeddings_by_agent_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &[i64],
+) -> Result<Vec<AgentEmbeddingDb>, sqlx::Error> {
+    let embeddings = sqlx::query_as!(
+        AgentEmbeddingDb,
+        r#"
+        SELECT agent_id, model, source_text, embedding
+        FROM agent_embeddings
+        WHERE agent_id = ANY($1)
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(embeddings)
+}
+
+// Store the embedding of an agent, replacing the previous one
+pub async fn upsert_agent_embedding(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    model: &str,
+    source_text: &str,
+    embedding: &[f64],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_embeddings (agent_id, model, source_text, embedding)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (agent_id) DO UPDATE
+        SET model = EXCLUDED.model,
+            source_text = EXCLUDED.source_text,
+            embedding = EXCLUDED.embedding
+        "#,
+        agent_id,
+        model,
+        source_text,
+        embedding
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Get the id of a ledger account, creating it on first use
+pub async fn get_or_create_ledger_account(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    kind: LedgerAccountKind,
+    owner_key: &str,
+) -> Result<i64, sqlx::Error> {
+    let account_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO ledger_accounts (kind, owner_key)
+        VALUES ($1, $2)
+        ON CONFLICT (kind, owner_key) DO UPDATE SET kind = EXCLUDED.kind
+        RETURNING id
+        "#,
+        kind.to_string(),
+        owner_key
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(account_id)
+}
+
+pub async fn insert_ledger_transaction(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    kind: LedgerTransactionKind,
+    reference: &str,
+) -> Result<i64, sqlx::Error> {
+    let transaction_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO ledger_transactions (kind, reference)
+        VALUES ($1, $2)
+        RETURNING id
+        "#,
+        kind.to_string(),
+        reference
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(transaction_id)
+}
+
+// Post an entry and move the account balance with it.
+// A user credit balance going negative fails with the ledger_accounts_user_credit_check constraint.
+pub async fn insert_ledger_entry(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    transaction_id: i64,
+    account_id: i64,
+    amount: Price,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO ledger_entries (transaction_id, account_id, amount)
+        VALUES ($1, $2, $3)
+        "#,
+        transaction_id,
+        account_id,
+        amount.units()
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE ledger_accounts
+        SET balance = balance + $2
+        WHERE id = $1
+        "#,
+        account_id,
+        amount.units()
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_ledger_account_balance(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    kind: LedgerAccountKind,
+    owner_key: &str,
+) -> Result<Price, sqlx::Error> {
+    let balance = sqlx::query_scalar!(
+        r#"
+        SELECT balance as "balance: Price"
+        FROM ledger_accounts
+        WHERE kind = $1 AND owner_key = $2
+        "#,
+        kind.to_string(),
+        owner_key
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(balance.unwrap_or(Price::ZERO))
+}
+
+pub async fn get_ledger_entries_by_account(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    kind: LedgerAccountKind,
+    owner_key: &str,
+    limit: i64,
+) -> Result<Vec<LedgerEntryDb>, sqlx::Error> {
+    let entries = sqlx::query_as!(
+        LedgerEntryDb,
+        r#"
+        SELECT e.id, e.transaction_id, t.kind, t.reference, e.amount as "amount: Price", e.created_at
+        FROM ledger_entries e
+        JOIN ledger_transactions t ON e.transaction_id = t.id
+        JOIN ledger_accounts a ON e.account_id = a.id
+        WHERE a.kind = $1 AND a.owner_key = $2
+        ORDER BY e.id DESC
+        LIMIT $3
+        "#,
+        kind.to_string(),
+        owner_key,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(entries)
+}
+
+// Get the user whose credits were debited by a transaction
+pub async fn get_ledger_transaction_user_address(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    reference: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let user_address = sqlx::query_scalar!(
+        r#"
+        SELECT a.owner_key
+        FROM ledger_entries e
+        JOIN ledger_transactions t ON e.transaction_id = t.id
+        JOIN ledger_accounts a ON e.account_id = a.id
+        WHERE t.reference = $1 AND a.kind = 'user_credit'
+        LIMIT 1
+        "#,
+        reference
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(user_address)
+}
+
+// Record an indexed on-chain event, returns None when it was already recorded
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_credit_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    kind: CreditEventKind,
+    tx_hash: &str,
+    log_index: i64,
+    block_number: i64,
+    user_address: Option<&str>,
+    token_id: Option<i64>,
+    amount: Price,
+) -> Result<Option<i64>, sqlx::Error> {
+    let event_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO credit_events (kind, tx_hash, log_index, block_number, user_address, token_id, amount)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (tx_hash, log_index) DO NOTHING
+        RETURNING id
+        "#,
+        kind.to_string(),
+        tx_hash,
+        log_index,
+        block_number,
+        user_address,
+        token_id,
+        amount.units()
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(event_id)
+}
+
+pub async fn update_credit_event_ledger_transaction(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    event_id: i64,
+    ledger_transaction_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE credit_events
+        SET ledger_transaction_id = $2
+        WHERE id = $1
+        "#,
+        event_id,
+        ledger_transaction_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Get the agents revenue accounts holding at least min_amount, with the NFT of the agent
+pub async fn get_settleable_agent_revenues(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    min_amount: Price,
+) -> Result<Vec<(i64, i64, Price)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT g.id as agent_id, g.nft_id as "nft_id!", a.balance as "balance: Price"
+        FROM ledger_accounts a
+        JOIN agents g ON a.owner_key = g.id::TEXT
+        WHERE a.kind = 'agent_revenue' AND a.balance >= $1 AND g.nft_id IS NOT NULL
+        "#,
+        min_amount.units()
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.agent_id, row.nft_id, row.balance))
+        .collect())
+}
+
+pub async fn insert_credit_settlement(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    token_id: i64,
+    amount: Price,
+) -> Result<CreditSettlementDb, sqlx::Error> {
+    let settlement = sqlx::query_as!(
+        CreditSettlementDb,
+        r#"
+        INSERT INTO credit_settlements (agent_id, token_id, amount)
+        VALUES ($1, $2, $3)
+        RETURNING id, agent_id, token_id, amount as "amount: Price", status, created_at, settled_at
+        "#,
+        agent_id,
+        token_id,
+        amount.units()
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(settlement)
+}
+
+// Mark the oldest pending settlement of a token for this amount as settled
+pub async fn settle_pending_credit_settlement(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token_id: i64,
+    amount: Price,
+) -> Result<Option<CreditSettlementDb>, sqlx::Error> {
+    let settlement = sqlx::query_as!(
+        CreditSettlementDb,
+        r#"
+        UPDATE credit_settlements
+        SET status = 'settled', settled_at = NOW()
+        WHERE id = (
+            SELECT id
+            FROM credit_settlements
+            WHERE token_id = $1 AND amount = $2 AND status = 'pending'
+            ORDER BY id
+            LIMIT 1
+            FOR UPDATE
+        )
+        RETURNING id, agent_id, token_id, amount as "amount: Price", status, created_at, settled_at
+        "#,
+        token_id,
+        amount.units()
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(settlement)
+}
+
+pub async fn get_credit_settlements(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    status: Option<&str>,
+) -> Result<Vec<CreditSettlementDb>, sqlx::Error> {
+    let settlements = sqlx::query_as!(
+        CreditSettlementDb,
+        r#"
+        SELECT id, agent_id, token_id, amount as "amount: Price", status, created_at, settled_at
+        FROM credit_settlements
+        WHERE $1::VARCHAR IS NULL OR status = $1
+        ORDER BY id
+        "#,
+        status
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(settlements)
+}
+
+pub async fn mark_refund_entitlement_credited(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    refund_entitlement_id: i64,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        UPDATE refund_entitlements
+        SET status = 'credited'
+        WHERE id = $1
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        refund_entitlement_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn get_fetcher_cursor(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    name: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let last_block = sqlx::query_scalar!(
+        r#"
+        SELECT last_block
+        FROM fetcher_cursors
+        WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(last_block)
+}
+
+pub async fn upsert_fetcher_cursor(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    name: &str,
+    last_block: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO fetcher_cursors (name, last_block)
+        VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET last_block = EXCLUDED.last_block
+        "#,
+        name,
+        last_block
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_ledger_reconciliation(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<LedgerReconciliation, sqlx::Error> {
+    let mismatched_account_ids = sqlx::query_scalar!(
+        r#"
+        SELECT a.id
+        FROM ledger_accounts a
+        LEFT JOIN ledger_entries e ON e.account_id = a.id
+        GROUP BY a.id
+        HAVING a.balance <> COALESCE(SUM(e.amount), 0)
+        ORDER BY a.id
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let unbalanced_transactions = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM (
+            SELECT transaction_id
+            FROM ledger_entries
+            GROUP BY transaction_id
+            HAVING SUM(amount) <> 0
+        ) unbalanced
+        "#
+    )
+    .fetch_one(db)
+    .await?;
+
+    let balances = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'onchain_deposits'), 0)::BIGINT as "onchain_deposits!",
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'onchain_settlements'), 0)::BIGINT as "onchain_settlements!",
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'user_credit'), 0)::BIGINT as "user_credit!",
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'agent_revenue'), 0)::BIGINT as "agent_revenue!",
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'settlements_pending'), 0)::BIGINT as "settlements_pending!",
+            COALESCE(SUM(balance) FILTER (WHERE kind = 'platform_funding'), 0)::BIGINT as "platform_funding!"
+        FROM ledger_accounts
+        "#
+    )
+    .fetch_one(db)
+    .await?;
+
+    let events = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(amount) FILTER (WHERE kind = 'deposit'), 0)::BIGINT as "deposits!",
+            COUNT(*) FILTER (WHERE kind = 'deposit' AND ledger_transaction_id IS NULL) as "unbooked_deposits!",
+            COALESCE(SUM(amount) FILTER (WHERE kind = 'settlement'), 0)::BIGINT as "settlements!",
+            COUNT(*) FILTER (WHERE kind = 'settlement' AND ledger_transaction_id IS NULL) as "unbooked_settlements!"
+        FROM credit_events
+        "#
+    )
+    .fetch_one(db)
+    .await?;
+
+    // The on-chain counterpart accounts hold the opposite of what was deposited and the settled amount
+    let credited_deposits = Price::from_units(-balances.onchain_deposits);
+    let booked_settlements = Price::from_units(balances.onchain_settlements);
+    let indexed_deposits = Price::from_units(events.deposits);
+    let indexed_settlements = Price::from_units(events.settlements);
+
+    let ledger_balanced = mismatched_account_ids.is_empty() && unbalanced_transactions == 0;
+
+    Ok(LedgerReconciliation {
+        ledger_balanced,
+        mismatched_account_ids,
+        indexed_deposits,
+        credited_deposits,
+        unbooked_deposit_events: events.unbooked_deposits,
+        indexed_settlements,
+        booked_settlements,
+        unbooked_settlement_events: events.unbooked_settlements,
+        outstanding_user_credits: Price::from_units(balances.user_credit),
+        accrued_agent_revenue: Price::from_units(balances.agent_revenue),
+        pending_settlements: Price::from_units(balances.settlements_pending),
+        platform_funding: Price::from_units(balances.platform_funding),
+        expected_credit_pool: Price::from_units(events.deposits - events.settlements),
+        reconciled: ledger_balanced
+            && credited_deposits == indexed_deposits
+            && booked_settlements == indexed_settlements
+            && events.unbooked_deposits == 0
+            && events.unbooked_settlements == 0
+            && balances.platform_funding >= 0,
+    })
+}
+
+pub async fn get_pricing_plans_by_agent_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &[i64],
+) -> Result<Vec<PricingPlanDb>, sqlx::Error> {
+    let plans = sqlx::query_as!(
+        PricingPlanDb,
+        r#"
+        SELECT agent_id, model, subscription_price as "subscription_price: Price",
+            subscription_period_days, free_sample_queries, created_at, updated_at
+        FROM agent_pricing_plans
+        WHERE agent_id = ANY($1)
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(plans)
+}
+
+pub async fn get_price_tiers_by_agent_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &[i64],
+) -> Result<Vec<PriceTierDb>, sqlx::Error> {
+    let tiers = sqlx::query_as!(
+        PriceTierDb,
+        r#"
+        SELECT agent_id, min_queries, price as "price: Price"
+        FROM agent_price_tiers
+        WHERE agent_id = ANY($1)
+        ORDER BY agent_id, min_queries
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(tiers)
+}
+
+pub async fn upsert_pricing_plan(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    model: PricingModel,
+    subscription_price: Option<Price>,
+    subscription_period_days: Option<i32>,
+    free_sample_queries: i32,
+) -> Result<PricingPlanDb, sqlx::Error> {
+    let plan = sqlx::query_as!(
+        PricingPlanDb,
+        r#"
+        INSERT INTO agent_pricing_plans (agent_id, model, subscription_price, subscription_period_days, free_sample_queries)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (agent_id) DO UPDATE SET
+            model = EXCLUDED.model,
+            subscription_price = EXCLUDED.subscription_price,
+            subscription_period_days = EXCLUDED.subscription_period_days,
+            free_sample_queries = EXCLUDED.free_sample_queries
+        RETURNING agent_id, model, subscription_price as "subscription_price: Price",
+            subscription_period_days, free_sample_queries, created_at, updated_at
+        "#,
+        agent_id,
+        model.to_string(),
+        subscription_price.map(Price::units),
+        subscription_period_days,
+        free_sample_queries
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(plan)
+}
+
+// Replace the volume tiers of an agent
+pub async fn replace_price_tiers(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    tiers: &[PriceTier],
+) -> Result<Vec<PriceTierDb>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM agent_price_tiers
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let min_queries: Vec<i32> = tiers.iter().map(|tier| tier.min_queries).collect();
+    let prices: Vec<i64> = tiers.iter().map(|tier| tier.price.units()).collect();
+
+    let tiers = sqlx::query_as!(
+        PriceTierDb,
+        r#"
+        INSERT INTO agent_price_tiers (agent_id, min_queries, price)
+        SELECT $1, * FROM UNNEST($2::INTEGER[], $3::BIGINT[])
+        RETURNING agent_id, min_queries, price as "price: Price"
+        "#,
+        agent_id,
+        &min_queries,
+        &prices
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(tiers)
+}
+
+// Count the queries of a user to each agent, in total and since `since`
+pub async fn get_agent_usage_counts<'e>(
+    db: impl sqlx::PgExecutor<'e>,
+    user_address: &str,
+    agent_ids: &[i64],
+    since: DateTime<Utc>,
+) -> Result<Vec<(i64, i64, i64)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT agent_id, COUNT(*) as "total!", COUNT(*) FILTER (WHERE created_at >= $3) as "recent!"
+        FROM agent_usage
+        WHERE user_address = $1 AND agent_id = ANY($2)
+        GROUP BY agent_id
+        "#,
+        user_address,
+        agent_ids,
+        since
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.agent_id, row.total, row.recent))
+        .collect())
+}
+
+// Serialize the payments of a user until the end of the transaction, so their usage is counted once per query
+pub async fn lock_agent_usage(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_address: &str,
+) -> Result<(), sqlx::Error> {
+    // The lock function returns void, which the checked macros can't decode
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(format!("agent_usage:{}", user_address.to_lowercase()))
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Claims a payment tx so it is only handled once, `tx_hash` being the canonical spelling of `B256`.
+/// Returns false when it was already claimed.
+pub async fn claim_payment_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    tx_hash: &str,
+    payer_address: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO handled_payments (tx_hash, payer_address)
+        VALUES ($1, $2)
+        ON CONFLICT (tx_hash) DO NOTHING
+        "#,
+        tx_hash,
+        payer_address
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn insert_agent_usages(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_address: &str,
+    payment_reference: &str,
+    charges: &[AgentCharge],
+) -> Result<(), sqlx::Error> {
+    let agent_ids: Vec<i64> = charges.iter().map(|charge| charge.agent_id).collect();
+    let bases: Vec<String> = charges
+        .iter()
+        .map(|charge| charge.basis.to_string())
+        .collect();
+    let amounts: Vec<i64> = charges.iter().map(|charge| charge.amount.units()).collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_usage (agent_id, user_address, payment_reference, basis, amount)
+        SELECT agent_id, $1, $2, basis, amount
+        FROM UNNEST($3::BIGINT[], $4::VARCHAR[], $5::BIGINT[]) AS usage(agent_id, basis, amount)
+        ON CONFLICT (payment_reference, agent_id) DO NOTHING
+        "#,
+        user_address,
+        payment_reference,
+        &agent_ids,
+        &bases,
+        &amounts
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Get the subscriptions of a user to the agents that are still running, latest expiry first
+pub async fn get_active_subscriptions<'e>(
+    db: impl sqlx::PgExecutor<'e>,
+    user_address: &str,
+    agent_ids: &[i64],
+) -> Result<Vec<AgentSubscriptionDb>, sqlx::Error> {
+    let subscriptions = sqlx::query_as!(
+        AgentSubscriptionDb,
+        r#"
+        SELECT id, agent_id, user_address, payment_reference, amount as "amount: Price",
+            starts_at, expires_at, created_at
+        FROM agent_subscriptions
+        WHERE user_address = $1 AND agent_id = ANY($2) AND starts_at <= NOW() AND expires_at > NOW()
+        ORDER BY expires_at DESC
+        "#,
+        user_address,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(subscriptions)
+}
+
+// Subscribe a user to an agent, starting when their latest subscription to it expires
+pub async fn insert_agent_subscription(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    user_address: &str,
+    payment_reference: &str,
+    amount: Price,
+    period_days: i32,
+) -> Result<AgentSubscriptionDb, sqlx::Error> {
+    let subscription = sqlx::query_as!(
+        AgentSubscriptionDb,
+        r#"
+        WITH start AS (
+            SELECT GREATEST(NOW(), COALESCE(MAX(expires_at), NOW())) as starts_at
+            FROM agent_subscriptions
+            WHERE user_address = $2 AND agent_id = $1
+        )
+        INSERT INTO agent_subscriptions (agent_id, user_address, payment_reference, amount, starts_at, expires_at)
+        SELECT $1, $2, $3, $4, starts_at, starts_at + make_interval(days => $5)
+        FROM start
+        RETURNING id, agent_id, user_address, payment_reference, amount as "amount: Price",
+            starts_at, expires_at, created_at
+        "#,
+        agent_id,
+        user_address,
+        payment_reference,
+        amount.units(),
+        period_days
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(subscription)
+}
+
+// Record the prompt and answer of each paid agent, answers already recorded for the payment are kept
+pub async fn insert_agent_answers(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payment_reference: &str,
+    prompt: &str,
+    responses: &[AgentResponse],
+) -> Result<(), sqlx::Error> {
+    let agent_ids: Vec<i64> = responses.iter().map(|response| response.agent_id).collect();
+    let answers: Vec<String> = responses
+        .iter()
+        .map(|response| response.response.clone())
+        .collect();
+    let statuses: Vec<String> = responses
+        .iter()
+        .map(|response| response.status.to_string())
+        .collect();
+    // Empty for the answers without error, stored as NULL
+    let errors: Vec<String> = responses
+        .iter()
+        .map(|response| response.error.clone().unwrap_or_default())
+        .collect();
+    let dataset_versions: Vec<i32> = responses
+        .iter()
+        .map(|response| response.dataset_version)
+        .collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_answers (agent_id, payment_reference, prompt, response, status, error, dataset_version)
+        SELECT agent_id, $1, $2, response, status, NULLIF(error, ''), dataset_version
+        FROM UNNEST($3::BIGINT[], $4::TEXT[], $5::VARCHAR[], $6::TEXT[], $7::INT[])
+            AS answer(agent_id, response, status, error, dataset_version)
+        ON CONFLICT (payment_reference, agent_id) DO NOTHING
+        "#,
+        payment_reference,
+        prompt,
+        &agent_ids,
+        &answers,
+        &statuses,
+        &errors,
+        &dataset_versions
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_agent_answer(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payment_reference: &str,
+    agent_id: i64,
+) -> Result<Option<AgentAnswerDb>, sqlx::Error> {
+    let answer = sqlx::query_as!(
+        AgentAnswerDb,
+        r#"
+        SELECT id, agent_id, payment_reference, prompt, response, status, error, dataset_version, created_at
+        FROM agent_answers
+        WHERE payment_reference = $1 AND agent_id = $2
+        "#,
+        payment_reference,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(answer)
+}
+
+pub async fn get_agent_answer_by_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    id: i64,
+) -> Result<AgentAnswerDb, sqlx::Error> {
+    let answer = sqlx::query_as!(
+        AgentAnswerDb,
+        r#"
+        SELECT id, agent_id, payment_reference, prompt, response, status, error, dataset_version, created_at
+        FROM agent_answers
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(answer)
+}
+
+// Get who paid for an agent in a payment and what they were charged
+pub async fn get_agent_usage_payer(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payment_reference: &str,
+    agent_id: i64,
+) -> Result<Option<(String, Price)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_address, amount as "amount: Price"
+        FROM agent_usage
+        WHERE payment_reference = $1 AND agent_id = $2
+        "#,
+        payment_reference,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| (row.user_address, row.amount)))
+}
+
+pub async fn get_refund_entitlement(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+    agent_id: i64,
+) -> Result<Option<RefundEntitlementDb>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        SELECT id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        FROM refund_entitlements
+        WHERE tx_hash = $1 AND agent_id = $2
+        "#,
+        tx_hash,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn get_refund_entitlement_by_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    id: i64,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        SELECT id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        FROM refund_entitlements
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(record)
+}
+
+// Get the refunds left to execute, oldest first
+pub async fn get_pending_refund_entitlements(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<RefundEntitlementDb>, sqlx::Error> {
+    let records = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        SELECT id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        FROM refund_entitlements
+        WHERE status = 'pending'
+        ORDER BY id
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records)
+}
+
+// Move a pending refund to processing before sending it on-chain, None when another task already took it
+pub async fn claim_refund_entitlement(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    refund_entitlement_id: i64,
+) -> Result<Option<RefundEntitlementDb>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        UPDATE refund_entitlements
+        SET status = 'processing'
+        WHERE id = $1 AND status = 'pending'
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        refund_entitlement_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn mark_refund_entitlement_refunded(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    refund_entitlement_id: i64,
+    refund_tx_hash: &str,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        UPDATE refund_entitlements
+        SET status = 'refunded', refund_tx_hash = $2
+        WHERE id = $1
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        refund_entitlement_id,
+        refund_tx_hash
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn mark_refund_entitlement_failed(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    refund_entitlement_id: i64,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        UPDATE refund_entitlements
+        SET status = 'failed'
+        WHERE id = $1
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        refund_entitlement_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn insert_dispute(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    answer: &AgentAnswerDb,
+    user_address: &str,
+    category: DisputeCategory,
+    description: &str,
+) -> Result<DisputeDb, sqlx::Error> {
+    let dispute = sqlx::query_as!(
+        DisputeDb,
+        r#"
+        INSERT INTO disputes (answer_id, agent_id, user_address, category, description)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, answer_id, agent_id, user_address, category, description, status, owner_response,
+            owner_responded_at, resolution_note, resolved_by, resolved_at, refund_entitlement_id, created_at
+        "#,
+        answer.id,
+        answer.agent_id,
+        user_address,
+        category.to_string(),
+        description
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(dispute)
+}
+
+pub async fn get_dispute_by_id(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    id: i64,
+) -> Result<DisputeDb, sqlx::Error> {
+    let dispute = sqlx::query_as!(
+        DisputeDb,
+        r#"
+        SELECT id, answer_id, agent_id, user_address, category, description, status, owner_response,
+            owner_responded_at, resolution_note, resolved_by, resolved_at, refund_entitlement_id, created_at
+        FROM disputes
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(dispute)
+}
+
+// Get the disputes raised by an address or on the agents it owns, most recent first
+pub async fn get_disputes_by_address(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    address: &str,
+) -> Result<Vec<DisputeDb>, sqlx::Error> {
+    let disputes = sqlx::query_as!(
+        DisputeDb,
+        r#"
+        SELECT d.id, d.answer_id, d.agent_id, d.user_address, d.category, d.description, d.status,
+            d.owner_response, d.owner_responded_at, d.resolution_note, d.resolved_by, d.resolved_at,
+            d.refund_entitlement_id, d.created_at
+        FROM disputes d
+        JOIN agents g ON d.agent_id = g.id
+        JOIN users u ON g.owner_id = u.id
+        WHERE LOWER(d.user_address) = LOWER($1) OR LOWER(u.address) = LOWER($1)
+        ORDER BY d.created_at DESC
+        "#,
+        address
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(disputes)
+}
+
+// Record the response of the agent owner on an open dispute, None when it is no longer open
+pub async fn update_dispute_owner_response(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    dispute_id: i64,
+    owner_response: &str,
+) -> Result<Option<DisputeDb>, sqlx::Error> {
+    let dispute = sqlx::query_as!(
+        DisputeDb,
+        r#"
+        UPDATE disputes
+        SET status = 'owner_responded', owner_response = $2, owner_responded_at = NOW()
+        WHERE id = $1 AND status = 'open'
+        RETURNING id, answer_id, agent_id, user_address, category, description, status, owner_response,
+            owner_responded_at, resolution_note, resolved_by, resolved_at, refund_entitlement_id, created_at
+        "#,
+        dispute_id,
+        owner_response
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(dispute)
+}
+
+// Close a dispute that is still open or contested, None when it is already resolved
+pub async fn resolve_dispute(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    dispute_id: i64,
+    status: DisputeStatus,
+    resolved_by: &str,
+    owner_response: Option<&str>,
+    resolution_note: Option<&str>,
+) -> Result<Option<DisputeDb>, sqlx::Error> {
+    let dispute = sqlx::query_as!(
+        DisputeDb,
+        r#"
+        UPDATE disputes
+        SET status = $2,
+            resolved_by = $3,
+            resolved_at = NOW(),
+            owner_response = COALESCE($4, owner_response),
+            owner_responded_at = CASE WHEN $4::TEXT IS NULL THEN owner_responded_at ELSE NOW() END,
+            resolution_note = $5
+        WHERE id = $1 AND status IN ('open', 'owner_responded')
+        RETURNING id, answer_id, agent_id, user_address, category, description, status, owner_response,
+            owner_responded_at, resolution_note, resolved_by, resolved_at, refund_entitlement_id, created_at
+        "#,
+        dispute_id,
+        status.to_string(),
+        resolved_by,
+        owner_response,
+        resolution_note
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(dispute)
+}
+
+// Record the refund granted on a dispute and link it to the dispute
+pub async fn insert_dispute_refund_entitlement(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    dispute: &DisputeDb,
+    payment_reference: &str,
+    amount: Price,
+) -> Result<RefundEntitlementDb, sqlx::Error> {
+    let record = sqlx::query_as!(
+        RefundEntitlementDb,
+        r#"
+        INSERT INTO refund_entitlements (agent_id, tx_hash, amount, reason, error)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, agent_id, tx_hash, amount as "amount: Price", reason, error, status, refund_tx_hash, created_at
+        "#,
+        dispute.agent_id,
+        payment_reference,
+        amount.units(),
+        RefundReason::Disputed.to_string(),
+        dispute.resolution_note
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE disputes
+        SET refund_entitlement_id = $2
+        WHERE id = $1
+        "#,
+        dispute.id,
+        record.id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn insert_query(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    query: &NewQuery,
+) -> Result<i64, sqlx::Error> {
+    let query_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO queries (requester_address, agent_ids, prompt_hash, prompt, payment_reference, amount, outcome, latency_ms, streamed)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+        "#,
+        query.requester_address,
+        &query.agent_ids,
+        query.prompt_hash,
+        query.prompt,
+        query.payment_reference,
+        query.amount.units(),
+        query.outcome.to_string(),
+        query.latency_ms,
+        query.streamed
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(query_id)
+}
+
+pub async fn insert_query_agents(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    query_id: i64,
+    agents: &[NewQueryAgent],
+) -> Result<(), sqlx::Error> {
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.agent_id).collect();
+    let statuses: Vec<String> = agents
+        .iter()
+        .map(|agent| agent.status.to_string())
+        .collect();
+    // Missing values are bound as '' or -1 and stored as NULL
+    let response_hashes: Vec<String> = agents
+        .iter()
+        .map(|agent| agent.response_hash.clone().unwrap_or_default())
+        .collect();
+    let latencies: Vec<i64> = agents.iter().map(|agent| agent.latency_ms).collect();
+    let input_tokens: Vec<i64> = agents
+        .iter()
+        .map(|agent| {
+            agent
+                .usage
+                .as_ref()
+                .map_or(-1, |usage| usage.input_tokens as i64)
+        })
+        .collect();
+    let output_tokens: Vec<i64> = agents
+        .iter()
+        .map(|agent| {
+            agent
+                .usage
+                .as_ref()
+                .map_or(-1, |usage| usage.output_tokens as i64)
+        })
+        .collect();
+    let total_tokens: Vec<i64> = agents
+        .iter()
+        .map(|agent| {
+            agent
+                .usage
+                .as_ref()
+                .map_or(-1, |usage| usage.total_tokens as i64)
+        })
+        .collect();
+    let models: Vec<String> = agents.iter().map(|agent| agent.model.clone()).collect();
+    let dataset_versions: Vec<i32> = agents.iter().map(|agent| agent.dataset_version).collect();
+    let amounts: Vec<i64> = agents.iter().map(|agent| agent.amount.units()).collect();
+    let errors: Vec<String> = agents
+        .iter()
+        .map(|agent| agent.error.clone().unwrap_or_default())
+        .collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO query_agents (query_id, agent_id, status, response_hash, latency_ms, input_tokens,
+            output_tokens, total_tokens, model, dataset_version, amount, error)
+        SELECT $1, agent_id, status, NULLIF(response_hash, ''), latency_ms, NULLIF(input_tokens, -1),
+            NULLIF(output_tokens, -1), NULLIF(total_tokens, -1), model, dataset_version, amount, NULLIF(error, '')
+        FROM UNNEST($2::BIGINT[], $3::VARCHAR[], $4::VARCHAR[], $5::BIGINT[], $6::BIGINT[], $7::BIGINT[],
+            $8::BIGINT[], $9::VARCHAR[], $10::INT[], $11::BIGINT[], $12::TEXT[])
+            AS agent(agent_id, status, response_hash, latency_ms, input_tokens, output_tokens, total_tokens,
+                model, dataset_version, amount, error)
+        "#,
+        query_id,
+        &agent_ids,
+        &statuses,
+        &response_hashes,
+        &latencies,
+        &input_tokens,
+        &output_tokens,
+        &total_tokens,
+        &models,
+        &dataset_versions,
+        &amounts,
+        &errors
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Get the queries of a requester older than `before_id`, most recent first
+pub async fn get_queries_by_requester(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    requester_address: &str,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<QueryDb>, sqlx::Error> {
+    let queries = sqlx::query_as!(
+        QueryDb,
+        r#"
+        SELECT id, requester_address, agent_ids, prompt_hash, prompt, payment_reference,
+            amount as "amount: Price", outcome, latency_ms, streamed, created_at
+        FROM queries
+        WHERE requester_address = $1 AND ($2::BIGINT IS NULL OR id < $2)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+        requester_address,
+        before_id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(queries)
+}
+
+// Get the queries asked to an agent older than `before_id`, most recent first
+pub async fn get_queries_by_agent(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AgentQueryDb>, sqlx::Error> {
+    let queries = sqlx::query_as!(
+        AgentQueryDb,
+        r#"
+        SELECT q.id as query_id, q.prompt_hash, q.streamed, a.status, a.response_hash, a.latency_ms,
+            a.input_tokens, a.output_tokens, a.total_tokens, a.model, a.dataset_version,
+            a.amount as "amount: Price", a.error, q.created_at
+        FROM queries q
+        JOIN query_agents a ON a.query_id = q.id
+        WHERE a.agent_id = $1 AND ($2::BIGINT IS NULL OR q.id < $2)
+        ORDER BY q.id DESC
+        LIMIT $3
+        "#,
+        agent_id,
+        before_id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(queries)
+}
+
+// Get the agents outcomes of the queries
+pub async fn get_query_agents(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    query_ids: &[i64],
+) -> Result<Vec<QueryAgentDb>, sqlx::Error> {
+    let agents = sqlx::query_as!(
+        QueryAgentDb,
+        r#"
+        SELECT query_id, agent_id, status, response_hash, latency_ms, input_tokens, output_tokens,
+            total_tokens, model, dataset_version, amount as "amount: Price", error
+        FROM query_agents
+        WHERE query_id = ANY($1)
+        ORDER BY query_id DESC, id
+        "#,
+        query_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(agents)
+}
+
+// Refresh the materialised usage aggregates the marketplace statistics are computed from
+pub async fn refresh_stats_views(db: &sqlx::Pool<sqlx::Postgres>) -> Result<(), sqlx::Error> {
+    for view in [
+        "stats_agent_daily_usage",
+        "stats_agent_daily_latency",
+        "stats_daily_buyers",
+    ] {
+        sqlx::query(&format!("REFRESH MATERIALIZED VIEW CONCURRENTLY {}", view))
+            .execute(db)
+            .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO stats_refreshes (name, refreshed_at)
+        VALUES ('marketplace', NOW())
+        ON CONFLICT (name) DO UPDATE SET refreshed_at = EXCLUDED.refreshed_at
+        "#
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_stats_refreshed_at(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let refreshed_at = sqlx::query_scalar!(
+        r#"
+        SELECT refreshed_at FROM stats_refreshes WHERE name = 'marketplace'
+        "#
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(refreshed_at)
+}
+
+pub async fn get_marketplace_totals(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<MarketplaceTotals, sqlx::Error> {
+    let totals = sqlx::query_as!(
+        MarketplaceTotals,
+        r#"
+        WITH usage AS (
+            SELECT COALESCE(SUM(queries), 0)::BIGINT as queries,
+                COALESCE(SUM(paid_queries), 0)::BIGINT as paid_queries,
+                COALESCE(SUM(revenue - refunded), 0)::BIGINT as revenue,
+                COALESCE(SUM(refunded), 0)::BIGINT as refunded
+            FROM stats_agent_daily_usage
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+        ), latency AS (
+            SELECT SUM(total_latency_ms)::FLOAT8 / NULLIF(SUM(answers), 0) as average_latency_ms
+            FROM stats_agent_daily_latency
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+        ), buyers AS (
+            SELECT COUNT(DISTINCT user_address) as active_buyers
+            FROM stats_daily_buyers
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+        )
+        SELECT
+            (SELECT COUNT(*) FROM agents WHERE status <> 'deleted' AND created_at < $2) as "datasets!",
+            usage.queries as "queries!",
+            usage.paid_queries as "paid_queries!",
+            usage.revenue as "revenue!: Price",
+            usage.refunded as "refunded!: Price",
+            buyers.active_buyers as "active_buyers!",
+            latency.average_latency_ms
+        FROM usage, latency, buyers
+        "#,
+        from,
+        to
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(totals)
+}
+
+// Get the datasets, queries, revenue and latency of each category in the range, most queried first
+pub async fn get_category_stats(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<CategoryStats>, sqlx::Error> {
+    let categories = sqlx::query_as!(
+        CategoryStats,
+        r#"
+        SELECT
+            g.category as "category!: AgentCategory",
+            COUNT(*) FILTER (WHERE g.status <> 'deleted' AND g.created_at < $2) as "datasets!",
+            COALESCE(SUM(u.queries), 0)::BIGINT as "queries!",
+            COALESCE(SUM(u.revenue), 0)::BIGINT as "revenue!: Price",
+            SUM(l.total_latency_ms)::FLOAT8 / NULLIF(SUM(l.answers), 0) as average_latency_ms
+        FROM agents g
+        LEFT JOIN (
+            SELECT agent_id, SUM(queries) as queries, SUM(revenue - refunded) as revenue
+            FROM stats_agent_daily_usage
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+            GROUP BY agent_id
+        ) u ON u.agent_id = g.id
+        LEFT JOIN (
+            SELECT agent_id, SUM(answers) as answers, SUM(total_latency_ms) as total_latency_ms
+            FROM stats_agent_daily_latency
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+            GROUP BY agent_id
+        ) l ON l.agent_id = g.id
+        GROUP BY g.category
+        ORDER BY 3 DESC, g.category
+        "#,
+        from,
+        to
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(categories)
+}
+
+// Get the datasets queried in the range, by revenue when `by_revenue` is set, by queries otherwise
+pub async fn get_top_datasets(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    by_revenue: bool,
+    limit: i64,
+) -> Result<Vec<DatasetStats>, sqlx::Error> {
+    let datasets = sqlx::query_as!(
+        DatasetStats,
+        r#"
+        SELECT
+            g.id as agent_id,
+            g.name,
+            g.category as "category: AgentCategory",
+            u.queries::BIGINT as "queries!",
+            u.revenue::BIGINT as "revenue!: Price",
+            l.total_latency_ms::FLOAT8 / NULLIF(l.answers, 0) as average_latency_ms
+        FROM agents g
+        JOIN (
+            SELECT agent_id, SUM(queries) as queries, SUM(revenue - refunded) as revenue
+            FROM stats_agent_daily_usage
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+            GROUP BY agent_id
+        ) u ON u.agent_id = g.id
+        LEFT JOIN (
+            SELECT agent_id, SUM(answers) as answers, SUM(total_latency_ms) as total_latency_ms
+            FROM stats_agent_daily_latency
+            WHERE day >= date_trunc('day', $1::TIMESTAMPTZ) AND day < $2
+            GROUP BY agent_id
+        ) l ON l.agent_id = g.id
+        WHERE g.status <> 'deleted'
+        ORDER BY CASE WHEN $3 THEN u.revenue ELSE u.queries END DESC, g.id
+        LIMIT $4
+        "#,
+        from,
+        to,
+        by_revenue,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(datasets)
+}
+
+// Count the datasets created in each `interval` (day, week or month) of the range
+pub async fn get_new_datasets_over_time(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: &str,
+) -> Result<Vec<TimeBucketCount>, sqlx::Error> {
+    let buckets = sqlx::query_as!(
+        TimeBucketCount,
+        r#"
+        SELECT b.period_start as "period_start!", COUNT(g.id) as "count!"
+        FROM generate_series(date_trunc($3::TEXT, $1::TIMESTAMPTZ), $2, ('1 ' || $3)::INTERVAL) AS b(period_start)
+        LEFT JOIN agents g ON date_trunc($3::TEXT, g.created_at) = b.period_start
+            AND g.created_at >= $1 AND g.created_at < $2
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        from,
+        to,
+        interval
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(buckets)
+}
+
+// Count the distinct users who asked a question in each `interval` (day, week or month) of the range
+pub async fn get_active_buyers_over_time(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: &str,
+) -> Result<Vec<TimeBucketCount>, sqlx::Error> {
+    let buckets = sqlx::query_as!(
+        TimeBucketCount,
+        r#"
+        SELECT b.period_start as "period_start!", COUNT(DISTINCT d.user_address) as "count!"
+        FROM generate_series(date_trunc($3::TEXT, $1::TIMESTAMPTZ), $2, ('1 ' || $3)::INTERVAL) AS b(period_start)
+        LEFT JOIN stats_daily_buyers d ON date_trunc($3::TEXT, d.day) = b.period_start
+            AND d.day >= date_trunc('day', $1::TIMESTAMPTZ) AND d.day < $2
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        from,
+        to,
+        interval
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(buckets)
+}
+
+// Whether the address paid for at least one query to the agent, free samples don't count
+pub async fn has_paid_agent_query(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    user_address: &str,
+) -> Result<bool, sqlx::Error> {
+    let paid = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM agent_usage
+            WHERE agent_id = $1 AND LOWER(user_address) = LOWER($2) AND basis <> 'free_sample'
+        ) as "paid!"
+        "#,
+        agent_id,
+        user_address
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(paid)
+}
+
+// Insert the review of an address on an agent, replacing its previous one
+pub async fn upsert_agent_review(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    reviewer_address: &str,
+    rating: i16,
+    comment: Option<&str>,
+) -> Result<AgentReviewDb, sqlx::Error> {
+    let review = sqlx::query_as!(
+        AgentReviewDb,
+        r#"
+        INSERT INTO agent_reviews (agent_id, reviewer_address, rating, comment)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (agent_id, reviewer_address)
+        DO UPDATE SET rating = EXCLUDED.rating, comment = EXCLUDED.comment
+        RETURNING id, agent_id, reviewer_address, rating, comment, created_at, updated_at
+        "#,
+        agent_id,
+        reviewer_address,
+        rating,
+        comment
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(review)
+}
+
+// Get the reviews of an agent older than `before_id`, most recent first
+pub async fn get_agent_reviews(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AgentReviewDb>, sqlx::Error> {
+    let reviews = sqlx::query_as!(
+        AgentReviewDb,
+        r#"
+        SELECT id, agent_id, reviewer_address, rating, comment, created_at, updated_at
+        FROM agent_reviews
+        WHERE agent_id = $1 AND ($2::BIGINT IS NULL OR id < $2)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+        agent_id,
+        before_id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(reviews)
+}
+
+pub async fn insert_evaluation_cases(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    created_by: &str,
+    cases: &[NewEvaluationCase],
+) -> Result<Vec<EvaluationCaseDb>, sqlx::Error> {
+    let questions: Vec<String> = cases.iter().map(|case| case.question.clone()).collect();
+    let expected_answers: Vec<String> = cases
+        .iter()
+        .map(|case| case.expected_answer.clone())
+        .collect();
+    let match_kinds: Vec<String> = cases
+        .iter()
+        .map(|case| case.match_kind.to_string())
+        .collect();
+    // Tolerances are never negative, -1 is stored as NULL
+    let tolerances: Vec<f64> = cases
+        .iter()
+        .map(|case| case.tolerance.unwrap_or(-1.0))
+        .collect();
+
+    let cases = sqlx::query_as!(
+        EvaluationCaseDb,
+        r#"
+        INSERT INTO evaluation_cases (agent_id, created_by, question, expected_answer, match_kind, tolerance)
+        SELECT $1, $2, question, expected_answer, match_kind, NULLIF(tolerance, -1)
+        FROM UNNEST($3::TEXT[], $4::TEXT[], $5::VARCHAR[], $6::DOUBLE PRECISION[])
+            AS evaluation_case(question, expected_answer, match_kind, tolerance)
+        RETURNING id, agent_id, question, expected_answer, match_kind, tolerance, created_by, created_at
+        "#,
+        agent_id,
+        created_by,
+        &questions,
+        &expected_answers,
+        &match_kinds,
+        &tolerances
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(cases)
+}
+
+pub async fn get_evaluation_cases(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Vec<EvaluationCaseDb>, sqlx::Error> {
+    let cases = sqlx::query_as!(
+        EvaluationCaseDb,
+        r#"
+        SELECT id, agent_id, question, expected_answer, match_kind, tolerance, created_by, created_at
+        FROM evaluation_cases
+        WHERE agent_id = $1
+        ORDER BY id
+        "#,
+        agent_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(cases)
+}
+
+pub async fn insert_evaluation_run(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    dataset_version: i32,
+    model: &str,
+    total_cases: i32,
+    started_by: Option<&str>,
+) -> Result<EvaluationRunDb, sqlx::Error> {
+    let run = sqlx::query_as!(
+        EvaluationRunDb,
+        r#"
+        INSERT INTO evaluation_runs (agent_id, dataset_version, model, total_cases, started_by)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, agent_id, dataset_version, model, status, total_cases, passed_cases, score, error,
+            started_by, created_at, completed_at
+        "#,
+        agent_id,
+        dataset_version,
+        model,
+        total_cases,
+        started_by
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(run)
+}
+
+pub async fn insert_evaluation_result(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run_id: i64,
+    result: &NewEvaluationResult,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO evaluation_results (run_id, case_id, answer, passed, score, judge_reasoning, error, latency_ms)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        run_id,
+        result.case_id,
+        result.answer,
+        result.passed,
+        result.score,
+        result.judge_reasoning,
+        result.error,
+        result.latency_ms
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn complete_evaluation_run(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run_id: i64,
+    passed_cases: i32,
+    score: Option<f64>,
+) -> Result<EvaluationRunDb, sqlx::Error> {
+    let run = sqlx::query_as!(
+        EvaluationRunDb,
+        r#"
+        UPDATE evaluation_runs
+        SET status = $2, passed_cases = $3, score = $4, completed_at = NOW()
+        WHERE id = $1
+        RETURNING id, agent_id, dataset_version, model, status, total_cases, passed_cases, score, error,
+            started_by, created_at, completed_at
+        "#,
+        run_id,
+        EvaluationRunStatus::Completed.to_string(),
+        passed_cases,
+        score
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(run)
+}
+
+pub async fn fail_evaluation_run(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run_id: i64,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE evaluation_runs
+        SET status = $2, error = $3, completed_at = NOW()
+        WHERE id = $1
+        "#,
+        run_id,
+        EvaluationRunStatus::Failed.to_string(),
+        error
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_evaluation_run(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run_id: i64,
+) -> Result<EvaluationRunDb, sqlx::Error> {
+    let run = sqlx::query_as!(
+        EvaluationRunDb,
+        r#"
+        SELECT id, agent_id, dataset_version, model, status, total_cases, passed_cases, score, error,
+            started_by, created_at, completed_at
+        FROM evaluation_runs
+        WHERE id = $1
+        "#,
+        run_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(run)
+}
+
+// Get the latest evaluation runs of an agent, most recent first
+pub async fn get_evaluation_runs(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    limit: i64,
+) -> Result<Vec<EvaluationRunDb>, sqlx::Error> {
+    let runs = sqlx::query_as!(
+        EvaluationRunDb,
+        r#"
+        SELECT id, agent_id, dataset_version, model, status, total_cases, passed_cases, score, error,
+            started_by, created_at, completed_at
+        FROM evaluation_runs
+        WHERE agent_id = $1
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+        agent_id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(runs)
+}
+
+pub async fn get_evaluation_results(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run_id: i64,
+) -> Result<Vec<EvaluationResultDb>, sqlx::Error> {
+    let results = sqlx::query_as!(
+        EvaluationResultDb,
+        r#"
+        SELECT id, run_id, case_id, answer, passed, score, judge_reasoning, error, latency_ms, created_at
+        FROM evaluation_results
+        WHERE run_id = $1
+        ORDER BY case_id
+        "#,
+        run_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(results)
+}
+
+pub async fn get_preview_mode(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    let mode = sqlx::query_scalar!(
+        r#"
+        SELECT mode
+        FROM agent_preview_settings
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(mode)
+}
+
+pub async fn upsert_preview_mode(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    mode: PreviewMode,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_preview_settings (agent_id, mode)
+        VALUES ($1, $2)
+        ON CONFLICT (agent_id) DO UPDATE SET mode = EXCLUDED.mode
+        "#,
+        agent_id,
+        mode.to_string()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Serialize the preview questions of an IP or address until the end of the transaction
+pub async fn lock_preview_requester(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    requester: &str,
+) -> Result<(), sqlx::Error> {
+    // The lock function returns void, which the checked macros can't decode
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(requester)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+// Count the preview questions asked today (UTC) from an IP or by an address, whichever is the highest
+pub async fn count_preview_queries_today(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ip_address: &str,
+    requester_address: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT GREATEST(
+            COUNT(*) FILTER (WHERE ip_address = $1),
+            COUNT(*) FILTER (WHERE requester_address = $2)
+        ) as "count!"
+        FROM preview_queries
+        WHERE created_at >= date_trunc('day', NOW() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+            AND (ip_address = $1 OR requester_address = $2)
+        "#,
+        ip_address,
+        requester_address
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(count)
+}
+
+pub async fn insert_preview_query(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    ip_address: &str,
+    requester_address: Option<&str>,
+    prompt_hash: &str,
+    mode: PreviewMode,
+) -> Result<i64, sqlx::Error> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO preview_queries (agent_id, ip_address, requester_address, prompt_hash, mode)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        agent_id,
+        ip_address,
+        requester_address,
+        prompt_hash,
+        mode.to_string()
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn delete_preview_query(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM preview_queries
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Record the nonce of a signed request, returns false when the address already used it
+pub async fn claim_auth_nonce(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    address: &str,
+    nonce: &str,
+) -> Result<bool, sqlx::Error> {
+    let insert_result = sqlx::query!(
+        r#"
+        INSERT INTO auth_nonces (address, nonce)
+        VALUES ($1, $2)
+        ON CONFLICT (address, nonce) DO NOTHING
+        "#,
+        address,
+        nonce
+    )
+    .execute(db)
+    .await?;
+
+    Ok(insert_result.rows_affected() == 1)
+}
+
+// Delete the nonces older than `max_age_secs`, their signatures are expired
+pub async fn prune_auth_nonces(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    max_age_secs: i64,
+) -> Result<u64, sqlx::Error> {
+    let delete_result = sqlx::query!(
+        r#"
+        DELETE FROM auth_nonces
+        WHERE created_at < NOW() - make_interval(secs => $1)
+        "#,
+        max_age_secs as f64
+    )
+    .execute(db)
+    .await?;
+
+    Ok(delete_result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::B256;
+    use sqlx::migrate::Migrator;
+
+    use super::*;
+
+    // Migration storing agent prices as integer base units
+    const INTEGER_PRICES_MIGRATION: i64 = 20261018170000;
+
+    #[sqlx::test]
+    async fn payment_txs_are_claimed_once(db: sqlx::Pool<sqlx::Postgres>) {
+        let tx_hash = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060";
+
+        let mut tx = db.begin().await.unwrap();
+        assert!(claim_payment_tx(&mut tx, tx_hash, "0xpayer").await.unwrap());
+        tx.commit().await.unwrap();
+
+        // Claimed in the database, so refused after a restart or from a concurrent request
+        let mut tx = db.begin().await.unwrap();
+        assert!(!claim_payment_tx(&mut tx, tx_hash, "0xpayer").await.unwrap());
+        assert!(!claim_payment_tx(&mut tx, tx_hash, "0xother").await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn payment_txs_are_claimed_once_whatever_their_spelling(db: sqlx::Pool<sqlx::Postgres>) {
+        let prefixed: B256 = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+            .parse()
+            .unwrap();
+        let unprefixed: B256 = "5C504ED432CB51138BCF09AA5E8A410DD4A1E204EF84BFED1BE16DFBA1B22060"
+            .parse()
+            .unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        assert!(
+            claim_payment_tx(&mut tx, &prefixed.to_string(), "0xpayer")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !claim_payment_tx(&mut tx, &unprefixed.to_string(), "0xpayer")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test]
+    async fn rolled_back_payment_claims_can_be_retried(db: sqlx::Pool<sqlx::Postgres>) {
+        let tx_hash = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060";
+
+        // The usage insert failing rolls the claim back with it
+        let mut tx = db.begin().await.unwrap();
+        assert!(claim_payment_tx(&mut tx, tx_hash, "0xpayer").await.unwrap());
+        tx.rollback().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        assert!(claim_payment_tx(&mut tx, tx_hash, "0xpayer").await.unwrap());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn integer_prices_migration_pauses_agents_with_invalid_prices(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let migrator = sqlx::migrate!("./migrations");
+        let before_integer_prices = Migrator {
+            migrations: migrator
+                .migrations
+                .iter()
+                .filter(|migration| migration.version < INTEGER_PRICES_MIGRATION)
+                .cloned()
+                .collect::<Vec<_>>()
+                .into(),
+            ..sqlx::migrate!("./migrations")
+        };
+        before_integer_prices.run(&db).await.unwrap();
+
+        let owner_id: i64 =
+            sqlx::query_scalar("INSERT INTO users (address) VALUES ('0xowner') RETURNING id")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        for price in [0.0, 1e-9, 2.5] {
+            sqlx::query(
+                r#"
+                INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+                VALUES ($1, 'Sales', 'Sales per city', $2, 'sales.csv', 'Analytics', 1.0)
+                "#,
+            )
+            .bind(owner_id)
+            .bind(price)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        migrator.run(&db).await.unwrap();
+
+        let agents: Vec<(i64, String)> =
+            sqlx::query_as("SELECT price, status FROM agents ORDER BY id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            agents,
+            vec![
+                (1, "paused".to_string()),
+                (1, "paused".to_string()),
+                (250000000, "active".to_string()),
+            ]
+        );
+    }
+}
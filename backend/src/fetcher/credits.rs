@@ -0,0 +1,308 @@
+use std::str::FromStr;
+
+use alloy::{
+    primitives::Address,
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol,
+    sol_types::SolEvent,
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+
+use crate::{
+    config::{
+        APP_CONFIG, CREDIT_SETTLEMENT_INTERVAL_SECS, CREDIT_SETTLEMENT_MIN_UNITS,
+        CREDITS_FETCHER_BLOCK_RANGE, CREDITS_FETCHER_CURSOR, ENCLAVA_CONTRACT_ADDRESS,
+        HEDERA_TESTNET_RPC_URL,
+    },
+    database,
+    helpers::credits::{accrue_settlements, credit_deposit, record_settlement},
+    types::{Price, WebAppState},
+};
+
+sol! {
+    event CreditsDeposited(address indexed user, uint256 amount);
+    event CreditsSettled(uint256 indexed tokenId, uint256 amount);
+}
+
+/// Indexes the CreditsDeposited and CreditsSettled events into the ledger.
+/// The last indexed block is stored, so events emitted while the backend was down are indexed on restart.
+pub async fn credits_fetcher(app_state: &WebAppState) -> Result<()> {
+    tracing::info!("Starting credits fetcher (Polling Mod)...");
+
+    let provider = ProviderBuilder::new().connect_http(HEDERA_TESTNET_RPC_URL.parse()?);
+
+    let contract_address =
+        Address::from_str(ENCLAVA_CONTRACT_ADDRESS).context("Failed to parse contract address")?;
+
+    let mut next_block = match database::get_fetcher_cursor(&app_state.db, CREDITS_FETCHER_CURSOR)
+        .await
+        .context("Failed to get credits fetcher cursor")?
+    {
+        Some(last_block) => last_block as u64 + 1,
+        None => provider
+            .get_block_number()
+            .await
+            .context("Failed to get initial block number")?,
+    };
+
+    tracing::info!("Credits fetcher initialized at block {}", next_block);
+
+    loop {
+        if let Err(e) =
+            poll_credit_events(&provider, contract_address, &mut next_block, app_state).await
+        {
+            tracing::warn!("Error during credit events polling (will retry): {}", e);
+
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    }
+}
+
+/// Books the credit events between `next_block` and the current block, `CREDITS_FETCHER_BLOCK_RANGE` blocks at a time,
+/// and moves the cursor past each range once its events are booked. Booking an event twice is a no-op.
+async fn poll_credit_events(
+    provider: &impl Provider,
+    contract_address: Address,
+    next_block: &mut u64,
+    app_state: &WebAppState,
+) -> Result<()> {
+    let current_block = provider
+        .get_block_number()
+        .await
+        .context("Failed to get current block number")?;
+
+    while *next_block <= current_block {
+        let to_block = current_block.min(*next_block + CREDITS_FETCHER_BLOCK_RANGE - 1);
+
+        tracing::debug!("Polling credit events from {} to {}", next_block, to_block);
+
+        let filter = Filter::new()
+            .address(contract_address)
+            .events([CreditsDeposited::SIGNATURE, CreditsSettled::SIGNATURE])
+            .from_block(*next_block)
+            .to_block(to_block);
+
+        let logs = provider
+            .get_logs(&filter)
+            .await
+            .context("Failed to fetch logs from provider")?;
+
+        for log in logs {
+            handle_credit_log(app_state, &log).await?;
+        }
+
+        database::upsert_fetcher_cursor(&app_state.db, CREDITS_FETCHER_CURSOR, to_block as i64)
+            .await
+            .context("Failed to save credits fetcher cursor")?;
+
+        *next_block = to_block + 1;
+    }
+
+    Ok(())
+}
+
+/// Books a credit event. Logs that can't be decoded are skipped so they don't block the events after them,
+/// failing to book a decoded event is returned and the range is polled again.
+async fn handle_credit_log(app_state: &WebAppState, log: &Log) -> Result<()> {
+    let event = match decode_credit_log(log) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::error!(
+                "Skipping credit event {:?} at index {:?}: {:?}",
+                log.transaction_hash,
+                log.log_index,
+                e
+            );
+            return Ok(());
+        }
+    };
+
+    match event {
+        CreditEvent::Deposit {
+            tx_hash,
+            log_index,
+            block_number,
+            user_address,
+            amount,
+        } => {
+            tracing::info!(
+                "New CreditsDeposited event: user: {}, amount: {}",
+                user_address,
+                amount
+            );
+
+            credit_deposit(
+                &app_state.db,
+                &tx_hash,
+                log_index,
+                block_number,
+                &user_address,
+                amount,
+                APP_CONFIG.platform_funding_address.as_deref(),
+            )
+            .await
+        }
+        CreditEvent::Settlement {
+            tx_hash,
+            log_index,
+            block_number,
+            token_id,
+            amount,
+        } => {
+            tracing::info!(
+                "New CreditsSettled event: tokenId: {}, amount: {}",
+                token_id,
+                amount
+            );
+
+            record_settlement(
+                &app_state.db,
+                &tx_hash,
+                log_index,
+                block_number,
+                token_id,
+                amount,
+            )
+            .await
+        }
+    }
+}
+
+enum CreditEvent {
+    Deposit {
+        tx_hash: String,
+        log_index: i64,
+        block_number: i64,
+        user_address: String,
+        amount: Price,
+    },
+    Settlement {
+        tx_hash: String,
+        log_index: i64,
+        block_number: i64,
+        token_id: i64,
+        amount: Price,
+    },
+}
+
+fn decode_credit_log(log: &Log) -> Result<CreditEvent> {
+    let tx_hash = log
+        .transaction_hash
+        .ok_or_else(|| eyre!("Credit event without tx hash"))?
+        .to_string();
+    let log_index = log
+        .log_index
+        .ok_or_else(|| eyre!("Credit event without log index"))? as i64;
+    let block_number = log
+        .block_number
+        .ok_or_else(|| eyre!("Credit event without block number"))? as i64;
+
+    match log.topic0() {
+        Some(topic) if *topic == CreditsDeposited::SIGNATURE_HASH => {
+            let event = CreditsDeposited::decode_log_data(log.data())?;
+
+            Ok(CreditEvent::Deposit {
+                tx_hash,
+                log_index,
+                block_number,
+                user_address: event.user.to_string(),
+                amount: Price::from_u256(event.amount)?,
+            })
+        }
+        Some(topic) if *topic == CreditsSettled::SIGNATURE_HASH => {
+            let event = CreditsSettled::decode_log_data(log.data())?;
+
+            let token_id = i64::try_from(event.tokenId)
+                .map_err(|_| eyre!("Token id {} is too large", event.tokenId))?;
+
+            Ok(CreditEvent::Settlement {
+                tx_hash,
+                log_index,
+                block_number,
+                token_id,
+                amount: Price::from_u256(event.amount)?,
+            })
+        }
+        _ => Err(eyre!("Not a credit event")),
+    }
+}
+
+/// Accrues the agents revenue into pending settlements every `CREDIT_SETTLEMENT_INTERVAL_SECS`
+pub async fn credit_settlement_scheduler(app_state: &WebAppState) -> Result<()> {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        CREDIT_SETTLEMENT_INTERVAL_SECS,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match accrue_settlements(
+            &app_state.db,
+            Price::from_units(CREDIT_SETTLEMENT_MIN_UNITS),
+        )
+        .await
+        {
+            Ok(count) => tracing::info!("Created {} pending credit settlements", count),
+            Err(e) => tracing::error!("Failed to accrue credit settlements: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{B256, LogData, U256};
+
+    use super::*;
+
+    fn log(data: LogData) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data,
+            },
+            transaction_hash: Some(B256::repeat_byte(1)),
+            log_index: Some(3),
+            block_number: Some(42),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_credit_events_and_refuses_the_others() {
+        let deposit = CreditsDeposited {
+            user: Address::repeat_byte(0xa1),
+            amount: U256::from(150_000_000u64),
+        };
+
+        match decode_credit_log(&log(deposit.encode_log_data())).unwrap() {
+            CreditEvent::Deposit {
+                log_index,
+                block_number,
+                user_address,
+                amount,
+                ..
+            } => {
+                assert_eq!((log_index, block_number), (3, 42));
+                assert_eq!(user_address, Address::repeat_byte(0xa1).to_string());
+                assert_eq!(amount, Price::from_units(150_000_000));
+            }
+            CreditEvent::Settlement { .. } => panic!("Decoded a deposit as a settlement"),
+        }
+
+        let unknown = LogData::new_unchecked(vec![B256::repeat_byte(9)], Default::default());
+        assert!(decode_credit_log(&log(unknown)).is_err());
+
+        let too_large = CreditsSettled {
+            tokenId: U256::MAX,
+            amount: U256::from(1u64),
+        };
+        assert!(decode_credit_log(&log(too_large.encode_log_data())).is_err());
+    }
+}
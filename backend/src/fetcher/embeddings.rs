@@ -0,0 +1,32 @@
+use color_eyre::Result;
+
+use crate::{config::EMBEDDING_INDEX_INTERVAL_SECS, database, helpers, types::WebAppState};
+
+/// Computes every `EMBEDDING_INDEX_INTERVAL_SECS` the embeddings of the active agents that are missing or outdated,
+/// the ones that failed when their agent changed or all of them after a change of embedding model
+pub async fn embedding_indexer(app_state: &WebAppState) -> Result<()> {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        EMBEDDING_INDEX_INTERVAL_SECS,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match index_agent_embeddings(app_state).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Computed the embeddings of {} agents", count),
+            Err(e) => tracing::error!("Failed to index agent embeddings: {:?}", e),
+        }
+    }
+}
+
+async fn index_agent_embeddings(app_state: &WebAppState) -> Result<usize> {
+    let db = &app_state.db;
+
+    let agents = database::get_active_agents(db).await?;
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+    let agents_columns = database::get_dataset_columns_by_agent_ids(db, &agent_ids).await?;
+
+    helpers::embeddings::refresh_agent_embeddings(db, &app_state.embedder, &agents, &agents_columns)
+        .await
+}
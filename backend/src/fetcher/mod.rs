@@ -1,15 +1,64 @@
+pub mod credits;
+pub mod embeddings;
 pub mod mint;
+pub mod nonces;
+pub mod refunds;
+pub mod stats;
+
+use std::future::Future;
 
 use color_eyre::eyre::Result;
 
-use crate::{fetcher::mint::mint_nft_fetcher, types::WebAppState};
+use crate::{
+    fetcher::{
+        credits::{credit_settlement_scheduler, credits_fetcher},
+        embeddings::embedding_indexer,
+        mint::mint_nft_fetcher,
+        nonces::auth_nonce_pruner,
+        refunds::refund_executor,
+        stats::stats_refresher,
+    },
+    types::WebAppState,
+};
 
 /// Starts all log fetchers with automatic retry mechanism
 ///
-/// This function ensures that the mint_nft_fetcher never stops running by implementing
-/// an exponential backoff retry strategy. If the fetcher fails, it will automatically
+/// This function ensures that the fetchers never stop running by implementing
+/// an exponential backoff retry strategy. If a fetcher fails, it will automatically
 /// restart with increasing delays between attempts (capped at 5 minutes).
 pub async fn open_all_logs_fetcher(app_state: &WebAppState) -> Result<()> {
+    spawn_with_retry("mint_nft_fetcher", app_state, |app_state| async move {
+        mint_nft_fetcher(&app_state).await
+    });
+    spawn_with_retry("credits_fetcher", app_state, |app_state| async move {
+        credits_fetcher(&app_state).await
+    });
+    spawn_with_retry(
+        "credit_settlement_scheduler",
+        app_state,
+        |app_state| async move { credit_settlement_scheduler(&app_state).await },
+    );
+    spawn_with_retry("refund_executor", app_state, |app_state| async move {
+        refund_executor(&app_state).await
+    });
+    spawn_with_retry("stats_refresher", app_state, |app_state| async move {
+        stats_refresher(&app_state).await
+    });
+    spawn_with_retry("auth_nonce_pruner", app_state, |app_state| async move {
+        auth_nonce_pruner(&app_state).await
+    });
+    spawn_with_retry("embedding_indexer", app_state, |app_state| async move {
+        embedding_indexer(&app_state).await
+    });
+
+    Ok(())
+}
+
+fn spawn_with_retry<F, Fut>(name: &'static str, app_state: &WebAppState, fetcher: F)
+where
+    F: Fn(WebAppState) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
     let app_state = app_state.clone();
 
     tokio::spawn(async move {
@@ -18,12 +67,12 @@ pub async fn open_all_logs_fetcher(app_state: &WebAppState) -> Result<()> {
         let initial_retry_delay_secs = 10; // Start with 10 second
 
         loop {
-            tracing::info!("Starting mint_nft_fetcher (attempt #{})", retry_count + 1);
+            tracing::info!("Starting {} (attempt #{})", name, retry_count + 1);
 
-            match mint_nft_fetcher(&app_state).await {
+            match fetcher(app_state.clone()).await {
                 Ok(_) => {
                     // Fetcher completed successfully (should never happen as it's an infinite loop)
-                    tracing::warn!("mint_nft_fetcher completed unexpectedly, restarting...");
+                    tracing::warn!("{} completed unexpectedly, restarting...", name);
                     retry_count = 0; // Reset retry count on successful run
                 }
                 Err(e) => {
@@ -36,7 +85,8 @@ pub async fn open_all_logs_fetcher(app_state: &WebAppState) -> Result<()> {
                     );
 
                     tracing::error!(
-                        "mint_nft_fetcher failed (attempt #{}): {}. Retrying in {} seconds...",
+                        "{} failed (attempt #{}): {}. Retrying in {} seconds...",
+                        name,
                         retry_count,
                         e,
                         delay_secs
@@ -53,6 +103,4 @@ pub async fn open_all_logs_fetcher(app_state: &WebAppState) -> Result<()> {
             }
         }
     });
-
-    Ok(())
 }
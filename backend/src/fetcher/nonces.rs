@@ -0,0 +1,25 @@
+use color_eyre::Result;
+
+use crate::{
+    config::{AUTH_NONCE_PRUNE_INTERVAL_SECS, AUTH_SIGNATURE_MAX_AGE_SECS},
+    database,
+    types::WebAppState,
+};
+
+/// Deletes every `AUTH_NONCE_PRUNE_INTERVAL_SECS` the nonces whose signatures can no longer be used.
+/// Twice the max age is kept since the timestamp of a signature may be ahead of the server clock.
+pub async fn auth_nonce_pruner(app_state: &WebAppState) -> Result<()> {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        AUTH_NONCE_PRUNE_INTERVAL_SECS,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match database::prune_auth_nonces(&app_state.db, 2 * AUTH_SIGNATURE_MAX_AGE_SECS).await {
+            Ok(0) => {}
+            Ok(count) => tracing::debug!("Pruned {} expired auth nonces", count),
+            Err(e) => tracing::error!("Failed to prune auth nonces: {:?}", e),
+        }
+    }
+}
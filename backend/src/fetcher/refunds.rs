@@ -0,0 +1,24 @@
+use color_eyre::Result;
+
+use crate::{
+    config::REFUND_EXECUTOR_INTERVAL_SECS, helpers::refunds::execute_pending_refunds,
+    types::WebAppState,
+};
+
+/// Executes the pending refunds every `REFUND_EXECUTOR_INTERVAL_SECS`,
+/// refunds of on-chain payments are recorded by the answer handlers and executed here
+pub async fn refund_executor(app_state: &WebAppState) -> Result<()> {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        REFUND_EXECUTOR_INTERVAL_SECS,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match execute_pending_refunds(app_state).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Executed {} pending refunds", count),
+            Err(e) => tracing::error!("Failed to execute pending refunds: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use color_eyre::Result;
+
+use crate::{config::STATS_REFRESH_INTERVAL_SECS, database, types::WebAppState};
+
+/// Refreshes the materialised usage aggregates behind the marketplace statistics every `STATS_REFRESH_INTERVAL_SECS`
+pub async fn stats_refresher(app_state: &WebAppState) -> Result<()> {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        STATS_REFRESH_INTERVAL_SECS,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match database::refresh_stats_views(&app_state.db).await {
+            Ok(()) => tracing::debug!("Refreshed marketplace statistics"),
+            Err(e) => tracing::error!("Failed to refresh marketplace statistics: {:?}", e),
+        }
+    }
+}
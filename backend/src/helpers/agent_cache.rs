@@ -0,0 +1,323 @@
+use std::{
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use lru::LruCache;
+use tracing::{debug, warn};
+
+use crate::{
+    config::UPLOAD_DIR,
+    helpers::agents::init_agent,
+    state::TeeAgent,
+    types::{AgentCacheMetrics, AgentDb},
+};
+
+/// Agents built on demand, keyed by agent id, dataset version and update time of the agent row,
+/// so an edit of the agent is never served from an agent built before it.
+///
+/// The cache is bounded both by its number of agents and by the total size of the datasets they hold,
+/// the least recently used agents are evicted first.
+pub struct AgentCache {
+    entries: Mutex<CacheEntries>,
+    capacity: usize,
+    max_dataset_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    load_failures: AtomicU64,
+}
+
+/// Agent id, dataset version and update time of the agent row
+type CacheKey = (i64, i32, DateTime<Utc>);
+
+struct CacheEntries {
+    agents: LruCache<CacheKey, Arc<TeeAgent>>,
+    dataset_bytes: usize,
+}
+
+impl AgentCache {
+    pub fn new(capacity: usize, max_dataset_bytes: usize) -> Self {
+        let capacity = capacity.max(1);
+
+        Self {
+            entries: Mutex::new(CacheEntries {
+                agents: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+                dataset_bytes: 0,
+            }),
+            capacity,
+            max_dataset_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            load_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the agent answering from the current dataset version and details of `agent_db`, building it on a miss
+    pub async fn get_or_load(
+        &self,
+        agent_db: &AgentDb,
+        ai_model: &rig::providers::gemini::Client,
+    ) -> Result<Arc<TeeAgent>> {
+        let key = (agent_db.id, agent_db.current_version, agent_db.updated_at);
+
+        if let Some(agent) = self.lock().agents.get(&key).cloned() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(agent);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        debug!(
+            "Agent cache miss for agent {} version {}",
+            agent_db.id, agent_db.current_version
+        );
+
+        // Two concurrent misses on the same agent both build it, the last one stays cached
+        let dataset_csv_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
+
+        let agent = match init_agent(&dataset_csv_path, ai_model, agent_db).await {
+            Ok(agent) => agent,
+            Err(e) => {
+                self.load_failures.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Failed to load agent {} from {}: {}",
+                    agent_db.id,
+                    dataset_csv_path.display(),
+                    e
+                );
+                return Err(e);
+            }
+        };
+
+        Ok(self.insert(agent_db.id, agent))
+    }
+
+    /// Caches an already built agent, replacing any older version of it.
+    /// An agent built from an older row than the cached one, by a load that raced an update, is returned but not cached.
+    pub fn insert(&self, agent_id: i64, agent: TeeAgent) -> Arc<TeeAgent> {
+        let agent = Arc::new(agent);
+        let key = (agent_id, agent.version, agent.updated_at);
+
+        let mut entries = self.lock();
+
+        let newer_cached = entries
+            .agents
+            .iter()
+            .any(|(cached, _)| cached.0 == agent_id && (cached.1, cached.2) > (key.1, key.2));
+
+        if newer_cached {
+            debug!("Not caching an outdated build of agent {}", agent_id);
+            return agent;
+        }
+
+        entries.remove_agent(agent_id);
+
+        entries.dataset_bytes += agent.dataset.len();
+
+        if let Some((_, evicted)) = entries.agents.push(key, agent.clone()) {
+            entries.dataset_bytes -= evicted.dataset.len();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Always keep the agent just inserted, even when its dataset alone is over the limit
+        while entries.dataset_bytes > self.max_dataset_bytes && entries.agents.len() > 1 {
+            let Some((_, evicted)) = entries.agents.pop_lru() else {
+                break;
+            };
+
+            entries.dataset_bytes -= evicted.dataset.len();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        agent
+    }
+
+    /// Drops every cached version of an agent so the next request rebuilds it
+    pub fn invalidate(&self, agent_id: i64) {
+        self.lock().remove_agent(agent_id);
+    }
+
+    pub fn metrics(&self) -> AgentCacheMetrics {
+        let entries = self.lock();
+
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+
+        AgentCacheMetrics {
+            hits,
+            misses,
+            hit_rate: if hits + misses > 0 {
+                hits as f64 / (hits + misses) as f64
+            } else {
+                0.0
+            },
+            evictions: self.evictions.load(Ordering::Relaxed),
+            load_failures: self.load_failures.load(Ordering::Relaxed),
+            cached_agents: entries.agents.len(),
+            capacity: self.capacity,
+            cached_dataset_bytes: entries.dataset_bytes,
+            max_dataset_bytes: self.max_dataset_bytes,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheEntries> {
+        // Keep serving from the cache even if a request panicked while holding the lock
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CacheEntries {
+    fn remove_agent(&mut self, agent_id: i64) {
+        let keys: Vec<CacheKey> = self
+            .agents
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(|(id, _, _)| *id == agent_id)
+            .collect();
+
+        for key in keys {
+            if let Some(removed) = self.agents.pop(&key) {
+                self.dataset_bytes -= removed.dataset.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rig::providers::gemini;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{config::INIT_AGENT_MODEL, state::AppState};
+
+    fn tee_agent(version: i32, updated_at: DateTime<Utc>, dataset: &str) -> TeeAgent {
+        TeeAgent {
+            agent: gemini::Client::new("test").agent(INIT_AGENT_MODEL).build(),
+            dataset: dataset.to_string(),
+            version,
+            updated_at,
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_agents_by_count() {
+        let cache = AgentCache::new(2, 1000);
+        let now = Utc::now();
+
+        for agent_id in 1..=3 {
+            cache.insert(agent_id, tee_agent(1, now, "a,b"));
+        }
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.cached_agents, 2);
+        assert_eq!(metrics.evictions, 1);
+        assert_eq!(metrics.cached_dataset_bytes, 6);
+        assert!(!cache.lock().agents.contains(&(1, 1, now)));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_agents_by_dataset_bytes() {
+        let cache = AgentCache::new(10, 10);
+        let now = Utc::now();
+
+        cache.insert(1, tee_agent(1, now, "aaaaaa"));
+        cache.insert(2, tee_agent(1, now, "bbbbbb"));
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.cached_agents, 1);
+        assert_eq!(metrics.cached_dataset_bytes, 6);
+        assert!(cache.lock().agents.contains(&(2, 1, now)));
+
+        // An agent over the limit on its own is still kept
+        cache.insert(3, tee_agent(1, now, &"c".repeat(20)));
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.cached_agents, 1);
+        assert_eq!(metrics.cached_dataset_bytes, 20);
+        assert_eq!(metrics.evictions, 2);
+    }
+
+    #[test]
+    fn outdated_builds_are_not_cached() {
+        let cache = AgentCache::new(10, 1000);
+        let now = Utc::now();
+
+        cache.insert(1, tee_agent(2, now, "new"));
+        cache.insert(1, tee_agent(1, now, "old"));
+
+        assert_eq!(cache.metrics().cached_agents, 1);
+        assert!(cache.lock().agents.contains(&(1, 2, now)));
+    }
+
+    #[sqlx::test]
+    async fn new_versions_and_edits_miss_the_cache(db: sqlx::Pool<sqlx::Postgres>) {
+        let app_state = AppState::for_tests(db);
+
+        let mut agent_db = AgentDb::for_tests(1, 10);
+        agent_db.dataset_path = format!("agent-cache-{}.csv", Uuid::new_v4());
+
+        let dataset_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
+        tokio::fs::create_dir_all(UPLOAD_DIR).await.unwrap();
+        tokio::fs::write(&dataset_path, "city,sales\nParis,10\n")
+            .await
+            .unwrap();
+
+        let cache = &app_state.agent_cache;
+
+        let first = cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+        let second = cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        agent_db.current_version += 1;
+        let new_version = cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+        assert_eq!(new_version.version, 2);
+
+        agent_db.updated_at += Duration::seconds(1);
+        cache
+            .get_or_load(&agent_db, &app_state.ai_model)
+            .await
+            .unwrap();
+
+        tokio::fs::remove_file(&dataset_path).await.unwrap();
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.hits, 1);
+        assert_eq!(metrics.misses, 3);
+        assert_eq!(metrics.hit_rate, 0.25);
+        // Older versions of the agent are replaced, not evicted
+        assert_eq!(metrics.cached_agents, 1);
+        assert_eq!(metrics.evictions, 0);
+
+        // A dataset that can't be read is a load failure
+        let mut missing = AgentDb::for_tests(2, 20);
+        missing.dataset_path = format!("agent-cache-{}.csv", Uuid::new_v4());
+        assert!(
+            cache
+                .get_or_load(&missing, &app_state.ai_model)
+                .await
+                .is_err()
+        );
+        assert_eq!(cache.metrics().load_failures, 1);
+    }
+}
@@ -1,26 +1,34 @@
-use std::path::{Path, PathBuf};
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use actix_web::web;
 use alloy::{
-    primitives::utils::format_units,
+    eips::BlockNumberOrTag,
+    primitives::{Address, B256, U256},
     providers::{Provider, ProviderBuilder},
     sol,
     sol_types::SolEvent,
 };
-use dashmap::DashMap;
-use rig::{agent::Agent, completion::Prompt, providers::gemini::completion::CompletionModel};
+use futures_util::StreamExt;
+use rig::{
+    completion::{Chat, Message, Prompt, Usage},
+    streaming::{StreamedAssistantContent, StreamingCompletion},
+};
 
-use color_eyre::{Result, eyre::Context};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
 use serde_json::json;
 
 use crate::{
     config::{
         APP_CONFIG, DATASET_DETAILS_GEN_AGENT_MODEL, ENCLAVA_CONTRACT_ADDRESS,
-        HEDERA_TESTNET_RPC_URL, INIT_AGENT_MODEL, UPLOAD_DIR,
+        HEDERA_TESTNET_RPC_URL, INIT_AGENT_MODEL,
     },
     database,
-    state::AppState,
-    types::{AgentCategory, AgentDb, DatasetAIDetails, UserDb},
+    helpers::{auth::SignedAddress, guardrail, pricing, quote},
+    state::{AppState, TeeAgent},
+    types::{AgentCharge, AgentDb, ChargeBasis, DatasetAIDetails, PaymentQuote, UserDb},
 };
 
 sol! {
@@ -38,54 +46,12 @@ pub async fn init_ai_agent_with_dataset(
 
     let agent = init_agent(&dataset_csv_path, ai_model, &agent_db).await?;
 
-    // Save the agent to the AppState tee_agents using its id
-    app_state.tee_agents.insert(agent_db.id, agent);
+    // Save the agent to the AppState agent cache using its id
+    app_state.agent_cache.insert(agent_db.id, agent);
 
     Ok(())
 }
 
-pub async fn load_db_agents(
-    db: &sqlx::Pool<sqlx::Postgres>,
-    ai_model: &rig::providers::gemini::Client,
-) -> Result<DashMap<i64, Agent<CompletionModel>>> {
-    let tee_agents = DashMap::new();
-
-    let db_agents = sqlx::query_as!(
-        AgentDb,
-        r#"
-    SELECT
-        g.id,
-        g.name,
-        g.description,
-        g.price,
-        g.owner_id,
-        g.dataset_path,
-        g.status,
-        g.category as "category: AgentCategory",
-        g.dataset_size,
-        g.created_at,
-        g.updated_at,
-        g.nft_id,
-        g.nft_tx, 
-        u.address as "owner_address: String"
-    FROM agents g
-    JOIN users u ON g.owner_id = u.id
-    "#
-    )
-    .fetch_all(db)
-    .await?;
-
-    for agent_db in db_agents {
-        let dataset_csv_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
-
-        let agent = init_agent(&dataset_csv_path, ai_model, &agent_db).await?;
-
-        tee_agents.insert(agent_db.id, agent);
-    }
-
-    Ok(tee_agents)
-}
-
 pub async fn generate_dataset_details(
     csv_text: &str,
     ai_model: &rig::providers::gemini::Client,
@@ -116,37 +82,73 @@ pub async fn generate_dataset_details(
     Ok(dataset_details)
 }
 
+/// Payment transaction that can't be verified because of what it contains, not because of the chain or the database
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct InvalidPayment(pub String);
+
+/// Payment transaction checked against the pricing plans of the agents
+pub struct VerifiedPayment {
+    /// Canonical hash of the transaction, the same whatever spelling it was sent with
+    pub tx_hash: String,
+    /// Sender of the transaction
+    pub payer: String,
+    pub charges: Vec<AgentCharge>,
+}
+
+/// Checks that `tx_hash` paid for the agents what their pricing plans charge its sender,
+/// returns the sender and the charges when it did and records them as the sender's usage.
+/// Free samples, subscriptions and volume tiers are only applied when `signer` is the sender.
 pub async fn verif_selected_agents_payment(
     app_state: &web::Data<AppState>,
     agent_ids: &Vec<i64>,
     tx_hash: &str,
-) -> Result<bool> {
-    // Check if the tx hash is already handled
-    if app_state.handled_txs.contains(tx_hash) {
-        tracing::error!("Transaction hash {} already handled", tx_hash);
-        return Ok(false);
-    }
+    quote: Option<&PaymentQuote>,
+    signer: Option<&SignedAddress>,
+) -> Result<Option<VerifiedPayment>> {
+    let Ok(parsed_tx_hash) = tx_hash.parse::<B256>() else {
+        tracing::error!("Invalid transaction hash: {}", tx_hash);
+        return Ok(None);
+    };
+
+    // The hash is parsed with or without 0x and in any case, it is claimed and recorded in one spelling
+    let tx_hash = parsed_tx_hash.to_string();
 
     // Get all agents from the database
     let db = &app_state.db;
 
     let agents_db = database::get_agents_by_ids(db, agent_ids).await?;
 
-    let total_price_to_pay = agents_db.iter().fold(0.0, |acc, agent| acc + agent.price);
-
-    tracing::debug!("Total price to pay By Used Agents: {}", total_price_to_pay);
+    // Only active agents can be paid for
+    for agent_id in agent_ids {
+        match agents_db.iter().find(|agent| agent.id == *agent_id) {
+            Some(agent) if agent.status == "active" => {}
+            Some(agent) => {
+                tracing::error!(
+                    "Agent {} is {} and can't be paid for",
+                    agent.id,
+                    agent.status
+                );
+                return Ok(None);
+            }
+            None => {
+                tracing::error!("Agent {} not found", agent_id);
+                return Ok(None);
+            }
+        }
+    }
 
     let rpc_url = HEDERA_TESTNET_RPC_URL;
 
     let provider = ProviderBuilder::new().connect_http(rpc_url.parse()?);
 
-    let tx_receipt = provider.get_transaction_receipt(tx_hash.parse()?).await?;
+    let tx_receipt = provider.get_transaction_receipt(parsed_tx_hash).await?;
 
     println!("Transaction receipt: {:?}", tx_receipt);
 
     if tx_receipt.is_none() {
         tracing::error!("Transaction receipt not found for tx hash: {}", tx_hash);
-        return Ok(false);
+        return Ok(None);
     }
 
     let tx_receipt = tx_receipt.unwrap();
@@ -158,7 +160,7 @@ pub async fn verif_selected_agents_payment(
 
     if !tx_success {
         tracing::error!("Transaction of {} is not successful", tx_hash);
-        return Ok(false);
+        return Ok(None);
     }
 
     // Chck if the tx is for the correct enclava smart contract
@@ -171,71 +173,466 @@ pub async fn verif_selected_agents_payment(
             ENCLAVA_CONTRACT_ADDRESS,
             tx_contract
         );
-        return Ok(false);
+        return Ok(None);
+    }
+
+    // The payer is charged under the pricing plan of each agent: free samples, subscription or volume tier
+    let payer = tx_receipt.from.to_string();
+    let charges = pricing::agent_charges(db, Some(&payer), &agents_db).await?;
+
+    // Any contract tx of the payer would otherwise spend their free or discounted queries
+    let uses_entitlements = charges
+        .iter()
+        .any(|charge| charge.basis != ChargeBasis::PerQuery);
+
+    if uses_entitlements && !signer.is_some_and(|signer| signer.matches(&payer)) {
+        tracing::error!(
+            "Transaction of {} uses the pricing plan entitlements of {} without their signature",
+            tx_hash,
+            payer
+        );
+        return Ok(None);
     }
 
+    // Money is compared in on-chain units, never as floats
+    let total_price_to_pay = charges
+        .iter()
+        .map(|charge| charge.amount.to_u256())
+        .sum::<Result<U256>>()?;
+
+    tracing::debug!("Total price to pay By Used Agents: {}", total_price_to_pay);
+
     // Get the tx logs and decode them
     let tx_logs = tx_receipt.logs();
 
-    let mut total_amount_paid = 0.0;
+    let mut total_amount_paid = U256::ZERO;
+    let mut payments: Vec<(i64, U256)> = Vec::new();
 
     for log in tx_logs {
         let log_data = log.data();
 
         if let Ok(decoded_log) = DatasetUsed::decode_log_data(log_data) {
-            let amount_paid_u256 = decoded_log.amount;
+            let amount_paid = decoded_log.amount;
             let token_nft_id = decoded_log.tokenId;
 
-            let amount_paid: f64 = format_units(amount_paid_u256, 8)?.parse()?;
-            let nft_id: i64 = token_nft_id.to_string().parse()?;
+            let nft_id: i64 = token_nft_id.try_into().map_err(|_| {
+                InvalidPayment(format!("Token id {} is out of range", token_nft_id))
+            })?;
 
             tracing::debug!("Amount paid: {}", amount_paid);
             tracing::debug!("NFT ID: {}", nft_id);
 
-            // Get the agent that has the nft_id
-            let agent = agents_db.iter().find(|agent| agent.nft_id == Some(nft_id));
-
-            if agent.is_none() {
+            if !agents_db.iter().any(|agent| agent.nft_id == Some(nft_id)) {
                 tracing::error!("Agent with nft_id {} not found", nft_id);
-                return Ok(false);
+                return Ok(None);
             }
 
-            let agent = agent.unwrap();
+            total_amount_paid += amount_paid;
+            payments.push((nft_id, amount_paid));
+        }
+    }
 
-            if agent.price > amount_paid {
-                tracing::error!(
-                    "Agent {} price is {} but only {} was paid",
-                    agent.id,
-                    agent.price,
-                    amount_paid
-                );
-                return Ok(false);
-            }
+    if !charges_are_paid(&agents_db, &charges, &payments)? {
+        return Ok(None);
+    }
 
-            total_amount_paid += amount_paid;
+    if let Some(quote) = quote {
+        // Quotes expire relative to the payment, not to when the answer is asked
+        let block_number = tx_receipt
+            .block_number
+            .ok_or_else(|| eyre!("Transaction {} is not in a block", tx_hash))?;
+        let block = provider
+            .get_block_by_number(BlockNumberOrTag::Number(block_number))
+            .await?
+            .ok_or_else(|| eyre!("Block {} not found", block_number))?;
+        let paid_at = block.header.timestamp as i64;
+
+        if let Err(e) = verify_payment_matches_quote(
+            app_state,
+            quote,
+            agent_ids,
+            tx_receipt.from,
+            &payments,
+            paid_at,
+        ) {
+            tracing::error!("Payment {} does not match its quote: {}", tx_hash, e);
+            return Ok(None);
         }
     }
 
     if total_amount_paid < total_price_to_pay {
         tracing::error!(
-            "Total amount paid {} is less than total price to pay {}",
+            "Total amount paid {} units is less than total price to pay {} units",
             total_amount_paid,
             total_price_to_pay
         );
-        return Ok(false);
+        return Ok(None);
+    }
+
+    // The tx is claimed with its usage so it can't be handled twice, even after a restart or by concurrent requests
+    let mut tx = db.begin().await?;
+
+    if !database::claim_payment_tx(&mut tx, &tx_hash, &payer).await? {
+        tracing::error!("Transaction hash {} already handled", tx_hash);
+        return Ok(None);
+    }
+
+    // A concurrent payment of the payer may have used the entitlements these charges were computed with
+    let claimed_charges = pricing::claim_agent_charges(db, &mut tx, &payer, &agents_db).await?;
+
+    if claimed_charges != charges {
+        tracing::error!(
+            "Charges of {} changed from {:?} to {:?} while {} was verified",
+            payer,
+            charges,
+            claimed_charges,
+            tx_hash
+        );
+        return Ok(None);
     }
 
-    // Add the transaction hash to the app_state for future reference
-    app_state.handled_txs.insert(tx_hash.to_string());
+    database::insert_agent_usages(&mut tx, &payer, &tx_hash, &charges).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(VerifiedPayment {
+        tx_hash,
+        payer,
+        charges,
+    }))
+}
+
+/// Checks that every charge is covered by the payments (NFT id, amount) of its own agent,
+/// overpaying another agent doesn't cover it
+fn charges_are_paid(
+    agents_db: &[AgentDb],
+    charges: &[AgentCharge],
+    payments: &[(i64, U256)],
+) -> Result<bool> {
+    for charge in charges {
+        let agent = agents_db
+            .iter()
+            .find(|agent| agent.id == charge.agent_id)
+            .ok_or_else(|| eyre!("No agent for the charge of agent {}", charge.agent_id))?;
+
+        let amount_paid: U256 = payments
+            .iter()
+            .filter(|(nft_id, _)| agent.nft_id == Some(*nft_id))
+            .map(|(_, amount)| *amount)
+            .sum();
+
+        if charge.amount.to_u256()? > amount_paid {
+            tracing::error!(
+                "Agent {} charge is {} units but only {} units were paid",
+                agent.id,
+                charge.amount.units(),
+                amount_paid
+            );
+            return Ok(false);
+        }
+    }
 
     Ok(true)
 }
 
-async fn init_agent(
+/// Checks that the payment was sent by the quote payer, for the quoted agents, with exactly the quoted amounts
+fn verify_payment_matches_quote(
+    app_state: &web::Data<AppState>,
+    quote: &PaymentQuote,
+    agent_ids: &[i64],
+    payer: Address,
+    payments: &[(i64, U256)],
+    paid_at: i64,
+) -> Result<()> {
+    quote::verify_quote(&app_state.quote_signer, quote, paid_at)?;
+
+    let mut quoted_agent_ids = quote.agent_ids.clone();
+    let mut paid_agent_ids = agent_ids.to_vec();
+    quoted_agent_ids.sort();
+    paid_agent_ids.sort();
+
+    if quoted_agent_ids != paid_agent_ids {
+        return Err(eyre!(
+            "Quoted agents {:?} but asked agents {:?}",
+            quote.agent_ids,
+            agent_ids
+        ));
+    }
+
+    if Address::from_str(&quote.payer_address)? != payer {
+        return Err(eyre!(
+            "Quoted for {} but paid by {}",
+            quote.payer_address,
+            payer
+        ));
+    }
+
+    let mut quoted_payments = quote::quote_payments(quote)?;
+    let mut payments = payments.to_vec();
+    quoted_payments.sort();
+    payments.sort();
+
+    if quoted_payments != payments {
+        return Err(eyre!(
+            "Quoted payments {:?} but paid {:?}",
+            quoted_payments,
+            payments
+        ));
+    }
+
+    Ok(())
+}
+
+/// Prompts the agent of `agent_db`, after the previous turns of the conversation if any,
+/// and filters its answer through the output guardrail.
+/// Returns the answer with the dataset version it was given from.
+pub async fn prompt_agent(
+    app_state: &web::Data<AppState>,
+    agent_db: &AgentDb,
+    prompt: &str,
+    history: Vec<Message>,
+) -> Result<(String, i32)> {
+    let tee_agent = app_state
+        .agent_cache
+        .get_or_load(agent_db, &app_state.ai_model)
+        .await
+        .context("Failed to load agent")?;
+
+    tracing::debug!(
+        "Prompting agent with id {} with prompt {}",
+        agent_db.id,
+        prompt
+    );
+
+    let response = tee_agent
+        .agent
+        .chat(prompt, history)
+        .await
+        .context("Failed to get AI response")?;
+
+    let response = filter_agent_response(
+        app_state,
+        agent_db.id,
+        prompt,
+        &response,
+        &tee_agent.dataset,
+    )
+    .await;
+
+    Ok((response, tee_agent.version))
+}
+
+/// Answer streamed by an agent, once the stream is over
+pub struct StreamedAnswer {
+    /// Full answer, filtered by the output guardrail
+    pub response: String,
+    pub dataset_version: i32,
+    pub usage: Option<Usage>,
+}
+
+/// Streams the answer of the agent of `agent_db`, calling `on_chunk` with each new piece of text.
+///
+/// Text is released line by line once the line passes the output guardrail, and nothing more is released
+/// as soon as the answer so far would be refused. The returned response is checked again as a whole.
+pub async fn stream_agent_answer(
+    app_state: &web::Data<AppState>,
+    agent_db: &AgentDb,
+    prompt: &str,
+    history: Vec<Message>,
+    mut on_chunk: impl FnMut(String),
+) -> Result<StreamedAnswer> {
+    let tee_agent = app_state
+        .agent_cache
+        .get_or_load(agent_db, &app_state.ai_model)
+        .await
+        .context("Failed to load agent")?;
+
+    tracing::debug!(
+        "Streaming agent with id {} with prompt {}",
+        agent_db.id,
+        prompt
+    );
+
+    let mut stream = tee_agent
+        .agent
+        .stream_completion(prompt, history)
+        .await?
+        .stream()
+        .await
+        .context("Failed to start AI response stream")?;
+
+    let mut usage = None;
+    let mut release = GuardedRelease::default();
+
+    while let Some(content) = stream.next().await {
+        match content.context("Failed to get AI response")? {
+            StreamedAssistantContent::Text(text) => {
+                release.push(&text.text, &tee_agent, &mut on_chunk).await?
+            }
+            StreamedAssistantContent::Final(final_response) => {
+                // Gemini only reports the total of a streamed answer
+                let total_tokens = final_response.usage_metadata.total_token_count.max(0) as u64;
+
+                usage = Some(Usage {
+                    input_tokens: 0,
+                    output_tokens: 0,
+                    total_tokens,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let raw_response = release.finish(&tee_agent, &mut on_chunk).await?;
+
+    let response = filter_agent_response(
+        app_state,
+        agent_db.id,
+        prompt,
+        &raw_response,
+        &tee_agent.dataset,
+    )
+    .await;
+
+    Ok(StreamedAnswer {
+        response,
+        dataset_version: tee_agent.version,
+        usage,
+    })
+}
+
+/// Text of a streamed answer, released line by line once each line passes the output guardrail.
+/// Nothing more is released as soon as the answer so far would be refused.
+#[derive(Default)]
+struct GuardedRelease {
+    raw_response: String,
+    /// Bytes of `raw_response` already checked
+    released: usize,
+    refused: bool,
+    guard: guardrail::StreamGuard,
+}
+
+impl GuardedRelease {
+    /// Adds streamed text and releases the lines it completes
+    async fn push(
+        &mut self,
+        text: &str,
+        tee_agent: &Arc<TeeAgent>,
+        on_chunk: &mut impl FnMut(String),
+    ) -> Result<()> {
+        self.raw_response.push_str(text);
+
+        if self.refused {
+            return Ok(());
+        }
+
+        let Some(line_end) = self.raw_response[self.released..].rfind('\n') else {
+            return Ok(());
+        };
+        let complete_end = self.released + line_end + 1;
+
+        self.refused = release_checked_text(
+            &mut self.guard,
+            self.raw_response[self.released..complete_end].to_string(),
+            tee_agent,
+            on_chunk,
+        )
+        .await?;
+        self.released = complete_end;
+
+        Ok(())
+    }
+
+    /// Releases the last line, which has no line break, and returns the whole answer as the agent wrote it
+    async fn finish(
+        mut self,
+        tee_agent: &Arc<TeeAgent>,
+        on_chunk: &mut impl FnMut(String),
+    ) -> Result<String> {
+        if !self.refused && self.released < self.raw_response.len() {
+            release_checked_text(
+                &mut self.guard,
+                self.raw_response[self.released..].to_string(),
+                tee_agent,
+                on_chunk,
+            )
+            .await?;
+        }
+
+        Ok(self.raw_response)
+    }
+}
+
+/// Releases `text` through the guardrail, unless the answer up to it would be refused.
+/// Returns whether it was refused. The check scans the dataset, so it runs on a blocking thread.
+async fn release_checked_text(
+    guard: &mut guardrail::StreamGuard,
+    text: String,
+    tee_agent: &Arc<TeeAgent>,
+    on_chunk: &mut impl FnMut(String),
+) -> Result<bool> {
+    let mut moved_guard = std::mem::take(guard);
+    let tee_agent = tee_agent.clone();
+
+    let (moved_guard, checked) = tokio::task::spawn_blocking(move || {
+        let checked = moved_guard.check_line(&text, &tee_agent.dataset);
+        (moved_guard, checked)
+    })
+    .await?;
+
+    *guard = moved_guard;
+
+    match checked {
+        Some(checked) => {
+            on_chunk(checked);
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
+
+/// Runs the output guardrail on a full agent answer and records what it filtered for the dataset owner
+pub async fn filter_agent_response(
+    app_state: &web::Data<AppState>,
+    agent_id: i64,
+    prompt: &str,
+    response: &str,
+    dataset: &str,
+) -> String {
+    // Check the response for leaked dataset rows and PII before returning it
+    let outcome = guardrail::check_agent_response(response, dataset);
+
+    for event in &outcome.events {
+        tracing::warn!(
+            "Guardrail {:?} response of agent {} ({:?})",
+            event.action,
+            agent_id,
+            event.kind
+        );
+
+        if let Err(e) = database::insert_guardrail_event(
+            &app_state.db,
+            agent_id,
+            event.kind,
+            event.action,
+            event.leaked_rows,
+            event.pii_matches,
+            prompt,
+        )
+        .await
+        {
+            tracing::error!("Failed to record guardrail event: {}", e);
+        }
+    }
+
+    outcome.response
+}
+
+pub async fn init_agent(
     dataset_csv_path: &PathBuf,
     ai_model: &rig::providers::gemini::Client,
     agent_db: &AgentDb,
-) -> Result<Agent<CompletionModel>> {
+) -> Result<TeeAgent> {
     let agent_builder = ai_model.agent(INIT_AGENT_MODEL);
 
     let dataset_content = tokio::fs::read_to_string(dataset_csv_path).await?;
@@ -260,5 +657,126 @@ async fn init_agent(
         ))
         .build();
 
-    Ok(agent)
+    Ok(TeeAgent {
+        agent,
+        dataset: dataset_content,
+        version: agent_db.current_version,
+        updated_at: agent_db.updated_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use rig::providers::gemini;
+
+    use super::*;
+    use crate::types::Price;
+
+    const DATASET: &str = "name,email,city,amount\n\
+        Alice Martin,alice@example.com,Grenoble,12345\n\
+        Bruno Petit,bruno@example.com,Toulouse,23456\n\
+        Chloe Bernard,chloe@example.com,Bordeaux,34567\n\
+        David Moreau,david@example.com,Marseille,45678\n\
+        Emma Laurent,emma@example.com,Strasbourg,56789\n";
+
+    fn charge(agent_id: i64, units: i64, basis: ChargeBasis) -> AgentCharge {
+        AgentCharge {
+            agent_id,
+            amount: Price::from_units(units),
+            basis,
+        }
+    }
+
+    #[test]
+    fn each_charged_agent_must_be_paid_by_its_own_logs() {
+        let agents = [AgentDb::for_tests(1, 10), AgentDb::for_tests(2, 20)];
+        let charges = [
+            charge(1, 100, ChargeBasis::PerQuery),
+            charge(2, 100, ChargeBasis::PerQuery),
+        ];
+
+        let paid = [(10, U256::from(100)), (20, U256::from(100))];
+        assert!(charges_are_paid(&agents, &charges, &paid).unwrap());
+
+        // Overpaying the first agent doesn't cover the second one
+        let overpaid = [(10, U256::from(200))];
+        assert!(!charges_are_paid(&agents, &charges, &overpaid).unwrap());
+
+        let underpaid = [(10, U256::from(100)), (20, U256::from(99))];
+        assert!(!charges_are_paid(&agents, &charges, &underpaid).unwrap());
+
+        // Several logs for the same agent add up
+        let split = [
+            (10, U256::from(100)),
+            (20, U256::from(60)),
+            (20, U256::from(40)),
+        ];
+        assert!(charges_are_paid(&agents, &charges, &split).unwrap());
+    }
+
+    #[test]
+    fn free_charges_need_no_payment_log() {
+        let agents = [AgentDb::for_tests(1, 10), AgentDb::for_tests(2, 20)];
+        let charges = [
+            charge(1, 0, ChargeBasis::FreeSample),
+            charge(2, 100, ChargeBasis::PerQuery),
+        ];
+
+        assert!(charges_are_paid(&agents, &charges, &[(20, U256::from(100))]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn streamed_answers_are_released_line_by_line_and_cut_on_leaks() {
+        let tee_agent = Arc::new(TeeAgent {
+            agent: gemini::Client::new("test").agent(INIT_AGENT_MODEL).build(),
+            dataset: DATASET.to_string(),
+            version: 1,
+            updated_at: Utc::now(),
+        });
+        let mut chunks = Vec::new();
+        let mut on_chunk = |chunk: String| chunks.push(chunk);
+        let mut release = GuardedRelease::default();
+
+        // Nothing is released before a line is complete
+        release
+            .push("Sales grew ", &tee_agent, &mut on_chunk)
+            .await
+            .unwrap();
+        release
+            .push(
+                "in every city.\nAlice Martin, Grenoble",
+                &tee_agent,
+                &mut on_chunk,
+            )
+            .await
+            .unwrap();
+
+        // Leaked rows are redacted until there are too many of them
+        for piece in [
+            ", 12345\nBruno Petit, Toulouse, 23456\n",
+            "Chloe Bernard, Bordeaux, 34567\n",
+            "David Moreau, Marseille, 45678\n",
+            "Emma Laurent, Strasbourg",
+        ] {
+            release
+                .push(piece, &tee_agent, &mut on_chunk)
+                .await
+                .unwrap();
+        }
+
+        let raw_response = release.finish(&tee_agent, &mut on_chunk).await.unwrap();
+
+        // The fourth leaked row cuts the answer, nothing after it is released
+        assert_eq!(
+            chunks,
+            [
+                "Sales grew in every city.\n",
+                "[redacted], [redacted], [redacted]\n[redacted], [redacted], [redacted]\n",
+                "[redacted], [redacted], [redacted]\n",
+            ]
+        );
+        assert!(raw_response.starts_with("Sales grew in every city.\nAlice Martin"));
+        assert!(raw_response.ends_with("Emma Laurent, Strasbourg"));
+    }
 }
@@ -0,0 +1,113 @@
+use color_eyre::{Result, eyre::eyre};
+
+use crate::types::{AggregateFilter, AggregateFunction, AggregateQueryRequest, FilterOperator};
+
+/// Exact result of an aggregate query over a dataset
+pub struct AggregateResult {
+    /// Number of rows matching the filters
+    pub count: usize,
+    /// Sum of the column over the matching rows (0 for count queries)
+    pub sum: f64,
+}
+
+impl AggregateResult {
+    pub fn value(&self, function: AggregateFunction) -> f64 {
+        match function {
+            AggregateFunction::Count => self.count as f64,
+            AggregateFunction::Sum => self.sum,
+            AggregateFunction::Mean if self.count == 0 => 0.0,
+            AggregateFunction::Mean => self.sum / self.count as f64,
+        }
+    }
+}
+
+/// Runs a count/sum/mean query with filters directly on the csv dataset.
+/// Column values are clamped to `bounds` so the sensitivity of sums stays known.
+pub fn run_aggregate_query(
+    dataset: &str,
+    query: &AggregateQueryRequest,
+    bounds: Option<(f64, f64)>,
+) -> Result<AggregateResult> {
+    let mut reader = csv::Reader::from_reader(dataset.as_bytes());
+    let headers = reader.headers()?.clone();
+
+    let column_index = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| eyre!("Unknown column: {}", name))
+    };
+
+    let aggregated_column = match (&query.function, &query.column) {
+        (AggregateFunction::Count, _) => None,
+        (_, Some(column)) => Some(column_index(column)?),
+        (_, None) => return Err(eyre!("A column is required for sum and mean queries")),
+    };
+
+    let filters = query
+        .filters
+        .iter()
+        .map(|filter| Ok((column_index(&filter.column)?, filter)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut result = AggregateResult { count: 0, sum: 0.0 };
+
+    for record in reader.records() {
+        let record = record?;
+
+        let matches = filters.iter().all(|(index, filter)| {
+            record
+                .get(*index)
+                .map(|value| filter_matches(value.trim(), filter))
+                .unwrap_or(false)
+        });
+
+        if !matches {
+            continue;
+        }
+
+        match aggregated_column {
+            None => result.count += 1,
+            Some(index) => {
+                // Rows without a numeric value are left out of sums and means
+                let Some(value) = record
+                    .get(index)
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                else {
+                    continue;
+                };
+
+                let value = match bounds {
+                    Some((lower, upper)) => value.clamp(lower, upper),
+                    None => value,
+                };
+
+                result.count += 1;
+                result.sum += value;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn filter_matches(value: &str, filter: &AggregateFilter) -> bool {
+    // Compare numerically when both sides are numbers, as text otherwise
+    let ordering = match (value.parse::<f64>(), filter.value.trim().parse::<f64>()) {
+        (Ok(left), Ok(right)) => left.partial_cmp(&right),
+        _ => Some(value.cmp(filter.value.trim())),
+    };
+
+    let Some(ordering) = ordering else {
+        return false;
+    };
+
+    match filter.operator {
+        FilterOperator::Eq => ordering.is_eq(),
+        FilterOperator::Neq => ordering.is_ne(),
+        FilterOperator::Gt => ordering.is_gt(),
+        FilterOperator::Gte => ordering.is_ge(),
+        FilterOperator::Lt => ordering.is_lt(),
+        FilterOperator::Lte => ordering.is_le(),
+    }
+}
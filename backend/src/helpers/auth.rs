@@ -0,0 +1,312 @@
+use std::str::FromStr;
+
+use actix_web::{
+    FromRequest, HttpMessage, HttpRequest, HttpResponse,
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{ErrorPayloadTooLarge, InternalError},
+    middleware::Next,
+    web,
+};
+use alloy::primitives::{Address, B256, Signature, keccak256};
+use chrono::Utc;
+use color_eyre::{Result, eyre::eyre};
+use futures_util::{StreamExt, future::LocalBoxFuture};
+
+use crate::{
+    config::{
+        AUTH_ADDRESS_HEADER, AUTH_MAX_SIGNED_BODY_BYTES, AUTH_NONCE_HEADER, AUTH_NONCE_MAX_CHARS,
+        AUTH_SIGNATURE_HEADER, AUTH_SIGNATURE_MAX_AGE_SECS, AUTH_TIMESTAMP_HEADER,
+    },
+    database,
+    state::AppState,
+    types::ErrorResponse,
+};
+
+/// Address that proved it controls its private key by signing the authentication message.
+///
+/// Clients send four headers: the address, a unix timestamp, a nonce and the EIP-191 (`personal_sign`)
+/// signature of the message returned by [`auth_message`] for the request. Signatures older than
+/// `AUTH_SIGNATURE_MAX_AGE_SECS` are refused, and each nonce can only be used once per address.
+#[derive(Debug, Clone)]
+pub struct SignedAddress {
+    pub address: Address,
+}
+
+impl SignedAddress {
+    /// Compares with an address stored as text, whatever its case
+    pub fn matches(&self, address: &str) -> bool {
+        Address::from_str(address)
+            .map(|address| address == self.address)
+            .unwrap_or(false)
+    }
+}
+
+impl FromRequest for SignedAddress {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            verify_signed_request(&req).await.map_err(|e| {
+                tracing::warn!("Authentication failed: {}", e);
+
+                let response = HttpResponse::Unauthorized().json(ErrorResponse {
+                    success: false,
+                    message: format!("Authentication failed: {}", e),
+                    error_code: Some("UNAUTHORIZED".to_string()),
+                });
+
+                InternalError::from_response(e, response).into()
+            })
+        })
+    }
+}
+
+/// Keccak-256 of the body of a signed request, set by [`hash_signed_body`]
+#[derive(Debug, Clone, Copy)]
+struct SignedBodyHash(B256);
+
+/// Message the client must sign to authenticate as `address` for one request.
+/// `path` includes the query string and `body_hash` is the keccak-256 of the raw body, of no bytes without body.
+pub fn auth_message(
+    address: &Address,
+    timestamp: i64,
+    nonce: &str,
+    method: &str,
+    path: &str,
+    body_hash: &B256,
+) -> String {
+    format!(
+        "Enclava authentication\nAddress: {}\nTimestamp: {}\nNonce: {}\nMethod: {}\nPath: {}\nBody: {}",
+        address, timestamp, nonce, method, path, body_hash
+    )
+}
+
+/// Middleware buffering the body of requests carrying a signature, so [`SignedAddress`] can check its hash.
+/// The handlers read the same body afterwards.
+pub async fn hash_signed_body(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.headers().contains_key(AUTH_SIGNATURE_HEADER) {
+        let mut payload = req.parts_mut().1.take();
+        let mut body = web::BytesMut::new();
+
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk?;
+
+            if body.len() + chunk.len() > AUTH_MAX_SIGNED_BODY_BYTES {
+                return Err(ErrorPayloadTooLarge("Signed request body is too large"));
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        let body = body.freeze();
+
+        req.extensions_mut()
+            .insert(SignedBodyHash(keccak256(&body)));
+        req.set_payload(Payload::from(body));
+    }
+
+    next.call(req).await
+}
+
+async fn verify_signed_request(req: &HttpRequest) -> Result<SignedAddress> {
+    let address = Address::from_str(header_value(req, AUTH_ADDRESS_HEADER)?)?;
+    let timestamp: i64 = header_value(req, AUTH_TIMESTAMP_HEADER)?.parse()?;
+    let nonce = header_value(req, AUTH_NONCE_HEADER)?;
+    let signature = Signature::from_str(header_value(req, AUTH_SIGNATURE_HEADER)?)?;
+
+    if nonce.is_empty() || nonce.chars().count() > AUTH_NONCE_MAX_CHARS {
+        return Err(eyre!(
+            "Nonce must be between 1 and {} characters",
+            AUTH_NONCE_MAX_CHARS
+        ));
+    }
+
+    if (Utc::now().timestamp() - timestamp).abs() > AUTH_SIGNATURE_MAX_AGE_SECS {
+        return Err(eyre!("Signature expired"));
+    }
+
+    let SignedBodyHash(body_hash) = req
+        .extensions()
+        .get::<SignedBodyHash>()
+        .copied()
+        .ok_or_else(|| eyre!("Request body was not hashed"))?;
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| req.path());
+
+    let message = auth_message(
+        &address,
+        timestamp,
+        nonce,
+        req.method().as_str(),
+        path,
+        &body_hash,
+    );
+    let signer = signature.recover_address_from_msg(message)?;
+
+    if signer != address {
+        return Err(eyre!("Signature does not match address {}", address));
+    }
+
+    // Checked last so invalid signatures can't burn the nonces of an address
+    let app_state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| eyre!("Missing app state"))?;
+
+    if !database::claim_auth_nonce(&app_state.db, &address.to_string(), nonce).await? {
+        return Err(eyre!("Nonce already used"));
+    }
+
+    Ok(SignedAddress { address })
+}
+
+/// Request to `uri` signed by `signer` with a fresh nonce, for services wrapped in [`hash_signed_body`].
+/// A non empty `body` is sent as json.
+#[cfg(test)]
+pub fn signed_test_request(
+    signer: &alloy::signers::local::PrivateKeySigner,
+    method: actix_web::http::Method,
+    uri: &str,
+    body: &str,
+) -> actix_web::test::TestRequest {
+    use alloy::signers::SignerSync;
+
+    let timestamp = Utc::now().timestamp();
+    let nonce = uuid::Uuid::new_v4().to_string();
+    let message = auth_message(
+        &signer.address(),
+        timestamp,
+        &nonce,
+        method.as_str(),
+        uri,
+        &keccak256(body.as_bytes()),
+    );
+    let signature = signer.sign_message_sync(message.as_bytes()).unwrap();
+
+    let request = actix_web::test::TestRequest::default()
+        .method(method)
+        .uri(uri)
+        .insert_header((AUTH_ADDRESS_HEADER, signer.address().to_string()))
+        .insert_header((AUTH_TIMESTAMP_HEADER, timestamp.to_string()))
+        .insert_header((AUTH_NONCE_HEADER, nonce))
+        .insert_header((AUTH_SIGNATURE_HEADER, signature.to_string()));
+
+    if body.is_empty() {
+        request
+    } else {
+        request
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body.to_string())
+    }
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Result<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| eyre!("Missing {} header", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, http::StatusCode, middleware, post, test};
+    use alloy::signers::{SignerSync, local::PrivateKeySigner};
+
+    use super::*;
+
+    #[post("/signed")]
+    async fn signed_service(
+        auth: SignedAddress,
+        body: web::Json<serde_json::Value>,
+    ) -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({
+            "address": auth.address.to_string(),
+            "body": body.into_inner(),
+        }))
+    }
+
+    fn signed_request(
+        signer: &PrivateKeySigner,
+        nonce: &str,
+        signed_body: &str,
+        sent_body: &str,
+    ) -> test::TestRequest {
+        let timestamp = Utc::now().timestamp();
+        let message = auth_message(
+            &signer.address(),
+            timestamp,
+            nonce,
+            "POST",
+            "/signed?limit=1",
+            &keccak256(signed_body.as_bytes()),
+        );
+        let signature = signer.sign_message_sync(message.as_bytes()).unwrap();
+
+        test::TestRequest::post()
+            .uri("/signed?limit=1")
+            .insert_header((AUTH_ADDRESS_HEADER, signer.address().to_string()))
+            .insert_header((AUTH_TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((AUTH_NONCE_HEADER, nonce))
+            .insert_header((AUTH_SIGNATURE_HEADER, signature.to_string()))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(sent_body.to_string())
+    }
+
+    #[sqlx::test]
+    async fn signed_requests_bind_the_body_and_cannot_be_replayed(db: sqlx::Pool<sqlx::Postgres>) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(db)))
+                .wrap(middleware::from_fn(hash_signed_body))
+                .service(signed_service),
+        )
+        .await;
+
+        let signer = PrivateKeySigner::random();
+        let body = r#"{"amount":1}"#;
+
+        let response = test::call_service(
+            &app,
+            signed_request(&signer, "nonce-1", body, body).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The handler still reads the body the middleware hashed
+        let response: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(response["address"], signer.address().to_string());
+        assert_eq!(response["body"]["amount"], 1);
+
+        let replayed = test::call_service(
+            &app,
+            signed_request(&signer, "nonce-1", body, body).to_request(),
+        )
+        .await;
+        assert_eq!(replayed.status(), StatusCode::UNAUTHORIZED);
+
+        let tampered = test::call_service(
+            &app,
+            signed_request(&signer, "nonce-2", body, r#"{"amount":1000}"#).to_request(),
+        )
+        .await;
+        assert_eq!(tampered.status(), StatusCode::UNAUTHORIZED);
+
+        // The tampered request didn't use its nonce
+        let response = test::call_service(
+            &app,
+            signed_request(&signer, "nonce-2", body, body).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
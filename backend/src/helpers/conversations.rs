@@ -0,0 +1,136 @@
+use color_eyre::Result;
+use rig::completion::Message;
+
+use crate::{
+    config::CONVERSATION_MAX_HISTORY_TURNS,
+    database,
+    types::{AgentResponse, AgentResponseStatus, MessageDb, MessageRole},
+};
+
+/// Chat history of one agent in a conversation: the previous questions it answered, with its answers.
+/// Only the last `CONVERSATION_MAX_HISTORY_TURNS` turns are kept.
+pub fn agent_chat_history(messages: &[MessageDb], agent_id: i64) -> Vec<Message> {
+    let mut turns = Vec::new();
+    let mut question: Option<&MessageDb> = None;
+
+    for message in messages {
+        if message.role == MessageRole::User.to_string() {
+            question = Some(message);
+        } else if message.agent_id == Some(agent_id)
+            && let Some(question) = question
+        {
+            turns.push((question.content.clone(), message.content.clone()));
+        }
+    }
+
+    let skipped = turns.len().saturating_sub(CONVERSATION_MAX_HISTORY_TURNS);
+
+    turns
+        .into_iter()
+        .skip(skipped)
+        .flat_map(|(question, answer)| [Message::user(question), Message::assistant(answer)])
+        .collect()
+}
+
+/// Saves a question and the answers of the agents that succeeded to the conversation
+pub async fn save_conversation_turn(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    conversation_id: i64,
+    prompt: &str,
+    tx_hash: &str,
+    agent_responses: &[AgentResponse],
+) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    database::insert_message(
+        &mut tx,
+        conversation_id,
+        MessageRole::User,
+        None,
+        prompt,
+        None,
+        Some(tx_hash),
+    )
+    .await?;
+
+    for agent_response in agent_responses
+        .iter()
+        .filter(|response| response.status == AgentResponseStatus::Success)
+    {
+        database::insert_message(
+            &mut tx,
+            conversation_id,
+            MessageRole::Assistant,
+            Some(agent_response.agent_id),
+            &agent_response.response,
+            Some(agent_response.dataset_version),
+            None,
+        )
+        .await?;
+    }
+
+    database::touch_conversation(&mut tx, conversation_id).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn message(role: MessageRole, agent_id: Option<i64>, content: String) -> MessageDb {
+        MessageDb {
+            id: 0,
+            conversation_id: 1,
+            role: role.to_string(),
+            agent_id,
+            content,
+            dataset_version: agent_id.map(|_| 1),
+            tx_hash: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn history_keeps_the_last_turns_answered_by_the_agent() {
+        let turns = CONVERSATION_MAX_HISTORY_TURNS + 2;
+        let mut messages = Vec::new();
+
+        for turn in 1..=turns {
+            messages.push(message(
+                MessageRole::User,
+                None,
+                format!("Question {}", turn),
+            ));
+            messages.push(message(
+                MessageRole::Assistant,
+                Some(1),
+                format!("Answer {}", turn),
+            ));
+            messages.push(message(
+                MessageRole::Assistant,
+                Some(2),
+                format!("Other answer {}", turn),
+            ));
+        }
+
+        // The agent failed to answer the last question, it is left out of its history
+        messages.push(message(MessageRole::User, None, "Unanswered".to_string()));
+
+        let history = agent_chat_history(&messages, 1);
+
+        assert_eq!(history.len(), CONVERSATION_MAX_HISTORY_TURNS * 2);
+        assert_eq!(history[0], Message::user("Question 3"));
+        assert_eq!(history[1], Message::assistant("Answer 3"));
+        assert_eq!(
+            history.last(),
+            Some(&Message::assistant(format!("Answer {}", turns)))
+        );
+
+        assert!(agent_chat_history(&messages, 3).is_empty());
+    }
+}
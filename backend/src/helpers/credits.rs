@@ -0,0 +1,562 @@
+use color_eyre::{Result, eyre::eyre};
+use uuid::Uuid;
+
+use crate::{
+    database,
+    types::{
+        AgentCharge, CreditEventKind, LedgerAccountKind, LedgerTransactionKind, Price,
+        RefundEntitlementDb,
+    },
+};
+
+// Prefix of the payment reference of answers paid with credits, used in place of a tx hash
+pub const CREDIT_PAYMENT_PREFIX: &str = "credits:";
+
+const USER_CREDIT_CHECK_CONSTRAINT: &str = "ledger_accounts_user_credit_check";
+
+pub fn is_credit_payment(payment_reference: &str) -> bool {
+    payment_reference.starts_with(CREDIT_PAYMENT_PREFIX)
+}
+
+/// Debits the charges of an answer from the credits of `payer` and accrues them to each agent revenue, in `tx`.
+/// Returns the payment reference, or None when the credits don't cover the total, `tx` must then be dropped.
+pub async fn debit_answer(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    payer: &str,
+    charges: &[AgentCharge],
+) -> Result<Option<String>> {
+    let reference = format!("{}{}", CREDIT_PAYMENT_PREFIX, Uuid::new_v4());
+    let amounts: Vec<(i64, Price)> = charges
+        .iter()
+        .map(|charge| (charge.agent_id, charge.amount))
+        .collect();
+
+    if !debit_credits(
+        tx,
+        payer,
+        LedgerTransactionKind::AnswerDebit,
+        &reference,
+        &amounts,
+    )
+    .await?
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(reference))
+}
+
+/// Posts a ledger transaction moving `amounts` from the credits of `payer` to the revenue of each agent.
+/// Nothing is posted when every amount is zero. Returns false when the credits don't cover the total,
+/// the database transaction is then aborted and must be dropped.
+pub async fn debit_credits(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    payer: &str,
+    kind: LedgerTransactionKind,
+    reference: &str,
+    amounts: &[(i64, Price)],
+) -> Result<bool> {
+    let amounts: Vec<(i64, Price)> = amounts
+        .iter()
+        .copied()
+        .filter(|(_, amount)| *amount != Price::ZERO)
+        .collect();
+
+    if amounts.is_empty() {
+        return Ok(true);
+    }
+
+    let total: Price = amounts.iter().map(|(_, amount)| *amount).sum();
+
+    let transaction_id = database::insert_ledger_transaction(tx, kind, reference).await?;
+
+    let user_account =
+        database::get_or_create_ledger_account(tx, LedgerAccountKind::UserCredit, payer).await?;
+
+    // The balance check fails here when the credits are insufficient
+    match database::insert_ledger_entry(tx, transaction_id, user_account, -total).await {
+        Ok(()) => {}
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some(USER_CREDIT_CHECK_CONSTRAINT) => {
+            return Ok(false);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    for (agent_id, amount) in amounts {
+        let revenue_account = database::get_or_create_ledger_account(
+            tx,
+            LedgerAccountKind::AgentRevenue,
+            &agent_id.to_string(),
+        )
+        .await?;
+
+        database::insert_ledger_entry(tx, transaction_id, revenue_account, amount).await?;
+    }
+
+    Ok(true)
+}
+
+/// Gives back to the payer, as credits, the price of an agent that failed to answer or lost a dispute.
+/// A credits payment is refunded from the agent revenue. An on-chain payment was paid to the agent NFT and never reached
+/// its revenue, it is refunded from the platform funding, which deposits of the platform funding address back in creditPool.
+pub async fn refund_to_credits(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    refund_entitlement: &RefundEntitlementDb,
+) -> Result<RefundEntitlementDb> {
+    let mut tx = db.begin().await?;
+
+    let (payer, source_account) = if is_credit_payment(&refund_entitlement.tx_hash) {
+        let payer =
+            database::get_ledger_transaction_user_address(&mut tx, &refund_entitlement.tx_hash)
+                .await?
+                .ok_or_else(|| eyre!("No credits debit {}", refund_entitlement.tx_hash))?;
+
+        let revenue_account = database::get_or_create_ledger_account(
+            &mut tx,
+            LedgerAccountKind::AgentRevenue,
+            &refund_entitlement.agent_id.to_string(),
+        )
+        .await?;
+
+        (payer, revenue_account)
+    } else {
+        // Payments made on-chain are credited to the address that sent them
+        let (payer, _) = database::get_agent_usage_payer(
+            db,
+            &refund_entitlement.tx_hash,
+            refund_entitlement.agent_id,
+        )
+        .await?
+        .ok_or_else(|| eyre!("No payer recorded for {}", refund_entitlement.tx_hash))?;
+
+        let funding_account =
+            database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::PlatformFunding, "")
+                .await?;
+
+        (payer, funding_account)
+    };
+
+    let transaction_id = database::insert_ledger_transaction(
+        &mut tx,
+        LedgerTransactionKind::Refund,
+        &format!("refund:{}", refund_entitlement.id),
+    )
+    .await?;
+
+    let user_account =
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::UserCredit, &payer)
+            .await?;
+
+    database::insert_ledger_entry(
+        &mut tx,
+        transaction_id,
+        source_account,
+        -refund_entitlement.amount,
+    )
+    .await?;
+    database::insert_ledger_entry(
+        &mut tx,
+        transaction_id,
+        user_account,
+        refund_entitlement.amount,
+    )
+    .await?;
+
+    let refund_entitlement =
+        database::mark_refund_entitlement_credited(&mut tx, refund_entitlement.id).await?;
+
+    tx.commit().await?;
+
+    Ok(refund_entitlement)
+}
+
+/// Whether a deposit comes from the platform funding address and funds the platform instead of its sender credits
+pub fn is_platform_funding(user_address: &str, platform_funding_address: Option<&str>) -> bool {
+    platform_funding_address.is_some_and(|address| address.eq_ignore_ascii_case(user_address))
+}
+
+/// Credits an indexed CreditsDeposited event to the depositor, events already indexed are skipped.
+/// Deposits of the platform funding address are credited to the platform funding.
+pub async fn credit_deposit(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+    log_index: i64,
+    block_number: i64,
+    user_address: &str,
+    amount: Price,
+    platform_funding_address: Option<&str>,
+) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    let Some(event_id) = database::insert_credit_event(
+        &mut tx,
+        CreditEventKind::Deposit,
+        tx_hash,
+        log_index,
+        block_number,
+        Some(user_address),
+        None,
+        amount,
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let transaction_id = database::insert_ledger_transaction(
+        &mut tx,
+        LedgerTransactionKind::Deposit,
+        &format!("deposit:{}:{}", tx_hash, log_index),
+    )
+    .await?;
+
+    let deposits_account =
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::OnchainDeposits, "")
+            .await?;
+    let credited_account = if is_platform_funding(user_address, platform_funding_address) {
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::PlatformFunding, "")
+            .await?
+    } else {
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::UserCredit, user_address)
+            .await?
+    };
+
+    database::insert_ledger_entry(&mut tx, transaction_id, deposits_account, -amount).await?;
+    database::insert_ledger_entry(&mut tx, transaction_id, credited_account, amount).await?;
+
+    database::update_credit_event_ledger_transaction(&mut tx, event_id, transaction_id).await?;
+
+    tx.commit().await?;
+
+    tracing::info!("Credited {} to {}", amount, user_address);
+
+    Ok(())
+}
+
+/// Books an indexed CreditsSettled event against the oldest matching pending settlement.
+/// Events matching none are kept unbooked and reported by the reconciliation.
+pub async fn record_settlement(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+    log_index: i64,
+    block_number: i64,
+    token_id: i64,
+    amount: Price,
+) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    let Some(event_id) = database::insert_credit_event(
+        &mut tx,
+        CreditEventKind::Settlement,
+        tx_hash,
+        log_index,
+        block_number,
+        None,
+        Some(token_id),
+        amount,
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let Some(settlement) =
+        database::settle_pending_credit_settlement(&mut tx, token_id, amount).await?
+    else {
+        tracing::warn!(
+            "Settlement of {} to token {} in tx {} matches no pending settlement",
+            amount,
+            token_id,
+            tx_hash
+        );
+        tx.commit().await?;
+        return Ok(());
+    };
+
+    let transaction_id = database::insert_ledger_transaction(
+        &mut tx,
+        LedgerTransactionKind::Settlement,
+        &format!("settlement:{}:{}", tx_hash, log_index),
+    )
+    .await?;
+
+    let pending_account =
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::SettlementsPending, "")
+            .await?;
+    let settled_account =
+        database::get_or_create_ledger_account(&mut tx, LedgerAccountKind::OnchainSettlements, "")
+            .await?;
+
+    database::insert_ledger_entry(&mut tx, transaction_id, pending_account, -amount).await?;
+    database::insert_ledger_entry(&mut tx, transaction_id, settled_account, amount).await?;
+
+    database::update_credit_event_ledger_transaction(&mut tx, event_id, transaction_id).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(
+        "Settlement {} of agent {} settled on-chain",
+        settlement.id,
+        settlement.agent_id
+    );
+
+    Ok(())
+}
+
+/// Moves the revenue of every agent holding at least `min_amount` to a pending settlement,
+/// to be paid on-chain with settleCredits. Returns the number of settlements created.
+pub async fn accrue_settlements(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    min_amount: Price,
+) -> Result<usize> {
+    let revenues = database::get_settleable_agent_revenues(db, min_amount).await?;
+
+    for (agent_id, token_id, amount) in revenues.iter().copied() {
+        let mut tx = db.begin().await?;
+
+        let settlement =
+            database::insert_credit_settlement(&mut tx, agent_id, token_id, amount).await?;
+
+        let transaction_id = database::insert_ledger_transaction(
+            &mut tx,
+            LedgerTransactionKind::SettlementAccrual,
+            &format!("settlement_accrual:{}", settlement.id),
+        )
+        .await?;
+
+        let revenue_account = database::get_or_create_ledger_account(
+            &mut tx,
+            LedgerAccountKind::AgentRevenue,
+            &agent_id.to_string(),
+        )
+        .await?;
+        let pending_account = database::get_or_create_ledger_account(
+            &mut tx,
+            LedgerAccountKind::SettlementsPending,
+            "",
+        )
+        .await?;
+
+        database::insert_ledger_entry(&mut tx, transaction_id, revenue_account, -amount).await?;
+        database::insert_ledger_entry(&mut tx, transaction_id, pending_account, amount).await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(revenues.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ChargeBasis, RefundReason};
+
+    const PAYER: &str = "0x00000000000000000000000000000000000000a1";
+    const PLATFORM: &str = "0x00000000000000000000000000000000000000f0";
+    // The indexed events give checksummed addresses
+    const PLATFORM_CHECKSUMMED: &str = "0x00000000000000000000000000000000000000F0";
+    const TOKEN_ID: i64 = 7;
+
+    async fn insert_test_agent(db: &sqlx::Pool<sqlx::Postgres>) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size, nft_id)
+            SELECT id, 'Sales', 'Sales per city', 200, 'sales.csv', 'Analytics', 1.0, $1 FROM owner
+            RETURNING id
+            "#,
+        )
+        .bind(TOKEN_ID)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    fn charge(agent_id: i64, units: i64) -> AgentCharge {
+        AgentCharge {
+            agent_id,
+            amount: Price::from_units(units),
+            basis: ChargeBasis::PerQuery,
+        }
+    }
+
+    async fn debit(db: &sqlx::Pool<sqlx::Postgres>, charges: &[AgentCharge]) -> Option<String> {
+        let mut tx = db.begin().await.unwrap();
+        let reference = debit_answer(&mut tx, PAYER, charges).await.unwrap();
+
+        if reference.is_some() {
+            tx.commit().await.unwrap();
+        }
+
+        reference
+    }
+
+    async fn balance(db: &sqlx::Pool<sqlx::Postgres>, kind: LedgerAccountKind, owner: &str) -> i64 {
+        database::get_ledger_account_balance(db, kind, owner)
+            .await
+            .unwrap()
+            .units()
+    }
+
+    #[sqlx::test]
+    async fn credits_are_debited_refunded_and_settled(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id = insert_test_agent(&db).await;
+        let revenue = agent_id.to_string();
+
+        // Indexing the same deposit twice credits it once
+        for _ in 0..2 {
+            credit_deposit(&db, "0xdeposit", 0, 10, PAYER, Price::from_units(300), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            balance(&db, LedgerAccountKind::UserCredit, PAYER).await,
+            300
+        );
+
+        let reference = debit(&db, &[charge(agent_id, 200)]).await.unwrap();
+        assert!(is_credit_payment(&reference));
+
+        // The credits left don't cover a second answer, nothing is debited
+        let refused = debit(&db, &[charge(agent_id, 200)]).await;
+        assert!(refused.is_none());
+        assert_eq!(
+            balance(&db, LedgerAccountKind::UserCredit, PAYER).await,
+            100
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::AgentRevenue, &revenue).await,
+            200
+        );
+
+        let refund_entitlement = database::insert_refund_entitlement(
+            &db,
+            agent_id,
+            &reference,
+            Price::from_units(200),
+            RefundReason::Failed,
+            None,
+        )
+        .await
+        .unwrap();
+        let refund_entitlement = refund_to_credits(&db, &refund_entitlement).await.unwrap();
+
+        assert_eq!(refund_entitlement.status, "credited");
+        assert_eq!(
+            balance(&db, LedgerAccountKind::UserCredit, PAYER).await,
+            300
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::AgentRevenue, &revenue).await,
+            0
+        );
+
+        debit(&db, &[charge(agent_id, 200)]).await.unwrap();
+
+        let accrued = accrue_settlements(&db, Price::from_units(100))
+            .await
+            .unwrap();
+        assert_eq!(accrued, 1);
+        assert_eq!(
+            balance(&db, LedgerAccountKind::AgentRevenue, &revenue).await,
+            0
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::SettlementsPending, "").await,
+            200
+        );
+
+        record_settlement(&db, "0xsettle", 0, 11, TOKEN_ID, Price::from_units(200))
+            .await
+            .unwrap();
+        assert_eq!(
+            balance(&db, LedgerAccountKind::SettlementsPending, "").await,
+            0
+        );
+
+        let reconciliation = database::get_ledger_reconciliation(&db).await.unwrap();
+        assert!(reconciliation.reconciled);
+        assert_eq!(reconciliation.expected_credit_pool, Price::from_units(100));
+        assert_eq!(
+            reconciliation.outstanding_user_credits,
+            Price::from_units(100)
+        );
+    }
+
+    #[sqlx::test]
+    async fn onchain_payments_are_refunded_from_the_platform_funding(
+        db: sqlx::Pool<sqlx::Postgres>,
+    ) {
+        let agent_id = insert_test_agent(&db).await;
+
+        sqlx::query(
+            r#"
+            INSERT INTO agent_usage (agent_id, user_address, payment_reference, basis, amount)
+            VALUES ($1, $2, '0xpaid', 'per_query', 200)
+            "#,
+        )
+        .bind(agent_id)
+        .bind(PAYER)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let refund_entitlement = database::insert_refund_entitlement(
+            &db,
+            agent_id,
+            "0xpaid",
+            Price::from_units(200),
+            RefundReason::Failed,
+            None,
+        )
+        .await
+        .unwrap();
+        refund_to_credits(&db, &refund_entitlement).await.unwrap();
+
+        // The agent was paid on-chain, its revenue is left untouched
+        assert_eq!(
+            balance(&db, LedgerAccountKind::UserCredit, PAYER).await,
+            200
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::AgentRevenue, &agent_id.to_string()).await,
+            0
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::PlatformFunding, "").await,
+            -200
+        );
+
+        // creditPool is short of the refunded credits until the platform deposits them
+        let reconciliation = database::get_ledger_reconciliation(&db).await.unwrap();
+        assert!(!reconciliation.reconciled);
+
+        credit_deposit(
+            &db,
+            "0xfunding",
+            0,
+            12,
+            PLATFORM_CHECKSUMMED,
+            Price::from_units(200),
+            Some(PLATFORM),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            balance(&db, LedgerAccountKind::PlatformFunding, "").await,
+            0
+        );
+        assert_eq!(
+            balance(&db, LedgerAccountKind::UserCredit, PLATFORM_CHECKSUMMED).await,
+            0
+        );
+
+        let reconciliation = database::get_ledger_reconciliation(&db).await.unwrap();
+        assert!(reconciliation.reconciled);
+        assert_eq!(reconciliation.expected_credit_pool, Price::from_units(200));
+    }
+}
@@ -1,5 +1,10 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use color_eyre::Result;
 
+use crate::types::{ColumnDataType, DatasetColumn};
+
 pub fn validate_and_count_csv(data: &[u8]) -> Result<usize> {
     let content = String::from_utf8(data.to_vec())?;
 
@@ -31,3 +36,297 @@ pub async fn csv_bytes_to_string(data: &[u8]) -> Result<String> {
 
     Ok(content)
 }
+
+/// Appends the rows of a new csv to an existing one, both must have the same header
+pub fn append_csv(existing: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    let mut existing_reader = csv::Reader::from_reader(existing);
+    let mut new_reader = csv::Reader::from_reader(new);
+
+    let headers = existing_reader.headers()?.clone();
+
+    if new_reader.headers()? != &headers {
+        return Err(color_eyre::eyre::eyre!(
+            "CSV header does not match the current dataset version"
+        ));
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&headers)?;
+
+    for record in existing_reader.records().chain(new_reader.records()) {
+        writer.write_record(&record?)?;
+    }
+
+    let data = writer
+        .into_inner()
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to write appended CSV: {}", e))?;
+
+    Ok(data)
+}
+
+/// Header and first `rows` rows of a csv
+pub fn sample_csv_rows(data: &[u8], rows: usize) -> Result<String> {
+    let mut reader = csv::Reader::from_reader(data);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    writer.write_record(reader.headers()?)?;
+
+    for record in reader.records().take(rows) {
+        writer.write_record(&record?)?;
+    }
+
+    let data = writer
+        .into_inner()
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to write sample CSV: {}", e))?;
+
+    Ok(String::from_utf8(data)?)
+}
+
+/// Maximum number of distinct sample values kept per column
+const MAX_COLUMN_SAMPLE_VALUES: usize = 5;
+/// Maximum length of a sample value before it gets truncated
+const MAX_SAMPLE_VALUE_LEN: usize = 64;
+
+/// Infers the name, type, null rate, cardinality, min/max and sample values of every column of the csv
+pub fn profile_csv_columns(data: &[u8]) -> Result<Vec<DatasetColumn>> {
+    let mut reader = csv::Reader::from_reader(data);
+
+    let headers = reader.headers()?.clone();
+
+    let mut columns_values: Vec<Vec<String>> = vec![Vec::new(); headers.len()];
+    let mut row_count = 0usize;
+
+    for result in reader.records() {
+        let record = result?;
+        row_count += 1;
+
+        for (index, values) in columns_values.iter_mut().enumerate() {
+            let value = record.get(index).unwrap_or("").trim();
+
+            if !is_null_value(value) {
+                values.push(value.to_string());
+            }
+        }
+    }
+
+    let columns = headers
+        .iter()
+        .zip(columns_values)
+        .enumerate()
+        .map(|(position, (name, values))| profile_column(position, name, &values, row_count))
+        .collect();
+
+    Ok(columns)
+}
+
+fn profile_column(
+    position: usize,
+    name: &str,
+    values: &[String],
+    row_count: usize,
+) -> DatasetColumn {
+    let data_type = infer_column_type(values);
+
+    let null_rate = if row_count == 0 {
+        0.0
+    } else {
+        (row_count - values.len()) as f64 / row_count as f64
+    };
+
+    let distinct_count = values.iter().collect::<HashSet<_>>().len() as i64;
+
+    let (min_value, max_value) = column_min_max(&data_type, values);
+
+    let mut sample_values: Vec<String> = Vec::new();
+    for value in values {
+        if sample_values.len() >= MAX_COLUMN_SAMPLE_VALUES {
+            break;
+        }
+
+        let value: String = value.chars().take(MAX_SAMPLE_VALUE_LEN).collect();
+        if !sample_values.contains(&value) {
+            sample_values.push(value);
+        }
+    }
+
+    DatasetColumn {
+        position: position as i32,
+        name: name.trim().to_string(),
+        data_type,
+        null_rate,
+        distinct_count,
+        min_value,
+        max_value,
+        sample_values,
+    }
+}
+
+fn is_null_value(value: &str) -> bool {
+    value.is_empty()
+        || ["null", "none", "nan", "n/a", "na"].contains(&value.to_lowercase().as_str())
+}
+
+/// Returns the narrowest type that every non-null value of the column satisfies
+fn infer_column_type(values: &[String]) -> ColumnDataType {
+    if values.is_empty() {
+        return ColumnDataType::Empty;
+    }
+
+    if values.iter().all(|v| v.parse::<i64>().is_ok()) {
+        ColumnDataType::Integer
+    } else if values.iter().all(|v| v.parse::<f64>().is_ok()) {
+        ColumnDataType::Float
+    } else if values
+        .iter()
+        .all(|v| v.eq_ignore_ascii_case("true") || v.eq_ignore_ascii_case("false"))
+    {
+        ColumnDataType::Boolean
+    } else if values.iter().all(|v| parse_date(v).is_some()) {
+        ColumnDataType::Date
+    } else if values.iter().all(|v| parse_datetime(v).is_some()) {
+        ColumnDataType::Datetime
+    } else {
+        ColumnDataType::String
+    }
+}
+
+fn column_min_max(
+    data_type: &ColumnDataType,
+    values: &[String],
+) -> (Option<String>, Option<String>) {
+    match data_type {
+        ColumnDataType::Integer => {
+            let parsed = values.iter().filter_map(|v| v.parse::<i64>().ok());
+            (
+                parsed.clone().min().map(|v| v.to_string()),
+                parsed.max().map(|v| v.to_string()),
+            )
+        }
+        ColumnDataType::Float => {
+            let parsed = values.iter().filter_map(|v| v.parse::<f64>().ok());
+            (
+                parsed.clone().reduce(f64::min).map(|v| v.to_string()),
+                parsed.reduce(f64::max).map(|v| v.to_string()),
+            )
+        }
+        ColumnDataType::Date => {
+            let parsed = values.iter().filter_map(|v| parse_date(v));
+            (
+                parsed.clone().min().map(|v| v.to_string()),
+                parsed.max().map(|v| v.to_string()),
+            )
+        }
+        ColumnDataType::Datetime => {
+            let parsed = values.iter().filter_map(|v| parse_datetime(v));
+            (
+                parsed.clone().min().map(|v| v.to_string()),
+                parsed.max().map(|v| v.to_string()),
+            )
+        }
+        // Min/Max are not meaningful for text and boolean columns
+        _ => (None, None),
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%Y/%m/%d", "%d/%m/%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.naive_utc());
+    }
+
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATASET: &str = "id,price,active,signup,last_login,city,notes\n\
+        3,12.5,true,2024-01-15,2024-01-15 08:30:00,Grenoble,\n\
+        1,7,FALSE,2023-12-01,2024-02-01T10:00:00Z,Toulouse,n/a\n\
+        2,,true,2024/03/02,2024-03-02 09:15,Grenoble,null\n";
+
+    fn column<'a>(columns: &'a [DatasetColumn], name: &str) -> &'a DatasetColumn {
+        columns.iter().find(|c| c.name == name).unwrap()
+    }
+
+    #[test]
+    fn infers_the_narrowest_type_of_each_column() {
+        let columns = profile_csv_columns(DATASET.as_bytes()).unwrap();
+
+        let types: Vec<_> = columns
+            .iter()
+            .map(|c| (c.name.as_str(), c.data_type))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("id", ColumnDataType::Integer),
+                ("price", ColumnDataType::Float),
+                ("active", ColumnDataType::Boolean),
+                ("signup", ColumnDataType::Date),
+                ("last_login", ColumnDataType::Datetime),
+                ("city", ColumnDataType::String),
+                ("notes", ColumnDataType::Empty),
+            ]
+        );
+        assert_eq!(
+            columns.iter().map(|c| c.position).collect::<Vec<_>>(),
+            (0..7).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn computes_min_max_null_rate_and_cardinality() {
+        let columns = profile_csv_columns(DATASET.as_bytes()).unwrap();
+
+        let id = column(&columns, "id");
+        assert_eq!(id.min_value.as_deref(), Some("1"));
+        assert_eq!(id.max_value.as_deref(), Some("3"));
+        assert_eq!(id.null_rate, 0.0);
+        assert_eq!(id.distinct_count, 3);
+
+        let price = column(&columns, "price");
+        assert_eq!(price.min_value.as_deref(), Some("7"));
+        assert_eq!(price.max_value.as_deref(), Some("12.5"));
+        assert!((price.null_rate - 1.0 / 3.0).abs() < 1e-9);
+
+        let signup = column(&columns, "signup");
+        assert_eq!(signup.min_value.as_deref(), Some("2023-12-01"));
+        assert_eq!(signup.max_value.as_deref(), Some("2024-03-02"));
+
+        let city = column(&columns, "city");
+        assert_eq!(city.min_value, None);
+        assert_eq!(city.max_value, None);
+        assert_eq!(city.distinct_count, 2);
+        assert_eq!(city.sample_values, vec!["Grenoble", "Toulouse"]);
+
+        let notes = column(&columns, "notes");
+        assert_eq!(notes.null_rate, 1.0);
+        assert_eq!(notes.distinct_count, 0);
+        assert!(notes.sample_values.is_empty());
+    }
+
+    #[test]
+    fn truncates_and_caps_sample_values() {
+        let long_value = "x".repeat(MAX_SAMPLE_VALUE_LEN + 10);
+        let mut data = String::from("label\n");
+        data.push_str(&format!("{}\n", long_value));
+        for i in 0..10 {
+            data.push_str(&format!("value {}\n", i));
+        }
+
+        let columns = profile_csv_columns(data.as_bytes()).unwrap();
+        let samples = &columns[0].sample_values;
+
+        assert_eq!(samples.len(), MAX_COLUMN_SAMPLE_VALUES);
+        assert_eq!(samples[0].len(), MAX_SAMPLE_VALUE_LEN);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::eyre};
+use rig::{client::EmbeddingsClient, embeddings::EmbeddingModel, providers::gemini};
+
+use crate::{
+    config::{EMBEDDING_MODEL, LOCAL_EMBEDDING_DIMENSIONS},
+    database,
+    types::{AgentDb, DatasetColumn},
+};
+
+pub enum Embedder {
+    Gemini(gemini::embedding::EmbeddingModel),
+    /// Deterministic embedding computed locally by hashing the words of the text,
+    /// the same text always gives the same vector. Used in tests and without an api key.
+    Local,
+}
+
+impl Embedder {
+    pub fn new(provider: &str, ai_model: &gemini::Client) -> Self {
+        match provider {
+            "local" => Self::Local,
+            _ => Self::Gemini(ai_model.embedding_model(EMBEDDING_MODEL)),
+        }
+    }
+
+    /// Name stored with the embeddings, embeddings of different models are never compared
+    pub fn model_name(&self) -> String {
+        match self {
+            Self::Gemini(_) => EMBEDDING_MODEL.to_string(),
+            Self::Local => format!("local-hash-{}", LOCAL_EMBEDDING_DIMENSIONS),
+        }
+    }
+
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f64>>> {
+        match self {
+            Self::Gemini(model) => {
+                let count = texts.len();
+                let embeddings = model.embed_texts(texts).await?;
+
+                if embeddings.len() != count {
+                    return Err(eyre!(
+                        "Expected {} embeddings, got {}",
+                        count,
+                        embeddings.len()
+                    ));
+                }
+
+                Ok(embeddings
+                    .into_iter()
+                    .map(|embedding| embedding.vec)
+                    .collect())
+            }
+            Self::Local => Ok(texts.iter().map(|text| local_embedding(text)).collect()),
+        }
+    }
+}
+
+/// Text describing an agent for its embedding: name, description, category and dataset columns
+pub fn agent_embedding_text(agent: &AgentDb, columns: Option<&Vec<DatasetColumn>>) -> String {
+    let columns = columns
+        .map(|columns| {
+            columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+
+    format!(
+        "{}\n{}\nCategory: {}\nColumns: {}",
+        agent.name,
+        agent.description,
+        agent.category.to_string(),
+        columns
+    )
+}
+
+/// Computes and stores the embeddings of the agents that are missing, computed from an outdated description or by another model.
+/// Returns the number of embeddings computed.
+pub async fn refresh_agent_embeddings(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedder: &Embedder,
+    agents: &[AgentDb],
+    agents_columns: &HashMap<i64, Vec<DatasetColumn>>,
+) -> Result<usize> {
+    let model = embedder.model_name();
+
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+
+    let mut stored: HashMap<i64, _> = database::get_agent_embeddings_by_agent_ids(db, &agent_ids)
+        .await?
+        .into_iter()
+        .map(|embedding| (embedding.agent_id, embedding))
+        .collect();
+
+    let mut stale = Vec::new();
+
+    for agent in agents {
+        let text = agent_embedding_text(agent, agents_columns.get(&agent.id));
+
+        match stored.remove(&agent.id) {
+            Some(embedding) if embedding.model == model && embedding.source_text == text => {}
+            _ => stale.push((agent.id, text)),
+        }
+    }
+
+    if stale.is_empty() {
+        return Ok(0);
+    }
+
+    let vectors = embedder
+        .embed(stale.iter().map(|(_, text)| text.clone()).collect())
+        .await?;
+
+    for ((agent_id, text), vector) in stale.iter().zip(vectors) {
+        database::upsert_agent_embedding(db, *agent_id, &model, text, &vector).await?;
+    }
+
+    Ok(stale.len())
+}
+
+/// Refreshes the embedding of an agent after it was uploaded, edited or got a new dataset version.
+/// A failure is only logged, the embedding indexer computes it later.
+pub async fn refresh_agent_embedding(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedder: &Embedder,
+    agent: &AgentDb,
+) {
+    let refreshed = async {
+        let columns = database::get_dataset_columns_by_agent_id(db, agent.id).await?;
+
+        refresh_agent_embeddings(
+            db,
+            embedder,
+            std::slice::from_ref(agent),
+            &HashMap::from([(agent.id, columns)]),
+        )
+        .await
+    }
+    .await;
+
+    if let Err(e) = refreshed {
+        tracing::warn!(
+            "Failed to compute the embedding of agent {}: {:?}",
+            agent.id,
+            e
+        );
+    }
+}
+
+/// Returns the stored embedding of each agent, agents without one computed by the current model are left out.
+/// Embeddings are computed when agents change, never on the request path.
+async fn stored_agent_embeddings(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedder: &Embedder,
+    agents: &[AgentDb],
+) -> Result<HashMap<i64, Vec<f64>>> {
+    let model = embedder.model_name();
+
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+
+    let embeddings: HashMap<i64, Vec<f64>> =
+        database::get_agent_embeddings_by_agent_ids(db, &agent_ids)
+            .await?
+            .into_iter()
+            .filter(|embedding| embedding.model == model)
+            .map(|embedding| (embedding.agent_id, embedding.embedding))
+            .collect();
+
+    if embeddings.len() < agents.len() {
+        tracing::debug!(
+            "{} agents have no embedding yet and are not ranked",
+            agents.len() - embeddings.len()
+        );
+    }
+
+    Ok(embeddings)
+}
+
+/// Ranks the agents by the similarity of their embedding with the query, most similar first
+pub async fn rank_agents_by_similarity(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedder: &Embedder,
+    query: &str,
+    agents: &[AgentDb],
+) -> Result<Vec<(i64, f64)>> {
+    let agents_embeddings = stored_agent_embeddings(db, embedder, agents).await?;
+
+    let query_embedding = embedder
+        .embed(vec![query.to_string()])
+        .await?
+        .pop()
+        .ok_or_else(|| eyre!("No embedding returned for the query"))?;
+
+    let mut similarities: Vec<(i64, f64)> = agents_embeddings
+        .iter()
+        .map(|(agent_id, embedding)| (*agent_id, cosine_similarity(&query_embedding, embedding)))
+        .collect();
+
+    // Ties are broken by id so the order is stable
+    similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    Ok(similarities)
+}
+
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Hashes each lowercase word into one of `LOCAL_EMBEDDING_DIMENSIONS` buckets, with a sign also taken from the hash,
+/// then normalizes the vector. Texts sharing words get similar vectors.
+fn local_embedding(text: &str) -> Vec<f64> {
+    let mut vector = vec![0.0; LOCAL_EMBEDDING_DIMENSIONS];
+
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let hash = fnv1a(word.to_lowercase().as_bytes());
+        let bucket = (hash % LOCAL_EMBEDDING_DIMENSIONS as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+
+        vector[bucket] += sign;
+    }
+
+    let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+
+    vector
+}
+
+// FNV-1a, stable across builds unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_embedding_is_deterministic_and_normalized() {
+        let embedding = local_embedding("Hospital admissions per city");
+
+        assert_eq!(embedding.len(), LOCAL_EMBEDDING_DIMENSIONS);
+        assert_eq!(embedding, local_embedding("hospital ADMISSIONS, per city!"));
+
+        let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-9);
+
+        assert!(local_embedding("").iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn cosine_similarity_of_vectors() {
+        assert!((cosine_similarity(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-9);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-9);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[sqlx::test]
+    async fn agents_are_ranked_by_their_stored_embedding(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, name, description, 1000, 'data.csv', category::agent_category, 1.0
+            FROM owner, (VALUES
+                ('Hospital admissions', 'Patients admitted per hospital and diagnosis', 'Healthcare'),
+                ('Token transfers', 'Transfers of tokens between wallets', 'Web3'),
+                ('Game sessions', 'Sessions played per game and player level', 'Gaming')
+            ) AS agent(name, description, category)
+            RETURNING agents.id
+            "#,
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        let agents = database::get_agents_by_ids(&db, &agent_ids).await.unwrap();
+        let embedder = Embedder::Local;
+
+        // Nothing is ranked before the embeddings are computed
+        let similarities = rank_agents_by_similarity(&db, &embedder, "tokens", &agents)
+            .await
+            .unwrap();
+        assert!(similarities.is_empty());
+
+        let computed = refresh_agent_embeddings(&db, &embedder, &agents, &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(computed, 3);
+
+        // Up to date embeddings are not computed again
+        let computed = refresh_agent_embeddings(&db, &embedder, &agents, &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(computed, 0);
+
+        let similarities = rank_agents_by_similarity(
+            &db,
+            &embedder,
+            "transfers of tokens between wallets",
+            &agents,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(similarities.len(), 3);
+        assert_eq!(similarities[0].0, agent_ids[1]);
+        assert!(similarities[0].1 > similarities[1].1);
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use rig::completion::Prompt;
+
+use crate::{
+    config::{
+        AGENT_RESPONSE_TIMEOUT_SECS, EVALUATION_DEFAULT_NUMERIC_TOLERANCE, EVALUATION_JUDGE_MODEL,
+        INIT_AGENT_MODEL,
+    },
+    database,
+    helpers::guardrail,
+    state::TeeAgent,
+    types::{
+        AgentDb, EvaluationCaseDb, EvaluationMatchKind, EvaluationRunDb, EvaluationRunResponse,
+        JudgeVerdict, NewEvaluationResult, WebAppState,
+    },
+};
+
+/// Model answering the questions of an evaluation
+pub enum EvaluationModel {
+    /// Agent built on the dataset like the ones answering users, llm_judge cases are judged by EVALUATION_JUDGE_MODEL
+    Agent {
+        agent: Arc<TeeAgent>,
+        ai_model: rig::providers::gemini::Client,
+    },
+    /// Canned answers by question, to evaluate without calling any model.
+    /// llm_judge cases pass when the answer contains the expected one.
+    Mock(HashMap<String, String>),
+}
+
+impl EvaluationModel {
+    /// Model answering from the current dataset version of `agent_db`
+    pub async fn agent(app_state: &WebAppState, agent_db: &AgentDb) -> Result<Self> {
+        let agent = app_state
+            .agent_cache
+            .get_or_load(agent_db, &app_state.ai_model)
+            .await
+            .context("Failed to load agent")?;
+
+        Ok(EvaluationModel::Agent {
+            agent,
+            ai_model: app_state.ai_model.clone(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            EvaluationModel::Agent { .. } => INIT_AGENT_MODEL,
+            EvaluationModel::Mock(_) => "mock",
+        }
+    }
+
+    pub fn dataset_version(&self, agent_db: &AgentDb) -> i32 {
+        match self {
+            EvaluationModel::Agent { agent, .. } => agent.version,
+            EvaluationModel::Mock(_) => agent_db.current_version,
+        }
+    }
+
+    /// Answer to `question`, filtered by the output guardrail like the answers given to users
+    async fn answer(&self, question: &str) -> Result<String> {
+        match self {
+            EvaluationModel::Agent { agent, .. } => {
+                let response = agent.agent.prompt(question).await?;
+
+                // Guardrail events aren't recorded, the owner asked the question
+                Ok(guardrail::check_agent_response(&response, &agent.dataset).response)
+            }
+            EvaluationModel::Mock(answers) => answers
+                .get(question)
+                .cloned()
+                .ok_or_else(|| eyre!("No mock answer for question: {}", question)),
+        }
+    }
+
+    async fn judge(&self, case: &EvaluationCaseDb, answer: &str) -> Result<JudgeVerdict> {
+        let ai_model = match self {
+            EvaluationModel::Agent { ai_model, .. } => ai_model,
+            EvaluationModel::Mock(_) => {
+                let correct =
+                    normalize_answer(answer).contains(&normalize_answer(&case.expected_answer));
+
+                return Ok(JudgeVerdict {
+                    correct,
+                    reasoning: "Mock judge: the answer must contain the expected answer"
+                        .to_string(),
+                });
+            }
+        };
+
+        let judge = ai_model
+            .agent(EVALUATION_JUDGE_MODEL)
+            .preamble("You are an AI agent grading the answer of a dataset agent to a question. Decide whether the answer gives the same facts as the expected answer, ignoring wording and formatting. Extra details are fine, missing or wrong facts are not. Return the response as a json object with the following format: {correct: boolean, reasoning: string}. ")
+            .temperature(0.0)
+            .build();
+
+        let prompt = format!(
+            "Question: {}\nExpected answer: {}\nAnswer to grade: {}",
+            case.question, case.expected_answer, answer
+        );
+
+        let response = judge.prompt(prompt).await?;
+
+        // Remove any markdown from the response
+        let formatted_response = response.replace("```json", "").replace("```", "");
+
+        serde_json::from_str(&formatted_response).context("Failed to parse judge response")
+    }
+}
+
+/// Answers and scores every case, recording a result per case and the score of the run.
+/// Failing cases score 0, the run only fails when its results can't be recorded.
+pub async fn execute_evaluation_run(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    run: &EvaluationRunDb,
+    cases: &[EvaluationCaseDb],
+    model: &EvaluationModel,
+) -> Result<EvaluationRunDb> {
+    let mut passed_cases = 0;
+    let mut total_score = 0.0;
+
+    for case in cases {
+        let result = evaluate_case(model, case).await;
+
+        if let Err(e) = database::insert_evaluation_result(db, run.id, &result).await {
+            database::fail_evaluation_run(db, run.id, &e.to_string()).await?;
+            return Err(e.into());
+        }
+
+        passed_cases += i32::from(result.passed);
+        total_score += result.score;
+    }
+
+    let score = (!cases.is_empty()).then(|| total_score / cases.len() as f64);
+
+    tracing::info!(
+        "Evaluation run {} of agent {} passed {}/{} cases",
+        run.id,
+        run.agent_id,
+        passed_cases,
+        cases.len()
+    );
+
+    Ok(database::complete_evaluation_run(db, run.id, passed_cases, score).await?)
+}
+
+async fn evaluate_case(model: &EvaluationModel, case: &EvaluationCaseDb) -> NewEvaluationResult {
+    let started = Instant::now();
+
+    let answer = tokio::time::timeout(
+        Duration::from_secs(AGENT_RESPONSE_TIMEOUT_SECS),
+        model.answer(&case.question),
+    )
+    .await
+    .unwrap_or_else(|_| Err(eyre!("Agent timed out")));
+
+    let latency_ms = started.elapsed().as_millis() as i64;
+
+    let answer = match answer {
+        Ok(answer) => answer,
+        Err(e) => {
+            return NewEvaluationResult {
+                case_id: case.id,
+                answer: None,
+                passed: false,
+                score: 0.0,
+                judge_reasoning: None,
+                error: Some(e.to_string()),
+                latency_ms,
+            };
+        }
+    };
+
+    let (passed, judge_reasoning, error) = match case.match_kind.parse() {
+        Ok(EvaluationMatchKind::Exact) => (exact_match(&case.expected_answer, &answer), None, None),
+        Ok(EvaluationMatchKind::Numeric) => (
+            numeric_match(
+                &case.expected_answer,
+                &answer,
+                case.tolerance
+                    .unwrap_or(EVALUATION_DEFAULT_NUMERIC_TOLERANCE),
+            ),
+            None,
+            None,
+        ),
+        Ok(EvaluationMatchKind::LlmJudge) => match model.judge(case, &answer).await {
+            Ok(verdict) => (verdict.correct, Some(verdict.reasoning), None),
+            Err(e) => (false, None, Some(e.to_string())),
+        },
+        Err(e) => (false, None, Some(e.to_string())),
+    };
+
+    NewEvaluationResult {
+        case_id: case.id,
+        answer: Some(answer),
+        passed,
+        score: if passed { 1.0 } else { 0.0 },
+        judge_reasoning,
+        error,
+        latency_ms,
+    }
+}
+
+/// Same answer once case, whitespace and trailing punctuation are ignored
+pub fn exact_match(expected: &str, answer: &str) -> bool {
+    normalize_answer(expected) == normalize_answer(answer)
+}
+
+/// Whether a number of the answer is within `tolerance` (relative) of the first number of the expected answer
+pub fn numeric_match(expected: &str, answer: &str, tolerance: f64) -> bool {
+    let Some(expected) = extract_first_number(expected) else {
+        return false;
+    };
+
+    extract_numbers(answer)
+        .into_iter()
+        .any(|number| (number - expected).abs() <= tolerance * expected.abs() + f64::EPSILON)
+}
+
+fn normalize_answer(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(['.', '!', '?'])
+        .to_lowercase()
+}
+
+pub fn extract_first_number(text: &str) -> Option<f64> {
+    extract_numbers(text).first().copied()
+}
+
+/// Numbers written in a text, thousands separators included
+fn extract_numbers(text: &str) -> Vec<f64> {
+    let chars: Vec<char> = text.chars().collect();
+    let followed_by_digit = |i: usize| chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+
+    let mut numbers = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let starts_number =
+            chars[i].is_ascii_digit() || (matches!(chars[i], '-' | '.') && followed_by_digit(i));
+
+        if !starts_number {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+
+        // Separators only belong to the number when a digit follows them
+        while i < chars.len()
+            && (chars[i].is_ascii_digit()
+                || (matches!(chars[i], '.' | ',') && followed_by_digit(i)))
+        {
+            i += 1;
+        }
+
+        let number: String = chars[start..i].iter().filter(|c| **c != ',').collect();
+
+        if let Ok(number) = number.parse() {
+            numbers.push(number);
+        }
+    }
+
+    numbers
+}
+
+/// Runs the evaluation of an agent from the command line and prints its results:
+/// `enclava_backend evaluate <agent_id> [mock_answers.json]`.
+/// The optional file maps each question to a canned answer, the agent model answers otherwise.
+pub async fn run_cli(app_state: &WebAppState, args: &[String]) -> Result<()> {
+    let agent_id: i64 = args
+        .first()
+        .ok_or_else(|| eyre!("Usage: enclava_backend evaluate <agent_id> [mock_answers.json]"))?
+        .parse()
+        .context("Invalid agent id")?;
+
+    let db = &app_state.db;
+
+    let agent_db = database::get_agent_by_id(db, agent_id).await?;
+    let cases = database::get_evaluation_cases(db, agent_id).await?;
+
+    if cases.is_empty() {
+        return Err(eyre!("Agent {} has no evaluation cases", agent_id));
+    }
+
+    let model = match args.get(1) {
+        Some(path) => {
+            let answers = tokio::fs::read_to_string(path).await?;
+            EvaluationModel::Mock(
+                serde_json::from_str(&answers).context("Failed to parse mock answers")?,
+            )
+        }
+        None => EvaluationModel::agent(app_state, &agent_db).await?,
+    };
+
+    let run = database::insert_evaluation_run(
+        db,
+        agent_id,
+        model.dataset_version(&agent_db),
+        model.name(),
+        cases.len() as i32,
+        None,
+    )
+    .await?;
+
+    let run = execute_evaluation_run(db, &run, &cases, &model).await?;
+    let results = database::get_evaluation_results(db, run.id).await?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&EvaluationRunResponse {
+            success: true,
+            run,
+            results,
+        })?
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NewEvaluationCase;
+
+    async fn insert_test_agent(db: &sqlx::Pool<sqlx::Postgres>) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 1000, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    fn case(
+        question: &str,
+        expected_answer: &str,
+        match_kind: EvaluationMatchKind,
+    ) -> NewEvaluationCase {
+        NewEvaluationCase {
+            question: question.to_string(),
+            expected_answer: expected_answer.to_string(),
+            match_kind,
+            tolerance: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn mock_run_scores_exact_numeric_and_judge_cases(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id = insert_test_agent(&db).await;
+
+        let cases = database::insert_evaluation_cases(
+            &db,
+            agent_id,
+            "0x0000000000000000000000000000000000000001",
+            &[
+                case(
+                    "Which city sells the most?",
+                    "Paris.",
+                    EvaluationMatchKind::Exact,
+                ),
+                case(
+                    "Which city sells the least?",
+                    "Lyon",
+                    EvaluationMatchKind::Exact,
+                ),
+                case(
+                    "What is the total revenue?",
+                    "12,500",
+                    EvaluationMatchKind::Numeric,
+                ),
+                case(
+                    "What is the average basket?",
+                    "40",
+                    EvaluationMatchKind::Numeric,
+                ),
+                case(
+                    "What is sold in Paris?",
+                    "bikes",
+                    EvaluationMatchKind::LlmJudge,
+                ),
+                case(
+                    "What is sold in Lyon?",
+                    "scooters",
+                    EvaluationMatchKind::LlmJudge,
+                ),
+                case("How many stores?", "3", EvaluationMatchKind::Exact),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let answers = [
+            ("Which city sells the most?", "  paris "),
+            ("Which city sells the least?", "Marseille"),
+            (
+                "What is the total revenue?",
+                "Total revenue is 12,550 dollars",
+            ),
+            ("What is the average basket?", "About 45"),
+            ("What is sold in Paris?", "Mostly Bikes and helmets"),
+            ("What is sold in Lyon?", "Cars"),
+        ];
+        let model = EvaluationModel::Mock(
+            answers
+                .iter()
+                .map(|(question, answer)| (question.to_string(), answer.to_string()))
+                .collect(),
+        );
+
+        let run = database::insert_evaluation_run(&db, agent_id, 1, model.name(), 7, None)
+            .await
+            .unwrap();
+        let run = execute_evaluation_run(&db, &run, &cases, &model)
+            .await
+            .unwrap();
+
+        assert_eq!(run.status, "completed");
+        assert_eq!(run.passed_cases, 3);
+        assert!((run.score.unwrap() - 3.0 / 7.0).abs() < 1e-9);
+
+        let results = database::get_evaluation_results(&db, run.id).await.unwrap();
+        let passed: Vec<bool> = cases
+            .iter()
+            .map(|case| {
+                results
+                    .iter()
+                    .find(|result| result.case_id == case.id)
+                    .unwrap()
+                    .passed
+            })
+            .collect();
+
+        // 12,550 is within the default 1% of 12,500, 45 is not within 1% of 40
+        assert_eq!(passed, [true, false, true, false, true, false, false]);
+
+        let judged = results
+            .iter()
+            .find(|result| result.case_id == cases[4].id)
+            .unwrap();
+        assert!(judged.judge_reasoning.is_some());
+
+        let unanswered = results
+            .iter()
+            .find(|result| result.case_id == cases[6].id)
+            .unwrap();
+        assert_eq!(unanswered.answer, None);
+        assert_eq!(unanswered.score, 0.0);
+        assert!(unanswered.error.is_some());
+    }
+
+    #[test]
+    fn numeric_match_reads_thousands_separators_and_tolerance() {
+        assert!(numeric_match("1,000", "It is 1005 units", 0.01));
+        assert!(!numeric_match("1,000", "It is 1020 units", 0.01));
+        assert!(numeric_match("-2.5", "between 3 and -2.5", 0.0));
+        assert!(!numeric_match("no number", "42", 0.5));
+        assert_eq!(extract_first_number("v. 2.75, then 2"), Some(2.75));
+    }
+
+    #[test]
+    fn exact_match_ignores_case_whitespace_and_trailing_punctuation() {
+        assert!(exact_match("New  York.", "new york"));
+        assert!(!exact_match("New York", "York"));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+
+use crate::{
+    config::{
+        GUARDRAIL_MAX_LEAKED_ROWS, GUARDRAIL_MIN_ROW_CELLS_MATCH, GUARDRAIL_REFUSAL_MESSAGE,
+        GUARDRAIL_STREAM_WINDOW_CHARS,
+    },
+    helpers::pii,
+    types::{GuardrailAction, GuardrailEventKind},
+};
+
+/// Result of checking an agent response before returning it to the user
+pub struct GuardrailOutcome {
+    /// Response to return to the user (redacted or replaced by a refusal)
+    pub response: String,
+    /// Events to record for the dataset owner
+    pub events: Vec<GuardrailFinding>,
+}
+
+pub struct GuardrailFinding {
+    pub kind: GuardrailEventKind,
+    pub action: GuardrailAction,
+    pub leaked_rows: i32,
+    pub pii_matches: i32,
+}
+
+/// Checks an agent response for verbatim dataset rows and PII.
+/// Responses reproducing more rows than allowed are refused, smaller leaks and PII are redacted.
+pub fn check_agent_response(response: &str, dataset: &str) -> GuardrailOutcome {
+    let mut events = Vec::new();
+
+    let leaked_cells = find_leaked_rows(response, dataset);
+    let leaked_rows = leaked_cells.len();
+
+    if leaked_rows > GUARDRAIL_MAX_LEAKED_ROWS {
+        events.push(GuardrailFinding {
+            kind: GuardrailEventKind::RowLeak,
+            action: GuardrailAction::Refused,
+            leaked_rows: leaked_rows as i32,
+            pii_matches: 0,
+        });
+
+        return GuardrailOutcome {
+            response: GUARDRAIL_REFUSAL_MESSAGE.to_string(),
+            events,
+        };
+    }
+
+    let mut redacted = response.to_string();
+
+    if leaked_rows > 0 {
+        for (_, cells) in &leaked_cells {
+            for cell in cells {
+                redacted = redacted.replace(cell.as_str(), "[redacted]");
+            }
+        }
+
+        events.push(GuardrailFinding {
+            kind: GuardrailEventKind::RowLeak,
+            action: GuardrailAction::Redacted,
+            leaked_rows: leaked_rows as i32,
+            pii_matches: 0,
+        });
+    }
+
+    let pii_matches = pii::detect_inline_pii(&redacted).len();
+
+    if pii_matches > 0 {
+        redacted = pii::mask_inline_pii(&redacted);
+
+        events.push(GuardrailFinding {
+            kind: GuardrailEventKind::PiiLeak,
+            action: GuardrailAction::Redacted,
+            leaked_rows: 0,
+            pii_matches: pii_matches as i32,
+        });
+    }
+
+    GuardrailOutcome {
+        response: redacted,
+        events,
+    }
+}
+
+/// Checks a streamed answer line by line, each line only with the end of the previous ones
+/// so the cost of a line doesn't grow with the answer.
+#[derive(Default)]
+pub struct StreamGuard {
+    /// Positions of the dataset rows reproduced so far
+    leaked_rows: HashSet<usize>,
+    /// Last `GUARDRAIL_STREAM_WINDOW_CHARS` released, for rows split across lines
+    window: String,
+}
+
+impl StreamGuard {
+    /// Returns the line to release, redacted, or None when the answer so far reproduces too many rows
+    pub fn check_line(&mut self, line: &str, dataset: &str) -> Option<String> {
+        self.window.push_str(line);
+
+        let leaked_cells = find_leaked_rows(&self.window, dataset);
+
+        self.leaked_rows
+            .extend(leaked_cells.iter().map(|(position, _)| *position));
+
+        if self.leaked_rows.len() > GUARDRAIL_MAX_LEAKED_ROWS {
+            return None;
+        }
+
+        // Cells of rows started in the previous lines are redacted too
+        let mut redacted = line.to_string();
+
+        for (_, cells) in &leaked_cells {
+            for cell in cells {
+                redacted = redacted.replace(cell.as_str(), "[redacted]");
+            }
+        }
+
+        let window_chars = self.window.chars().count();
+
+        if window_chars > GUARDRAIL_STREAM_WINDOW_CHARS {
+            self.window = self
+                .window
+                .chars()
+                .skip(window_chars - GUARDRAIL_STREAM_WINDOW_CHARS)
+                .collect();
+        }
+
+        Some(pii::mask_inline_pii(&redacted))
+    }
+}
+
+/// Returns the position and the distinctive cells of every dataset row reproduced in the response.
+/// A row counts as reproduced when enough of its distinctive cells appear verbatim in the response.
+fn find_leaked_rows(response: &str, dataset: &str) -> Vec<(usize, Vec<String>)> {
+    let mut reader = csv::Reader::from_reader(dataset.as_bytes());
+    let mut leaked = Vec::new();
+
+    for (position, record) in reader.records().enumerate() {
+        let Ok(record) = record else {
+            continue;
+        };
+
+        let distinctive_cells: Vec<&str> = record
+            .iter()
+            .map(str::trim)
+            .filter(|cell| is_distinctive_cell(cell))
+            .collect();
+
+        // Rows with a single distinctive value can't be told apart from a normal answer
+        if distinctive_cells.len() < 2 {
+            continue;
+        }
+
+        let matched_cells: Vec<String> = distinctive_cells
+            .iter()
+            .filter(|cell| response.contains(*cell))
+            .map(|cell| cell.to_string())
+            .collect();
+
+        let required = GUARDRAIL_MIN_ROW_CELLS_MATCH.min(distinctive_cells.len());
+
+        if matched_cells.len() >= required {
+            leaked.push((position, matched_cells));
+        }
+    }
+
+    leaked
+}
+
+/// Short values and small numbers show up in legitimate answers all the time
+fn is_distinctive_cell(cell: &str) -> bool {
+    if cell
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        cell.len() >= 5
+    } else {
+        cell.chars().count() >= 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATASET: &str = "name,email,city,amount\n\
+        Alice Martin,alice@example.com,Grenoble,12345\n\
+        Bruno Petit,bruno@example.com,Toulouse,23456\n\
+        Chloe Bernard,chloe@example.com,Bordeaux,34567\n\
+        David Moreau,david@example.com,Marseille,45678\n\
+        Emma Laurent,emma@example.com,Strasbourg,56789\n";
+
+    #[test]
+    fn passes_answers_without_leaks() {
+        let outcome = check_agent_response("Grenoble has the highest average amount.", DATASET);
+
+        assert_eq!(outcome.response, "Grenoble has the highest average amount.");
+        assert!(outcome.events.is_empty());
+    }
+
+    #[test]
+    fn redacts_a_few_leaked_rows_and_pii() {
+        let outcome = check_agent_response(
+            "Top buyer: Alice Martin, Grenoble, 12345. Reach bob@mail.org for details.",
+            DATASET,
+        );
+
+        assert!(!outcome.response.contains("Alice Martin"));
+        assert!(!outcome.response.contains("12345"));
+        assert!(outcome.response.contains("b***@mail.org"));
+
+        let events: Vec<_> = outcome
+            .events
+            .iter()
+            .map(|e| (e.kind, e.action, e.leaked_rows, e.pii_matches))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (GuardrailEventKind::RowLeak, GuardrailAction::Redacted, 1, 0),
+                (GuardrailEventKind::PiiLeak, GuardrailAction::Redacted, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn masks_spaced_card_and_phone_numbers() {
+        let outcome = check_agent_response(
+            "The card 4111 1111 1111 1111 belongs to the buyer on +33 6 12 34 56 78.",
+            DATASET,
+        );
+
+        assert_eq!(
+            outcome.response,
+            "The card **** **** **** 1111 belongs to the buyer on +** * ** ** 56 78."
+        );
+        assert_eq!(outcome.events.len(), 1);
+        assert_eq!(outcome.events[0].kind, GuardrailEventKind::PiiLeak);
+        assert_eq!(outcome.events[0].pii_matches, 2);
+
+        // Streamed lines are masked the same way
+        let mut guard = StreamGuard::default();
+        assert_eq!(
+            guard.check_line("Call +33 6 12 34 56 78\n", DATASET),
+            Some("Call +** * ** ** 56 78\n".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_answers_reproducing_too_many_rows() {
+        let outcome = check_agent_response(DATASET, DATASET);
+
+        assert_eq!(outcome.response, GUARDRAIL_REFUSAL_MESSAGE);
+        assert_eq!(outcome.events.len(), 1);
+        assert_eq!(outcome.events[0].action, GuardrailAction::Refused);
+        assert_eq!(outcome.events[0].leaked_rows, 5);
+    }
+
+    #[test]
+    fn ignores_rows_with_too_few_matching_cells() {
+        // Two cells of a row are not enough to count it as reproduced
+        let outcome = check_agent_response("Alice Martin spent 12345", DATASET);
+
+        assert!(outcome.events.is_empty());
+    }
+
+    #[test]
+    fn stream_guard_counts_rows_across_lines_and_refuses_past_the_limit() {
+        let mut guard = StreamGuard::default();
+
+        assert_eq!(
+            guard.check_line("Sales grew in every city.\n", DATASET),
+            Some("Sales grew in every city.\n".to_string())
+        );
+
+        // A row split across two lines is still found
+        let released = guard
+            .check_line("Alice Martin lives in\n", DATASET)
+            .unwrap();
+        assert_eq!(released, "Alice Martin lives in\n");
+        let released = guard
+            .check_line("Grenoble and spent 12345\n", DATASET)
+            .unwrap();
+        assert!(!released.contains("12345"));
+
+        for row in [
+            "Bruno Petit, Toulouse, 23456\n",
+            "Chloe Bernard, Bordeaux, 34567\n",
+        ] {
+            assert!(guard.check_line(row, DATASET).is_some());
+        }
+
+        assert_eq!(
+            guard.check_line("David Moreau, Marseille, 45678\n", DATASET),
+            None
+        );
+    }
+}
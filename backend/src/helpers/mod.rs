@@ -1,3 +1,20 @@
+pub mod agent_cache;
 pub mod agents;
+pub mod aggregate;
+pub mod auth;
+pub mod conversations;
+pub mod credits;
 pub mod csv;
-pub mod nft;
\ No newline at end of file
+pub mod embeddings;
+pub mod evaluation;
+pub mod guardrail;
+pub mod nft;
+pub mod pii;
+pub mod preview;
+pub mod pricing;
+pub mod privacy;
+pub mod queries;
+pub mod quote;
+pub mod refunds;
+pub mod router;
+pub mod synthesis;
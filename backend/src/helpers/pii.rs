@@ -0,0 +1,786 @@
+use std::{collections::HashMap, ops::Range};
+
+use alloy::{hex, primitives::keccak256};
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    config::{APP_CONFIG, DEFAULT_PII_POLICY, PII_COLUMN_MATCH_RATIO},
+    types::{PiiDetection, PiiFinding, PiiKind, PiiPolicy, PiiReport},
+};
+
+/// Characters stripped around a word before checking it for inline PII
+const INLINE_TRIM_CHARS: &[char] = &[',', ';', ':', '(', ')', '<', '>', '"', '\'', '[', ']'];
+
+/// Most words an inline card or phone number is matched across, like `+33 6 12 34 56 78`
+const INLINE_MAX_GROUPS: usize = 8;
+
+/// Policies applied to each kind of PII found in a dataset
+#[derive(Debug, Clone)]
+pub struct PiiPolicies {
+    default: PiiPolicy,
+    overrides: HashMap<PiiKind, PiiPolicy>,
+    hash_salt: Option<String>,
+}
+
+impl Default for PiiPolicies {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_PII_POLICY,
+            overrides: HashMap::new(),
+            hash_salt: None,
+        }
+    }
+}
+
+impl PiiPolicies {
+    /// Parses a policy definition like `mask` or `mask,email=hash,card_number=reject`.
+    /// A bare policy sets the default, `kind=policy` entries override it for one kind of PII.
+    pub fn parse(value: &str) -> Result<Self> {
+        Self::parse_with_salt(value, APP_CONFIG.pii_hash_salt.as_deref())
+    }
+
+    /// Parses a policy definition hashing values with `hash_salt`, the `hash` policy is refused without one
+    pub fn parse_with_salt(value: &str, hash_salt: Option<&str>) -> Result<Self> {
+        let mut policies = Self {
+            hash_salt: hash_salt.map(str::to_string),
+            ..Self::default()
+        };
+
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((kind, policy)) => {
+                    let kind = PiiKind::from_string(kind.trim())
+                        .ok_or_else(|| eyre!("Unknown PII kind: {}", kind))?;
+                    let policy = PiiPolicy::from_string(policy.trim())
+                        .ok_or_else(|| eyre!("Unknown PII policy: {}", policy))?;
+
+                    policies.overrides.insert(kind, policy);
+                }
+                None => {
+                    policies.default = PiiPolicy::from_string(entry)
+                        .ok_or_else(|| eyre!("Unknown PII policy: {}", entry))?;
+                }
+            }
+        }
+
+        if policies.uses(PiiPolicy::Hash) && policies.hash_salt.is_none() {
+            return Err(eyre!(
+                "The hash policy is not available, PII_HASH_SALT is not set"
+            ));
+        }
+
+        Ok(policies)
+    }
+
+    pub fn policy_for(&self, kind: PiiKind) -> PiiPolicy {
+        *self.overrides.get(&kind).unwrap_or(&self.default)
+    }
+
+    /// Whether `policy` applies to at least one kind of PII
+    pub fn uses(&self, policy: PiiPolicy) -> bool {
+        self.default == policy || self.overrides.values().any(|p| *p == policy)
+    }
+}
+
+/// Dataset after the PII policies have been applied
+pub struct SanitizedDataset {
+    pub data: Vec<u8>,
+    pub report: PiiReport,
+}
+
+/// Scans the csv for PII columns and inline PII values and applies the configured policies.
+/// When a `reject` policy is hit, the report is flagged as rejected and the data is returned untouched.
+pub fn scan_and_redact_csv(data: &[u8], policies: &PiiPolicies) -> Result<SanitizedDataset> {
+    let mut reader = csv::Reader::from_reader(data);
+
+    let headers = reader.headers()?.clone();
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        rows.push(record.iter().map(|v| v.to_string()).collect());
+    }
+
+    let mut redactor = Redactor::new(policies.hash_salt.clone());
+    let mut findings: Vec<PiiFinding> = Vec::new();
+
+    for (index, header) in headers.iter().enumerate() {
+        let values = rows.iter().filter_map(|row| row.get(index));
+
+        match detect_column_kind(header, values) {
+            Some(kind) => {
+                let action = policies.policy_for(kind);
+                let mut matches = 0;
+
+                for row in rows.iter_mut() {
+                    if let Some(value) = row.get_mut(index) {
+                        if value.trim().is_empty() {
+                            continue;
+                        }
+
+                        matches += 1;
+                        *value = redactor.apply(kind, action, value.trim());
+                    }
+                }
+
+                findings.push(PiiFinding {
+                    column: header.to_string(),
+                    kind,
+                    detection: PiiDetection::Column,
+                    matches,
+                    action,
+                });
+            }
+            None => {
+                // Look for PII values embedded in free text
+                let mut inline_matches: HashMap<PiiKind, usize> = HashMap::new();
+
+                for row in rows.iter_mut() {
+                    if let Some(value) = row.get_mut(index)
+                        && let Some(redacted) =
+                            redact_inline(value, policies, &mut redactor, &mut inline_matches)
+                    {
+                        *value = redacted;
+                    }
+                }
+
+                let mut inline_matches: Vec<_> = inline_matches.into_iter().collect();
+                inline_matches.sort_by_key(|(kind, _)| kind.to_string());
+
+                for (kind, matches) in inline_matches {
+                    findings.push(PiiFinding {
+                        column: header.to_string(),
+                        kind,
+                        detection: PiiDetection::Inline,
+                        matches,
+                        action: policies.policy_for(kind),
+                    });
+                }
+            }
+        }
+    }
+
+    let rejected = findings
+        .iter()
+        .any(|finding| finding.action == PiiPolicy::Reject);
+
+    let report = PiiReport {
+        scanned_rows: rows.len(),
+        rejected,
+        findings,
+    };
+
+    if rejected {
+        return Ok(SanitizedDataset {
+            data: data.to_vec(),
+            report,
+        });
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&headers)?;
+    for row in &rows {
+        writer.write_record(row)?;
+    }
+
+    let data = writer
+        .into_inner()
+        .map_err(|e| eyre!("Failed to write sanitized CSV: {}", e))?;
+
+    Ok(SanitizedDataset { data, report })
+}
+
+/// Returns the kinds of PII found in a free text, like an agent response
+pub fn detect_inline_pii(text: &str) -> Vec<(PiiKind, String)> {
+    find_inline_pii(text)
+        .into_iter()
+        .map(|(kind, range)| (kind, text[range].to_string()))
+        .collect()
+}
+
+/// Masks every inline PII value found in a free text
+pub fn mask_inline_pii(text: &str) -> String {
+    let mut redactor = Redactor::new(None);
+
+    replace_inline_pii(text, &find_inline_pii(text), |kind, value| {
+        redactor.apply(kind, PiiPolicy::Mask, value)
+    })
+}
+
+fn redact_inline(
+    value: &str,
+    policies: &PiiPolicies,
+    redactor: &mut Redactor,
+    inline_matches: &mut HashMap<PiiKind, usize>,
+) -> Option<String> {
+    let found = find_inline_pii(value);
+
+    if found.is_empty() {
+        return None;
+    }
+
+    for (kind, _) in &found {
+        *inline_matches.entry(*kind).or_insert(0) += 1;
+    }
+
+    Some(replace_inline_pii(value, &found, |kind, pii| {
+        redactor.apply(kind, policies.policy_for(kind), pii)
+    }))
+}
+
+/// Returns the kind and byte range of every inline PII value of a free text, in order.
+/// Card and phone numbers are matched across the spaces between their groups of digits,
+/// the longest match starting at a word wins.
+fn find_inline_pii(text: &str) -> Vec<(PiiKind, Range<usize>)> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut offset = 0;
+
+    for word in text.split_whitespace() {
+        let start = offset + text[offset..].find(word).unwrap_or(0);
+        offset = start + word.len();
+
+        let trimmed_start = word.len() - word.trim_start_matches(INLINE_TRIM_CHARS).len();
+        let trimmed = word.trim_matches(INLINE_TRIM_CHARS).trim_end_matches('.');
+
+        if !trimmed.is_empty() {
+            words.push(start + trimmed_start..start + trimmed_start + trimmed.len());
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut first = 0;
+
+    while first < words.len() {
+        // Groups of digits only join when nothing but whitespace or parentheses separates them
+        let mut last = first;
+        while last + 1 < words.len()
+            && last + 1 - first < INLINE_MAX_GROUPS
+            && is_digit_group(&text[words[last].clone()])
+            && is_digit_group(&text[words[last + 1].clone()])
+            && text[words[last].end..words[last + 1].start]
+                .chars()
+                .all(|c| c.is_whitespace() || "()".contains(c))
+        {
+            last += 1;
+        }
+
+        let longest = (first..=last).rev().find_map(|end| {
+            let range = words[first].start..words[end].end;
+            detect_value_kind(&text[range.clone()], true).map(|kind| (kind, range, end))
+        });
+
+        match longest {
+            Some((kind, range, end)) => {
+                found.push((kind, range));
+                first = end + 1;
+            }
+            None => first += 1,
+        }
+    }
+
+    found
+}
+
+/// Replaces the `found` ranges of `text` by what `replace` returns for them
+fn replace_inline_pii(
+    text: &str,
+    found: &[(PiiKind, Range<usize>)],
+    mut replace: impl FnMut(PiiKind, &str) -> String,
+) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut copied = 0;
+
+    for (kind, range) in found {
+        replaced.push_str(&text[copied..range.start]);
+        replaced.push_str(&replace(*kind, &text[range.clone()]));
+        copied = range.end;
+    }
+
+    replaced.push_str(&text[copied..]);
+
+    replaced
+}
+
+/// Part of a card or phone number, like `4111`, `+33` or `12-34`
+fn is_digit_group(word: &str) -> bool {
+    word.chars().any(|c| c.is_ascii_digit())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-.()".contains(c))
+}
+
+/// A column holds PII when its header says so or when most of its values look like PII
+fn detect_column_kind<'a>(
+    header: &str,
+    values: impl Iterator<Item = &'a String>,
+) -> Option<PiiKind> {
+    if let Some(kind) = detect_header_kind(header) {
+        return Some(kind);
+    }
+
+    let mut non_empty = 0usize;
+    let mut kind_counts: HashMap<PiiKind, usize> = HashMap::new();
+
+    for value in values {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        non_empty += 1;
+
+        if let Some(kind) = detect_value_kind(value, false) {
+            *kind_counts.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    if non_empty == 0 {
+        return None;
+    }
+
+    kind_counts
+        .into_iter()
+        .filter(|(_, count)| *count as f64 / non_empty as f64 >= PII_COLUMN_MATCH_RATIO)
+        .max_by_key(|(_, count)| *count)
+        .map(|(kind, _)| kind)
+}
+
+fn detect_header_kind(header: &str) -> Option<PiiKind> {
+    let normalized: String = header
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+
+    let person_prefixes = [
+        "customer", "client", "patient", "user", "employee", "contact", "owner", "member",
+        "person", "buyer", "seller", "student",
+    ];
+
+    if normalized.contains("email") {
+        Some(PiiKind::Email)
+    } else if normalized.contains("phone")
+        || normalized.contains("mobile")
+        || ["tel", "telephone", "cell", "cellnumber"].contains(&normalized.as_str())
+    {
+        Some(PiiKind::Phone)
+    } else if normalized.contains("creditcard")
+        || normalized.contains("cardnumber")
+        || normalized.contains("cardno")
+    {
+        Some(PiiKind::CardNumber)
+    } else if normalized.contains("wallet") {
+        Some(PiiKind::WalletAddress)
+    } else if ["ssn", "nationalid", "socialsecurity", "passport", "taxid"]
+        .iter()
+        .any(|hint| normalized.contains(hint))
+    {
+        Some(PiiKind::NationalId)
+    } else if [
+        "firstname",
+        "lastname",
+        "fullname",
+        "surname",
+        "givenname",
+        "familyname",
+        "middlename",
+    ]
+    .iter()
+    .any(|hint| normalized.ends_with(hint))
+        || (normalized.ends_with("name")
+            && person_prefixes
+                .iter()
+                .any(|prefix| normalized.starts_with(prefix)))
+    {
+        Some(PiiKind::Name)
+    } else {
+        None
+    }
+}
+
+/// Detects the kind of PII of a single value. `inline` is stricter on phone numbers
+/// because plain numbers are common in free text.
+fn detect_value_kind(value: &str, inline: bool) -> Option<PiiKind> {
+    if is_email(value) {
+        Some(PiiKind::Email)
+    } else if is_wallet_address(value) {
+        Some(PiiKind::WalletAddress)
+    } else if is_national_id(value) {
+        Some(PiiKind::NationalId)
+    } else if is_card_number(value) {
+        Some(PiiKind::CardNumber)
+    } else if is_phone(value, inline) {
+        Some(PiiKind::Phone)
+    } else {
+        None
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+
+    let valid_chars = |part: &str| {
+        part.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._%+-".contains(c))
+    };
+
+    let tld = domain.rsplit('.').next().unwrap_or("");
+
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && valid_chars(local)
+        && valid_chars(domain)
+        && tld.len() >= 2
+        && tld.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_wallet_address(value: &str) -> bool {
+    value.len() == 42
+        && (value.starts_with("0x") || value.starts_with("0X"))
+        && value[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Social security number format: 123-45-6789
+fn is_national_id(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+
+    parts.len() == 3
+        && [3, 2, 4]
+            .iter()
+            .zip(&parts)
+            .all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_card_number(value: &str) -> bool {
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_digit() || " -.".contains(c))
+    {
+        return false;
+    }
+
+    let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    (13..=19).contains(&digits.len()) && luhn_check(&digits)
+}
+
+fn luhn_check(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| {
+            if i % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                *digit
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+fn is_phone(value: &str, inline: bool) -> bool {
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_digit() || " +-().".contains(c))
+    {
+        return false;
+    }
+
+    let digit_count = value.chars().filter(|c| c.is_ascii_digit()).count();
+    if !(8..=15).contains(&digit_count) {
+        return false;
+    }
+
+    // Plain integers are ids or amounts far more often than phone numbers
+    if inline {
+        value.starts_with('+')
+    } else {
+        value.starts_with('+') || value.chars().any(|c| " -().".contains(c))
+    }
+}
+
+/// Applies a policy to PII values, keeping tokens stable across the whole dataset
+struct Redactor {
+    tokens: HashMap<(PiiKind, String), String>,
+    token_counters: HashMap<PiiKind, usize>,
+    hash_salt: Option<String>,
+}
+
+impl Redactor {
+    fn new(hash_salt: Option<String>) -> Self {
+        Self {
+            tokens: HashMap::new(),
+            token_counters: HashMap::new(),
+            hash_salt,
+        }
+    }
+
+    fn apply(&mut self, kind: PiiKind, policy: PiiPolicy, value: &str) -> String {
+        match policy {
+            // Rejected datasets are never stored, the value is kept for the report only
+            PiiPolicy::Reject => value.to_string(),
+            PiiPolicy::Mask => mask_value(kind, value),
+            PiiPolicy::Hash => match &self.hash_salt {
+                Some(salt) => hash_value(salt, value),
+                // Policies are parsed with a salt, unsalted hashes would be reversible
+                None => mask_value(kind, value),
+            },
+            PiiPolicy::Tokenise => self.tokenise(kind, value),
+        }
+    }
+
+    fn tokenise(&mut self, kind: PiiKind, value: &str) -> String {
+        if let Some(token) = self.tokens.get(&(kind, value.to_string())) {
+            return token.clone();
+        }
+
+        let counter = self.token_counters.entry(kind).or_insert(0);
+        *counter += 1;
+
+        let token = format!("{}_{}", kind.to_string().to_uppercase(), counter);
+        self.tokens.insert((kind, value.to_string()), token.clone());
+
+        token
+    }
+}
+
+fn hash_value(salt: &str, value: &str) -> String {
+    let salted = format!("{}:{}", salt, value);
+    let hash = hex::encode(keccak256(salted.as_bytes()));
+
+    hash[..16].to_string()
+}
+
+fn mask_value(kind: PiiKind, value: &str) -> String {
+    match kind {
+        PiiKind::Email => match value.split_once('@') {
+            Some((local, domain)) => {
+                format!("{}***@{}", local.chars().next().unwrap_or('*'), domain)
+            }
+            None => "***".to_string(),
+        },
+        PiiKind::Phone | PiiKind::CardNumber | PiiKind::NationalId => mask_digits(value, 4),
+        PiiKind::WalletAddress if is_wallet_address(value) => {
+            format!("{}...{}", &value[..6], &value[value.len() - 4..])
+        }
+        PiiKind::WalletAddress => "***".to_string(),
+        PiiKind::Name => value
+            .split_whitespace()
+            .map(|word| format!("{}***", word.chars().next().unwrap_or('*')))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Replaces every digit with `*` except the last `keep` ones
+fn mask_digits(value: &str, keep: usize) -> String {
+    let digit_count = value.chars().filter(|c| c.is_ascii_digit()).count();
+    let mut seen = 0;
+
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                seen += 1;
+                if seen + keep > digit_count { c } else { '*' }
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: &str = "test-salt";
+
+    const DATASET: &str = "customer_name,contact,card,comment\n\
+        Alice Martin,alice@example.com,4111 1111 1111 1111,Call me on +33612345678\n\
+        Bruno Petit,bruno@example.com,5555 5555 5555 4444,Nothing to add\n\
+        Alice Martin,alice@example.com,4111 1111 1111 1111,Order 123456789 shipped\n";
+
+    fn sanitize(policy: &str) -> (Vec<Vec<String>>, PiiReport) {
+        let policies = PiiPolicies::parse_with_salt(policy, Some(SALT)).unwrap();
+        let sanitized = scan_and_redact_csv(DATASET.as_bytes(), &policies).unwrap();
+
+        let mut reader = csv::Reader::from_reader(sanitized.data.as_slice());
+        let rows = reader
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect();
+
+        (rows, sanitized.report)
+    }
+
+    #[test]
+    fn detects_inline_pii_in_free_text() {
+        let found = detect_inline_pii(
+            "Reach alice@example.com or +33612345678, card 4111111111111111 (ref 123456789).",
+        );
+
+        assert_eq!(
+            found,
+            vec![
+                (PiiKind::Email, "alice@example.com".to_string()),
+                (PiiKind::Phone, "+33612345678".to_string()),
+                (PiiKind::CardNumber, "4111111111111111".to_string()),
+            ]
+        );
+
+        // Fails the Luhn check
+        assert!(detect_inline_pii("card 4111111111111112").is_empty());
+    }
+
+    #[test]
+    fn masks_inline_pii() {
+        let masked = mask_inline_pii("Mail alice@example.com or call +33612345678");
+
+        assert_eq!(masked, "Mail a***@example.com or call +*******5678");
+    }
+
+    #[test]
+    fn detects_card_and_phone_numbers_split_by_separators() {
+        let found = detect_inline_pii(
+            "Card 4111 1111 1111 1111, backup 5555-5555-5555-4444 or 4111.1111.1111.1111. \
+             Call +33 6 12 34 56 78 or +1 (555) 123-4567 before 2024.",
+        );
+
+        assert_eq!(
+            found,
+            vec![
+                (PiiKind::CardNumber, "4111 1111 1111 1111".to_string()),
+                (PiiKind::CardNumber, "5555-5555-5555-4444".to_string()),
+                (PiiKind::CardNumber, "4111.1111.1111.1111".to_string()),
+                (PiiKind::Phone, "+33 6 12 34 56 78".to_string()),
+                (PiiKind::Phone, "+1 (555) 123-4567".to_string()),
+            ]
+        );
+
+        // Numbers separated by more than spaces stay apart
+        assert!(detect_inline_pii("Scores: 4111, 1111, 1111, 1111").is_empty());
+    }
+
+    #[test]
+    fn masks_spaced_numbers_by_their_position() {
+        let masked = mask_inline_pii(
+            "Pay with 4111 1111 1111 1111 (ref ID4111111111111111) or call +33 6 12 34 56 78.",
+        );
+
+        assert_eq!(
+            masked,
+            "Pay with **** **** **** 1111 (ref ID4111111111111111) or call +** * ** ** 56 78."
+        );
+
+        // Each occurrence is masked in place, the text around it is untouched
+        let masked = mask_inline_pii("4111111111111111 then 4111111111111111x");
+        assert_eq!(masked, "************1111 then 4111111111111111x");
+    }
+
+    #[test]
+    fn redacts_spaced_numbers_in_free_text_columns() {
+        let dataset = "id,comment\n1,Card 4111 1111 1111 1111 and phone +33 6 12 34 56 78\n";
+        let policies = PiiPolicies::parse_with_salt("mask,phone=tokenise", None).unwrap();
+        let sanitized = scan_and_redact_csv(dataset.as_bytes(), &policies).unwrap();
+
+        let mut reader = csv::Reader::from_reader(sanitized.data.as_slice());
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[1], "Card **** **** **** 1111 and phone PHONE_1");
+
+        let kinds: Vec<_> = sanitized
+            .report
+            .findings
+            .iter()
+            .map(|f| (f.kind, f.detection, f.matches))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (PiiKind::CardNumber, PiiDetection::Inline, 1),
+                (PiiKind::Phone, PiiDetection::Inline, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn masks_pii_columns_and_inline_values() {
+        let (rows, report) = sanitize("mask");
+
+        assert!(!report.rejected);
+        assert_eq!(report.scanned_rows, 3);
+        assert_eq!(
+            rows[0],
+            vec![
+                "A*** M***",
+                "a***@example.com",
+                "**** **** **** 1111",
+                "Call me on +*******5678"
+            ]
+        );
+        assert_eq!(rows[2][3], "Order 123456789 shipped");
+
+        let columns: Vec<_> = report
+            .findings
+            .iter()
+            .map(|f| (f.column.as_str(), f.kind, f.detection, f.matches))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("customer_name", PiiKind::Name, PiiDetection::Column, 3),
+                ("contact", PiiKind::Email, PiiDetection::Column, 3),
+                ("card", PiiKind::CardNumber, PiiDetection::Column, 3),
+                ("comment", PiiKind::Phone, PiiDetection::Inline, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn hashes_with_the_salt_and_tokenises_stably() {
+        let (rows, _) = sanitize("mask,email=hash,name=tokenise");
+
+        assert_eq!(rows[0][1], hash_value(SALT, "alice@example.com"));
+        assert_eq!(rows[0][1], rows[2][1]);
+        assert_ne!(rows[0][1], rows[1][1]);
+        assert_ne!(rows[0][1], hash_value("other-salt", "alice@example.com"));
+
+        assert_eq!(rows[0][0], "NAME_1");
+        assert_eq!(rows[1][0], "NAME_2");
+        assert_eq!(rows[2][0], "NAME_1");
+    }
+
+    #[test]
+    fn refuses_the_hash_policy_without_a_salt() {
+        assert!(PiiPolicies::parse_with_salt("hash", None).is_err());
+        assert!(PiiPolicies::parse_with_salt("mask,email=hash", None).is_err());
+        assert!(PiiPolicies::parse_with_salt("mask,email=tokenise", None).is_ok());
+        assert!(PiiPolicies::parse_with_salt("mask,email=shred", Some(SALT)).is_err());
+    }
+
+    #[test]
+    fn reports_the_policies_in_use() {
+        let policies = PiiPolicies::parse_with_salt("mask,name=tokenise", None).unwrap();
+
+        assert!(policies.uses(PiiPolicy::Mask));
+        assert!(policies.uses(PiiPolicy::Tokenise));
+        assert!(!policies.uses(PiiPolicy::Reject));
+        assert!(!PiiPolicies::default().uses(PiiPolicy::Tokenise));
+    }
+
+    #[test]
+    fn rejects_the_dataset_untouched() {
+        let policies = PiiPolicies::parse_with_salt("mask,card_number=reject", None).unwrap();
+        let sanitized = scan_and_redact_csv(DATASET.as_bytes(), &policies).unwrap();
+
+        assert!(sanitized.report.rejected);
+        assert_eq!(sanitized.data, DATASET.as_bytes());
+    }
+}
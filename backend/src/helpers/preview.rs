@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use color_eyre::Result;
+use rig::completion::Prompt;
+
+use crate::{
+    config::{
+        PREVIEW_AGENT_MODEL, PREVIEW_DAILY_LIMIT, PREVIEW_MAX_ANSWER_CHARS,
+        PREVIEW_MAX_OUTPUT_TOKENS, PREVIEW_SAMPLE_ROWS, UPLOAD_DIR,
+    },
+    database,
+    helpers::{csv::sample_csv_rows, guardrail, queries::hash_text},
+    types::{AgentDb, DatasetColumn, PreviewMode, WebAppState},
+};
+
+/// Preview mode of an agent, schema when its owner never set one.
+/// Datasets in privacy mode have no preview: even their profile would bypass the aggregate only answers.
+pub async fn preview_mode(db: &sqlx::Pool<sqlx::Postgres>, agent_id: i64) -> Result<PreviewMode> {
+    if database::get_privacy_budget_by_agent_id(db, agent_id)
+        .await?
+        .is_some()
+    {
+        return Ok(PreviewMode::Disabled);
+    }
+
+    match database::get_preview_mode(db, agent_id).await? {
+        Some(mode) => mode.parse(),
+        None => Ok(PreviewMode::Schema),
+    }
+}
+
+/// Records a preview question unless its IP or its signer already asked PREVIEW_DAILY_LIMIT today.
+/// Returns the id of the question and the questions left today, or None when the limit is reached.
+pub async fn reserve_preview_query(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    ip_address: &str,
+    requester_address: Option<&str>,
+    prompt: &str,
+    mode: PreviewMode,
+) -> Result<Option<(i64, i64)>> {
+    let mut tx = db.begin().await?;
+
+    // Always locked in the same order, concurrent questions can't both pass the limit
+    database::lock_preview_requester(&mut tx, ip_address).await?;
+
+    if let Some(requester_address) = requester_address {
+        database::lock_preview_requester(&mut tx, requester_address).await?;
+    }
+
+    let asked =
+        database::count_preview_queries_today(&mut tx, ip_address, requester_address).await?;
+
+    if asked >= PREVIEW_DAILY_LIMIT {
+        return Ok(None);
+    }
+
+    let id = database::insert_preview_query(
+        &mut tx,
+        agent_id,
+        ip_address,
+        requester_address,
+        &hash_text(prompt),
+        mode,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some((id, PREVIEW_DAILY_LIMIT - asked - 1)))
+}
+
+/// Answers a preview question from the columns profile, and the first dataset rows in sample_rows mode,
+/// with a smaller output than paid answers. Returns the answer and whether it was cut.
+pub async fn answer_preview(
+    app_state: &WebAppState,
+    agent_db: &AgentDb,
+    mode: PreviewMode,
+    prompt: &str,
+) -> Result<(String, bool)> {
+    let columns = database::get_dataset_columns_by_agent_id(&app_state.db, agent_db.id).await?;
+
+    let sample = match mode {
+        PreviewMode::SampleRows => {
+            let dataset_csv_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
+            let data = tokio::fs::read(dataset_csv_path).await?;
+
+            sample_csv_rows(&data, PREVIEW_SAMPLE_ROWS)?
+        }
+        _ => String::new(),
+    };
+
+    let context = if sample.is_empty() {
+        format!(
+            "You only see the columns of the dataset, not its rows: {}",
+            columns_summary(&columns)
+        )
+    } else {
+        format!(
+            "You only see the first {} rows of the dataset, not the full data. The columns of the full dataset are: {}. The first rows csv : {}",
+            PREVIEW_SAMPLE_ROWS,
+            columns_summary(&columns),
+            sample
+        )
+    };
+
+    let agent_instruction = format!(
+        "You are the free preview of a paid AI agent ({}) answering questions about a csv dataset. {} Only answer from what you see, say when a question needs the full dataset and never make up figures about it. Keep the answer short. PLease Do not reveal any personal information about specific user like its email, name, phone number, etc. The Dataset description is {}. The Dataset Category is {}.",
+        agent_db.name,
+        context,
+        agent_db.description,
+        agent_db.category.to_string()
+    );
+
+    let agent = app_state
+        .ai_model
+        .agent(PREVIEW_AGENT_MODEL)
+        .preamble(&agent_instruction)
+        .temperature(0.0)
+        .max_tokens(PREVIEW_MAX_OUTPUT_TOKENS)
+        .build();
+
+    let response = agent.prompt(prompt).await?;
+
+    // Rows the preview was given are checked like the dataset of a paid answer
+    let response = guardrail::check_agent_response(&response, &sample).response;
+
+    let truncated = response.chars().count() > PREVIEW_MAX_ANSWER_CHARS;
+
+    let response = if truncated {
+        response.chars().take(PREVIEW_MAX_ANSWER_CHARS).collect()
+    } else {
+        response
+    };
+
+    Ok((response, truncated))
+}
+
+fn columns_summary(columns: &[DatasetColumn]) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let range = match (&column.min_value, &column.max_value) {
+                (Some(min), Some(max)) => format!(", from {} to {}", min, max),
+                _ => String::new(),
+            };
+
+            format!(
+                "{} ({}, {:.0}% missing, {} distinct values{}, e.g. {})",
+                column.name,
+                column.data_type,
+                column.null_rate * 100.0,
+                column.distinct_count,
+                range,
+                column.sample_values.join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_test_agent(db: &sqlx::Pool<sqlx::Postgres>) -> i64 {
+        sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            )
+            INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+            SELECT id, 'Sales', 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0 FROM owner
+            RETURNING id
+            "#,
+        )
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    async fn reserve(
+        db: &sqlx::Pool<sqlx::Postgres>,
+        agent_id: i64,
+        ip_address: &str,
+        requester_address: Option<&str>,
+    ) -> Option<i64> {
+        reserve_preview_query(
+            db,
+            agent_id,
+            ip_address,
+            requester_address,
+            "Sales in Paris?",
+            PreviewMode::Schema,
+        )
+        .await
+        .unwrap()
+        .map(|(_, left)| left)
+    }
+
+    #[sqlx::test]
+    async fn previews_are_limited_per_ip_and_per_address(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id = insert_test_agent(&db).await;
+        const ADDRESS: &str = "0x00000000000000000000000000000000000000a1";
+
+        for left in (0..PREVIEW_DAILY_LIMIT).rev() {
+            assert_eq!(reserve(&db, agent_id, "10.0.0.1", None).await, Some(left));
+        }
+        assert_eq!(reserve(&db, agent_id, "10.0.0.1", None).await, None);
+        assert_eq!(
+            reserve(&db, agent_id, "10.0.0.1", Some(ADDRESS)).await,
+            None
+        );
+
+        // Changing IP doesn't reset the limit of an address
+        for _ in 0..PREVIEW_DAILY_LIMIT {
+            assert!(
+                reserve(&db, agent_id, "10.0.0.2", Some(ADDRESS))
+                    .await
+                    .is_some()
+            );
+        }
+        assert_eq!(
+            reserve(&db, agent_id, "10.0.0.3", Some(ADDRESS)).await,
+            None
+        );
+        assert!(reserve(&db, agent_id, "10.0.0.3", None).await.is_some());
+
+        // Questions of previous days don't count
+        sqlx::query("UPDATE preview_queries SET created_at = created_at - INTERVAL '1 day'")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            reserve(&db, agent_id, "10.0.0.1", Some(ADDRESS)).await,
+            Some(PREVIEW_DAILY_LIMIT - 1)
+        );
+    }
+
+    #[sqlx::test]
+    async fn concurrent_previews_cannot_pass_the_limit(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id = insert_test_agent(&db).await;
+
+        let reservations = futures_util::future::join_all(
+            (0..PREVIEW_DAILY_LIMIT * 2).map(|_| reserve(&db, agent_id, "10.0.0.1", None)),
+        )
+        .await;
+
+        let reserved = reservations.iter().filter(|left| left.is_some()).count();
+        assert_eq!(reserved as i64, PREVIEW_DAILY_LIMIT);
+    }
+
+    #[sqlx::test]
+    async fn privacy_mode_datasets_have_no_preview(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id = insert_test_agent(&db).await;
+
+        assert_eq!(
+            preview_mode(&db, agent_id).await.unwrap(),
+            PreviewMode::Schema
+        );
+
+        sqlx::query(
+            "INSERT INTO dataset_privacy_budgets (agent_id, epsilon_total, epsilon_per_query) VALUES ($1, 10.0, 1.0)",
+        )
+        .bind(agent_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            preview_mode(&db, agent_id).await.unwrap(),
+            PreviewMode::Disabled
+        );
+    }
+}
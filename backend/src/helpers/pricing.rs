@@ -0,0 +1,321 @@
+use std::{collections::HashMap, str::FromStr};
+
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+
+use crate::{
+    config::VOLUME_TIER_WINDOW_DAYS,
+    database,
+    types::{
+        AgentCharge, AgentDb, ChargeBasis, Price, PriceTier, PriceTierDb, PricingModel,
+        PricingPlan, PricingPlanDb,
+    },
+};
+
+/// Plan of an agent as exposed by the API, agents without a stored plan are priced per query
+pub fn pricing_plan(
+    agent: &AgentDb,
+    plan: Option<&PricingPlanDb>,
+    tiers: &[PriceTierDb],
+) -> PricingPlan {
+    let Some(plan) = plan else {
+        return PricingPlan {
+            model: PricingModel::PerQuery,
+            per_query_price: agent.price,
+            subscription_price: None,
+            subscription_period_days: None,
+            free_sample_queries: 0,
+            tiers: Vec::new(),
+        };
+    };
+
+    PricingPlan {
+        model: PricingModel::from_str(&plan.model).unwrap_or(PricingModel::PerQuery),
+        per_query_price: agent.price,
+        subscription_price: plan.subscription_price,
+        subscription_period_days: plan.subscription_period_days,
+        free_sample_queries: plan.free_sample_queries,
+        tiers: tiers
+            .iter()
+            .filter(|tier| tier.agent_id == agent.id)
+            .map(|tier| PriceTier {
+                min_queries: tier.min_queries,
+                price: tier.price,
+            })
+            .collect(),
+    }
+}
+
+/// Pricing plans of the agents, in the same order
+pub async fn pricing_plans(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agents: &[AgentDb],
+) -> Result<Vec<PricingPlan>> {
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+
+    let plans: HashMap<i64, PricingPlanDb> =
+        database::get_pricing_plans_by_agent_ids(db, &agent_ids)
+            .await?
+            .into_iter()
+            .map(|plan| (plan.agent_id, plan))
+            .collect();
+    let tiers = database::get_price_tiers_by_agent_ids(db, &agent_ids).await?;
+
+    Ok(agents
+        .iter()
+        .map(|agent| pricing_plan(agent, plans.get(&agent.id), &tiers))
+        .collect())
+}
+
+/// Charges of the next query of `payer` to each agent, in the same order.
+/// Without a payer, the list price is charged: no free samples, subscription or volume discount.
+/// The usage is read outside any lock, the charges are only an estimate until claimed with [`claim_agent_charges`].
+pub async fn agent_charges(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payer: Option<&str>,
+    agents: &[AgentDb],
+) -> Result<Vec<AgentCharge>> {
+    let plans = pricing_plans(db, agents).await?;
+
+    let Some(payer) = payer else {
+        return Ok(agents
+            .iter()
+            .map(|agent| AgentCharge {
+                agent_id: agent.id,
+                amount: agent.price,
+                basis: ChargeBasis::PerQuery,
+            })
+            .collect());
+    };
+
+    let mut conn = db.acquire().await?;
+
+    payer_charges(&mut conn, payer, agents, &plans).await
+}
+
+/// Charges of the next query of `payer` to each agent, in the same order, counted in `tx` after locking the usage of the payer.
+/// The usage must be recorded in `tx`, so concurrent payments can't each be granted the last free sample.
+pub async fn claim_agent_charges(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    payer: &str,
+    agents: &[AgentDb],
+) -> Result<Vec<AgentCharge>> {
+    let plans = pricing_plans(db, agents).await?;
+
+    database::lock_agent_usage(tx, payer).await?;
+
+    payer_charges(tx, payer, agents, &plans).await
+}
+
+async fn payer_charges(
+    conn: &mut sqlx::PgConnection,
+    payer: &str,
+    agents: &[AgentDb],
+    plans: &[PricingPlan],
+) -> Result<Vec<AgentCharge>> {
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+    let window_start = Utc::now() - Duration::days(VOLUME_TIER_WINDOW_DAYS);
+
+    let usage: HashMap<i64, (i64, i64)> =
+        database::get_agent_usage_counts(&mut *conn, payer, &agent_ids, window_start)
+            .await?
+            .into_iter()
+            .map(|(agent_id, total, recent)| (agent_id, (total, recent)))
+            .collect();
+
+    let subscribed: Vec<i64> = database::get_active_subscriptions(&mut *conn, payer, &agent_ids)
+        .await?
+        .into_iter()
+        .map(|subscription| subscription.agent_id)
+        .collect();
+
+    Ok(agents
+        .iter()
+        .zip(plans)
+        .map(|(agent, plan)| {
+            charge_for(
+                agent.id,
+                plan,
+                usage.get(&agent.id).copied().unwrap_or((0, 0)),
+                subscribed.contains(&agent.id),
+            )
+        })
+        .collect())
+}
+
+/// Charge of the next query to an agent given the user's queries to it (in total and in the tier window)
+/// and whether they hold an active subscription
+pub fn charge_for(
+    agent_id: i64,
+    plan: &PricingPlan,
+    (total_queries, recent_queries): (i64, i64),
+    subscribed: bool,
+) -> AgentCharge {
+    let charge = |amount, basis| AgentCharge {
+        agent_id,
+        amount,
+        basis,
+    };
+
+    if total_queries < plan.free_sample_queries as i64 {
+        return charge(Price::ZERO, ChargeBasis::FreeSample);
+    }
+
+    match plan.model {
+        PricingModel::Subscription if subscribed => charge(Price::ZERO, ChargeBasis::Subscription),
+        PricingModel::VolumeTiers => {
+            let price = plan
+                .tiers
+                .iter()
+                .filter(|tier| tier.min_queries as i64 <= recent_queries)
+                .max_by_key(|tier| tier.min_queries)
+                .map(|tier| tier.price)
+                .unwrap_or(plan.per_query_price);
+
+            charge(price, ChargeBasis::VolumeTier)
+        }
+        _ => charge(plan.per_query_price, ChargeBasis::PerQuery),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use super::*;
+
+    const PAYER: &str = "0x00000000000000000000000000000000000000a1";
+
+    fn plan(model: PricingModel, free_sample_queries: i32) -> PricingPlan {
+        PricingPlan {
+            model,
+            per_query_price: Price::from_units(100),
+            subscription_price: Some(Price::from_units(1000)),
+            subscription_period_days: Some(30),
+            free_sample_queries,
+            tiers: vec![
+                PriceTier {
+                    min_queries: 10,
+                    price: Price::from_units(80),
+                },
+                PriceTier {
+                    min_queries: 50,
+                    price: Price::from_units(50),
+                },
+            ],
+        }
+    }
+
+    fn charged(plan: &PricingPlan, usage: (i64, i64), subscribed: bool) -> (i64, ChargeBasis) {
+        let charge = charge_for(1, plan, usage, subscribed);
+        (charge.amount.units(), charge.basis)
+    }
+
+    #[test]
+    fn free_samples_are_used_before_any_model() {
+        let plan = plan(PricingModel::Subscription, 2);
+
+        assert_eq!(charged(&plan, (0, 0), true), (0, ChargeBasis::FreeSample));
+        assert_eq!(charged(&plan, (1, 1), false), (0, ChargeBasis::FreeSample));
+        // Exhausted once the user asked as many queries as there are samples
+        assert_eq!(charged(&plan, (2, 2), false), (100, ChargeBasis::PerQuery));
+    }
+
+    #[test]
+    fn subscriptions_are_only_free_while_active() {
+        let plan = plan(PricingModel::Subscription, 0);
+
+        assert_eq!(charged(&plan, (5, 5), true), (0, ChargeBasis::Subscription));
+        assert_eq!(charged(&plan, (5, 5), false), (100, ChargeBasis::PerQuery));
+
+        // A subscription doesn't apply to an agent priced per query
+        let per_query = self::plan(PricingModel::PerQuery, 0);
+        assert_eq!(
+            charged(&per_query, (5, 5), true),
+            (100, ChargeBasis::PerQuery)
+        );
+    }
+
+    #[test]
+    fn volume_tiers_apply_from_their_min_queries() {
+        let plan = plan(PricingModel::VolumeTiers, 0);
+
+        // Below the first tier the per query price applies
+        assert_eq!(
+            charged(&plan, (100, 9), false),
+            (100, ChargeBasis::VolumeTier)
+        );
+        assert_eq!(
+            charged(&plan, (100, 10), false),
+            (80, ChargeBasis::VolumeTier)
+        );
+        assert_eq!(
+            charged(&plan, (100, 49), false),
+            (80, ChargeBasis::VolumeTier)
+        );
+        assert_eq!(
+            charged(&plan, (100, 50), false),
+            (50, ChargeBasis::VolumeTier)
+        );
+
+        // Only the queries in the tier window count
+        assert_eq!(
+            charged(&plan, (100, 0), false),
+            (100, ChargeBasis::VolumeTier)
+        );
+    }
+
+    #[sqlx::test]
+    async fn concurrent_payments_get_the_last_free_sample_once(db: sqlx::Pool<sqlx::Postgres>) {
+        let agent_id: i64 = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (address) VALUES ('0x0000000000000000000000000000000000000001')
+                RETURNING id
+            ), agent AS (
+                INSERT INTO agents (owner_id, name, description, price, dataset_path, category, dataset_size)
+                SELECT id, 'Sales', 'Sales per city', 100, 'sales.csv', 'Analytics', 1.0 FROM owner
+                RETURNING id
+            )
+            INSERT INTO agent_pricing_plans (agent_id, free_sample_queries)
+            SELECT id, 1 FROM agent
+            RETURNING agent_id
+            "#,
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let agents = database::get_agents_by_ids(&db, &[agent_id]).await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        let charges = claim_agent_charges(&db, &mut tx, PAYER, &agents)
+            .await
+            .unwrap();
+        assert_eq!(charges[0].basis, ChargeBasis::FreeSample);
+
+        let concurrent = tokio::spawn({
+            let db = db.clone();
+            let agents = agents.clone();
+            async move {
+                let mut tx = db.begin().await.unwrap();
+                claim_agent_charges(&db, &mut tx, PAYER, &agents)
+                    .await
+                    .unwrap()
+            }
+        });
+
+        // The second payment waits for the first one to record its usage
+        tokio::time::sleep(StdDuration::from_millis(200)).await;
+        assert!(!concurrent.is_finished());
+
+        database::insert_agent_usages(&mut tx, PAYER, "0xfirst", &charges)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let charges = concurrent.await.unwrap();
+        assert_eq!(charges[0].basis, ChargeBasis::PerQuery);
+        assert_eq!(charges[0].amount, Price::from_units(100));
+    }
+}
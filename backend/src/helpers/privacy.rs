@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::eyre};
+use rand::Rng;
+
+use crate::{
+    helpers::aggregate::AggregateResult,
+    types::{AggregateFunction, DatasetColumn},
+};
+
+/// Parses the clamp bounds declared by the owner, like `amount=0:1000,age=0:120`.
+/// Bounds can't be derived from the data itself without leaking it, so sums and means are only allowed on declared columns.
+pub fn parse_column_bounds(value: &str) -> Result<HashMap<String, (f64, f64)>> {
+    let mut bounds = HashMap::new();
+
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (column, range) = entry
+            .split_once('=')
+            .ok_or_else(|| eyre!("Expected column=lower:upper, got {}", entry))?;
+        let (lower, upper) = range
+            .split_once(':')
+            .ok_or_else(|| eyre!("Expected lower:upper, got {}", range))?;
+
+        let lower = lower.trim().parse::<f64>()?;
+        let upper = upper.trim().parse::<f64>()?;
+
+        if !lower.is_finite() || !upper.is_finite() || lower >= upper {
+            return Err(eyre!("Invalid bounds for column {}", column.trim()));
+        }
+
+        bounds.insert(column.trim().to_string(), (lower, upper));
+    }
+
+    Ok(bounds)
+}
+
+/// Drops the values held by the columns profile of a dataset in privacy mode and keeps its schema.
+/// Exact bounds and sample values would answer questions outside of the epsilon budget.
+pub fn redact_column_profiles(columns: &mut [DatasetColumn]) {
+    for column in columns {
+        column.min_value = None;
+        column.max_value = None;
+        column.sample_values.clear();
+    }
+}
+
+/// Samples noise from a Laplace distribution centered on 0 with the given scale
+pub fn laplace_noise(scale: f64) -> f64 {
+    // Inverse CDF sampling, u in [-0.5, 0.5)
+    let u: f64 = rand::thread_rng().gen_range(-0.5..0.5);
+
+    // u = -0.5 would give ln(0)
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).max(f64::MIN_POSITIVE).ln()
+}
+
+/// Applies the Laplace mechanism to an aggregate result so the query is `epsilon`-differentially private.
+/// Sums are computed on values clamped to `bounds`, which sets their sensitivity.
+/// Means spend half of the budget on the sum and half on the count.
+pub fn privatize_aggregate(
+    result: &AggregateResult,
+    function: AggregateFunction,
+    epsilon: f64,
+    bounds: Option<(f64, f64)>,
+) -> f64 {
+    let sum_sensitivity = bounds
+        .map(|(lower, upper)| lower.abs().max(upper.abs()))
+        .unwrap_or(1.0);
+
+    match function {
+        AggregateFunction::Count => (result.count as f64 + laplace_noise(1.0 / epsilon)).max(0.0),
+        AggregateFunction::Sum => result.sum + laplace_noise(sum_sensitivity / epsilon),
+        AggregateFunction::Mean => {
+            let noisy_sum = result.sum + laplace_noise(sum_sensitivity / (epsilon / 2.0));
+            let noisy_count = (result.count as f64 + laplace_noise(1.0 / (epsilon / 2.0))).max(1.0);
+
+            let mean = noisy_sum / noisy_count;
+
+            match bounds {
+                Some((lower, upper)) => mean.clamp(lower, upper),
+                None => mean,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 20_000;
+
+    #[test]
+    fn parses_column_bounds() {
+        let bounds = parse_column_bounds(" amount=0:1000, age = -1.5:120 ,").unwrap();
+
+        assert_eq!(bounds.len(), 2);
+        assert_eq!(bounds["amount"], (0.0, 1000.0));
+        assert_eq!(bounds["age"], (-1.5, 120.0));
+
+        for invalid in [
+            "amount",
+            "amount=1000",
+            "amount=a:b",
+            "amount=5:5",
+            "amount=10:0",
+        ] {
+            assert!(parse_column_bounds(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn laplace_noise_is_centered_with_the_given_scale() {
+        let scale = 2.0;
+        let samples: Vec<f64> = (0..SAMPLES).map(|_| laplace_noise(scale)).collect();
+
+        assert!(samples.iter().all(|x| x.is_finite()));
+
+        // The mean of a Laplace(0, b) is 0 and the mean absolute deviation is b
+        let mean = samples.iter().sum::<f64>() / SAMPLES as f64;
+        let mean_abs = samples.iter().map(|x| x.abs()).sum::<f64>() / SAMPLES as f64;
+
+        assert!(mean.abs() < 0.1, "mean {}", mean);
+        assert!((mean_abs - scale).abs() < 0.1, "mean |x| {}", mean_abs);
+    }
+
+    #[test]
+    fn privatized_counts_and_means_stay_in_range() {
+        let result = AggregateResult { count: 0, sum: 0.0 };
+        for _ in 0..1000 {
+            assert!(privatize_aggregate(&result, AggregateFunction::Count, 0.1, None) >= 0.0);
+        }
+
+        let result = AggregateResult {
+            count: 3,
+            sum: 150.0,
+        };
+        for _ in 0..1000 {
+            let mean =
+                privatize_aggregate(&result, AggregateFunction::Mean, 0.1, Some((0.0, 100.0)));
+            assert!((0.0..=100.0).contains(&mean));
+        }
+    }
+}
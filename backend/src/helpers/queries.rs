@@ -0,0 +1,38 @@
+use alloy::{hex, primitives::keccak256};
+use color_eyre::Result;
+
+use crate::{
+    database,
+    types::{AgentResponseStatus, NewQuery, QueryOutcome},
+};
+
+/// Keccak-256 of a prompt or answer, as logged in place of the text
+pub fn hash_text(text: &str) -> String {
+    hex::encode(keccak256(text.as_bytes()))
+}
+
+/// Outcome of a question given the status of each of its agents
+pub fn query_outcome(statuses: &[AgentResponseStatus]) -> QueryOutcome {
+    let answered = statuses
+        .iter()
+        .filter(|status| **status == AgentResponseStatus::Success)
+        .count();
+
+    match answered {
+        0 => QueryOutcome::Failed,
+        answered if answered == statuses.len() => QueryOutcome::Success,
+        _ => QueryOutcome::Partial,
+    }
+}
+
+/// Adds a question to the queries audit log with the outcome of each of its agents
+pub async fn log_query(db: &sqlx::Pool<sqlx::Postgres>, query: &NewQuery) -> Result<i64> {
+    let mut tx = db.begin().await?;
+
+    let query_id = database::insert_query(&mut tx, query).await?;
+    database::insert_query_agents(&mut tx, query_id, &query.agents).await?;
+
+    tx.commit().await?;
+
+    Ok(query_id)
+}
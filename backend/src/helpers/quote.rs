@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use alloy::{
+    hex,
+    primitives::{Address, Signature, U256},
+    signers::{SignerSync, local::PrivateKeySigner},
+    sol,
+    sol_types::SolCall,
+};
+use chrono::Utc;
+use color_eyre::{Result, eyre::eyre};
+use uuid::Uuid;
+
+use crate::{
+    config::{
+        ENCLAVA_CONTRACT_ADDRESS, HEDERA_TESTNET_CHAIN_ID, PAYMENT_QUOTE_TTL_SECS,
+        PAYMENT_TOKEN_DECIMALS,
+    },
+    types::{AgentCharge, AgentDb, PaymentQuote, PaymentTxRequest, Price},
+};
+
+sol! {
+    function payForMultipleDatasets(uint256[] tokenIds, uint256[] amounts);
+}
+
+// The relay expects the value in 18 decimals while amounts are in the 8 decimals of the contract
+const TX_VALUE_DECIMALS: u8 = 18;
+
+/// Builds and signs a quote for paying the `charges` of `agents` from `payer`, valid for `PAYMENT_QUOTE_TTL_SECS`.
+/// Every agent must be minted, its nft id is the token id paid for. Agents charged nothing are not paid for.
+pub fn build_quote(
+    signer: &PrivateKeySigner,
+    payer: Address,
+    agents: &[AgentDb],
+    charges: &[AgentCharge],
+) -> Result<PaymentQuote> {
+    let mut token_ids = Vec::new();
+    let mut amounts = Vec::new();
+    let mut total_cost = Price::ZERO;
+
+    for agent in agents {
+        let nft_id = agent
+            .nft_id
+            .ok_or_else(|| eyre!("Agent {} has no NFT yet", agent.id))?;
+        let charge = charges
+            .iter()
+            .find(|charge| charge.agent_id == agent.id)
+            .ok_or_else(|| eyre!("No charge computed for agent {}", agent.id))?;
+
+        if charge.amount == Price::ZERO {
+            continue;
+        }
+
+        token_ids.push(nft_id);
+        amounts.push(charge.amount.to_u256()?.to_string());
+        total_cost = total_cost
+            .checked_add(charge.amount)
+            .ok_or_else(|| eyre!("Total cost overflow"))?;
+    }
+
+    let mut quote = PaymentQuote {
+        quote_id: Uuid::new_v4().to_string(),
+        payer_address: payer.to_string(),
+        agent_ids: agents.iter().map(|agent| agent.id).collect(),
+        token_ids,
+        amounts,
+        total_cost,
+        total_cost_units: total_cost.to_u256()?.to_string(),
+        expires_at: Utc::now().timestamp() + PAYMENT_QUOTE_TTL_SECS,
+        signature: String::new(),
+    };
+
+    let signature = signer.sign_message_sync(quote_message(&quote).as_bytes())?;
+    quote.signature = hex::encode_prefixed(signature.as_bytes());
+
+    Ok(quote)
+}
+
+/// Checks that the quote was signed by `signer` and had not expired when paid, `paid_at` being the timestamp of the payment block
+pub fn verify_quote(signer: &PrivateKeySigner, quote: &PaymentQuote, paid_at: i64) -> Result<()> {
+    let signature = Signature::from_str(&quote.signature)?;
+
+    if signature.recover_address_from_msg(quote_message(quote))? != signer.address() {
+        return Err(eyre!("Quote {} was not signed by Enclava", quote.quote_id));
+    }
+
+    if paid_at > quote.expires_at {
+        return Err(eyre!("Quote {} expired before the payment", quote.quote_id));
+    }
+
+    Ok(())
+}
+
+/// Token ids paid for with the amount in on-chain units, in the order of the quote
+pub fn quote_payments(quote: &PaymentQuote) -> Result<Vec<(i64, U256)>> {
+    if quote.token_ids.len() != quote.amounts.len() {
+        return Err(eyre!("Quote {} is malformed", quote.quote_id));
+    }
+
+    quote
+        .token_ids
+        .iter()
+        .zip(&quote.amounts)
+        .map(|(token_id, amount)| Ok((*token_id, U256::from_str(amount)?)))
+        .collect()
+}
+
+/// Transaction calling payForMultipleDatasets with the token ids and amounts of the quote
+pub fn quote_tx_request(quote: &PaymentQuote) -> Result<PaymentTxRequest> {
+    let (token_ids, amounts): (Vec<U256>, Vec<U256>) = quote_payments(quote)?
+        .into_iter()
+        .map(|(token_id, amount)| (U256::from(token_id), amount))
+        .unzip();
+
+    let data = payForMultipleDatasetsCall {
+        tokenIds: token_ids,
+        amounts,
+    }
+    .abi_encode();
+
+    let value = tx_value(U256::from_str(&quote.total_cost_units)?);
+
+    Ok(PaymentTxRequest {
+        from: quote.payer_address.clone(),
+        to: ENCLAVA_CONTRACT_ADDRESS.to_string(),
+        data: hex::encode_prefixed(data),
+        value: value.to_string(),
+        chain_id: HEDERA_TESTNET_CHAIN_ID,
+    })
+}
+
+/// Value of a transaction sending `units` of the contract amounts
+pub fn tx_value(units: U256) -> U256 {
+    units * U256::from(10).pow(U256::from(TX_VALUE_DECIMALS - PAYMENT_TOKEN_DECIMALS))
+}
+
+/// Message signed for a quote, every field the payment is checked against is part of it
+fn quote_message(quote: &PaymentQuote) -> String {
+    let join = |values: Vec<String>| values.join(",");
+
+    format!(
+        "Enclava payment quote\nQuote: {}\nPayer: {}\nContract: {}\nAgents: {}\nToken ids: {}\nAmounts: {}\nExpires at: {}",
+        quote.quote_id,
+        quote.payer_address,
+        ENCLAVA_CONTRACT_ADDRESS,
+        join(quote.agent_ids.iter().map(|id| id.to_string()).collect()),
+        join(quote.token_ids.iter().map(|id| id.to_string()).collect()),
+        join(quote.amounts.clone()),
+        quote.expires_at
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ChargeBasis;
+
+    fn signed_quote(signer: &PrivateKeySigner) -> PaymentQuote {
+        let agents = [
+            AgentDb::for_tests(1, 10),
+            AgentDb::for_tests(2, 20),
+            AgentDb::for_tests(3, 30),
+        ];
+        let charges = [
+            AgentCharge {
+                agent_id: 1,
+                amount: Price::from_units(150_000_000),
+                basis: ChargeBasis::PerQuery,
+            },
+            AgentCharge {
+                agent_id: 2,
+                amount: Price::ZERO,
+                basis: ChargeBasis::FreeSample,
+            },
+            AgentCharge {
+                agent_id: 3,
+                amount: Price::from_units(25),
+                basis: ChargeBasis::VolumeTier,
+            },
+        ];
+
+        build_quote(signer, Address::repeat_byte(0xa1), &agents, &charges).unwrap()
+    }
+
+    #[test]
+    fn signed_quotes_round_trip() {
+        let signer = PrivateKeySigner::random();
+        let quote = signed_quote(&signer);
+
+        verify_quote(&signer, &quote, Utc::now().timestamp()).unwrap();
+
+        // Agents charged nothing are not paid for
+        assert_eq!(quote.agent_ids, vec![1, 2, 3]);
+        assert_eq!(
+            quote_payments(&quote).unwrap(),
+            vec![(10, U256::from(150_000_000)), (30, U256::from(25))]
+        );
+        assert_eq!(quote.total_cost_units, "150000025");
+
+        let tx = quote_tx_request(&quote).unwrap();
+        assert_eq!(tx.from, quote.payer_address);
+        assert_eq!(tx.value, "1500000250000000000");
+    }
+
+    #[test]
+    fn expired_quotes_are_refused() {
+        let signer = PrivateKeySigner::random();
+        let quote = signed_quote(&signer);
+
+        verify_quote(&signer, &quote, quote.expires_at).unwrap();
+        assert!(verify_quote(&signer, &quote, quote.expires_at + 1).is_err());
+    }
+
+    #[test]
+    fn tampered_quotes_are_refused() {
+        let signer = PrivateKeySigner::random();
+        let paid_at = Utc::now().timestamp();
+
+        let mut quote = signed_quote(&signer);
+        quote.amounts[0] = "1".to_string();
+        assert!(verify_quote(&signer, &quote, paid_at).is_err());
+
+        let mut quote = signed_quote(&signer);
+        quote.token_ids[1] = 20;
+        assert!(verify_quote(&signer, &quote, paid_at).is_err());
+
+        let mut quote = signed_quote(&signer);
+        quote.expires_at += PAYMENT_QUOTE_TTL_SECS;
+        assert!(verify_quote(&signer, &quote, paid_at).is_err());
+    }
+
+    #[test]
+    fn quotes_of_another_signer_are_refused() {
+        let quote = signed_quote(&PrivateKeySigner::random());
+
+        assert!(verify_quote(&PrivateKeySigner::random(), &quote, Utc::now().timestamp()).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use alloy::{
+    network::{EthereumWallet, TransactionBuilder},
+    primitives::Address,
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    signers::local::PrivateKeySigner,
+};
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    config::HEDERA_TESTNET_RPC_URL,
+    database,
+    helpers::{
+        credits::{is_credit_payment, refund_to_credits},
+        quote::tx_value,
+    },
+    types::{Price, RefundEntitlementDb, WebAppState},
+};
+
+/// Executes a pending refund: payments made on-chain are sent back by the refund signer,
+/// credits payments and every payment when no refund signer is configured are refunded as credits.
+/// Refunds sent on-chain are paid from the refund signer wallet, they move no credits and are not posted to the ledger.
+pub async fn execute_refund(
+    app_state: &WebAppState,
+    refund_entitlement: &RefundEntitlementDb,
+) -> Result<RefundEntitlementDb> {
+    let signer = app_state
+        .refund_signer
+        .as_ref()
+        .filter(|_| !is_credit_payment(&refund_entitlement.tx_hash));
+
+    let Some(signer) = signer else {
+        return refund_to_credits(&app_state.db, refund_entitlement).await;
+    };
+
+    let db = &app_state.db;
+
+    let (payer, _) = database::get_agent_usage_payer(
+        db,
+        &refund_entitlement.tx_hash,
+        refund_entitlement.agent_id,
+    )
+    .await?
+    .ok_or_else(|| eyre!("No payer recorded for {}", refund_entitlement.tx_hash))?;
+
+    // Claiming the refund first guarantees it is sent once
+    let Some(refund_entitlement) =
+        database::claim_refund_entitlement(db, refund_entitlement.id).await?
+    else {
+        return Ok(database::get_refund_entitlement_by_id(db, refund_entitlement.id).await?);
+    };
+
+    match send_refund(signer, &payer, refund_entitlement.amount).await {
+        Ok(refund_tx_hash) => {
+            tracing::info!(
+                "Refunded {} to {} for agent {} in tx {}",
+                refund_entitlement.amount,
+                payer,
+                refund_entitlement.agent_id,
+                refund_tx_hash
+            );
+
+            Ok(database::mark_refund_entitlement_refunded(
+                db,
+                refund_entitlement.id,
+                &refund_tx_hash,
+            )
+            .await?)
+        }
+        Err(e) => {
+            // The transaction may have been sent, it is not retried automatically
+            database::mark_refund_entitlement_failed(db, refund_entitlement.id).await?;
+
+            Err(e)
+        }
+    }
+}
+
+/// Sends `amount` to `payer` from the refund signer and waits for the transaction to succeed
+async fn send_refund(signer: &PrivateKeySigner, payer: &str, amount: Price) -> Result<String> {
+    let provider = ProviderBuilder::new()
+        .wallet(EthereumWallet::from(signer.clone()))
+        .connect_http(HEDERA_TESTNET_RPC_URL.parse()?);
+
+    let tx = TransactionRequest::default()
+        .with_to(Address::from_str(payer)?)
+        .with_value(tx_value(amount.to_u256()?));
+
+    let receipt = provider.send_transaction(tx).await?.get_receipt().await?;
+
+    if !receipt.status() {
+        return Err(eyre!(
+            "Refund transaction {} reverted",
+            receipt.transaction_hash
+        ));
+    }
+
+    Ok(receipt.transaction_hash.to_string())
+}
+
+/// Executes the pending refunds, a refund failing is logged and the next ones are still executed
+pub async fn execute_pending_refunds(app_state: &WebAppState) -> Result<usize> {
+    let refund_entitlements = database::get_pending_refund_entitlements(&app_state.db).await?;
+
+    let mut executed = 0;
+
+    for refund_entitlement in &refund_entitlements {
+        match execute_refund(app_state, refund_entitlement).await {
+            Ok(_) => executed += 1,
+            Err(e) => tracing::error!(
+                "Failed to execute refund {} of agent {} for {}: {:?}",
+                refund_entitlement.id,
+                refund_entitlement.agent_id,
+                refund_entitlement.tx_hash,
+                e
+            ),
+        }
+    }
+
+    Ok(executed)
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use color_eyre::{Result, eyre::eyre};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{ROUTER_AGENT_MODEL, ROUTER_FALLBACK_MIN_SCORE, ROUTER_TIMEOUT_SECS},
+    types::{AgentDb, AgentRanking, AgentRecommendation, DatasetColumn, Price, RoutingMethod},
+};
+
+/// Agents picked by the router model, filled through a tool call
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct RouterDecision {
+    /// Agents able to answer the question, best first. Empty when none is relevant.
+    agents: Vec<RouterPick>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct RouterPick {
+    /// Id of the agent, taken from the catalogue
+    id: i64,
+    /// Relevance of the agent dataset to the question, from 0.0 to 1.0
+    score: f64,
+    /// One short sentence explaining why the dataset answers the question
+    justification: String,
+}
+
+/// Agent as described to the router model
+#[derive(Serialize)]
+struct RouterCandidate<'a> {
+    id: i64,
+    name: &'a str,
+    description: &'a str,
+    category: String,
+    columns: Vec<String>,
+}
+
+pub struct RoutingOutcome {
+    /// Agents able to answer, best first
+    pub rankings: Vec<AgentRanking>,
+    pub method: RoutingMethod,
+    /// Ids returned by the router model that are not active agents
+    pub rejected_ids: Vec<i64>,
+}
+
+/// Ranks the agents able to answer `prompt`.
+/// The router model answers through a typed tool call, its picks are validated against `agents`.
+/// When the model fails or times out, agents are ranked by keyword retrieval instead.
+pub async fn route_prompt(
+    ai_model: &rig::providers::gemini::Client,
+    prompt: &str,
+    agents: &[AgentDb],
+    agents_columns: &HashMap<i64, Vec<DatasetColumn>>,
+) -> RoutingOutcome {
+    let decision = tokio::time::timeout(
+        Duration::from_secs(ROUTER_TIMEOUT_SECS),
+        ask_router_model(ai_model, prompt, agents, agents_columns),
+    )
+    .await;
+
+    match decision {
+        Ok(Ok(decision)) => validate_decision(decision, agents),
+        Ok(Err(e)) => {
+            tracing::warn!("Router model failed, falling back to retrieval: {:?}", e);
+            retrieval_ranking(prompt, agents, agents_columns)
+        }
+        Err(_) => {
+            tracing::warn!(
+                "Router model did not answer within {} seconds, falling back to retrieval",
+                ROUTER_TIMEOUT_SECS
+            );
+            retrieval_ranking(prompt, agents, agents_columns)
+        }
+    }
+}
+
+async fn ask_router_model(
+    ai_model: &rig::providers::gemini::Client,
+    prompt: &str,
+    agents: &[AgentDb],
+    agents_columns: &HashMap<i64, Vec<DatasetColumn>>,
+) -> Result<RouterDecision> {
+    let candidates: Vec<RouterCandidate> = agents
+        .iter()
+        .map(|agent| RouterCandidate {
+            id: agent.id,
+            name: &agent.name,
+            description: &agent.description,
+            category: agent.category.to_string(),
+            // Describe the dataset schema as "name (type)" pairs
+            columns: agents_columns
+                .get(&agent.id)
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .map(|column| format!("{} ({})", column.name, column.data_type))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    let extractor = ai_model
+        .extractor::<RouterDecision>(ROUTER_AGENT_MODEL)
+        .preamble("You are an AI agent that your main and only task is to select the agents that can respond to the user question. You decide using their name, description, category and dataset columns from the catalogue in your context. Only select ids present in the catalogue. Give each selected agent a relevance score between 0.0 and 1.0 and a one sentence justification. If no agent is relevant, select none.")
+        .build();
+
+    let router_prompt = format!(
+        "User question: {}. The agents catalogue is: {}",
+        prompt,
+        serde_json::to_string(&candidates)?
+    );
+
+    let decision = extractor.extract(router_prompt).await?;
+
+    tracing::debug!("Router decision: {:?}", decision);
+
+    Ok(decision)
+}
+
+fn validate_decision(decision: RouterDecision, agents: &[AgentDb]) -> RoutingOutcome {
+    let known_ids: HashSet<i64> = agents.iter().map(|agent| agent.id).collect();
+
+    let mut seen_ids = HashSet::new();
+    let mut rankings = Vec::new();
+    let mut rejected_ids = Vec::new();
+
+    for pick in decision.agents {
+        if !known_ids.contains(&pick.id) {
+            tracing::warn!("Router model returned unknown agent id {}", pick.id);
+            rejected_ids.push(pick.id);
+            continue;
+        }
+
+        if !seen_ids.insert(pick.id) {
+            continue;
+        }
+
+        rankings.push(AgentRanking {
+            agent_id: pick.id,
+            score: if pick.score.is_finite() {
+                pick.score.clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            justification: pick.justification,
+        });
+    }
+
+    rankings.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    RoutingOutcome {
+        rankings,
+        method: RoutingMethod::Model,
+        rejected_ids,
+    }
+}
+
+/// Ranks agents by the share of the question keywords found in their name, description, category and columns,
+/// each keyword weighted by how rare it is across the catalogue
+pub fn retrieval_ranking(
+    prompt: &str,
+    agents: &[AgentDb],
+    agents_columns: &HashMap<i64, Vec<DatasetColumn>>,
+) -> RoutingOutcome {
+    let query_terms: HashSet<String> = tokenize(prompt).collect();
+
+    let agents_terms: Vec<(i64, HashSet<String>)> = agents
+        .iter()
+        .map(|agent| {
+            let columns = agents_columns
+                .get(&agent.id)
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .map(|column| column.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+
+            let text = format!(
+                "{} {} {} {}",
+                agent.name,
+                agent.description,
+                agent.category.to_string(),
+                columns
+            );
+
+            (agent.id, tokenize(&text).collect())
+        })
+        .collect();
+
+    let inverse_frequency = |term: &String| {
+        let matching = agents_terms
+            .iter()
+            .filter(|(_, terms)| terms.contains(term))
+            .count();
+
+        ((agents_terms.len() as f64 + 1.0) / (matching as f64 + 1.0)).ln() + 1.0
+    };
+
+    let total_weight: f64 = query_terms.iter().map(inverse_frequency).sum();
+
+    let mut rankings: Vec<AgentRanking> = agents_terms
+        .iter()
+        .filter_map(|(agent_id, terms)| {
+            let matched: Vec<&String> = query_terms
+                .iter()
+                .filter(|term| terms.contains(*term))
+                .collect();
+
+            let score = matched
+                .iter()
+                .map(|term| inverse_frequency(term))
+                .sum::<f64>()
+                / total_weight.max(f64::MIN_POSITIVE);
+
+            if score < ROUTER_FALLBACK_MIN_SCORE {
+                return None;
+            }
+
+            let mut keywords: Vec<&str> = matched.iter().map(|term| term.as_str()).collect();
+            keywords.sort();
+
+            Some(AgentRanking {
+                agent_id: *agent_id,
+                score,
+                justification: format!("Dataset matches the keywords: {}", keywords.join(", ")),
+            })
+        })
+        .collect();
+
+    rankings.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    RoutingOutcome {
+        rankings,
+        method: RoutingMethod::Retrieval,
+        rejected_ids: Vec::new(),
+    }
+}
+
+/// Recommends the best ranked agents whose total price fits in `max_budget`, at most `max_agents` of them.
+/// An agent costing more than what is left of the budget is dropped, cheaper agents ranked after it can still fit.
+pub fn recommend_within_budget(
+    rankings: &[AgentRanking],
+    agents: &[AgentDb],
+    max_budget: Option<Price>,
+    max_agents: usize,
+) -> Result<AgentRecommendation> {
+    let mut agent_ids = Vec::new();
+    let mut dropped_for_budget = Vec::new();
+    let mut total_cost = Price::ZERO;
+
+    for ranking in rankings {
+        if agent_ids.len() >= max_agents {
+            break;
+        }
+
+        let Some(agent) = agents.iter().find(|agent| agent.id == ranking.agent_id) else {
+            continue;
+        };
+
+        let cost = total_cost
+            .checked_add(agent.price)
+            .ok_or_else(|| eyre!("Total cost overflow"))?;
+
+        if max_budget.is_some_and(|budget| cost > budget) {
+            dropped_for_budget.push(agent.id);
+            continue;
+        }
+
+        total_cost = cost;
+        agent_ids.push(agent.id);
+    }
+
+    Ok(AgentRecommendation {
+        agent_ids,
+        total_cost,
+        total_cost_units: total_cost.units().to_string(),
+        dropped_for_budget,
+    })
+}
+
+/// Lowercase words of at least 3 characters, common english words excluded
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    const STOP_WORDS: [&str; 24] = [
+        "the", "and", "for", "are", "was", "with", "what", "which", "who", "how", "many", "much",
+        "does", "from", "that", "this", "have", "has", "there", "their", "about", "into", "can",
+        "dataset",
+    ];
+
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.chars().count() >= 3 && !STOP_WORDS.contains(&word.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ColumnDataType;
+
+    fn agent(id: i64, name: &str, description: &str) -> AgentDb {
+        AgentDb {
+            name: name.to_string(),
+            description: description.to_string(),
+            ..AgentDb::for_tests(id, id)
+        }
+    }
+
+    fn pick(id: i64, score: f64) -> RouterPick {
+        RouterPick {
+            id,
+            score,
+            justification: format!("Agent {} answers", id),
+        }
+    }
+
+    fn column(name: &str) -> DatasetColumn {
+        DatasetColumn {
+            position: 0,
+            name: name.to_string(),
+            data_type: ColumnDataType::String,
+            null_rate: 0.0,
+            distinct_count: 1,
+            min_value: None,
+            max_value: None,
+            sample_values: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn router_picks_are_validated_against_the_catalogue() {
+        let agents = [agent(1, "Sales", ""), agent(2, "Weather", "")];
+
+        let outcome = validate_decision(
+            RouterDecision {
+                agents: vec![pick(1, 0.4), pick(99, 0.9), pick(2, 7.0), pick(1, 0.8)],
+            },
+            &agents,
+        );
+
+        assert_eq!(outcome.method, RoutingMethod::Model);
+        assert_eq!(outcome.rejected_ids, vec![99]);
+
+        // Duplicates keep the first pick, scores are clamped and sorted
+        let ranked: Vec<(i64, f64)> = outcome
+            .rankings
+            .iter()
+            .map(|ranking| (ranking.agent_id, ranking.score))
+            .collect();
+        assert_eq!(ranked, vec![(2, 1.0), (1, 0.4)]);
+
+        let outcome = validate_decision(
+            RouterDecision {
+                agents: vec![pick(1, f64::NAN)],
+            },
+            &agents,
+        );
+        assert_eq!(outcome.rankings[0].score, 0.0);
+    }
+
+    #[test]
+    fn retrieval_ranks_agents_by_their_keywords() {
+        let agents = [
+            agent(1, "Retail sales", "Monthly sales per store"),
+            agent(2, "Weather", "Daily temperature per city"),
+            agent(3, "Store staff", "Employees of each store"),
+        ];
+        let agents_columns = HashMap::from([(2, vec![column("rainfall")])]);
+
+        let outcome = retrieval_ranking(
+            "What were the monthly sales of the store?",
+            &agents,
+            &agents_columns,
+        );
+
+        assert_eq!(outcome.method, RoutingMethod::Retrieval);
+        assert!(outcome.rejected_ids.is_empty());
+
+        let ranked: Vec<i64> = outcome
+            .rankings
+            .iter()
+            .map(|ranking| ranking.agent_id)
+            .collect();
+        // Only sharing "store" with the question is under the minimum score
+        assert_eq!(ranked, vec![1]);
+        assert_eq!(
+            outcome.rankings[0].justification,
+            "Dataset matches the keywords: monthly, sales, store"
+        );
+
+        // Columns are searched too
+        let outcome = retrieval_ranking("Total rainfall", &agents, &agents_columns);
+        assert_eq!(outcome.rankings.len(), 1);
+        assert_eq!(outcome.rankings[0].agent_id, 2);
+
+        // Common words alone match nothing
+        let outcome = retrieval_ranking("What is there about this?", &agents, &agents_columns);
+        assert!(outcome.rankings.is_empty());
+    }
+
+    fn ranking(agent_id: i64) -> AgentRanking {
+        AgentRanking {
+            agent_id,
+            score: 1.0,
+            justification: String::new(),
+        }
+    }
+
+    fn priced(id: i64, units: i64) -> AgentDb {
+        AgentDb {
+            price: Price::from_units(units),
+            ..AgentDb::for_tests(id, id)
+        }
+    }
+
+    #[test]
+    fn recommendations_fit_in_the_budget() {
+        let agents = [
+            priced(1, 150_000_000),
+            priced(2, 200_000_000),
+            priced(3, 50_000_000),
+        ];
+        let rankings = [ranking(1), ranking(2), ranking(3)];
+
+        // The second agent is over what is left, the cheaper third one still fits
+        let recommendation =
+            recommend_within_budget(&rankings, &agents, Some(Price::from_units(250_000_000)), 5)
+                .unwrap();
+        assert_eq!(recommendation.agent_ids, vec![1, 3]);
+        assert_eq!(recommendation.dropped_for_budget, vec![2]);
+        assert_eq!(recommendation.total_cost, Price::from_units(200_000_000));
+        assert_eq!(recommendation.total_cost_units, "200000000");
+
+        // A budget of exactly the total keeps every agent
+        let recommendation =
+            recommend_within_budget(&rankings, &agents, Some(Price::from_units(400_000_000)), 5)
+                .unwrap();
+        assert_eq!(recommendation.agent_ids, vec![1, 2, 3]);
+        assert!(recommendation.dropped_for_budget.is_empty());
+        assert_eq!(recommendation.total_cost_units, "400000000");
+    }
+
+    #[test]
+    fn recommendations_keep_the_best_ranked_agents() {
+        let agents = [priced(1, 100), priced(2, 100), priced(3, 100)];
+        // Rankings of agents that are no longer listed are skipped
+        let rankings = [ranking(3), ranking(99), ranking(1), ranking(2)];
+
+        let recommendation = recommend_within_budget(&rankings, &agents, None, 2).unwrap();
+        assert_eq!(recommendation.agent_ids, vec![3, 1]);
+        assert!(recommendation.dropped_for_budget.is_empty());
+        assert_eq!(recommendation.total_cost_units, "200");
+
+        // Agents past the max count are not reported as dropped for the budget
+        let recommendation =
+            recommend_within_budget(&rankings, &agents, Some(Price::from_units(100)), 1).unwrap();
+        assert_eq!(recommendation.agent_ids, vec![3]);
+        assert!(recommendation.dropped_for_budget.is_empty());
+    }
+}
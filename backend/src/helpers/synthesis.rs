@@ -0,0 +1,157 @@
+use color_eyre::{Result, eyre::eyre};
+use rig::completion::Prompt;
+
+use crate::{
+    config::SYNTHESIS_AGENT_MODEL,
+    types::{AgentDb, AgentResponse, AgentResponseStatus, AnswerCitation, SynthesizedAnswer},
+};
+
+/// Merges the successful answers of several agents into one answer citing its sources with `[n]` markers.
+/// Only the sources actually cited in the answer are returned as citations.
+pub async fn synthesize_answer(
+    ai_model: &rig::providers::gemini::Client,
+    prompt: &str,
+    agents: &[(&AgentDb, &AgentResponse)],
+) -> Result<SynthesizedAnswer> {
+    let sources: Vec<(&AgentDb, &AgentResponse)> = agents
+        .iter()
+        .copied()
+        .filter(|(_, response)| response.status == AgentResponseStatus::Success)
+        .collect();
+
+    if sources.is_empty() {
+        return Err(eyre!("No agent answered"));
+    }
+
+    let sources_str = sources
+        .iter()
+        .enumerate()
+        .map(|(index, (agent, response))| {
+            format!(
+                "[{}] Dataset \"{}\" ({}): {}",
+                index + 1,
+                agent.name,
+                agent.description,
+                response.response
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let agent = ai_model
+        .agent(SYNTHESIS_AGENT_MODEL)
+        .preamble("You are an AI agent that merges the answers of several dataset agents into a single answer to the user question. Only use the numbered answers given to you, never add other knowledge. After each claim add the number of the answer it comes from between brackets, like [1] or [1][2]. When answers contradict each other, say so and cite both. Return only the merged answer.")
+        .temperature(0.0)
+        .build();
+
+    let synthesis_prompt = format!(
+        "User question: {}. The answers of the dataset agents are:\n\n{}",
+        prompt, sources_str
+    );
+
+    let answer = agent.prompt(synthesis_prompt).await?;
+
+    tracing::debug!("Synthesized answer: {}", answer);
+
+    let citations = citations(&answer, &sources);
+
+    Ok(SynthesizedAnswer { answer, citations })
+}
+
+/// Sources cited in the answer, `[n]` referring to the n-th source
+fn citations(answer: &str, sources: &[(&AgentDb, &AgentResponse)]) -> Vec<AnswerCitation> {
+    cited_markers(answer, sources.len())
+        .into_iter()
+        .map(|marker| {
+            let (agent, response) = sources[marker - 1];
+
+            AnswerCitation {
+                marker,
+                agent_id: agent.id,
+                agent_name: agent.name.clone(),
+                dataset_version: response.dataset_version,
+            }
+        })
+        .collect()
+}
+
+/// Returns the `[n]` markers of the text referring to one of the `sources_count` sources, in increasing order
+fn cited_markers(text: &str, sources_count: usize) -> Vec<usize> {
+    let mut markers: Vec<usize> = text
+        .split('[')
+        .skip(1)
+        .filter_map(|part| part.split_once(']'))
+        .filter_map(|(marker, _)| marker.trim().parse::<usize>().ok())
+        .filter(|marker| (1..=sources_count).contains(marker))
+        .collect();
+
+    markers.sort();
+    markers.dedup();
+
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(agent_id: i64, status: AgentResponseStatus, dataset_version: i32) -> AgentResponse {
+        AgentResponse {
+            agent_id,
+            prompt: "Sales in Paris?".to_string(),
+            response: format!("Answer of agent {}", agent_id),
+            dataset_version,
+            status,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn only_valid_markers_are_cited() {
+        let answer = "Paris sold 10 [2], more than Lyon [1][2]. Rome is unknown [3] [0] [x] [ 1 ].";
+
+        assert_eq!(cited_markers(answer, 2), vec![1, 2]);
+        assert_eq!(cited_markers(answer, 3), vec![1, 2, 3]);
+        assert!(cited_markers("No sources cited", 2).is_empty());
+    }
+
+    #[test]
+    fn citations_point_to_the_cited_sources() {
+        let agents = [
+            AgentDb::for_tests(1, 1),
+            AgentDb::for_tests(2, 2),
+            AgentDb::for_tests(3, 3),
+        ];
+        let responses = [
+            response(1, AgentResponseStatus::Success, 4),
+            response(2, AgentResponseStatus::Success, 1),
+            response(3, AgentResponseStatus::Success, 2),
+        ];
+        let sources: Vec<(&AgentDb, &AgentResponse)> = agents.iter().zip(&responses).collect();
+
+        let citations = citations("Lyon sold 5 [3] and Paris 10 [1][3].", &sources);
+
+        let cited: Vec<(usize, i64, i32)> = citations
+            .iter()
+            .map(|citation| (citation.marker, citation.agent_id, citation.dataset_version))
+            .collect();
+        assert_eq!(cited, vec![(1, 1, 4), (3, 3, 2)]);
+        assert_eq!(citations[1].agent_name, "Agent 3");
+    }
+
+    #[tokio::test]
+    async fn answers_are_not_synthesized_without_a_successful_agent() {
+        let agents = [AgentDb::for_tests(1, 1)];
+        let responses = [response(1, AgentResponseStatus::TimedOut, 1)];
+        let sources: Vec<(&AgentDb, &AgentResponse)> = agents.iter().zip(&responses).collect();
+
+        let synthesis = synthesize_answer(
+            &rig::providers::gemini::Client::new("test"),
+            "Sales in Paris?",
+            &sources,
+        )
+        .await;
+
+        assert!(synthesis.is_err());
+    }
+}
@@ -8,7 +8,7 @@ mod tee;
 mod types;
 
 use actix_cors::Cors;
-use actix_web::{App, HttpServer, web};
+use actix_web::{App, HttpServer, middleware, web};
 
 use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
@@ -46,6 +46,18 @@ async fn main() -> std::io::Result<()> {
     // Initialize a new application state
     let app_state = web::Data::new(AppState::new().await);
 
+    // `enclava_backend evaluate <agent_id> [mock_answers.json]` runs an evaluation and exits without serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("evaluate") {
+        return helpers::evaluation::run_cli(&app_state, &args[1..])
+            .await
+            .map_err(|e| {
+                error!("Evaluation failed: {:?}", e);
+                std::io::Error::other(format!("Evaluation error: {:?}", e))
+            });
+    }
+
     // Starting all enclava fetchers
     fetcher::open_all_logs_fetcher(&app_state)
         .await
@@ -72,20 +84,58 @@ async fn main() -> std::io::Result<()> {
             .allow_any_method()
             .allow_any_header(); // Cache preflight response for 1 hour
 
+        // CORS wraps last so preflight requests never reach the signed body hashing
         let (app, app_api) = App::new()
+            .wrap(middleware::from_fn(helpers::auth::hash_signed_body))
             .wrap(cors)
             .into_utoipa_app()
             .app_data(web::Data::clone(&app_state))
             .service(api::get_index_service)
             .service(api::get_health_service)
+            .service(api::get_agent_cache_metrics_service)
             .service(api::dataset::upload_dataset_service)
             .service(api::dataset::generate_dataset_details_service)
             .service(api::get_all_agents_service)
+            .service(api::search::search_agents_service)
             .service(api::get_agents_for_prompt_service)
+            .service(api::quote::create_quote_service)
             .service(api::get_response_from_agents_service)
+            .service(api::stream::stream_response_from_agents_service)
+            .service(api::aggregate::aggregate_query_service)
             .service(api::get_datasets_stats_service)
+            .service(api::stats::get_marketplace_stats_service)
             .service(api::profile::get_profile_service)
+            .service(api::profile::get_guardrail_events_service)
+            .service(api::conversations::get_conversations_service)
+            .service(api::conversations::get_conversation_service)
+            .service(api::credits::get_credits_service)
+            .service(api::credits::get_credit_settlements_service)
+            .service(api::credits::get_ledger_reconciliation_service)
             .service(api::get_agent_by_id_service)
+            .service(api::versions::upload_dataset_version_service)
+            .service(api::versions::get_dataset_versions_service)
+            .service(api::lifecycle::update_agent_service)
+            .service(api::lifecycle::delete_agent_service)
+            .service(api::pricing::get_pricing_plan_service)
+            .service(api::pricing::update_pricing_plan_service)
+            .service(api::pricing::subscribe_service)
+            .service(api::disputes::create_dispute_service)
+            .service(api::disputes::get_dispute_service)
+            .service(api::disputes::get_user_disputes_service)
+            .service(api::disputes::respond_dispute_service)
+            .service(api::disputes::resolve_dispute_service)
+            .service(api::queries::get_user_queries_service)
+            .service(api::queries::get_agent_queries_service)
+            .service(api::reviews::create_review_service)
+            .service(api::reviews::get_agent_reviews_service)
+            .service(api::evaluations::create_evaluation_cases_service)
+            .service(api::evaluations::get_evaluation_cases_service)
+            .service(api::evaluations::start_evaluation_service)
+            .service(api::evaluations::get_evaluation_runs_service)
+            .service(api::evaluations::get_evaluation_run_service)
+            .service(api::preview::get_preview_settings_service)
+            .service(api::preview::update_preview_settings_service)
+            .service(api::preview::preview_agent_service)
             .split_for_parts();
 
         app.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", app_api))
@@ -1,18 +1,34 @@
 use std::collections::HashMap;
 
-use dashmap::{DashMap, DashSet};
+use alloy::signers::local::PrivateKeySigner;
+use chrono::{DateTime, Utc};
 use rig::{agent::Agent, client::ProviderClient, providers};
 use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 
-use crate::{config::APP_CONFIG, helpers::agents::load_db_agents};
+use crate::{
+    config::{AGENT_CACHE_CAPACITY, AGENT_CACHE_MAX_DATASET_BYTES, APP_CONFIG},
+    helpers::{agent_cache::AgentCache, embeddings::Embedder},
+};
 
-use tracing::info;
+use tracing::{info, warn};
+
+pub struct TeeAgent {
+    pub agent: Agent<providers::gemini::completion::CompletionModel>,
+    /// Csv content the agent answers from, used to check responses for leaked rows
+    pub dataset: String,
+    /// Dataset version the agent was built from
+    pub version: i32,
+    /// Update time of the agent row it was built from, its name, description and price are part of the preamble
+    pub updated_at: DateTime<Utc>,
+}
 
 pub struct AppState {
     pub db: Pool<Postgres>,
     pub ai_model: providers::gemini::Client,
-    pub tee_agents: DashMap<i64, Agent<providers::gemini::completion::CompletionModel>>,
-    pub handled_txs: DashSet<String>,
+    pub agent_cache: AgentCache,
+    pub embedder: Embedder,
+    pub quote_signer: PrivateKeySigner,
+    pub refund_signer: Option<PrivateKeySigner>,
 }
 
 impl AppState {
@@ -33,20 +49,76 @@ impl AppState {
 
         info!("AI model client initialized successfully");
 
-        // Normally those tee agent will be on another enclave that will never stops, but for now we should intize them again using agents db table.
-        let tee_agents = load_db_agents(&db, &ai_model)
-            .await
-            .expect("Failed to load agents from database");
+        let embedder = Embedder::new(&APP_CONFIG.embedding_provider, &ai_model);
+
+        info!("Embedder initialized with model {}", embedder.model_name());
+
+        let quote_signer = match &APP_CONFIG.quote_signer_private_key {
+            Some(key) => key
+                .parse::<PrivateKeySigner>()
+                .expect("QUOTE_SIGNER_PRIVATE_KEY must be a valid private key"),
+            None => {
+                warn!(
+                    "QUOTE_SIGNER_PRIVATE_KEY not set, payment quotes are signed with a random key"
+                );
+                PrivateKeySigner::random()
+            }
+        };
+
+        info!("Payment quotes signed by {}", quote_signer.address());
+
+        let refund_signer = APP_CONFIG.refund_signer_private_key.as_ref().map(|key| {
+            key.parse::<PrivateKeySigner>()
+                .expect("REFUND_SIGNER_PRIVATE_KEY must be a valid private key")
+        });
+
+        match &refund_signer {
+            Some(signer) => info!("Refunds of on-chain payments sent by {}", signer.address()),
+            None => warn!(
+                "REFUND_SIGNER_PRIVATE_KEY not set, refunds of on-chain payments are recorded as credits"
+            ),
+        }
+
+        if APP_CONFIG.platform_funding_address.is_none() {
+            warn!(
+                "PLATFORM_FUNDING_ADDRESS not set, credits given as refunds of on-chain payments can't be backed in creditPool"
+            );
+        }
+
+        // Normally those tee agent will be on another enclave that will never stops, but for now they are built from the agents db table when first needed.
+        let agent_cache = AgentCache::new(AGENT_CACHE_CAPACITY, AGENT_CACHE_MAX_DATASET_BYTES);
 
-        info!("{} Tee agents loaded successfully", tee_agents.len());
+        info!(
+            "Agent cache initialized (capacity: {} agents, {} bytes)",
+            AGENT_CACHE_CAPACITY, AGENT_CACHE_MAX_DATASET_BYTES
+        );
 
-        let handled_txs = DashSet::new();
+        if APP_CONFIG.pii_hash_salt.is_none() {
+            warn!("PII_HASH_SALT not set, uploads with the hash PII policy are refused");
+        }
 
         Self {
             db,
             ai_model,
-            tee_agents,
-            handled_txs,
+            agent_cache,
+            embedder,
+            quote_signer,
+            refund_signer,
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State around a test database, agents and embeddings never call a model
+    pub fn for_tests(db: Pool<Postgres>) -> Self {
+        Self {
+            db,
+            ai_model: providers::gemini::Client::new("test"),
+            agent_cache: AgentCache::new(AGENT_CACHE_CAPACITY, AGENT_CACHE_MAX_DATASET_BYTES),
+            embedder: Embedder::Local,
+            quote_signer: PrivateKeySigner::random(),
+            refund_signer: None,
         }
     }
 }
@@ -1,10 +1,14 @@
+use std::str::FromStr;
+
 use actix_web::web;
+use alloy::primitives::U256;
 use chrono::{DateTime, Utc};
+use color_eyre::{Result, eyre::eyre};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::Type;
 use utoipa::ToSchema;
 
-use crate::state::AppState;
+use crate::{config::PAYMENT_TOKEN_DECIMALS, state::AppState};
 
 #[derive(Serialize, ToSchema)]
 pub struct DatasetUploadResponse {
@@ -24,6 +28,10 @@ pub struct DatasetUploadResponse {
     pub metadata: Option<DatasetMetadata>,
     /// Created Dataset ID
     pub dataset_id: i64,
+    /// Columns profile inferred from the dataset
+    pub columns: Vec<DatasetColumn>,
+    /// PII found in the dataset and the policies applied to it
+    pub pii_report: PiiReport,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -33,7 +41,8 @@ pub struct DatasetStatsResponse {
     /// Total number of datasets
     pub total_count: i64,
     /// Total price value of all datasets
-    pub total_price: f64,
+    #[schema(value_type = String, example = "12.5")]
+    pub total_price: Price,
     /// Total size of all datasets in bytes
     pub total_size: f64,
 }
@@ -52,9 +61,9 @@ pub struct ErrorResponse {
 pub struct DatasetMetadata {
     /// Blockchain address of the user
     pub user_address: String,
-    /// Dataset price
-    #[schema(minimum = 1.0, maximum = 50000000.0)]
-    pub dataset_price: f64,
+    /// Dataset price, between 1 and 50000000 with up to 8 decimals
+    #[schema(value_type = String, example = "1.5")]
+    pub dataset_price: Price,
     /// Description of the dataset
     pub description: String,
     /// Name of the dataset
@@ -63,6 +72,102 @@ pub struct DatasetMetadata {
     pub category: AgentCategory,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PiiReport {
+    /// Number of rows scanned
+    pub scanned_rows: usize,
+    /// Whether the dataset was rejected because of a `reject` policy
+    pub rejected: bool,
+    /// PII found per column
+    pub findings: Vec<PiiFinding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PiiFinding {
+    /// Column where the PII was found
+    pub column: String,
+    /// Kind of PII
+    pub kind: PiiKind,
+    /// Whether the whole column is PII or PII values are embedded in its text
+    pub detection: PiiDetection,
+    /// Number of values matched
+    pub matches: usize,
+    /// Policy applied to the matched values
+    pub action: PiiPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiKind {
+    Email,
+    Phone,
+    Name,
+    CardNumber,
+    WalletAddress,
+    NationalId,
+}
+
+impl std::fmt::Display for PiiKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            PiiKind::Email => "email",
+            PiiKind::Phone => "phone",
+            PiiKind::Name => "name",
+            PiiKind::CardNumber => "card_number",
+            PiiKind::WalletAddress => "wallet_address",
+            PiiKind::NationalId => "national_id",
+        };
+
+        f.write_str(value)
+    }
+}
+
+impl PiiKind {
+    pub fn from_string(kind: &str) -> Option<PiiKind> {
+        match kind {
+            "email" => Some(PiiKind::Email),
+            "phone" => Some(PiiKind::Phone),
+            "name" => Some(PiiKind::Name),
+            "card_number" => Some(PiiKind::CardNumber),
+            "wallet_address" => Some(PiiKind::WalletAddress),
+            "national_id" => Some(PiiKind::NationalId),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiPolicy {
+    /// Refuse the upload
+    Reject,
+    /// Keep only a few characters of the value (j***@mail.com)
+    Mask,
+    /// Replace the value by a salted hash
+    Hash,
+    /// Replace the value by a stable pseudonym (EMAIL_3)
+    Tokenise,
+}
+
+impl PiiPolicy {
+    pub fn from_string(policy: &str) -> Option<PiiPolicy> {
+        match policy {
+            "reject" => Some(PiiPolicy::Reject),
+            "mask" => Some(PiiPolicy::Mask),
+            "hash" => Some(PiiPolicy::Hash),
+            "tokenise" | "tokenize" => Some(PiiPolicy::Tokenise),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiDetection {
+    Column,
+    Inline,
+}
+
 #[derive(ToSchema)]
 pub struct DatasetUploadRequest {
     /// CSV file to upload
@@ -70,15 +175,23 @@ pub struct DatasetUploadRequest {
     pub file: Vec<u8>,
     /// Blockchain address of the user
     pub user_address: String,
-    /// Dataset price
-    #[schema(minimum = 1.0, maximum = 5800000.0)]
-    pub dataset_price: f64,
+    /// Dataset price, between 1 and 50000000 with up to 8 decimals
+    #[schema(value_type = String, example = "1.5")]
+    pub dataset_price: Price,
     /// Description of the dataset
     pub description: String,
     /// Name of the dataset
     pub name: String,
     // Category of dataset
     pub category: AgentCategory,
+    /// PII policies, e.g. `mask` or `mask,email=hash,card_number=reject` (default: mask)
+    pub pii_policy: Option<String>,
+    /// Total differential privacy budget, enables privacy mode when set
+    pub dp_epsilon_budget: Option<f64>,
+    /// Epsilon consumed by each aggregate query (default: 0.1)
+    pub dp_epsilon_per_query: Option<f64>,
+    /// Clamp bounds of the numeric columns sums and means are allowed on in privacy mode, e.g. `amount=0:1000,age=0:120`
+    pub dp_column_bounds: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -115,12 +228,168 @@ pub struct UserDb {
     pub address: String,
 }
 
+/// Amount of money held as an integer number of base units, the 8 decimals units paid on-chain.
+/// Serialized as a decimal string (e.g. "1.5") so no precision is lost, numbers are accepted when deserializing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Type)]
+#[sqlx(transparent)]
+pub struct Price(i64);
+
+impl Price {
+    pub const ZERO: Price = Price(0);
+
+    pub fn from_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    pub fn from_whole(amount: i64) -> Self {
+        Self(amount * Self::units_per_whole())
+    }
+
+    /// Base units, the amount paid on-chain
+    pub fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn to_u256(self) -> Result<U256> {
+        if self.0 < 0 {
+            return Err(eyre!("Negative price {} can't be paid on-chain", self));
+        }
+
+        Ok(U256::from(self.0))
+    }
+
+    pub fn from_u256(units: U256) -> Result<Self> {
+        Ok(Self(i64::try_from(units).map_err(|_| {
+            eyre!("Amount {} is too large to be a price", units)
+        })?))
+    }
+
+    pub fn checked_add(self, other: Price) -> Option<Price> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    fn units_per_whole() -> i64 {
+        10_i64.pow(PAYMENT_TOKEN_DECIMALS as u32)
+    }
+}
+
+// Ledger entries are signed, a debit is the negated amount
+impl std::ops::Neg for Price {
+    type Output = Price;
+
+    fn neg(self) -> Price {
+        Self(-self.0)
+    }
+}
+
+impl std::fmt::Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = self.0.unsigned_abs() / Self::units_per_whole() as u64;
+        let fraction = self.0.unsigned_abs() % Self::units_per_whole() as u64;
+
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, whole);
+        }
+
+        let fraction = format!(
+            "{:0width$}",
+            fraction,
+            width = PAYMENT_TOKEN_DECIMALS as usize
+        );
+
+        write!(f, "{}{}.{}", sign, whole, fraction.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for Price {
+    type Err = color_eyre::Report;
+
+    /// Parses a decimal amount such as "12", "0.5" or "1.25000000", negative amounts and more than 8 decimals are refused
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if s.starts_with('-') {
+            return Err(eyre!("Amount {} can't be negative", s));
+        }
+
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return Err(eyre!("Invalid amount: {}", s));
+        }
+
+        if fraction.len() > PAYMENT_TOKEN_DECIMALS as usize {
+            return Err(eyre!(
+                "Amount {} has more than {} decimals",
+                s,
+                PAYMENT_TOKEN_DECIMALS
+            ));
+        }
+
+        let whole: i64 = if whole.is_empty() { 0 } else { whole.parse()? };
+        let fraction: i64 = format!(
+            "{:0<width$}",
+            fraction,
+            width = PAYMENT_TOKEN_DECIMALS as usize
+        )
+        .parse()?;
+
+        let units = whole
+            .checked_mul(Self::units_per_whole())
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or_else(|| eyre!("Amount {} is too large", s))?;
+
+        Ok(Self(units))
+    }
+}
+
+impl Serialize for Price {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawPrice {
+            Text(String),
+            Number(serde_json::Number),
+        }
+
+        let raw = match RawPrice::deserialize(deserializer)? {
+            RawPrice::Text(text) => text,
+            RawPrice::Number(number) => number.to_string(),
+        };
+
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Saturates instead of overflowing, accepted prices are far below the i64 range
+impl std::iter::Sum for Price {
+    fn sum<I: Iterator<Item = Price>>(iter: I) -> Self {
+        iter.fold(Price::ZERO, |acc, price| {
+            acc.checked_add(price).unwrap_or(if price.0 < 0 {
+                Price(i64::MIN)
+            } else {
+                Price(i64::MAX)
+            })
+        })
+    }
+}
+
 #[derive(Debug, sqlx::FromRow, Serialize, Deserialize, Clone, ToSchema)]
 pub struct AgentDb {
     pub id: i64,
     pub name: String,
     pub description: String,
-    pub price: f64,
+    #[schema(value_type = String, example = "1.5")]
+    pub price: Price,
     pub owner_id: i64,
     pub owner_address: String,
     pub dataset_path: String,
@@ -128,41 +397,369 @@ pub struct AgentDb {
     pub dataset_size: f64,
     pub nft_id: Option<i64>,
     pub nft_tx: Option<String>,
+    /// Version of the dataset currently answering queries
+    pub current_version: i32,
     pub status: String,
     #[schema(value_type = String, format = DateTime)]
     pub created_at: DateTime<Utc>,
     #[schema(value_type = String, format = DateTime)]
     pub updated_at: DateTime<Utc>,
+    /// Number of reviews left by paying users
+    pub review_count: i64,
+    /// Average rating from 1 to 5, missing until the first review
+    pub average_rating: Option<f64>,
+}
+
+#[cfg(test)]
+impl AgentDb {
+    /// Active agent minted as `nft_id`, priced 100 units per query
+    pub fn for_tests(id: i64, nft_id: i64) -> Self {
+        Self {
+            id,
+            name: format!("Agent {}", id),
+            description: format!("Dataset of agent {}", id),
+            price: Price::from_units(100),
+            owner_id: 1,
+            owner_address: String::new(),
+            dataset_path: format!("agent-{}.csv", id),
+            category: AgentCategory::Analytics,
+            dataset_size: 0.0,
+            nft_id: Some(nft_id),
+            nft_tx: None,
+            current_version: 1,
+            status: "active".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            review_count: 0,
+            average_rating: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentDetailsResponse {
+    #[serde(flatten)]
+    pub agent: AgentDb,
+    /// Columns profile of the agent dataset
+    pub columns: Vec<DatasetColumn>,
+    /// Differential privacy budget, when the dataset is opted in
+    pub privacy_budget: Option<PrivacyBudgetDb>,
+    pub pricing_plan: PricingPlan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DatasetColumn {
+    /// Position of the column in the csv header (starting at 0)
+    pub position: i32,
+    /// Column name
+    pub name: String,
+    /// Inferred column type
+    pub data_type: ColumnDataType,
+    /// Share of rows where the value is missing (0.0 to 1.0)
+    pub null_rate: f64,
+    /// Number of distinct non-null values
+    pub distinct_count: i64,
+    /// Minimum value (numeric and date columns only)
+    pub min_value: Option<String>,
+    /// Maximum value (numeric and date columns only)
+    pub max_value: Option<String>,
+    /// A few distinct values taken from the dataset
+    pub sample_values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "column_data_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnDataType {
+    Integer,
+    Float,
+    Boolean,
+    Date,
+    Datetime,
+    String,
+    Empty,
+}
+
+impl std::fmt::Display for ColumnDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            ColumnDataType::Integer => "integer",
+            ColumnDataType::Float => "float",
+            ColumnDataType::Boolean => "boolean",
+            ColumnDataType::Date => "date",
+            ColumnDataType::Datetime => "datetime",
+            ColumnDataType::String => "string",
+            ColumnDataType::Empty => "empty",
+        };
+
+        f.write_str(value)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetAgentsForPromptRequest {
     pub prompt: String,
+    /// Maximum total price of the recommended agents
+    #[schema(value_type = Option<String>, example = "10")]
+    pub max_budget: Option<Price>,
+    /// Maximum number of recommended agents (default and max: 3)
+    pub max_agents: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetAgentsForPromptResponse {
+    /// Agents able to answer, best first
     pub agents: Vec<AgentDb>,
+    /// Score and justification of each agent, in the same order
+    pub rankings: Vec<AgentRanking>,
+    pub routing_method: RoutingMethod,
+    /// Ids returned by the router model that don't belong to an active agent
+    pub rejected_agent_ids: Vec<i64>,
+    pub recommendation: AgentRecommendation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentRecommendation {
+    /// Agents to pay for and ask, best first
+    pub agent_ids: Vec<i64>,
+    /// Total price of the recommended agents
+    #[schema(value_type = String, example = "2.5")]
+    pub total_cost: Price,
+    /// Total price in the on-chain units (8 decimals) paid through payForMultipleDatasets
+    pub total_cost_units: String,
+    /// Relevant agents left out because they would exceed the budget, best first
+    pub dropped_for_budget: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentRanking {
+    pub agent_id: i64,
+    /// Relevance to the prompt, from 0.0 to 1.0
+    pub score: f64,
+    pub justification: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingMethod {
+    /// Ranked by the router model
+    Model,
+    /// Ranked by keyword retrieval because the router model failed
+    Retrieval,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetResponseFromAgentsRequest {
     pub agent_ids: Vec<i64>,
     pub prompt: String,
+    #[serde(default)]
     pub tx_hash: String,
+    /// Merge the answers into a single cited answer
+    #[serde(default)]
+    pub synthesize: bool,
+    /// Conversation to continue. Requires the signature headers of its owner.
+    /// Without it, signed requests start a new conversation and unsigned ones are not persisted.
+    #[serde(default)]
+    pub conversation_id: Option<i64>,
+    /// Quote returned by /chat/quote, the payment is then checked against it exactly
+    #[serde(default)]
+    pub quote: Option<PaymentQuote>,
+    /// Pay from the prepaid credits of the signer instead of a transaction, tx_hash is then ignored
+    #[serde(default)]
+    pub pay_with_credits: bool,
+    /// Keep the prompt text in the queries log, only its hash is kept otherwise
+    #[serde(default)]
+    pub store_prompt: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QuoteRequest {
+    pub agent_ids: Vec<i64>,
+    /// Address that will send the payment
+    pub payer_address: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PaymentQuote {
+    pub quote_id: String,
+    pub payer_address: String,
+    pub agent_ids: Vec<i64>,
+    /// NFT ids of the agents, in the same order
+    pub token_ids: Vec<i64>,
+    /// Amount to pay for each token in on-chain units (8 decimals)
+    pub amounts: Vec<String>,
+    #[schema(value_type = String, example = "2.5")]
+    pub total_cost: Price,
+    /// Total amount in on-chain units (8 decimals)
+    pub total_cost_units: String,
+    /// Unix timestamp after which the quote is no longer accepted
+    pub expires_at: i64,
+    /// EIP-191 signature of the quote by the Enclava quote signer
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PaymentTxRequest {
+    pub from: String,
+    /// Enclava contract address
+    pub to: String,
+    /// ABI-encoded payForMultipleDatasets call
+    pub data: String,
+    /// Value to send, in wei (18 decimals) as expected by the JSON-RPC relay
+    pub value: String,
+    pub chain_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QuoteResponse {
+    pub success: bool,
+    pub quote: PaymentQuote,
+    /// Address the quote signatures can be checked against
+    pub signer_address: String,
+    pub tx_request: PaymentTxRequest,
+    /// What is charged for each agent under its pricing plan, agents charged nothing are not part of the transaction
+    pub charges: Vec<AgentCharge>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetResponseFromAgentsResponse {
     pub agent_responses: Vec<AgentResponse>,
+    /// True when at least one agent answered
     pub success: bool,
+    /// What was charged for each agent under its pricing plan
+    pub charges: Vec<AgentCharge>,
+    /// Refunds owed for the agents that failed after the payment
+    pub refund_entitlements: Vec<RefundEntitlementDb>,
+    /// Merged answer, when synthesis was requested and succeeded
+    pub synthesis: Option<SynthesizedAnswer>,
+    /// Conversation the question and answers were saved to
+    pub conversation_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SynthesizedAnswer {
+    /// Answer merging the agents answers, with `[n]` markers after each claim
+    pub answer: String,
+    pub citations: Vec<AnswerCitation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AnswerCitation {
+    /// Number used between brackets in the answer
+    pub marker: usize,
+    pub agent_id: i64,
+    pub agent_name: String,
+    /// Dataset version that answered
+    pub dataset_version: i32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AgentResponse {
     pub agent_id: i64,
     pub prompt: String,
+    /// Answer of the agent, empty when it failed
     pub response: String,
+    /// Dataset version that answered
+    pub dataset_version: i32,
+    pub status: AgentResponseStatus,
+    /// Why the agent failed to answer
+    pub error: Option<String>,
+}
+
+/// `chunk` event of the answer stream
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AnswerStreamChunk {
+    pub agent_id: i64,
+    /// Text following the previous chunks of the same agent
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentTokenUsage {
+    pub agent_id: i64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// `done` event of the answer stream, sent once every agent finished
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AnswerStreamDone {
+    pub agent_responses: Vec<AgentResponse>,
+    /// True when at least one agent answered
+    pub success: bool,
+    /// What was charged for each agent under its pricing plan
+    pub charges: Vec<AgentCharge>,
+    /// Refunds owed for the agents that failed after the payment
+    pub refund_entitlements: Vec<RefundEntitlementDb>,
+    /// Tokens used by each agent that reported them
+    pub usage: Vec<AgentTokenUsage>,
+    /// Merged answer, when synthesis was requested and succeeded
+    pub synthesis: Option<SynthesizedAnswer>,
+    /// Conversation the question and answers were saved to
+    pub conversation_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentResponseStatus {
+    Success,
+    Failed,
+    /// The agent did not answer within AGENT_RESPONSE_TIMEOUT_SECS
+    TimedOut,
+}
+
+impl std::fmt::Display for AgentResponseStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            AgentResponseStatus::Success => "success",
+            AgentResponseStatus::Failed => "failed",
+            AgentResponseStatus::TimedOut => "timed_out",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct RefundEntitlementDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// Payment transaction the refund is owed for, or credits debit reference
+    pub tx_hash: String,
+    /// Price paid for the agent
+    #[schema(value_type = String, example = "1.5")]
+    pub amount: Price,
+    /// failed or timed_out
+    pub reason: String,
+    pub error: Option<String>,
+    /// pending, processing, refunded, credited or failed
+    pub status: String,
+    /// Transaction sending the refund back to the payer, when refunded on-chain
+    pub refund_tx_hash: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundReason {
+    Failed,
+    TimedOut,
+    /// Refund granted on a dispute
+    Disputed,
+}
+
+impl std::fmt::Display for RefundReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            RefundReason::Failed => "failed",
+            RefundReason::TimedOut => "timed_out",
+            RefundReason::Disputed => "disputed",
+        };
+
+        f.write_str(value)
+    }
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
@@ -173,7 +770,7 @@ pub struct AgentQueryParams {
     pub category: Option<String>,
     /// Filter agents by status
     pub status: Option<String>,
-    /// Sort field: price, created_at, updated_at, name
+    /// Sort field: price, created_at, updated_at, name, rating, review_count
     pub sort_by: Option<String>,
     /// Sort order: asc or desc (default: asc)
     pub sort_order: Option<String>,
@@ -184,7 +781,7 @@ pub struct AgentQueryResult {
     pub id: i64,
     pub name: String,
     pub description: String,
-    pub price: f64,
+    pub price: Price,
     pub owner_id: i64,
     pub address: String,
     pub dataset_path: String,
@@ -195,6 +792,9 @@ pub struct AgentQueryResult {
     pub updated_at: DateTime<Utc>,
     pub nft_id: Option<i64>,
     pub nft_tx: Option<String>,
+    pub current_version: i32,
+    pub review_count: i64,
+    pub average_rating: Option<f64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
@@ -248,11 +848,1440 @@ impl AgentCategory {
     }
 }
 
-pub type WebAppState = web::Data<AppState>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "guardrail_event_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GuardrailEventKind {
+    /// The response reproduced raw dataset rows
+    RowLeak,
+    /// The response contained PII values
+    PiiLeak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "guardrail_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GuardrailAction {
+    Redacted,
+    Refused,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct GuardrailEventDb {
+    pub id: i64,
+    pub agent_id: i64,
+    pub agent_name: String,
+    pub kind: GuardrailEventKind,
+    pub action: GuardrailAction,
+    pub leaked_rows: i32,
+    pub pii_matches: i32,
+    /// Prompt that triggered the guardrail
+    pub prompt: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
-pub struct ProfileResponse {
-    pub sucess: bool,
+pub struct GuardrailEventsResponse {
+    pub success: bool,
+    pub events: Vec<GuardrailEventDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct PrivacyBudgetDb {
+    pub agent_id: i64,
+    /// Total epsilon the owner allows to be spent on the dataset
+    pub epsilon_total: f64,
+    /// Epsilon consumed by each aggregate query
+    pub epsilon_per_query: f64,
+    /// Epsilon already consumed
+    pub epsilon_spent: f64,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Mean,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterOperator {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AggregateFilter {
+    /// Column to filter on
+    pub column: String,
+    pub operator: FilterOperator,
+    /// Value compared numerically when both sides are numbers, as text otherwise
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AggregateQueryRequest {
+    pub function: AggregateFunction,
+    /// Column to aggregate (required for sum and mean)
+    pub column: Option<String>,
+    #[serde(default)]
+    pub filters: Vec<AggregateFilter>,
+    pub tx_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AggregateQueryResponse {
+    pub success: bool,
+    pub agent_id: i64,
+    pub function: AggregateFunction,
+    pub column: Option<String>,
+    pub value: f64,
+    /// Dataset version that answered
+    pub dataset_version: i32,
+    /// Whether differential privacy noise was added to the value
+    pub noisy: bool,
+    /// Epsilon consumed by this query
+    pub epsilon_spent: Option<f64>,
+    /// Epsilon left in the dataset privacy budget
+    pub epsilon_remaining: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetVersionMode {
+    /// First version, published at upload
+    Initial,
+    /// Rows added to the previous version
+    Append,
+    /// Previous version replaced entirely
+    Replace,
+}
+
+impl std::fmt::Display for DatasetVersionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            DatasetVersionMode::Initial => "initial",
+            DatasetVersionMode::Append => "append",
+            DatasetVersionMode::Replace => "replace",
+        };
+
+        f.write_str(value)
+    }
+}
+
+impl DatasetVersionMode {
+    pub fn from_string(mode: &str) -> Option<DatasetVersionMode> {
+        match mode {
+            "initial" => Some(DatasetVersionMode::Initial),
+            "append" => Some(DatasetVersionMode::Append),
+            "replace" => Some(DatasetVersionMode::Replace),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DatasetVersionDb {
+    pub id: i64,
+    pub agent_id: i64,
+    pub version: i32,
+    pub dataset_path: String,
+    pub dataset_size: f64,
+    pub row_count: Option<i64>,
+    /// initial, append or replace
+    pub mode: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Multipart form of a new dataset version, it only documents the request body
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct DatasetVersionUploadRequest {
+    /// CSV file of the new version
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+    /// append or replace (default: replace)
+    pub mode: Option<String>,
+    /// PII policies applied to the new rows (default: mask), tokenise is refused in append mode
+    pub pii_policy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DatasetVersionUploadResponse {
+    pub success: bool,
     pub message: String,
-    pub agents: Vec<AgentDb>,
+    pub version: DatasetVersionDb,
+    /// Columns profile of the new version
+    pub columns: Vec<DatasetColumn>,
+    /// PII found in the new rows and the policies applied to it
+    pub pii_report: PiiReport,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DatasetVersionsResponse {
+    pub success: bool,
+    pub current_version: i32,
+    pub versions: Vec<DatasetVersionDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateAgentRequest {
+    /// New status: active, paused or archived
+    pub status: Option<String>,
+    /// New price per query, between 1 and 50000000 with up to 8 decimals
+    #[schema(value_type = Option<String>, example = "1.5")]
+    pub price: Option<Price>,
+    /// New dataset name
+    pub name: Option<String>,
+    /// New dataset description
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateAgentResponse {
+    pub success: bool,
+    pub message: String,
+    pub agent: AgentDb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionReason {
+    /// The owner withdrew the dataset from the marketplace
+    OwnerWithdrawal,
+    /// A data subject requested the erasure of their data
+    ErasureRequest,
+}
+
+impl std::fmt::Display for DeletionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            DeletionReason::OwnerWithdrawal => "owner_withdrawal",
+            DeletionReason::ErasureRequest => "erasure_request",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeleteAgentRequest {
+    /// Why the dataset is deleted (default: owner_withdrawal)
+    pub reason: Option<DeletionReason>,
+    /// Free text kept in the deletion record, e.g. the erasure request reference
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DatasetDeletionDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// Address that requested the deletion
+    pub requested_by: String,
+    /// owner_withdrawal or erasure_request
+    pub reason: String,
+    pub note: Option<String>,
+    /// Dataset files of every version, removed from the upload directory once the deletion is committed
+    pub removed_files: Vec<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeleteAgentResponse {
+    pub success: bool,
+    pub message: String,
+    pub deletion: DatasetDeletionDb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentCacheMetrics {
+    /// Requests served by an agent already in memory
+    pub hits: u64,
+    /// Requests that had to build the agent from its dataset
+    pub misses: u64,
+    pub hit_rate: f64,
+    /// Agents dropped to stay within the cache bounds
+    pub evictions: u64,
+    /// Agents that could not be built, e.g. because their dataset file is unreadable
+    pub load_failures: u64,
+    pub cached_agents: usize,
+    pub capacity: usize,
+    /// Total size of the datasets held by cached agents
+    pub cached_dataset_bytes: usize,
+    pub max_dataset_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentCacheMetricsResponse {
+    pub success: bool,
+    pub metrics: AgentCacheMetrics,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct ConversationDb {
+    pub id: i64,
+    pub user_address: String,
+    /// First question of the conversation
+    pub title: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageRole {
+    User,
+    Assistant,
+}
+
+impl std::fmt::Display for MessageRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct MessageDb {
+    pub id: i64,
+    pub conversation_id: i64,
+    /// user or assistant
+    pub role: String,
+    /// Agent that answered, for assistant messages
+    pub agent_id: Option<i64>,
+    pub content: String,
+    pub dataset_version: Option<i32>,
+    /// Payment of the question, for user messages
+    pub tx_hash: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConversationsResponse {
+    pub success: bool,
+    pub conversations: Vec<ConversationDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConversationResponse {
+    pub success: bool,
+    pub conversation: ConversationDb,
+    pub messages: Vec<MessageDb>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AgentEmbeddingDb {
+    pub agent_id: i64,
+    /// Embedding model, embeddings of different models are not comparable
+    pub model: String,
+    /// Text the embedding was computed from
+    pub source_text: String,
+    pub embedding: Vec<f64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AgentSearchParams {
+    /// Free text describing the data looked for
+    pub q: String,
+    /// Maximum number of agents returned (default: 10)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentSearchResult {
+    pub agent: AgentDb,
+    /// Cosine similarity between the query and the agent, from -1.0 to 1.0
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentSearchResponse {
+    pub success: bool,
+    /// Most similar agents first
+    pub results: Vec<AgentSearchResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccountKind {
+    /// Prepaid credits of a user
+    UserCredit,
+    /// Revenue of an agent not yet moved to a settlement
+    AgentRevenue,
+    /// Revenue waiting to be settled on-chain with settleCredits
+    SettlementsPending,
+    /// Counterpart of the credits deposited on-chain
+    OnchainDeposits,
+    /// Counterpart of the revenue settled on-chain
+    OnchainSettlements,
+    /// Deposits of the platform funding address less the credits it gave as refunds of on-chain payments
+    PlatformFunding,
+}
+
+impl std::fmt::Display for LedgerAccountKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            LedgerAccountKind::UserCredit => "user_credit",
+            LedgerAccountKind::AgentRevenue => "agent_revenue",
+            LedgerAccountKind::SettlementsPending => "settlements_pending",
+            LedgerAccountKind::OnchainDeposits => "onchain_deposits",
+            LedgerAccountKind::OnchainSettlements => "onchain_settlements",
+            LedgerAccountKind::PlatformFunding => "platform_funding",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerTransactionKind {
+    Deposit,
+    AnswerDebit,
+    Refund,
+    SettlementAccrual,
+    Settlement,
+    Subscription,
+}
+
+impl std::fmt::Display for LedgerTransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            LedgerTransactionKind::Deposit => "deposit",
+            LedgerTransactionKind::AnswerDebit => "answer_debit",
+            LedgerTransactionKind::Refund => "refund",
+            LedgerTransactionKind::SettlementAccrual => "settlement_accrual",
+            LedgerTransactionKind::Settlement => "settlement",
+            LedgerTransactionKind::Subscription => "subscription",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditEventKind {
+    Deposit,
+    Settlement,
+}
+
+impl std::fmt::Display for CreditEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            CreditEventKind::Deposit => "deposit",
+            CreditEventKind::Settlement => "settlement",
+        };
+
+        f.write_str(value)
+    }
+}
+
+/// Entry of a ledger account with the transaction it belongs to
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct LedgerEntryDb {
+    pub id: i64,
+    pub transaction_id: i64,
+    /// deposit, answer_debit, refund, settlement_accrual or settlement
+    pub kind: String,
+    pub reference: String,
+    /// Positive when the account balance increased
+    #[schema(value_type = String, example = "-1.5")]
+    pub amount: Price,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct CreditSettlementDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// NFT of the agent to pass to settleCredits
+    pub token_id: i64,
+    #[schema(value_type = String, example = "12.5")]
+    pub amount: Price,
+    /// pending or settled
+    pub status: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub settled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreditBalanceResponse {
+    pub success: bool,
+    pub address: String,
+    #[schema(value_type = String, example = "10")]
+    pub balance: Price,
+    /// Latest movements of the credits, most recent first
+    pub entries: Vec<LedgerEntryDb>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreditSettlementsParams {
+    /// pending or settled
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreditSettlementsResponse {
+    pub success: bool,
+    pub settlements: Vec<CreditSettlementDb>,
+}
+
+/// Comparison of the ledger with itself and with the indexed on-chain events
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LedgerReconciliation {
+    /// Every transaction sums to zero and every account balance equals the sum of its entries
+    pub ledger_balanced: bool,
+    /// Accounts whose balance differs from the sum of their entries
+    pub mismatched_account_ids: Vec<i64>,
+    #[schema(value_type = String)]
+    pub indexed_deposits: Price,
+    /// Deposits credited to users, equal to the indexed deposits when reconciled
+    #[schema(value_type = String)]
+    pub credited_deposits: Price,
+    /// Indexed deposit events without a ledger transaction
+    pub unbooked_deposit_events: i64,
+    #[schema(value_type = String)]
+    pub indexed_settlements: Price,
+    /// Settlements booked in the ledger, equal to the indexed settlements when reconciled
+    #[schema(value_type = String)]
+    pub booked_settlements: Price,
+    /// Indexed settlement events that matched no pending settlement
+    pub unbooked_settlement_events: i64,
+    #[schema(value_type = String)]
+    pub outstanding_user_credits: Price,
+    #[schema(value_type = String)]
+    pub accrued_agent_revenue: Price,
+    #[schema(value_type = String)]
+    pub pending_settlements: Price,
+    /// Deposits of the platform funding address not yet given as refunds of on-chain payments,
+    /// negative when creditPool is short of the credits the platform gave
+    #[schema(value_type = String)]
+    pub platform_funding: Price,
+    /// creditPool the contract should hold: deposits not yet settled
+    #[schema(value_type = String)]
+    pub expected_credit_pool: Price,
+    pub reconciled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LedgerReconciliationResponse {
+    pub success: bool,
+    pub reconciliation: LedgerReconciliation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PricingModel {
+    /// Every query is paid at the agent price
+    PerQuery,
+    /// Subscribers query for free during their subscription, others pay per query
+    Subscription,
+    /// The price per query decreases with the queries made in the tier window
+    VolumeTiers,
+}
+
+impl std::fmt::Display for PricingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            PricingModel::PerQuery => "per_query",
+            PricingModel::Subscription => "subscription",
+            PricingModel::VolumeTiers => "volume_tiers",
+        };
+
+        f.write_str(value)
+    }
+}
+
+impl FromStr for PricingModel {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "per_query" => Ok(PricingModel::PerQuery),
+            "subscription" => Ok(PricingModel::Subscription),
+            "volume_tiers" => Ok(PricingModel::VolumeTiers),
+            _ => Err(eyre!("Invalid pricing model: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct PricingPlanDb {
+    pub agent_id: i64,
+    pub model: String,
+    #[schema(value_type = Option<String>)]
+    pub subscription_price: Option<Price>,
+    pub subscription_period_days: Option<i32>,
+    pub free_sample_queries: i32,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct PriceTierDb {
+    pub agent_id: i64,
+    pub min_queries: i32,
+    #[schema(value_type = String, example = "0.5")]
+    pub price: Price,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PriceTier {
+    /// Queries made to the agent in the last VOLUME_TIER_WINDOW_DAYS from which this price applies
+    pub min_queries: i32,
+    #[schema(value_type = String, example = "0.5")]
+    pub price: Price,
+}
+
+/// Pricing plan of an agent, agents without one are priced per query at their price
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PricingPlan {
+    pub model: PricingModel,
+    /// Price of a query without subscription and below the first tier
+    #[schema(value_type = String, example = "1.5")]
+    pub per_query_price: Price,
+    #[schema(value_type = Option<String>, example = "30")]
+    pub subscription_price: Option<Price>,
+    pub subscription_period_days: Option<i32>,
+    pub free_sample_queries: i32,
+    /// Volume tiers sorted by min_queries
+    pub tiers: Vec<PriceTier>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdatePricingPlanRequest {
+    pub model: PricingModel,
+    /// Required with the subscription model
+    #[schema(value_type = Option<String>, example = "30")]
+    pub subscription_price: Option<Price>,
+    /// Required with the subscription model
+    pub subscription_period_days: Option<i32>,
+    #[serde(default)]
+    pub free_sample_queries: i32,
+    /// Required with the volume_tiers model
+    #[serde(default)]
+    pub tiers: Vec<PriceTier>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeBasis {
+    PerQuery,
+    VolumeTier,
+    FreeSample,
+    Subscription,
+}
+
+impl std::fmt::Display for ChargeBasis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            ChargeBasis::PerQuery => "per_query",
+            ChargeBasis::VolumeTier => "volume_tier",
+            ChargeBasis::FreeSample => "free_sample",
+            ChargeBasis::Subscription => "subscription",
+        };
+
+        f.write_str(value)
+    }
+}
+
+/// Amount a user owes for their next query to an agent under its pricing plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AgentCharge {
+    pub agent_id: i64,
+    #[schema(value_type = String, example = "1.5")]
+    pub amount: Price,
+    pub basis: ChargeBasis,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct AgentSubscriptionDb {
+    pub id: i64,
+    pub agent_id: i64,
+    pub user_address: String,
+    pub payment_reference: String,
+    #[schema(value_type = String, example = "30")]
+    pub amount: Price,
+    #[schema(value_type = String, format = DateTime)]
+    pub starts_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub expires_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PricingPlanResponse {
+    pub success: bool,
+    pub agent_id: i64,
+    pub pricing_plan: PricingPlan,
+    /// Charge of the next query of the signer, when the request is signed
+    pub next_charge: Option<AgentCharge>,
+    /// Active subscription of the signer, when the request is signed
+    pub subscription: Option<AgentSubscriptionDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SubscribeResponse {
+    pub success: bool,
+    pub subscription: AgentSubscriptionDb,
+}
+
+/// Prompt and answer of a paid agent, kept as the evidence of disputes
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct AgentAnswerDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// Tx hash or credits debit reference of the question
+    pub payment_reference: String,
+    pub prompt: String,
+    pub response: String,
+    /// success, failed or timed_out
+    pub status: String,
+    pub error: Option<String>,
+    pub dataset_version: Option<i32>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeCategory {
+    /// The agent failed or did not answer
+    TechnicalFailure,
+    IncorrectAnswer,
+    IncompleteAnswer,
+    Other,
+}
+
+impl std::fmt::Display for DisputeCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            DisputeCategory::TechnicalFailure => "technical_failure",
+            DisputeCategory::IncorrectAnswer => "incorrect_answer",
+            DisputeCategory::IncompleteAnswer => "incomplete_answer",
+            DisputeCategory::Other => "other",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus {
+    Open,
+    /// The owner contested the dispute, it waits for the arbiter
+    OwnerResponded,
+    Refunded,
+    Rejected,
+}
+
+impl std::fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            DisputeStatus::Open => "open",
+            DisputeStatus::OwnerResponded => "owner_responded",
+            DisputeStatus::Refunded => "refunded",
+            DisputeStatus::Rejected => "rejected",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DisputeDb {
+    pub id: i64,
+    /// Disputed answer
+    pub answer_id: i64,
+    pub agent_id: i64,
+    /// Payer of the disputed answer
+    pub user_address: String,
+    /// technical_failure, incorrect_answer, incomplete_answer or other
+    pub category: String,
+    pub description: String,
+    /// open, owner_responded, refunded or rejected
+    pub status: String,
+    pub owner_response: Option<String>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub owner_responded_at: Option<DateTime<Utc>>,
+    pub resolution_note: Option<String>,
+    /// Agent owner accepting the refund or dispute arbiter
+    pub resolved_by: Option<String>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub refund_entitlement_id: Option<i64>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateDisputeRequest {
+    /// Tx hash or credits debit reference of the disputed answer
+    pub payment_reference: String,
+    pub agent_id: i64,
+    pub category: DisputeCategory,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DisputeOwnerResponseRequest {
+    pub response: String,
+    /// Refunds the user and closes the dispute, otherwise the dispute waits for the arbiter
+    #[serde(default)]
+    pub accept_refund: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ResolveDisputeRequest {
+    /// Refunds the user when true, rejects the dispute otherwise
+    pub refund: bool,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DisputeResponse {
+    pub success: bool,
+    pub dispute: DisputeDb,
+    /// Stored prompt and answer the dispute is about
+    pub evidence: AgentAnswerDb,
+    /// Refund granted on the dispute
+    pub refund_entitlement: Option<RefundEntitlementDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DisputesResponse {
+    pub success: bool,
+    pub disputes: Vec<DisputeDb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryOutcome {
+    /// Every agent answered
+    Success,
+    /// Some agents failed
+    Partial,
+    Failed,
+}
+
+impl std::fmt::Display for QueryOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            QueryOutcome::Success => "success",
+            QueryOutcome::Partial => "partial",
+            QueryOutcome::Failed => "failed",
+        };
+
+        f.write_str(value)
+    }
+}
+
+/// Paid question to log in the queries audit log
+#[derive(Debug, Clone)]
+pub struct NewQuery {
+    pub requester_address: String,
+    pub agent_ids: Vec<i64>,
+    pub prompt_hash: String,
+    pub prompt: Option<String>,
+    pub payment_reference: String,
+    pub amount: Price,
+    pub outcome: QueryOutcome,
+    pub latency_ms: i64,
+    pub streamed: bool,
+    pub agents: Vec<NewQueryAgent>,
+}
+
+/// Outcome of one agent of a logged question
+#[derive(Debug, Clone)]
+pub struct NewQueryAgent {
+    pub agent_id: i64,
+    pub status: AgentResponseStatus,
+    pub response_hash: Option<String>,
+    pub latency_ms: i64,
+    pub usage: Option<AgentTokenUsage>,
+    pub model: String,
+    pub dataset_version: i32,
+    pub amount: Price,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct QueryDb {
+    pub id: i64,
+    pub requester_address: String,
+    pub agent_ids: Vec<i64>,
+    /// Keccak-256 of the prompt
+    pub prompt_hash: String,
+    /// Prompt text, when the requester chose to store it
+    pub prompt: Option<String>,
+    /// Tx hash or credits debit reference
+    pub payment_reference: String,
+    /// Total charged to the requester
+    #[schema(value_type = String, example = "1.5")]
+    pub amount: Price,
+    /// success, partial or failed
+    pub outcome: String,
+    pub latency_ms: i64,
+    /// Whether the answers were streamed
+    pub streamed: bool,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct QueryAgentDb {
+    pub query_id: i64,
+    pub agent_id: i64,
+    /// success, failed or timed_out
+    pub status: String,
+    /// Keccak-256 of the answer, missing when the agent failed
+    pub response_hash: Option<String>,
+    pub latency_ms: i64,
+    /// Token usage, when the model reported it
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+    /// Model that answered
+    pub model: String,
+    pub dataset_version: i32,
+    /// Charged for the agent
+    #[schema(value_type = String, example = "1.5")]
+    pub amount: Price,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueryLogEntry {
+    #[serde(flatten)]
+    pub query: QueryDb,
+    /// Outcome of each agent asked
+    pub agents: Vec<QueryAgentDb>,
+}
+
+/// Question asked to an agent as its owner sees it, without the requester, the prompt or the other agents asked
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct AgentQueryDb {
+    pub query_id: i64,
+    /// Keccak-256 of the prompt
+    pub prompt_hash: String,
+    /// Whether the answers were streamed
+    pub streamed: bool,
+    /// success, failed or timed_out
+    pub status: String,
+    /// Keccak-256 of the answer, missing when the agent failed
+    pub response_hash: Option<String>,
+    pub latency_ms: i64,
+    /// Token usage, when the model reported it
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+    /// Model that answered
+    pub model: String,
+    pub dataset_version: i32,
+    /// Charged for the agent
+    #[schema(value_type = String, example = "1.5")]
+    pub amount: Price,
+    pub error: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentQueryLogResponse {
+    pub success: bool,
+    pub queries: Vec<AgentQueryDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueryLogParams {
+    /// Maximum number of queries returned (default: 50)
+    pub limit: Option<i64>,
+    /// Only queries older than this query id, to fetch the next page
+    pub before_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueryLogResponse {
+    pub success: bool,
+    pub queries: Vec<QueryLogEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MarketplaceStatsParams {
+    /// Start of the time range (default: 30 days before `to`)
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of the time range (default: now)
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub to: Option<DateTime<Utc>>,
+    /// Bucket of the time series: day, week or month (default: day)
+    pub interval: Option<String>,
+    /// Number of top datasets returned (default: 10)
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct MarketplaceTotals {
+    /// Datasets listed at the end of the range
+    pub datasets: i64,
+    pub queries: i64,
+    pub paid_queries: i64,
+    /// Charged for the queries, refunds deducted
+    #[schema(value_type = String, example = "12.5")]
+    pub revenue: Price,
+    #[schema(value_type = String, example = "1.5")]
+    pub refunded: Price,
+    /// Distinct users who asked at least one question
+    pub active_buyers: i64,
+    /// Average time of the agents that answered, missing without answers
+    pub average_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct CategoryStats {
+    pub category: AgentCategory,
+    /// Datasets listed at the end of the range
+    pub datasets: i64,
+    pub queries: i64,
+    #[schema(value_type = String, example = "12.5")]
+    pub revenue: Price,
+    pub average_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DatasetStats {
+    pub agent_id: i64,
+    pub name: String,
+    pub category: AgentCategory,
+    pub queries: i64,
+    #[schema(value_type = String, example = "12.5")]
+    pub revenue: Price,
+    pub average_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct TimeBucketCount {
+    /// Start of the bucket
+    #[schema(value_type = String, format = DateTime)]
+    pub period_start: DateTime<Utc>,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MarketplaceStatsResponse {
+    pub success: bool,
+    #[schema(value_type = String, format = DateTime)]
+    pub from: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub to: DateTime<Utc>,
+    pub interval: String,
+    /// Last refresh of the usage aggregates, queries made since are not counted yet
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub refreshed_at: Option<DateTime<Utc>>,
+    pub totals: MarketplaceTotals,
+    pub categories: Vec<CategoryStats>,
+    pub top_by_queries: Vec<DatasetStats>,
+    pub top_by_revenue: Vec<DatasetStats>,
+    /// Datasets created in each bucket of the range
+    pub new_datasets: Vec<TimeBucketCount>,
+    /// Distinct users who asked a question in each bucket of the range
+    pub active_buyers: Vec<TimeBucketCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct AgentReviewDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// Address that paid for a query to the agent
+    pub reviewer_address: String,
+    /// From 1 to 5
+    pub rating: i16,
+    pub comment: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateReviewRequest {
+    /// From 1 to 5
+    pub rating: i16,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentReviewsParams {
+    /// Maximum number of reviews returned (default: 50)
+    pub limit: Option<i64>,
+    /// Only reviews older than this review id, to fetch the next page
+    pub before_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReviewResponse {
+    pub success: bool,
+    pub review: AgentReviewDb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentReviewsResponse {
+    pub success: bool,
+    pub review_count: i64,
+    /// Average rating from 1 to 5, missing until the first review
+    pub average_rating: Option<f64>,
+    pub reviews: Vec<AgentReviewDb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluationMatchKind {
+    /// Same answer once case, whitespace and trailing punctuation are ignored
+    Exact,
+    /// A number of the answer is within the relative tolerance of the expected number
+    Numeric,
+    /// A judge model decides whether the answer means the same as the expected one
+    LlmJudge,
+}
+
+impl std::fmt::Display for EvaluationMatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            EvaluationMatchKind::Exact => "exact",
+            EvaluationMatchKind::Numeric => "numeric",
+            EvaluationMatchKind::LlmJudge => "llm_judge",
+        };
+
+        f.write_str(value)
+    }
+}
+
+impl FromStr for EvaluationMatchKind {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact" => Ok(EvaluationMatchKind::Exact),
+            "numeric" => Ok(EvaluationMatchKind::Numeric),
+            "llm_judge" => Ok(EvaluationMatchKind::LlmJudge),
+            _ => Err(eyre!("Invalid evaluation match kind: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluationRunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl std::fmt::Display for EvaluationRunStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            EvaluationRunStatus::Running => "running",
+            EvaluationRunStatus::Completed => "completed",
+            EvaluationRunStatus::Failed => "failed",
+        };
+
+        f.write_str(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct EvaluationCaseDb {
+    pub id: i64,
+    pub agent_id: i64,
+    pub question: String,
+    pub expected_answer: String,
+    /// exact, numeric or llm_judge
+    pub match_kind: String,
+    /// Relative tolerance of numeric matches
+    pub tolerance: Option<f64>,
+    pub created_by: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewEvaluationCase {
+    pub question: String,
+    pub expected_answer: String,
+    pub match_kind: EvaluationMatchKind,
+    /// Relative tolerance of numeric matches (default: 0.01)
+    pub tolerance: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateEvaluationCasesRequest {
+    pub cases: Vec<NewEvaluationCase>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EvaluationCasesResponse {
+    pub success: bool,
+    pub cases: Vec<EvaluationCaseDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct EvaluationRunDb {
+    pub id: i64,
+    pub agent_id: i64,
+    /// Dataset version the agent answered from
+    pub dataset_version: i32,
+    /// Model that answered, mock for canned answers
+    pub model: String,
+    /// running, completed or failed
+    pub status: String,
+    pub total_cases: i32,
+    pub passed_cases: i32,
+    /// Average score of the cases, from 0 to 1
+    pub score: Option<f64>,
+    pub error: Option<String>,
+    /// Address that started the run, missing for runs started from the command line
+    pub started_by: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Answer of a case and its score, before it is recorded
+#[derive(Debug, Clone)]
+pub struct NewEvaluationResult {
+    pub case_id: i64,
+    pub answer: Option<String>,
+    pub passed: bool,
+    pub score: f64,
+    pub judge_reasoning: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct EvaluationResultDb {
+    pub id: i64,
+    pub run_id: i64,
+    pub case_id: i64,
+    /// Answer after the output guardrail, missing when the agent failed
+    pub answer: Option<String>,
+    pub passed: bool,
+    pub score: f64,
+    /// Why the judge model accepted or rejected the answer of llm_judge cases
+    pub judge_reasoning: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: i64,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Verdict of the judge model on an llm_judge case
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JudgeVerdict {
+    pub correct: bool,
+    pub reasoning: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EvaluationRunResponse {
+    pub success: bool,
+    pub run: EvaluationRunDb,
+    pub results: Vec<EvaluationResultDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EvaluationRunsResponse {
+    pub success: bool,
+    pub runs: Vec<EvaluationRunDb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewMode {
+    /// No free preview questions
+    Disabled,
+    /// Answered from the columns profile only, no row of the dataset
+    Schema,
+    /// Answered from the first rows of the dataset
+    SampleRows,
+}
+
+impl std::fmt::Display for PreviewMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            PreviewMode::Disabled => "disabled",
+            PreviewMode::Schema => "schema",
+            PreviewMode::SampleRows => "sample_rows",
+        };
+
+        f.write_str(value)
+    }
+}
+
+impl FromStr for PreviewMode {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "disabled" => Ok(PreviewMode::Disabled),
+            "schema" => Ok(PreviewMode::Schema),
+            "sample_rows" => Ok(PreviewMode::SampleRows),
+            _ => Err(eyre!("Invalid preview mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdatePreviewSettingsRequest {
+    pub mode: PreviewMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PreviewSettingsResponse {
+    pub success: bool,
+    pub agent_id: i64,
+    /// Mode previews are answered in, always disabled for datasets in privacy mode
+    pub mode: PreviewMode,
+    /// Free preview questions per address and per IP each day (UTC), all agents included
+    pub daily_limit: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PreviewQueryRequest {
+    pub prompt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PreviewAnswerResponse {
+    pub success: bool,
+    pub agent_id: i64,
+    /// What the answer was given from, the full dataset answers paid questions
+    pub mode: PreviewMode,
+    pub response: String,
+    /// The answer was cut at the preview output limit
+    pub truncated: bool,
+    /// Free preview questions left today for this address or IP
+    pub remaining_today: i64,
+}
+
+pub type WebAppState = web::Data<AppState>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProfileResponse {
+    pub sucess: bool,
+    pub message: String,
+    pub agents: Vec<AgentDb>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_round_trips_through_8_decimals() {
+        for (text, units) in [
+            ("0", 0),
+            ("12", 1_200_000_000),
+            ("0.5", 50_000_000),
+            ("0.00000001", 1),
+            ("1.23456789", 123_456_789),
+        ] {
+            let price: Price = text.parse().unwrap();
+            assert_eq!(price.units(), units);
+            assert_eq!(price.to_string(), text);
+        }
+
+        // Trailing zeros are trimmed, leading and trailing dots are accepted
+        assert_eq!("1.25000000".parse::<Price>().unwrap().to_string(), "1.25");
+        assert_eq!(
+            ".5".parse::<Price>().unwrap(),
+            Price::from_units(50_000_000)
+        );
+        assert_eq!("2.".parse::<Price>().unwrap(), Price::from_whole(2));
+
+        let json = serde_json::to_string(&Price::from_units(150_000_000)).unwrap();
+        assert_eq!(json, "\"1.5\"");
+        assert_eq!(
+            serde_json::from_str::<Price>("1.5").unwrap(),
+            Price::from_units(150_000_000)
+        );
+    }
+
+    #[test]
+    fn price_refuses_rounding_and_invalid_amounts() {
+        // More precision than the token has would have to be rounded
+        assert!("0.000000001".parse::<Price>().is_err());
+        assert!("1.123456789".parse::<Price>().is_err());
+
+        for invalid in ["", ".", "abc", "1,5", "1.2.3", "+1", "1e3"] {
+            assert!(invalid.parse::<Price>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn price_refuses_negative_amounts() {
+        assert!("-1".parse::<Price>().is_err());
+        assert!("-0.5".parse::<Price>().is_err());
+        assert!(serde_json::from_str::<Price>("-1").is_err());
+
+        // Negative prices only come from ledger debits
+        assert_eq!((-Price::from_units(150_000_000)).to_string(), "-1.5");
+        assert!((-Price::from_whole(1)).to_u256().is_err());
+    }
+
+    #[test]
+    fn price_overflow_is_refused_or_saturated() {
+        assert!("92233720368.54775807".parse::<Price>().is_ok());
+        assert!("92233720368.54775808".parse::<Price>().is_err());
+        assert!("100000000000".parse::<Price>().is_err());
+
+        assert!(Price::from_u256(U256::from(u64::MAX)).is_err());
+        assert_eq!(
+            Price::from_u256(U256::from(42u64)).unwrap(),
+            Price::from_units(42)
+        );
+
+        let max = Price::from_units(i64::MAX);
+        assert_eq!(max.checked_add(Price::from_units(1)), None);
+        assert_eq!([max, Price::from_units(1)].into_iter().sum::<Price>(), max);
+        assert_eq!(
+            [-max, Price::from_units(-2)].into_iter().sum::<Price>(),
+            Price::from_units(i64::MIN)
+        );
+        assert_eq!(
+            [Price::from_whole(1), Price::from_units(5)]
+                .into_iter()
+                .sum::<Price>(),
+            Price::from_units(100_000_005)
+        );
+    }
 }
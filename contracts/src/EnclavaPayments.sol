@@ -20,6 +20,9 @@ contract EnclavaPayments is ERC721, ERC721Enumerable, Ownable, ReentrancyGuard {
     // Mapping from token ID to NFT metadata
     mapping(uint256 => DatasetNFT) public nftMetadata;
 
+    // Prepaid credits deposited by users and not yet settled to datasets
+    uint256 public creditPool;
+
     event Payment(address indexed to, uint256 amount);
     event TotalPayment(uint256 amount);
     event DatasetNFTMinted(
@@ -38,6 +41,8 @@ contract EnclavaPayments is ERC721, ERC721Enumerable, Ownable, ReentrancyGuard {
         address indexed user,
         uint256 amount
     );
+    event CreditsDeposited(address indexed user, uint256 amount);
+    event CreditsSettled(uint256 indexed tokenId, uint256 amount);
 
     constructor(
         address initialOwner
@@ -124,6 +129,46 @@ contract EnclavaPayments is ERC721, ERC721Enumerable, Ownable, ReentrancyGuard {
         emit TotalPayment(totalAmount);
     }
 
+    /// @notice Function to deposit prepaid credits, spent off-chain on dataset usage
+    function depositCredits() public payable nonReentrant {
+        require(msg.value > 0, "AMOUNT_MUST_BE_POSITIVE");
+
+        creditPool += msg.value;
+
+        emit CreditsDeposited(msg.sender, msg.value);
+    }
+
+    /// @notice Function to settle the credits spent on datasets to their owners
+    /// @param tokenIds Array of NFT token IDs
+    /// @param amounts Array of amounts earned by each dataset from credits
+    function settleCredits(
+        uint256[] calldata tokenIds,
+        uint256[] calldata amounts
+    ) public onlyOwner nonReentrant {
+        require(tokenIds.length == amounts.length, "ARRAY_LENGTH_MISMATCH");
+
+        for (uint256 i = 0; i < tokenIds.length; i++) {
+            require(
+                _ownerOf(tokenIds[i]) != address(0),
+                "TOKEN_DOES_NOT_EXIST"
+            );
+            require(amounts[i] > 0, "AMOUNT_MUST_BE_POSITIVE");
+            require(amounts[i] <= creditPool, "INSUFFICIENT_CREDIT_POOL");
+
+            creditPool -= amounts[i];
+
+            // Update NFT metadata
+            nftMetadata[tokenIds[i]].unclaimedAmount += amounts[i];
+            nftMetadata[tokenIds[i]].totalEarned += amounts[i];
+
+            emit CreditsSettled(tokenIds[i], amounts[i]);
+            emit UnclaimedAmountUpdated(
+                tokenIds[i],
+                nftMetadata[tokenIds[i]].unclaimedAmount
+            );
+        }
+    }
+
     // @notice Function for NFT owner to claim their earnings
     /// @param tokenId The NFT token ID to claim from
     /// @param amount Amount to claim (must be <= unclaimedAmount)
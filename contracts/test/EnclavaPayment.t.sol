@@ -28,6 +28,8 @@ contract EnclavaPaymentsTest is Test {
         uint256 amount
     );
     event UnclaimedAmountUpdated(uint256 indexed tokenId, uint256 newAmount);
+    event CreditsDeposited(address indexed user, uint256 amount);
+    event CreditsSettled(uint256 indexed tokenId, uint256 amount);
 
     function setUp() public {
         owner = makeAddr("owner");
@@ -218,6 +220,88 @@ contract EnclavaPaymentsTest is Test {
         );
     }
 
+    // ================================
+    // CREDITS TESTS
+    // ================================
+
+    function test_DepositCredits() public {
+        vm.expectEmit(true, false, false, true);
+        emit CreditsDeposited(dataConsumer, 2 ether);
+
+        vm.prank(dataConsumer);
+        enclavaPayments.depositCredits{value: 2 ether}();
+
+        assertEq(enclavaPayments.creditPool(), 2 ether);
+        assertEq(address(enclavaPayments).balance, 2 ether);
+    }
+
+    function test_DepositCredits_ZeroAmount() public {
+        vm.prank(dataConsumer);
+        vm.expectRevert("AMOUNT_MUST_BE_POSITIVE");
+        enclavaPayments.depositCredits{value: 0}();
+    }
+
+    function test_SettleCredits() public {
+        vm.prank(owner);
+        uint256 tokenId = enclavaPayments.safeMint(user1, "dataset_1");
+
+        vm.prank(dataConsumer);
+        enclavaPayments.depositCredits{value: 2 ether}();
+
+        uint256[] memory tokenIds = new uint256[](1);
+        tokenIds[0] = tokenId;
+
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = 1.5 ether;
+
+        vm.expectEmit(true, false, false, true);
+        emit CreditsSettled(tokenId, 1.5 ether);
+
+        vm.prank(owner);
+        enclavaPayments.settleCredits(tokenIds, amounts);
+
+        assertEq(enclavaPayments.creditPool(), 0.5 ether);
+        assertEq(enclavaPayments.getUnclaimedAmount(tokenId), 1.5 ether);
+
+        // Settled credits can be claimed like direct payments
+        vm.prank(user1);
+        enclavaPayments.claimAllEarnings(tokenId);
+        assertEq(user1.balance, 11.5 ether);
+    }
+
+    function test_SettleCredits_NotOwner() public {
+        vm.prank(owner);
+        uint256 tokenId = enclavaPayments.safeMint(user1, "dataset_1");
+
+        uint256[] memory tokenIds = new uint256[](1);
+        tokenIds[0] = tokenId;
+
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = 1 ether;
+
+        vm.prank(user1);
+        vm.expectRevert();
+        enclavaPayments.settleCredits(tokenIds, amounts);
+    }
+
+    function test_SettleCredits_InsufficientCreditPool() public {
+        vm.prank(owner);
+        uint256 tokenId = enclavaPayments.safeMint(user1, "dataset_1");
+
+        vm.prank(dataConsumer);
+        enclavaPayments.depositCredits{value: 1 ether}();
+
+        uint256[] memory tokenIds = new uint256[](1);
+        tokenIds[0] = tokenId;
+
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = 2 ether;
+
+        vm.prank(owner);
+        vm.expectRevert("INSUFFICIENT_CREDIT_POOL");
+        enclavaPayments.settleCredits(tokenIds, amounts);
+    }
+
     // ================================
     // CLAIMING TESTS
     // ================================
@@ -4,7 +4,7 @@ import { DATASET_NFT_CONTRACT } from "../contracts/DatasetNFT";
 
 export interface PaymentData {
   tokenIds: number[];
-  amounts: number[]; // amounts in Hedera
+  amounts: string[]; // amounts in Hedera, as decimal strings
 }
 
 export interface UseDatasetPaymentReturn {
@@ -261,7 +261,7 @@ const Chat: React.FC = () => {
 
     // Calculate total cost
     const totalCost = selectedAgentData.reduce(
-      (sum, agent) => sum + agent.price,
+      (sum, agent) => sum + Number(agent.price),
       0
     );
 
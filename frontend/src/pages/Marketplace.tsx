@@ -368,7 +368,7 @@ export const Marketplace: React.FC = () => {
             <div className="border-2 border-black p-4 text-center">
               <div className="font-mono text-2xl font-black text-violet-500">
                 {datasets
-                  .reduce((total, dataset) => total + dataset.price, 0)
+                  .reduce((total, dataset) => total + Number(dataset.price), 0)
                   .toFixed(1)}{" "}
                 HBAR
               </div>
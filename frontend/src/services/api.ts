@@ -187,7 +187,8 @@ export interface ChatAgent {
   id: number;
   name: string;
   description: string;
-  price: number;
+  // Decimal string with up to 8 decimals, e.g. "1.5"
+  price: string;
   owner_id: number;
   dataset_path: string;
   category: string;
@@ -303,7 +304,8 @@ export interface MarketplaceDataset {
   id: number;
   name: string;
   description: string;
-  price: number;
+  // Decimal string with up to 8 decimals, e.g. "1.5"
+  price: string;
   owner_id: number;
   owner_address: string;
   dataset_path: string;